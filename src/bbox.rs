@@ -59,6 +59,26 @@ impl BBox {
         x && y && z
     }
 
+    // The part of this box that is also inside b. Boxes that don't overlap
+    // clip to an empty box.
+    pub fn clipped_to(&self, b: &BBox) -> BBox {
+        let clipped = BBox::new_with(
+            Point::new_with(self.p_min.x.max(b.p_min.x),
+                            self.p_min.y.max(b.p_min.y),
+                            self.p_min.z.max(b.p_min.z)),
+            Point::new_with(self.p_max.x.min(b.p_max.x),
+                            self.p_max.y.min(b.p_max.y),
+                            self.p_max.z.min(b.p_max.z)));
+
+        if clipped.p_min.x > clipped.p_max.x ||
+            clipped.p_min.y > clipped.p_max.y ||
+            clipped.p_min.z > clipped.p_max.z {
+            BBox::new()
+        } else {
+            clipped
+        }
+    }
+
     pub fn inside(&self, p: &Point) -> bool {
         p.x >= self.p_min.x && p.x <= self.p_max.x &&
             p.y >= self.p_min.y && p.y <= self.p_max.y &&
@@ -301,6 +321,19 @@ mod tests {
         assert!(bbox_3.overlaps(&bbox_4));
     }
 
+    #[test]
+    fn it_can_be_clipped_to_others() {
+        let a = BBox::new_with(Point::new_with(0.0, 0.0, 0.0), Point::new_with(2.0, 2.0, 2.0));
+        let b = BBox::new_with(Point::new_with(1.0, -1.0, 1.0), Point::new_with(3.0, 1.0, 1.0));
+        assert_eq!(a.clipped_to(&b),
+                   BBox::new_with(Point::new_with(1.0, 0.0, 1.0), Point::new_with(2.0, 1.0, 1.0)));
+        assert_eq!(b.clipped_to(&a), a.clipped_to(&b));
+
+        let c = BBox::new_with(Point::new_with(3.0, 0.0, 0.0), Point::new_with(4.0, 2.0, 2.0));
+        assert_eq!(a.clipped_to(&c), BBox::new());
+        assert_eq!(a.clipped_to(&c).surface_area(), 0.0);
+    }
+
     #[test]
     fn it_knows_when_points_are_inside() {
         let bbox = BBox::new_with(
@@ -1,2 +1,193 @@
-pub struct BSSRDF;
+use spectrum::Spectrum;
 
+// Approximation to the average Fresnel reflectance over the hemisphere
+// for a dielectric with relative index of refraction eta.
+pub fn fdr(eta: f32) -> f32 {
+    if eta < 1.0 {
+        -0.4399 + 0.7099 / eta - 0.3319 / (eta * eta) + 0.0636 / (eta * eta * eta)
+    } else {
+        -1.4399 / (eta * eta) + 0.7099 / eta + 0.6681 + 0.0636 * eta
+    }
+}
+
+// Total diffuse reflectance of the dipole for a reduced albedo alphap
+fn rd_integral(alphap: f32, a: f32) -> f32 {
+    let sqrt_term = (3.0 * (1.0 - alphap)).sqrt();
+    alphap / 2.0 * (1.0 + (-4.0 / 3.0 * a * sqrt_term).exp()) * (-sqrt_term).exp()
+}
+
+// Inverts rd_integral to find the reduced albedo that produces the
+// given diffuse reflectance. rd_integral is monotonic in alphap, so
+// bisection is good enough.
+fn alphap_from_rd(rd: f32, a: f32) -> f32 {
+    let (mut lo, mut hi) = (0.0f32, 1.0f32);
+    if rd <= 0.0 {
+        return 0.0;
+    } else if rd >= rd_integral(1.0, a) {
+        return 1.0;
+    }
+
+    for _ in 0..32 {
+        let mid = 0.5 * (lo + hi);
+        if rd_integral(mid, a) < rd {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    0.5 * (lo + hi)
+}
+
+// Computes the scattering coefficients (sigma_a, sigma_prime_s) of a
+// medium with the given diffuse reflectance and mean free path.
+pub fn subsurface_from_diffuse(kd: &Spectrum, mean_free_path: f32, eta: f32)
+                               -> (Spectrum, Spectrum) {
+    let a = (1.0 + fdr(eta)) / (1.0 - fdr(eta));
+    let kd_rgb = kd.to_rgb();
+    let mut sigma_a = [0f32; 3];
+    let mut sigma_prime_s = [0f32; 3];
+    for i in 0..3 {
+        let alphap = alphap_from_rd(kd_rgb[i], a);
+        sigma_prime_s[i] = alphap / mean_free_path;
+        sigma_a[i] = 1.0 / mean_free_path - sigma_prime_s[i];
+    }
+
+    (Spectrum::from_rgb(sigma_a), Spectrum::from_rgb(sigma_prime_s))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BSSRDF {
+    eta: f32,
+    sigma_a: Spectrum,
+    sigma_prime_s: Spectrum
+}
+
+impl BSSRDF {
+    pub fn new(sa: Spectrum, sps: Spectrum, et: f32) -> BSSRDF {
+        BSSRDF {
+            eta: et,
+            sigma_a: sa,
+            sigma_prime_s: sps
+        }
+    }
+
+    pub fn eta(&self) -> f32 { self.eta }
+    pub fn sigma_a(&self) -> Spectrum { self.sigma_a }
+    pub fn sigma_prime_s(&self) -> Spectrum { self.sigma_prime_s }
+}
+
+// The dipole diffusion approximation of Jensen et al. 2001: the multiple
+// scattering within the medium is modeled as a pair of point sources, a
+// real one below the surface and a virtual one above it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffusionReflectance {
+    zpos: [f32; 3],
+    zneg: [f32; 3],
+    sigma_tr: [f32; 3],
+    alphap: [f32; 3]
+}
+
+impl DiffusionReflectance {
+    pub fn new(sigma_a: &Spectrum, sigma_prime_s: &Spectrum, eta: f32)
+               -> DiffusionReflectance {
+        let a = (1.0 + fdr(eta)) / (1.0 - fdr(eta));
+        let sa = sigma_a.to_rgb();
+        let sps = sigma_prime_s.to_rgb();
+
+        let mut dr = DiffusionReflectance {
+            zpos: [0.0; 3],
+            zneg: [0.0; 3],
+            sigma_tr: [0.0; 3],
+            alphap: [0.0; 3]
+        };
+
+        for i in 0..3 {
+            let sigmap_t = sa[i] + sps[i];
+            dr.sigma_tr[i] = (3.0 * sa[i] * sigmap_t).sqrt();
+            dr.alphap[i] = if sigmap_t > 0.0 { sps[i] / sigmap_t } else { 0.0 };
+            dr.zpos[i] = 1.0 / sigmap_t;
+            dr.zneg[i] = -dr.zpos[i] * (1.0 + 4.0 / 3.0 * a);
+        }
+
+        dr
+    }
+
+    pub fn from_bssrdf(bssrdf: &BSSRDF) -> DiffusionReflectance {
+        DiffusionReflectance::new(&bssrdf.sigma_a, &bssrdf.sigma_prime_s, bssrdf.eta)
+    }
+
+    // Diffuse reflectance due to light entering the surface at a point
+    // that is sqrt(d2) away from the point being shaded.
+    pub fn evaluate(&self, d2: f32) -> Spectrum {
+        let mut rd = [0f32; 3];
+        for i in 0..3 {
+            let dpos = (d2 + self.zpos[i] * self.zpos[i]).sqrt();
+            let dneg = (d2 + self.zneg[i] * self.zneg[i]).sqrt();
+            let st = self.sigma_tr[i];
+
+            let rpos = self.zpos[i] * (dpos * st + 1.0) * (-st * dpos).exp() /
+                (dpos * dpos * dpos);
+            let rneg = self.zneg[i] * (dneg * st + 1.0) * (-st * dneg).exp() /
+                (dneg * dneg * dneg);
+
+            let r = self.alphap[i] / (4.0 * ::std::f32::consts::PI) * (rpos - rneg);
+            rd[i] = if r.is_finite() { r.max(0.0) } else { 0.0 };
+        }
+
+        Spectrum::from_rgb(rd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectrum::Spectrum;
+
+    #[test]
+    fn it_computes_diffuse_fresnel_reflectance() {
+        // Diffuse reflectance of light hitting the boundary from inside,
+        // from integrating the Fresnel reflectance over the hemisphere
+        let exact = [(1.1, 0.19434), (1.3, 0.44446), (1.5, 0.59635), (2.0, 0.79015)];
+        for &(eta, r) in exact.iter() {
+            assert!((fdr(eta) - r).abs() < 1e-3, "fdr({}) = {}, expected {}", eta, fdr(eta), r);
+        }
+
+        // The fit doesn't go through zero for index matched boundaries
+        // but is off by 0.0017 there
+        assert!((fdr(1.0) - 0.0017).abs() < 1e-5);
+    }
+
+    #[test]
+    fn it_can_invert_diffuse_reflectance() {
+        let kd = Spectrum::from_rgb([0.2, 0.5, 0.8]);
+        let eta = 1.3;
+        let (sa, sps) = subsurface_from_diffuse(&kd, 0.5, eta);
+
+        let a = (1.0 + fdr(eta)) / (1.0 - fdr(eta));
+        let (sa, sps) = (sa.to_rgb(), sps.to_rgb());
+        for i in 0..3 {
+            // Reduced extinction is the inverse of the mean free path
+            assert!(((sa[i] + sps[i]) - 2.0).abs() < 1e-3);
+
+            let alphap = sps[i] / (sa[i] + sps[i]);
+            assert!((rd_integral(alphap, a) - kd.to_rgb()[i]).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn diffusion_falls_off_with_distance() {
+        let dr = DiffusionReflectance::new(&Spectrum::from_rgb([0.032, 0.17, 0.48]),
+                                           &Spectrum::from_rgb([0.74, 0.88, 1.01]),
+                                           1.3);
+        let near = dr.evaluate(0.01).to_rgb();
+        let far = dr.evaluate(4.0).to_rgb();
+        for i in 0..3 {
+            assert!(near[i] > far[i]);
+            assert!(far[i] >= 0.0);
+        }
+
+        // Red light travels further in skin than blue light does
+        assert!(far[0] > far[2]);
+    }
+}
@@ -0,0 +1,442 @@
+use bsdf;
+use bsdf::BxDF;
+use bsdf::microfacet::Microfacet;
+use bsdf::utils::*;
+use geometry::normal::Normalize;
+use geometry::vector::Dot;
+use geometry::vector::Vector;
+use geometry::vector::spherical_direction;
+use spectrum::Spectrum;
+use utils::Lerp;
+
+// The lobes of the Disney "principled" BRDF as described by Burley in
+// "Physically-Based Shading at Disney" (2012). The diffuse lobes are all
+// sampled with the default cosine-weighted hemisphere and only differ in
+// how they shape the response at grazing angles.
+
+// The half vector and the cosine of the angle between it and wi,
+// which is the quantity all of Burley's grazing terms are based on.
+fn cos_theta_d(wo: &Vector, wi: &Vector) -> Option<f32> {
+    let wh = wi + wo;
+    if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+        None
+    } else {
+        Some(wi.dot(&wh.normalize()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisneyDiffuse {
+    r: Spectrum
+}
+
+impl DisneyDiffuse {
+    pub fn new(r: Spectrum) -> DisneyDiffuse { DisneyDiffuse { r: r } }
+}
+
+impl BxDF for DisneyDiffuse {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_DIFFUSE).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        let fo = schlick_weight(abs_cos_theta(wo));
+        let fi = schlick_weight(abs_cos_theta(wi));
+
+        // Lambertian with the diffuse Fresnel retro-reflection removed,
+        // that part is handled by DisneyRetro
+        self.r / ::std::f32::consts::PI * (1.0 - fo / 2.0) * (1.0 - fi / 2.0)
+    }
+}
+
+// Hanrahan-Krueger inspired approximation of subsurface scattering
+// for thin and far away objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisneyFakeSS {
+    r: Spectrum,
+    roughness: f32
+}
+
+impl DisneyFakeSS {
+    pub fn new(r: Spectrum, roughness: f32) -> DisneyFakeSS {
+        DisneyFakeSS { r: r, roughness: roughness }
+    }
+}
+
+impl BxDF for DisneyFakeSS {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_DIFFUSE).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        let ctd = match cos_theta_d(wo, wi) {
+            Some(c) => c,
+            None => return Spectrum::from(0.0)
+        };
+
+        // Fss90 flattens the retro-reflection based on roughness
+        let fss90 = ctd * ctd * self.roughness;
+        let fo = schlick_weight(abs_cos_theta(wo));
+        let fi = schlick_weight(abs_cos_theta(wi));
+        let fss = 1.0f32.lerp(&fss90, fo) * 1.0f32.lerp(&fss90, fi);
+        let ss = 1.25 * (fss * (1.0 / (abs_cos_theta(wo) + abs_cos_theta(wi)) - 0.5) + 0.5);
+
+        self.r / ::std::f32::consts::PI * ss
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisneyRetro {
+    r: Spectrum,
+    roughness: f32
+}
+
+impl DisneyRetro {
+    pub fn new(r: Spectrum, roughness: f32) -> DisneyRetro {
+        DisneyRetro { r: r, roughness: roughness }
+    }
+}
+
+impl BxDF for DisneyRetro {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_DIFFUSE).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        let ctd = match cos_theta_d(wo, wi) {
+            Some(c) => c,
+            None => return Spectrum::from(0.0)
+        };
+
+        let fo = schlick_weight(abs_cos_theta(wo));
+        let fi = schlick_weight(abs_cos_theta(wi));
+        let rr = 2.0 * self.roughness * ctd * ctd;
+
+        self.r / ::std::f32::consts::PI * rr * (fo + fi + fo * fi * (rr - 1.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisneySheen {
+    r: Spectrum
+}
+
+impl DisneySheen {
+    pub fn new(r: Spectrum) -> DisneySheen { DisneySheen { r: r } }
+}
+
+impl BxDF for DisneySheen {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_DIFFUSE).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        match cos_theta_d(wo, wi) {
+            Some(ctd) => self.r * schlick_weight(ctd),
+            None => Spectrum::from(0.0)
+        }
+    }
+}
+
+// Generalized Trowbridge-Reitz with gamma = 1, which has the long
+// tails that the clearcoat layer is after.
+fn gtr1(cos_theta: f32, alpha: f32) -> f32 {
+    if alpha >= 1.0 {
+        return 1.0 / ::std::f32::consts::PI;
+    }
+
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) /
+        (::std::f32::consts::PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta * cos_theta))
+}
+
+fn smith_g_ggx(cos_theta: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let cos_theta2 = cos_theta * cos_theta;
+    1.0 / (cos_theta + (alpha2 + cos_theta2 - alpha2 * cos_theta2).sqrt())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisneyClearcoat {
+    weight: f32,
+    gloss: f32
+}
+
+impl DisneyClearcoat {
+    pub fn new(weight: f32, gloss: f32) -> DisneyClearcoat {
+        DisneyClearcoat { weight: weight, gloss: gloss }
+    }
+}
+
+impl BxDF for DisneyClearcoat {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_GLOSSY).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        let wh = wi + wo;
+        if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+            return Spectrum::from(0.0);
+        }
+
+        let wh = wh.normalize();
+
+        // The clearcoat is a fixed IOR of 1.5 (F0 = 0.04) dielectric
+        // with an unusually rough masking term
+        let dr = gtr1(abs_cos_theta(&wh), self.gloss);
+        let fr = 0.04f32.lerp(&1.0, schlick_weight(wo.dot(&wh)));
+        let gr = smith_g_ggx(abs_cos_theta(wo), 0.25) * smith_g_ggx(abs_cos_theta(wi), 0.25);
+
+        Spectrum::from(self.weight * gr * fr * dr / 4.0)
+    }
+
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        if wo.z == 0.0 {
+            return (Vector::new(), 0.0, Spectrum::from(0.0));
+        }
+
+        let alpha2 = self.gloss * self.gloss;
+        let cos_theta = ((1.0 - alpha2.powf(1.0 - u1)) / (1.0 - alpha2)).max(0.0).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * ::std::f32::consts::PI * u2;
+
+        let mut wh = spherical_direction(sin_theta, cos_theta, phi);
+        if !same_hemisphere(wo, &wh) {
+            wh = -wh;
+        }
+
+        let wi = reflect(wo, &wh);
+        if !same_hemisphere(wo, &wi) {
+            return (wi, 0.0, Spectrum::from(0.0));
+        }
+
+        let pdf = self.pdf(wo, &wi);
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        let wh = wi + wo;
+        if wh.x == 0.0 && wh.y == 0.0 && wh.z == 0.0 {
+            return 0.0;
+        }
+
+        // The clearcoat lobe samples the GTR1 distribution exactly
+        let wh = wh.normalize();
+        let dr = gtr1(abs_cos_theta(&wh), self.gloss);
+        dr * abs_cos_theta(&wh) / (4.0 * wo.dot(&wh))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisneyLobe {
+    Diffuse(DisneyDiffuse),
+    FakeSS(DisneyFakeSS),
+    Retro(DisneyRetro),
+    Sheen(DisneySheen),
+    Clearcoat(DisneyClearcoat),
+    Specular(Microfacet)
+}
+
+impl DisneyLobe {
+    fn bxdf(&self) -> &BxDF {
+        match self {
+            &DisneyLobe::Diffuse(ref b) => b,
+            &DisneyLobe::FakeSS(ref b) => b,
+            &DisneyLobe::Retro(ref b) => b,
+            &DisneyLobe::Sheen(ref b) => b,
+            &DisneyLobe::Clearcoat(ref b) => b,
+            &DisneyLobe::Specular(ref b) => b
+        }
+    }
+}
+
+// All of the lobes of the principled BRDF gathered into a single BxDF so
+// that we can pick which lobe to sample based on how much each of them
+// is expected to contribute rather than uniformly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisneyPrincipled {
+    lobes: Vec<DisneyLobe>,
+    weights: Vec<f32>
+}
+
+impl DisneyPrincipled {
+    pub fn new() -> DisneyPrincipled {
+        DisneyPrincipled {
+            lobes: Vec::with_capacity(6),
+            weights: Vec::with_capacity(6)
+        }
+    }
+
+    pub fn add_lobe(&mut self, lobe: DisneyLobe, weight: f32) {
+        if weight > 0.0 {
+            self.lobes.push(lobe);
+            self.weights.push(weight);
+        }
+    }
+
+    pub fn num_lobes(&self) -> usize { self.lobes.len() }
+
+    fn total_weight(&self) -> f32 {
+        self.weights.iter().fold(0.0, |acc, w| acc + w)
+    }
+
+}
+
+impl BxDF for DisneyPrincipled {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_DIFFUSE | bsdf::BSDF_GLOSSY).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        self.lobes.iter().fold(Spectrum::from(0.0), |acc, l| acc + l.bxdf().f(wo, wi))
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        let total = self.total_weight();
+        if total == 0.0 {
+            return 0.0;
+        }
+
+        self.lobes.iter().zip(self.weights.iter()).fold(0.0, |acc, (l, w)| {
+            acc + w * l.bxdf().pdf(wo, wi)
+        }) / total
+    }
+
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        let total = self.total_weight();
+        if total == 0.0 {
+            return (Vector::new(), 0.0, Spectrum::from(0.0));
+        }
+
+        // Choose a lobe proportionally to its weight and remap u1 so
+        // that it can be reused to sample the lobe itself
+        let target = u1 * total;
+        let mut which = self.lobes.len() - 1;
+        let mut cdf = 0.0;
+        for (i, w) in self.weights.iter().enumerate() {
+            if target < cdf + w {
+                which = i;
+                break;
+            }
+            cdf += *w;
+        }
+
+        let w = self.weights[which];
+        let u1_remapped = ((target - cdf) / w).min(1.0 - ::std::f32::EPSILON).max(0.0);
+
+        let (wi, pdf, _) = self.lobes[which].bxdf().sample_f(wo, u1_remapped, u2);
+        if pdf == 0.0 {
+            return (wi, 0.0, Spectrum::from(0.0));
+        }
+
+        // The direction could also have been generated by any of
+        // the other lobes, so account for them too
+        let pdf = self.pdf(wo, &wi);
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::BxDF;
+    use bsdf::fresnel::Fresnel;
+    use bsdf::microfacet::Microfacet;
+    use bsdf::microfacet::MicrofacetDistribution;
+    use geometry::normal::Normalize;
+    use geometry::vector::Dot;
+    use geometry::vector::Vector;
+    use geometry::vector::spherical_direction;
+    use rng::RNG;
+    use spectrum::Spectrum;
+
+    fn principled() -> DisneyPrincipled {
+        let c = Spectrum::from(0.5);
+        let mut p = DisneyPrincipled::new();
+        p.add_lobe(DisneyLobe::Diffuse(DisneyDiffuse::new(c)), 0.5);
+        p.add_lobe(DisneyLobe::Retro(DisneyRetro::new(c, 0.5)), 0.1);
+        p.add_lobe(DisneyLobe::Sheen(DisneySheen::new(c * 0.1)), 0.0);
+        p.add_lobe(DisneyLobe::Clearcoat(DisneyClearcoat::new(1.0, 0.1)), 0.25);
+        p.add_lobe(DisneyLobe::Specular(Microfacet::new(
+            Spectrum::from(1.0), Fresnel::disney(&Spectrum::from(0.04), 0.0, 1.5),
+            MicrofacetDistribution::trowbridge_reitz(0.25, 0.25))), 0.5);
+        p
+    }
+
+    #[test]
+    fn it_skips_lobes_with_no_weight() {
+        assert_eq!(principled().num_lobes(), 4);
+    }
+
+    #[test]
+    fn it_samples_consistent_pdfs() {
+        let p = principled();
+        let wo = Vector::new_with(0.3, -0.2, 0.8).normalize();
+        let mut rng = RNG::new(7);
+        for _ in 0..256 {
+            let (wi, pdf, f) = p.sample_f(&wo, rng.random_float(), rng.random_float());
+            if pdf == 0.0 {
+                continue;
+            }
+
+            assert!(wi.z > 0.0);
+            assert!((p.pdf(&wo, &wi) - pdf).abs() <= 1e-4 * pdf.max(1.0));
+            assert!(!f.has_nans());
+            assert!((f - p.f(&wo, &wi)).is_black());
+        }
+    }
+
+    #[test]
+    fn fake_subsurface_matches_burley() {
+        // Burley 2012, written out in full
+        let expected = |roughness: f32, cos_o: f32, cos_i: f32, cos_d: f32| {
+            let fss90 = cos_d * cos_d * roughness;
+            let fo = (1.0 - cos_o).powi(5);
+            let fi = (1.0 - cos_i).powi(5);
+            let fss = (1.0 + (fss90 - 1.0) * fo) * (1.0 + (fss90 - 1.0) * fi);
+            1.25 * (fss * (1.0 / (cos_o + cos_i) - 0.5) + 0.5) / ::std::f32::consts::PI
+        };
+
+        for &(roughness, theta_o, theta_i) in [(0.1f32, 0.2f32, 1.3f32), (0.5, 0.8, 0.4),
+                                                (0.9, 1.4, 1.1), (1.0, 0.0, 0.7)].iter() {
+            let wo = spherical_direction(theta_o.sin(), theta_o.cos(), 0.0);
+            let wi = spherical_direction(theta_i.sin(), theta_i.cos(), 2.0);
+            let cos_d = wi.dot(&(&wi + &wo).normalize());
+
+            let f = DisneyFakeSS::new(Spectrum::from(1.0), roughness).f(&wo, &wi);
+            let e = expected(roughness, theta_o.cos(), theta_i.cos(), cos_d);
+            assert!((f[0] - e).abs() < 1e-5 * e.max(1.0),
+                    "{} for roughness {} at ({}, {}), expected {}",
+                    f[0], roughness, theta_o, theta_i, e);
+        }
+    }
+
+    #[test]
+    fn diffuse_lobes_are_reciprocal() {
+        let wo = Vector::new_with(0.6, 0.0, 0.8);
+        let wi = Vector::new_with(-0.28, 0.96, 0.0).normalize() * 0.6
+            + Vector::new_with(0.0, 0.0, 0.8);
+        let r = Spectrum::from(0.7);
+
+        let lobes: Vec<Box<BxDF>> = vec![
+            Box::new(DisneyDiffuse::new(r)),
+            Box::new(DisneyFakeSS::new(r, 0.3)),
+            Box::new(DisneyRetro::new(r, 0.3)),
+            Box::new(DisneySheen::new(r)),
+            Box::new(DisneyClearcoat::new(1.0, 0.2))];
+
+        for l in lobes.iter() {
+            let a = l.f(&wo, &wi);
+            let b = l.f(&wi, &wo);
+            for i in 0..3 {
+                assert!((a[i] - b[i]).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use geometry::vector::Vector;
 use spectrum::Spectrum;
 use utils::Clamp;
+use utils::Lerp;
 
 fn fr_diel(cosi: f32, cost: f32, etai: &Spectrum,
            etat: &Spectrum) -> Spectrum {
@@ -30,6 +31,14 @@ fn fr_cond(cosi: f32, eta: &Spectrum,
     (rparl2 + rperp2) / 2.0
 }
 
+// Approximate the index of refraction of a non-absorbing conductor that
+// has the given reflectance at normal incidence.
+pub fn approx_eta(fr: &Spectrum) -> Spectrum {
+    let reflectance = fr.clamp(0.0, 0.999);
+    (Spectrum::from(1.0) + reflectance.sqrt()) /
+        (Spectrum::from(1.0) - reflectance.sqrt())
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Fresnel {
     Conductor {
@@ -40,6 +49,13 @@ pub enum Fresnel {
         eta_i: f32,
         eta_t: f32
     },
+    // Blend between a dielectric and a Schlick approximation of
+    // a tinted conductor, as used by the principled material
+    Disney {
+        r0: Spectrum,
+        metallic: f32,
+        eta: f32
+    },
     NoOp
 }
 
@@ -58,6 +74,14 @@ impl Fresnel {
         }
     }
 
+    pub fn disney(r0: &Spectrum, metallic: f32, eta: f32) -> Fresnel {
+        Fresnel::Disney {
+            r0: r0.clone(),
+            metallic: metallic,
+            eta: eta
+        }
+    }
+
     pub fn noop() -> Fresnel { Fresnel::NoOp }
 
     pub fn evaluate(&self, cosi: f32) -> Spectrum {
@@ -87,7 +111,29 @@ impl Fresnel {
                             &Spectrum::from(et))
                 }
             }
+            &Fresnel::Disney { ref r0, metallic, eta } => {
+                let diel = Fresnel::dielectric(1.0, eta).evaluate(cosi);
+                let w = (1.0 - cosi.abs()).clamp(0.0, 1.0).powi(5);
+                let schlick = r0.lerp(&Spectrum::from(1.0), w);
+                diel.lerp(&schlick, metallic)
+            }
             &Fresnel::NoOp => Spectrum::from(1.0)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectrum::Spectrum;
+
+    #[test]
+    fn approximate_conductors_match_reflectance() {
+        let r = Spectrum::from_rgb([0.2, 0.5, 0.9]);
+        let fr = Fresnel::conductor(&approx_eta(&r), &Spectrum::from(0.0));
+        let rr = fr.evaluate(1.0);
+        for i in 0..3 {
+            assert!((rr[i] - r[i]).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use std::f32::consts::PI;
+
+use bsdf;
+use bsdf::BxDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::utils::*;
+use geometry::vector::Vector;
+use spectrum::Spectrum;
+use utils::Clamp;
+use utils::Degrees;
+
+// Number of scattering events modelled explicitly: R, TT and TRT. All
+// of the remaining ones are lumped together into one more term.
+const P_MAX: usize = 3;
+
+const SQRT_PI_OVER_8: f32 = 0.626657069;
+
+// Absorption coefficients of the two pigments found in hair, per unit
+// of concentration, in RGB.
+const EUMELANIN_SIGMA_A: [f32; 3] = [0.419, 0.697, 1.37];
+const PHEOMELANIN_SIGMA_A: [f32; 3] = [0.187, 0.4, 1.05];
+
+fn sqr(x: f32) -> f32 { x * x }
+
+fn safe_sqrt(x: f32) -> f32 { x.max(0.0).sqrt() }
+
+fn safe_asin(x: f32) -> f32 { x.clamp(-1.0, 1.0).asin() }
+
+// Modified Bessel function of the first kind
+fn i0(x: f32) -> f32 {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f32;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+// Longitudinal scattering function
+fn mp(cos_theta_i: f32, cos_theta_o: f32, sin_theta_i: f32,
+      sin_theta_o: f32, v: f32) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // Evaluate in log space to avoid overflow for narrow lobes
+        (log_i0(a) - b - 1.0 / v + 0.6931 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        ((-b).exp() * i0(a)) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// Attenuation for each of the scattering events, where t is the
+// transmittance of a single pass through the fiber.
+fn ap(cos_theta_o: f32, eta: f32, h: f32, t: Spectrum) -> [Spectrum; P_MAX + 1] {
+    let cos_gamma_o = safe_sqrt(1.0 - h * h);
+    let cos_theta = cos_theta_o * cos_gamma_o;
+    let f = Fresnel::dielectric(1.0, eta).evaluate(cos_theta)[0];
+
+    let mut ap = [Spectrum::from(0.0); P_MAX + 1];
+    ap[0] = Spectrum::from(f);
+    ap[1] = sqr(1.0 - f) * t;
+    for p in 2..P_MAX {
+        ap[p] = ap[p - 1] * t * f;
+    }
+
+    // Sum of the geometric series for all of the remaining terms
+    ap[P_MAX] = ap[P_MAX - 1] * t * f / (Spectrum::from(1.0) - t * f);
+    ap
+}
+
+// Net change in azimuth after scattering event p
+fn phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    let p = p as f32;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = x.abs();
+    (-x / s).exp() / (s * sqr(1.0 + (-x / s).exp()))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+// Azimuthal scattering function
+fn np(phi_diff: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi_diff - phi(p, gamma_o, gamma_t);
+
+    // Remap dphi to [-pi, pi]
+    while dphi > PI { dphi -= 2.0 * PI; }
+    while dphi < -PI { dphi += 2.0 * PI; }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+// Pulls the even and odd bits of x apart to turn one uniform sample
+// into two.
+fn compact_1_by_1(x: u32) -> u32 {
+    let x = x & 0x55555555;
+    let x = (x ^ (x >> 1)) & 0x33333333;
+    let x = (x ^ (x >> 2)) & 0x0f0f0f0f;
+    let x = (x ^ (x >> 4)) & 0x00ff00ff;
+    (x ^ (x >> 8)) & 0x0000ffff
+}
+
+fn demux_float(f: f32) -> (f32, f32) {
+    let v = ((f as f64) * ((1u64 << 32) as f64)) as u64;
+    let bits = [compact_1_by_1(v as u32), compact_1_by_1((v >> 1) as u32)];
+    (bits[0] as f32 / ((1 << 16) as f32), bits[1] as f32 / ((1 << 16) as f32))
+}
+
+// Absorption coefficient for a fiber with the given concentrations of
+// eumelanin, which makes hair brown or black, and pheomelanin, which
+// makes it red or blonde.
+pub fn sigma_a_from_concentration(ce: f32, cp: f32) -> Spectrum {
+    let mut sigma_a = [0.0; 3];
+    for i in 0..3 {
+        sigma_a[i] = ce * EUMELANIN_SIGMA_A[i] + cp * PHEOMELANIN_SIGMA_A[i];
+    }
+    Spectrum::from_rgb(sigma_a)
+}
+
+// Absorption coefficient that gives roughly the color c after many
+// scattering events, for the azimuthal roughness beta_n.
+pub fn sigma_a_from_reflectance(c: &Spectrum, beta_n: f32) -> Spectrum {
+    let rgb = c.to_rgb();
+    let d = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+        + 5.574 * beta_n.powi(4) + 0.245 * beta_n.powi(5);
+    let mut sigma_a = [0.0; 3];
+    for i in 0..3 {
+        sigma_a[i] = sqr(rgb[i].ln() / d);
+    }
+    Spectrum::from_rgb(sigma_a)
+}
+
+// Scattering from a hair fiber after d'Eon et al. and Chiang et al. Light
+// either reflects off of the cuticle (R), passes through the fiber (TT),
+// or reflects once inside of it (TRT), and is absorbed along the way.
+//
+// The local frame has the y axis along the fiber and the z axis along
+// the surface normal, so that the longitudinal angle theta is measured
+// from the xz plane and phi is the angle around the fiber.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HairBSDF {
+    // Offset across the width of the fiber in [-1, 1]
+    h: f32,
+    gamma_o: f32,
+    eta: f32,
+    sigma_a: Spectrum,
+    // Longitudinal variance of each lobe
+    v: [f32; P_MAX + 1],
+    // Logistic scale factor for azimuthal roughness
+    s: f32,
+    // Shifts of the lobes due to the tilted scales on the cuticle
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3]
+}
+
+impl HairBSDF {
+    pub fn new(h: f32, eta: f32, sigma_a: Spectrum, beta_m: f32,
+               beta_n: f32, alpha: f32) -> HairBSDF {
+        debug_assert!(h >= -1.0 && h <= 1.0);
+        debug_assert!(beta_m >= 0.0 && beta_m <= 1.0);
+        debug_assert!(beta_n >= 0.0 && beta_n <= 1.0);
+
+        // Map the roughness parameters to lobe widths
+        let mut v = [0.0; P_MAX + 1];
+        v[0] = sqr(0.726 * beta_m + 0.812 * sqr(beta_m) + 3.7 * beta_m.powi(20));
+        v[1] = 0.25 * v[0];
+        v[2] = 4.0 * v[0];
+        for p in 3..(P_MAX + 1) {
+            v[p] = v[2];
+        }
+
+        let s = SQRT_PI_OVER_8 *
+            (0.265 * beta_n + 1.194 * sqr(beta_n) + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [0.0; 3];
+        let mut cos_2k_alpha = [0.0; 3];
+        sin_2k_alpha[0] = alpha.as_radians().sin();
+        cos_2k_alpha[0] = safe_sqrt(1.0 - sqr(sin_2k_alpha[0]));
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = sqr(cos_2k_alpha[i - 1]) - sqr(sin_2k_alpha[i - 1]);
+        }
+
+        HairBSDF {
+            h: h,
+            gamma_o: safe_asin(h),
+            eta: eta,
+            sigma_a: sigma_a,
+            v: v,
+            s: s,
+            sin_2k_alpha: sin_2k_alpha,
+            cos_2k_alpha: cos_2k_alpha
+        }
+    }
+
+    // Rotates theta_o to account for the scales on the cuticle
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin_op, cos_op) = match p {
+            0 => (sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                  cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1]),
+            1 => (sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                  cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0]),
+            2 => (sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                  cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2]),
+            _ => (sin_theta_o, cos_theta_o)
+        };
+        (sin_op, cos_op.abs())
+    }
+
+    // Refracted angle gamma_t inside the fiber and the transmittance of
+    // a single pass through it.
+    fn transmission(&self, sin_theta_o: f32, cos_theta_o: f32) -> (f32, Spectrum) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sqr(sin_theta_t));
+
+        // Modified index of refraction for the projected direction
+        let etap = (self.eta * self.eta - sqr(sin_theta_o)).sqrt() / cos_theta_o;
+        let sin_gamma_t = self.h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sqr(sin_gamma_t));
+        let t = (self.sigma_a * -(2.0 * cos_gamma_t / cos_theta_t)).exp();
+        (safe_asin(sin_gamma_t), t)
+    }
+
+    // Probability of sampling each of the scattering events
+    fn ap_pdf(&self, sin_theta_o: f32, cos_theta_o: f32) -> [f32; P_MAX + 1] {
+        let (_, t) = self.transmission(sin_theta_o, cos_theta_o);
+        let ap = ap(cos_theta_o, self.eta, self.h, t);
+        let sum_y = ap.iter().fold(0.0, |s, a| s + a.y());
+
+        let mut pdf = [0.0; P_MAX + 1];
+        for p in 0..(P_MAX + 1) {
+            pdf[p] = ap[p].y() / sum_y;
+        }
+        pdf
+    }
+
+    fn pdf_angles(&self, sin_theta_o: f32, cos_theta_o: f32, sin_theta_i: f32,
+                  cos_theta_i: f32, dphi: f32) -> f32 {
+        let (gamma_t, _) = self.transmission(sin_theta_o, cos_theta_o);
+        let ap_pdf = self.ap_pdf(sin_theta_o, cos_theta_o);
+        let lobes = (0..P_MAX).fold(0.0, |pdf, p| {
+            let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf + mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p]) * ap_pdf[p]
+                * np(dphi, p, self.s, self.gamma_o, gamma_t)
+        });
+
+        lobes + mp(cos_theta_i, cos_theta_o, sin_theta_i, sin_theta_o, self.v[P_MAX])
+            * ap_pdf[P_MAX] / (2.0 * PI)
+    }
+}
+
+// Longitudinal angle and azimuth of a direction in the hair frame
+fn hair_angles(w: &Vector) -> (f32, f32, f32) {
+    let sin_theta = w.y;
+    (sin_theta, safe_sqrt(1.0 - sqr(sin_theta)), w.z.atan2(w.x))
+}
+
+impl BxDF for HairBSDF {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_TRANSMISSION | bsdf::BSDF_GLOSSY).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        let (sin_theta_o, cos_theta_o, phi_o) = hair_angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = hair_angles(wi);
+
+        let (gamma_t, t) = self.transmission(sin_theta_o, cos_theta_o);
+        let ap = ap(cos_theta_o, self.eta, self.h, t);
+
+        let dphi = phi_i - phi_o;
+        let mut fsum = Spectrum::from(0.0);
+        for p in 0..P_MAX {
+            let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            fsum = fsum + mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p]) * ap[p]
+                * np(dphi, p, self.s, self.gamma_o, gamma_t);
+        }
+
+        // The remaining terms are spread uniformly around the fiber
+        fsum = fsum + mp(cos_theta_i, cos_theta_o, sin_theta_i, sin_theta_o, self.v[P_MAX])
+            * ap[P_MAX] / (2.0 * PI);
+
+        // Cancel out the cosine that the integrator multiplies in
+        if abs_cos_theta(wi) > 0.0 {
+            fsum / abs_cos_theta(wi)
+        } else {
+            fsum
+        }
+    }
+
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        let (sin_theta_o, cos_theta_o, phi_o) = hair_angles(wo);
+
+        // Need four samples: pick a lobe, then sample theta and phi
+        let (mut u_lobe, u_phi) = demux_float(u1);
+        let (u_theta, u_theta_phi) = demux_float(u2);
+
+        let ap_pdf = self.ap_pdf(sin_theta_o, cos_theta_o);
+        let mut p = 0;
+        while p < P_MAX && u_lobe >= ap_pdf[p] {
+            u_lobe -= ap_pdf[p];
+            p += 1;
+        }
+
+        // Sample the longitudinal lobe around the tilted direction
+        let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let u_theta = u_theta.max(1e-5);
+        let cos_theta = 1.0 + self.v[p] *
+            (u_theta + (1.0 - u_theta) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - sqr(cos_theta));
+        let cos_phi = (2.0 * PI * u_theta_phi).cos();
+        let sin_theta_i = -cos_theta * sin_op + sin_theta * cos_phi * cos_op;
+        let cos_theta_i = safe_sqrt(1.0 - sqr(sin_theta_i));
+
+        // Sample the azimuthal lobe
+        let (gamma_t, _) = self.transmission(sin_theta_o, cos_theta_o);
+        let dphi = if p < P_MAX {
+            phi(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(u_phi, self.s, -PI, PI)
+        } else {
+            2.0 * PI * u_phi
+        };
+
+        let phi_i = phi_o + dphi;
+        let wi = Vector::new_with(cos_theta_i * phi_i.cos(), sin_theta_i,
+                                  cos_theta_i * phi_i.sin());
+        let pdf = self.pdf_angles(sin_theta_o, cos_theta_o, sin_theta_i, cos_theta_i, dphi);
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        let (sin_theta_o, cos_theta_o, phi_o) = hair_angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = hair_angles(wi);
+        self.pdf_angles(sin_theta_o, cos_theta_o, sin_theta_i, cos_theta_i, phi_i - phi_o)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::BxDF;
+    use bsdf::validation;
+    use geometry::vector::Vector;
+    use montecarlo::uniform_sample_sphere;
+    use rng::RNG;
+    use spectrum::Spectrum;
+
+    fn random_hair(rng: &mut RNG, sigma_a: Spectrum) -> HairBSDF {
+        let h = -1.0 + 2.0 * rng.random_float();
+        let beta_m = 0.3 + 0.7 * rng.random_float();
+        let beta_n = 0.3 + 0.7 * rng.random_float();
+        HairBSDF::new(h, 1.55, sigma_a, beta_m, beta_n, 2.0)
+    }
+
+    #[test]
+    fn it_demuxes_samples() {
+        let (a, b) = demux_float(0.0);
+        assert_eq!((a, b), (0.0, 0.0));
+
+        // The even bits go to the first sample and the odd to the second
+        let (a, b) = demux_float(0.75);
+        assert!((a - 0.5).abs() < 1e-6);
+        assert!((b - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_computes_absorption_from_melanin() {
+        // More eumelanin means darker hair
+        let light = sigma_a_from_concentration(0.3, 0.0).to_rgb();
+        let dark = sigma_a_from_concentration(8.0, 0.0).to_rgb();
+        for i in 0..3 {
+            assert!(light[i] < dark[i]);
+        }
+
+        // Red light is absorbed the least
+        assert!(dark[0] < dark[1] && dark[1] < dark[2]);
+        assert!(sigma_a_from_concentration(0.0, 0.0).is_black());
+    }
+
+    #[test]
+    fn it_conserves_energy_without_absorption() {
+        // White furnace test: with no absorption all of the light
+        // reaching the fiber leaves it again
+        let mut rng = RNG::new(0);
+        for _ in 0..10 {
+            let hair = random_hair(&mut rng, Spectrum::from(0.0));
+            let wo = uniform_sample_sphere(rng.random_float(), rng.random_float());
+
+            let num_samples = 100000;
+            let sum = (0..num_samples).fold(0.0, |sum, _| {
+                let wi = uniform_sample_sphere(rng.random_float(), rng.random_float());
+                sum + hair.f(&wo, &wi).y() * abs_cos_theta(&wi)
+            });
+
+            let avg = sum * 4.0 * PI / (num_samples as f32);
+            assert!(avg > 0.95 && avg < 1.05, "Albedo was {}", avg);
+        }
+    }
+
+    #[test]
+    fn it_samples_proportionally_to_f() {
+        // Without absorption the sampling is exact so every sample has
+        // unit weight
+        let mut rng = RNG::new(1);
+        for _ in 0..10 {
+            let hair = random_hair(&mut rng, Spectrum::from(0.0));
+            let wo = uniform_sample_sphere(rng.random_float(), rng.random_float());
+            for _ in 0..100 {
+                let (wi, pdf, f) = hair.sample_f(&wo, rng.random_float(), rng.random_float());
+                if pdf > 0.0 {
+                    let weight = f.y() * abs_cos_theta(&wi) / pdf;
+                    assert!((weight - 1.0).abs() < 0.01, "Weight was {}", weight);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_samples_directions_with_its_pdf() {
+        let mut rng = RNG::new(2);
+        let sigma_a = sigma_a_from_concentration(1.3, 0.0);
+        for _ in 0..4 {
+            let hair = random_hair(&mut rng, sigma_a);
+            let wo = uniform_sample_sphere(rng.random_float(), rng.random_float());
+            let p = validation::chi_square_test(&hair, &wo, 10, 20, 20000, &mut rng);
+            assert!(p > 0.01, "Chi-square p-value was {}", p);
+        }
+    }
+
+    #[test]
+    fn it_absorbs_more_with_more_melanin() {
+        let mut rng = RNG::new(3);
+        let wo = Vector::new_with(0.6, 0.0, 0.8);
+        let albedo = |sigma_a: Spectrum, rng: &mut RNG| {
+            let hair = HairBSDF::new(0.3, 1.55, sigma_a, 0.3, 0.3, 2.0);
+            validation::albedo(&hair, &wo, 4096, rng).y()
+        };
+
+        let blonde = albedo(sigma_a_from_concentration(0.3, 0.0), &mut rng);
+        let brown = albedo(sigma_a_from_concentration(1.3, 0.0), &mut rng);
+        let black = albedo(sigma_a_from_concentration(8.0, 0.0), &mut rng);
+        assert!(blonde > brown && brown > black);
+
+        // Even black hair reflects off of the cuticle
+        assert!(black > 0.0);
+    }
+
+    #[test]
+    fn it_matches_the_requested_color() {
+        let c = Spectrum::from_rgb([0.5, 0.3, 0.1]);
+        let sigma_a = sigma_a_from_reflectance(&c, 0.3).to_rgb();
+        assert!(sigma_a[0] < sigma_a[1] && sigma_a[1] < sigma_a[2]);
+        assert!(sigma_a_from_reflectance(&Spectrum::from(1.0), 0.3).is_black());
+    }
+}
@@ -22,10 +22,6 @@ impl BxDF for Lambertian {
         self.r * invpi
     }
 
-    fn sample_f(&self, _: &Vector, _: f32, _: f32) -> (Vector, f32, Spectrum) {
-        unimplemented!()
-    }
-
     fn rho_hd(&self, _: &Vector, _: &[f32]) -> Spectrum { self.r.clone() }
 
     fn rho_hh(&self, _: &[f32], _: &[f32]) -> Spectrum { self.r.clone() }
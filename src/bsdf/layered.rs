@@ -0,0 +1,308 @@
+use bsdf;
+use bsdf::BxDF;
+use bsdf::microfacet::RoughDielectric;
+use bsdf::utils::*;
+use geometry::vector::Vector;
+use montecarlo::cosine_sample_hemisphere;
+use montecarlo::power_heuristic;
+use rng::RNG;
+use spectrum::Spectrum;
+
+// A dielectric coat on top of another BxDF, such as varnish over wood or
+// a clear coat over car paint. Light may bounce between the coat and the
+// base any number of times before leaving, so instead of a closed form the
+// BxDF is evaluated stochastically by following random walks between the
+// two layers. The walks are seeded from the directions so that evaluating
+// the same pair of directions twice gives the same answer.
+#[derive(Debug)]
+pub struct CoatedBxDF {
+    coat: RoughDielectric,
+    base: Box<BxDF>,
+    max_depth: usize,
+    num_samples: usize
+}
+
+impl CoatedBxDF {
+    pub fn new(coat: RoughDielectric, base: Box<BxDF>,
+               max_depth: usize, num_samples: usize) -> CoatedBxDF {
+        CoatedBxDF {
+            coat: coat,
+            base: base,
+            max_depth: max_depth,
+            num_samples: num_samples.max(1)
+        }
+    }
+
+    fn base_is_specular(&self) -> bool {
+        self.base.get_type().contains(bsdf::BSDF_SPECULAR)
+    }
+
+    // Probability of sampling the reflection off the coat itself
+    // rather than the light scattered by the base
+    fn coat_probability(&self, wo: &Vector) -> f32 {
+        self.coat.reflectance(wo).max(0.25).min(0.75)
+    }
+
+    fn rng_for(wo: &Vector, wi: &Vector) -> RNG {
+        let seed = [wo.x, wo.y, wo.z, wi.x, wi.y, wi.z].iter().fold(0usize, |h, c| {
+            h.wrapping_mul(31).wrapping_add(c.to_bits() as usize)
+        });
+
+        // RNG::new raises the seed to the fourth power, which has to fit
+        RNG::new(seed % 65535)
+    }
+
+    // Estimates the light that enters the coat from wo, scatters any
+    // number of times between the base and the underside of the coat and
+    // then leaves in direction wi. Both directions are above the surface.
+    fn random_walk(&self, wo: &Vector, wi: &Vector, rng: &mut RNG) -> Spectrum {
+        let zero = Spectrum::from(0.0);
+
+        // Refract into the layer from both ends of the path
+        let (w_enter, pdf_enter, f_enter) = match self.coat.sample_lobes(
+            wo, rng.random_float(), rng.random_float(), false, true) {
+            Some(s) => s,
+            None => return zero
+        };
+
+        let (w_exit, pdf_exit) = match self.coat.sample_lobes(
+            wi, rng.random_float(), rng.random_float(), false, true) {
+            Some((w, pdf, _)) => (w, pdf),
+            None => return zero
+        };
+
+        // Light travels from w_exit to wi, which is the opposite of how the
+        // direction was sampled, and only that way round is the radiance
+        // scaled by the change in refractive index.
+        let f_exit = self.coat.f(&w_exit, wi);
+
+        let mut f = zero;
+        let mut beta = f_enter * abs_cos_theta(&w_enter) / pdf_enter;
+        let mut w = w_enter;
+        for depth in 0..self.max_depth {
+            // Russian roulette once the path has lost most of its energy
+            if depth > 3 {
+                let max_beta = beta.to_rgb().iter().fold(0.0f32, |m, c| m.max(*c));
+                if max_beta < 0.25 {
+                    let q = (1.0 - max_beta).max(0.0);
+                    if rng.random_float() < q {
+                        break;
+                    }
+                    beta = beta / (1.0 - q);
+                }
+            }
+
+            // Arriving at the base, the path is travelling down
+            let base_wo = -&w;
+            if !self.base_is_specular() {
+                // Connect to the exit direction through the coat
+                let w_out = -&w_exit;
+                let base_pdf = self.base.pdf(&base_wo, &w_out);
+                let wt = power_heuristic(1, pdf_exit, 1, base_pdf);
+                f = f + beta * self.base.f(&base_wo, &w_out) * abs_cos_theta(&w_exit) * wt *
+                    f_exit / pdf_exit;
+            }
+
+            let (w_base, pdf_base, f_base) =
+                self.base.sample_f(&base_wo, rng.random_float(), rng.random_float());
+            if pdf_base == 0.0 || f_base.is_black() || w_base.z <= 0.0 {
+                break;
+            }
+
+            beta = beta * f_base * abs_cos_theta(&w_base) / pdf_base;
+            w = w_base;
+
+            // Leave through the coat along the sampled direction
+            let coat_wo = -&w;
+            if !self.base_is_specular() {
+                let f_leave = self.coat.f(&coat_wo, wi);
+                if !f_leave.is_black() {
+                    let leave_pdf = self.coat.pdf_lobes(&coat_wo, wi, false, true);
+                    let wt = power_heuristic(1, pdf_base, 1, leave_pdf);
+                    f = f + beta * f_leave * wt;
+                }
+            } else {
+                f = f + beta * self.coat.f(&coat_wo, wi);
+            }
+
+            // Otherwise it's reflected back down by the coat
+            match self.coat.sample_lobes(&coat_wo, rng.random_float(),
+                                         rng.random_float(), true, false) {
+                Some((w_coat, pdf_coat, f_coat)) => {
+                    beta = beta * f_coat * abs_cos_theta(&w_coat) / pdf_coat;
+                    w = w_coat;
+                }
+                None => break
+            }
+        }
+
+        f
+    }
+}
+
+impl BxDF for CoatedBxDF {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_GLOSSY).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        if !same_hemisphere(wo, wi) {
+            return Spectrum::from(0.0);
+        }
+
+        // The coat is two-sided, so work from above the surface
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo.clone(), wi.clone()) };
+
+        let mut rng = CoatedBxDF::rng_for(&wo, &wi);
+        let walks = (0..self.num_samples).fold(Spectrum::from(0.0), |f, _| {
+            f + self.random_walk(&wo, &wi, &mut rng)
+        });
+
+        self.coat.f(&wo, &wi) + walks / (self.num_samples as f32)
+    }
+
+    // Either sample the reflection off the coat or a cosine-weighted
+    // direction for the light that made it to the base and back.
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        let flip = wo.z < 0.0;
+        let wo_up = if flip { -wo } else { wo.clone() };
+        let pc = self.coat_probability(&wo_up);
+
+        let wi = if u1 < pc {
+            match self.coat.sample_lobes(&wo_up, u1 / pc, u2, true, false) {
+                Some((wi, _, _)) => wi,
+                None => return (Vector::new(), 0.0, Spectrum::from(0.0))
+            }
+        } else {
+            cosine_sample_hemisphere(((u1 - pc) / (1.0 - pc)).min(0.99999), u2)
+        };
+
+        let wi = if flip { -wi } else { wi };
+        let pdf = self.pdf(wo, &wi);
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo.clone(), wi.clone()) };
+        let pc = self.coat_probability(&wo);
+        pc * self.coat.pdf_lobes(&wo, &wi, true, false) +
+            (1.0 - pc) * abs_cos_theta(&wi) / ::std::f32::consts::PI
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf;
+    use bsdf::BRDFtoBTDF;
+    use bsdf::BxDF;
+    use bsdf::ScaledBxDF;
+    use bsdf::lambertian::Lambertian;
+    use bsdf::microfacet::MicrofacetDistribution;
+    use bsdf::microfacet::RoughDielectric;
+    use bsdf::validation;
+    use geometry::normal::Normalize;
+    use geometry::vector::Vector;
+    use rng::RNG;
+    use spectrum::Spectrum;
+
+    fn coat(alpha: f32) -> RoughDielectric {
+        RoughDielectric::new(Spectrum::from(1.0), Spectrum::from(1.0),
+                             MicrofacetDistribution::trowbridge_reitz(alpha, alpha), 1.5)
+    }
+
+    fn coated(base: Box<BxDF>) -> CoatedBxDF {
+        CoatedBxDF::new(coat(0.3), base, 10, 4)
+    }
+
+    #[test]
+    fn it_is_deterministic() {
+        let brdf = coated(Box::new(Lambertian::new(Spectrum::from(0.5))));
+        let wo = Vector::new_with(0.3, 0.1, 0.9).normalize();
+        let wi = Vector::new_with(-0.2, 0.4, 0.8).normalize();
+        assert_eq!(brdf.f(&wo, &wi), brdf.f(&wo, &wi));
+        assert!(!brdf.f(&wo, &wi).is_black());
+
+        // Two-sided, but doesn't transmit
+        assert_eq!(brdf.f(&wo, &wi), brdf.f(&-&wo, &-&wi));
+        assert!(brdf.f(&wo, &-&wi).is_black());
+    }
+
+    #[test]
+    fn it_conserves_energy() {
+        let mut rng = RNG::new(0);
+        let white = coated(Box::new(Lambertian::new(Spectrum::from(1.0))));
+        let black = coated(Box::new(Lambertian::new(Spectrum::from(0.0))));
+        let dirs = [Vector::new_with(0.0, 0.0, 1.0),
+                    Vector::new_with(0.5, 0.0, 0.75f32.sqrt()),
+                    Vector::new_with(0.1, -0.6, 0.64f32.sqrt())];
+        for wo in dirs.iter() {
+            let wo = wo.clone().normalize();
+            let a_white = validation::albedo(&white, &wo, 2048, &mut rng).to_rgb();
+            let a_black = validation::albedo(&black, &wo, 2048, &mut rng).to_rgb();
+
+            // Covering the base with a coat only loses energy, and
+            // the coat on its own reflects only a little light
+            assert!(a_white[0] <= 1.02, "albedo {}", a_white[0]);
+            assert!(a_white[0] > 0.6, "albedo {}", a_white[0]);
+            assert!(a_black[0] < 0.1, "albedo {}", a_black[0]);
+            assert!(a_black[0] < a_white[0]);
+        }
+    }
+
+    #[test]
+    fn sampled_directions_match_pdf() {
+        let mut rng = RNG::new(1);
+        let brdf = coated(Box::new(Lambertian::new(Spectrum::from(0.5))));
+        for _ in 0..2 {
+            let wo = validation::random_direction(&mut rng);
+            let p = validation::chi_square_test(&brdf, &wo, 10, 20, 20000, &mut rng);
+            assert!(p > 1e-3, "p = {}", p);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_matches_pdf() {
+        let mut rng = RNG::new(2);
+        let bsdf = coat(0.4);
+        for _ in 0..2 {
+            let wo = validation::random_direction(&mut rng);
+            let p = validation::chi_square_test(&bsdf, &wo, 16, 32, 100000, &mut rng);
+            assert!(p > 1e-3, "p = {}", p);
+
+            let p = validation::chi_square_test(&bsdf, &-wo, 16, 32, 100000, &mut rng);
+            assert!(p > 1e-3, "p = {}", p);
+        }
+    }
+
+    #[test]
+    fn it_composes_with_other_bxdfs() {
+        let wo = Vector::new_with(0.3, 0.1, 0.9).normalize();
+        let wi = Vector::new_with(-0.2, 0.4, 0.8).normalize();
+        let f = coated(Box::new(Lambertian::new(Spectrum::from(0.5)))).f(&wo, &wi);
+
+        // Scaling the coated BxDF
+        let scaled = ScaledBxDF::new(
+            Box::new(coated(Box::new(Lambertian::new(Spectrum::from(0.5))))),
+            Spectrum::from(0.5));
+        assert_eq!(scaled.get_type(), bsdf::BSDF_REFLECTION | bsdf::BSDF_GLOSSY);
+        assert!((scaled.f(&wo, &wi)[0] - 0.5 * f[0]).abs() < 1e-6);
+
+        // Turning it into a transmissive one
+        let btdf = BRDFtoBTDF::new(coated(Box::new(Lambertian::new(Spectrum::from(0.5)))));
+        assert_eq!(btdf.get_type(), bsdf::BSDF_TRANSMISSION | bsdf::BSDF_GLOSSY);
+        let wt = Vector::new_with(wi.x, wi.y, -wi.z);
+        assert_eq!(btdf.f(&wo, &wt), f);
+
+        // Coating a scaled base
+        let base = ScaledBxDF::new(Box::new(Lambertian::new(Spectrum::from(1.0))),
+                                   Spectrum::from(0.5));
+        let f2 = coated(Box::new(base)).f(&wo, &wi);
+        assert!((f2[0] - f[0]).abs() < 1e-6);
+    }
+}
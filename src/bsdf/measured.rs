@@ -8,7 +8,9 @@ use spectrum::Spectrum;
 use utils::Clamp;
 use utils::kdtree::*;
 
-fn brdf_remap(wo: &Vector, wi: &Vector) -> Point {
+use std::sync::Arc;
+
+pub fn brdf_remap(wo: &Vector, wi: &Vector) -> Point {
     let cosi = cos_theta(wi);
     let coso = cos_theta(wo);
 
@@ -55,11 +57,11 @@ impl HasPoint for IrregIsotropicSample {
 
 #[derive(Debug, Clone)]
 pub struct IrregIsotropic {
-    iso_data: KdTree<IrregIsotropicSample>
+    iso_data: Arc<KdTree<IrregIsotropicSample>>
 }
 
 impl IrregIsotropic {
-    pub fn new(data: KdTree<IrregIsotropicSample>) -> IrregIsotropic {
+    pub fn new(data: Arc<KdTree<IrregIsotropicSample>>) -> IrregIsotropic {
         IrregIsotropic { iso_data: data }
     }
 }
@@ -105,16 +107,16 @@ impl BxDF for IrregIsotropic {
             self.iso_data.lookup(&m, &mut p, max_dist_sq);
 
             if p.num_found > 2 || last_max_dist_sq > 1.5 {
-                return p.v.clamp(1.0, ::std::f32::MAX) / p.sum_weights;
+                if p.sum_weights == 0.0 {
+                    return Spectrum::from(0.0);
+                }
+
+                return p.v.clamp(0.0, ::std::f32::MAX) / p.sum_weights;
             }
 
             last_max_dist_sq *= 2.0;
         }
     }
-
-    fn sample_f(&self, _: &Vector, _: f32, _: f32) -> (Vector, f32, Spectrum) {
-        unimplemented!()
-    }
 }
 
 #[derive(Debug, Clone)]
@@ -122,12 +124,14 @@ pub struct RegularHalfangle {
     num_theta_h: usize,
     num_theta_d: usize,
     num_phi_d: usize,
-    brdf: Vec<f32>
+    brdf: Arc<Vec<f32>>
 }
 
 impl RegularHalfangle {
-    pub fn new(nthh: usize, nthd: usize, nphd: usize, d: Vec<f32>) -> RegularHalfangle {
-        assert_eq!(nthh * nthd * nphd, d.len());
+    pub fn new(nthh: usize, nthd: usize, nphd: usize,
+               d: Arc<Vec<f32>>) -> RegularHalfangle {
+        // Three channels (RGB) per table entry
+        assert_eq!(nthh * nthd * nphd * 3, d.len());
         RegularHalfangle {
             num_theta_h: nthh,
             num_theta_d: nthd,
@@ -180,8 +184,4 @@ impl BxDF for RegularHalfangle {
                    self.brdf[index * 3 + 2]];
         Spectrum::from_rgb(rgb)
     }
-
-    fn sample_f(&self, _: &Vector, _: f32, _: f32) -> (Vector, f32, Spectrum) {
-        unimplemented!()
-    }
 }
@@ -4,14 +4,26 @@ use bsdf::fresnel::Fresnel;
 use bsdf::utils::*;
 use geometry::normal::Normalize;
 use geometry::vector::Vector;
+use geometry::vector::Cross;
 use geometry::vector::Dot;
+use geometry::vector::spherical_direction;
+use montecarlo::cosine_sample_hemisphere;
 use spectrum::Spectrum;
 use utils::Degrees;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MicrofacetDistribution {
     Blinn(f32),
-    Anisotropic(f32, f32)
+    Anisotropic(f32, f32),
+    Beckmann(f32, f32),
+    TrowbridgeReitz(f32, f32)
+}
+
+// Maps a perceptually linear roughness in [0, 1], as used by most
+// GGX-based renderers, to the alpha parameter of the distributions.
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    let r = roughness.max(1e-3);
+    r * r
 }
 
 impl MicrofacetDistribution {
@@ -29,7 +41,16 @@ impl MicrofacetDistribution {
         MicrofacetDistribution::Anisotropic(x1, x2)
     }
 
-    fn d(&self, wh: &Vector) -> f32 {
+    pub fn beckmann(alpha_x: f32, alpha_y: f32) -> MicrofacetDistribution {
+        MicrofacetDistribution::Beckmann(alpha_x.max(1e-3), alpha_y.max(1e-3))
+    }
+
+    // Also known as GGX, parameterized by the roughness along x and y
+    pub fn trowbridge_reitz(alpha_x: f32, alpha_y: f32) -> MicrofacetDistribution {
+        MicrofacetDistribution::TrowbridgeReitz(alpha_x.max(1e-3), alpha_y.max(1e-3))
+    }
+
+    pub fn d(&self, wh: &Vector) -> f32 {
         let invtwopi = 1.0 / (2.0 * ::std::f32::consts::PI);
         match self {
             &MicrofacetDistribution::Blinn(e) => {
@@ -46,8 +67,287 @@ impl MicrofacetDistribution {
                 let e = (ex * wh.x * wh.x + ey * wh.y * wh.y) / d;
                 ((ex + 2.0) * (ey + 2.0)).sqrt() * invtwopi * costhetah.powf(e)
             }
+            &MicrofacetDistribution::Beckmann(ax, ay) => {
+                let tan2theta = tan_theta2(wh);
+                if tan2theta.is_infinite() || tan2theta.is_nan() {
+                    return 0.0;
+                }
+
+                let cos2theta = cos_theta(wh) * cos_theta(wh);
+                let cos4theta = cos2theta * cos2theta;
+                let (cosphi, sinphi) = (cos_phi(wh), sin_phi(wh));
+                let e = (cosphi * cosphi / (ax * ax) + sinphi * sinphi / (ay * ay)) * tan2theta;
+                (-e).exp() / (::std::f32::consts::PI * ax * ay * cos4theta)
+            }
+            &MicrofacetDistribution::TrowbridgeReitz(ax, ay) => {
+                let tan2theta = tan_theta2(wh);
+                if tan2theta.is_infinite() || tan2theta.is_nan() {
+                    return 0.0;
+                }
+
+                let cos2theta = cos_theta(wh) * cos_theta(wh);
+                let cos4theta = cos2theta * cos2theta;
+                let (cosphi, sinphi) = (cos_phi(wh), sin_phi(wh));
+                let e = (cosphi * cosphi / (ax * ax) + sinphi * sinphi / (ay * ay)) * tan2theta;
+                1.0 / (::std::f32::consts::PI * ax * ay * cos4theta * (1.0 + e) * (1.0 + e))
+            }
         }
     }
+
+    // Smith's auxiliary function for the distributions that support it:
+    // the ratio of hidden to visible microfacet area in the direction w.
+    fn lambda(&self, w: &Vector) -> f32 {
+        match self {
+            &MicrofacetDistribution::Beckmann(ax, ay) => {
+                let tan2theta = tan_theta2(w);
+                if tan2theta.is_infinite() || tan2theta.is_nan() {
+                    return 0.0;
+                }
+
+                // Polynomial approximation of the exact erf based form
+                let (cosphi, sinphi) = (cos_phi(w), sin_phi(w));
+                let alpha = (cosphi * cosphi * ax * ax + sinphi * sinphi * ay * ay).sqrt();
+                let a = 1.0 / (alpha * tan2theta.sqrt());
+                if a >= 1.6 {
+                    return 0.0;
+                }
+
+                (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+            }
+            &MicrofacetDistribution::TrowbridgeReitz(ax, ay) => {
+                let tan2theta = tan_theta2(w);
+                if tan2theta.is_infinite() || tan2theta.is_nan() {
+                    return 0.0;
+                }
+
+                let (cosphi, sinphi) = (cos_phi(w), sin_phi(w));
+                let alpha2 = cosphi * cosphi * ax * ax + sinphi * sinphi * ay * ay;
+                (-1.0 + (1.0 + alpha2 * tan2theta).sqrt()) / 2.0
+            }
+            _ => unreachable!("{:?} has no Smith masking function", self)
+        }
+    }
+
+    fn has_smith_masking(&self) -> bool {
+        match self {
+            &MicrofacetDistribution::Beckmann(_, _) => true,
+            &MicrofacetDistribution::TrowbridgeReitz(_, _) => true,
+            _ => false
+        }
+    }
+
+    // Smith masking function: the fraction of microfacets visible from w.
+    // Only defined for the Beckmann and Trowbridge-Reitz distributions, so
+    // callers have to check has_smith_masking first.
+    fn g1(&self, w: &Vector) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vector, wi: &Vector, wh: &Vector) -> f32 {
+        match self {
+            // Height-correlated Smith masking-shadowing
+            &MicrofacetDistribution::Beckmann(_, _) |
+            &MicrofacetDistribution::TrowbridgeReitz(_, _) =>
+                1.0 / (1.0 + self.lambda(wo) + self.lambda(wi)),
+            _ => {
+                // Torrance-Sparrow v-cavities
+                let ndotwh = abs_cos_theta(wh);
+                let ndotwo = abs_cos_theta(wo);
+                let ndotwi = abs_cos_theta(wi);
+                let wodotwh = wo.abs_dot(wh);
+                (2.0 * ndotwh * ndotwo / wodotwh)
+                    .min(2.0 * ndotwh * ndotwi / wodotwh)
+                    .min(1.0)
+            }
+        }
+    }
+
+    // Samples a microfacet normal in the same hemisphere as wo. Distributions
+    // with a Smith masking function only sample the normals that are visible
+    // from wo, the others sample proportionally to D(wh) * |cos(theta_h)|
+    pub fn sample_wh(&self, wo: &Vector, u1: f32, u2: f32) -> Vector {
+        let flip = wo.z < 0.0;
+        let wo_up = if flip { -wo } else { wo.clone() };
+        let wh = match self {
+            &MicrofacetDistribution::Blinn(e) => {
+                // D(wh) * cos(theta_h) is proportional to cos(theta_h)^(e + 1)
+                let costheta = u1.powf(1.0 / (e + 2.0));
+                let sintheta = (1.0 - costheta * costheta).max(0.0).sqrt();
+                let phi = 2.0 * ::std::f32::consts::PI * u2;
+                spherical_direction(sintheta, costheta, phi)
+            }
+            &MicrofacetDistribution::Anisotropic(ex, ey) => {
+                // Sample the first quadrant and mirror it into the
+                // other three depending on which quarter u2 falls in
+                let (u, quadrant) = if u2 < 0.25 {
+                    (4.0 * u2, 0)
+                } else if u2 < 0.5 {
+                    (4.0 * (0.5 - u2), 1)
+                } else if u2 < 0.75 {
+                    (4.0 * (u2 - 0.5), 2)
+                } else {
+                    (4.0 * (1.0 - u2), 3)
+                };
+
+                let (p, costheta) = anisotropic_sample_first_quadrant(ex, ey, u1, u);
+                let phi = match quadrant {
+                    0 => p,
+                    1 => ::std::f32::consts::PI - p,
+                    2 => ::std::f32::consts::PI + p,
+                    _ => 2.0 * ::std::f32::consts::PI - p
+                };
+
+                let sintheta = (1.0 - costheta * costheta).max(0.0).sqrt();
+                spherical_direction(sintheta, costheta, phi)
+            }
+            &MicrofacetDistribution::Beckmann(ax, ay) =>
+                beckmann_sample_visible(&wo_up, ax, ay, u1, u2),
+            &MicrofacetDistribution::TrowbridgeReitz(ax, ay) =>
+                trowbridge_reitz_sample_visible(&wo_up, ax, ay, u1, u2)
+        };
+
+        if flip { -wh } else { wh }
+    }
+
+    // Density of sample_wh with respect to solid angle around wh
+    pub fn pdf(&self, wo: &Vector, wh: &Vector) -> f32 {
+        if self.has_smith_masking() {
+            let cos_theta_o = abs_cos_theta(wo);
+            if cos_theta_o == 0.0 {
+                return 0.0;
+            }
+
+            self.d(wh) * self.g1(wo) * wo.dot(wh).max(0.0) / cos_theta_o
+        } else {
+            self.d(wh) * abs_cos_theta(wh)
+        }
+    }
+}
+
+// Samples phi in [0, pi/2) and cos(theta) for the Ashikhmin-Shirley
+// distribution so that the density is proportional to D(wh) * cos(theta_h).
+fn anisotropic_sample_first_quadrant(ex: f32, ey: f32, u1: f32, u2: f32) -> (f32, f32) {
+    let phi = if ex == ey {
+        ::std::f32::consts::PI * u2 * 0.5
+    } else {
+        (((ex + 2.0) / (ey + 2.0)).sqrt() * (::std::f32::consts::PI * u2 * 0.5).tan()).atan()
+    };
+
+    let (cosphi, sinphi) = (phi.cos(), phi.sin());
+    let costheta = u1.powf(1.0 / (ex * cosphi * cosphi + ey * sinphi * sinphi + 2.0));
+    (phi, costheta)
+}
+
+// Samples the slopes of Beckmann microfacets visible from a direction
+// with the given cos(theta) in the unit roughness configuration.
+fn beckmann_sample_slopes(cos_theta_i: f32, u1: f32, u2: f32) -> (f32, f32) {
+    // Normal incidence is just the regular distribution of slopes
+    if cos_theta_i > 0.9999 {
+        let r = (-(1.0 - u1).ln()).sqrt();
+        let phi = 2.0 * ::std::f32::consts::PI * u2;
+        return (r * phi.cos(), r * phi.sin());
+    }
+
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let tan_theta_i = sin_theta_i / cos_theta_i;
+    let cot_theta_i = 1.0 / tan_theta_i;
+    let inv_sqrt_pi = 1.0 / ::std::f32::consts::PI.sqrt();
+
+    // Search for the slope x with the inverse of the cdf using a Newton
+    // bisection hybrid, starting from a fitted first guess
+    let mut a = -1.0;
+    let mut c = erf(cot_theta_i);
+    let sample_x = u1.max(1e-6);
+    let theta_i = cos_theta_i.acos();
+    let fit = 1.0 + theta_i * (-0.876 + theta_i * (0.4265 - 0.0594 * theta_i));
+    let mut b = c - (1.0 + c) * (1.0 - sample_x).powf(fit);
+
+    let normalization = 1.0 /
+        (1.0 + c + inv_sqrt_pi * tan_theta_i * (-cot_theta_i * cot_theta_i).exp());
+    for _ in 0..10 {
+        if !(b >= a && b <= c) {
+            b = 0.5 * (a + c);
+        }
+
+        let inv_erf = erf_inv(b);
+        let value = normalization *
+            (1.0 + b + inv_sqrt_pi * tan_theta_i * (-inv_erf * inv_erf).exp()) - sample_x;
+        let derivative = normalization * (1.0 - inv_erf * tan_theta_i);
+        if value.abs() < 1e-5 {
+            break;
+        }
+
+        if value > 0.0 { c = b; } else { a = b; }
+        b -= value / derivative;
+    }
+
+    (erf_inv(b), erf_inv(2.0 * u2.max(1e-6) - 1.0))
+}
+
+fn beckmann_sample_visible(wo: &Vector, ax: f32, ay: f32, u1: f32, u2: f32) -> Vector {
+    // Stretch wo to the unit roughness configuration
+    let wo_stretched = Vector::new_with(ax * wo.x, ay * wo.y, wo.z).normalize();
+    let (sx, sy) = beckmann_sample_slopes(cos_theta(&wo_stretched), u1, u2);
+
+    // Rotate, unstretch and turn the slopes into a normal
+    let (cosphi, sinphi) = (cos_phi(&wo_stretched), sin_phi(&wo_stretched));
+    let slope_x = ax * (cosphi * sx - sinphi * sy);
+    let slope_y = ay * (sinphi * sx + cosphi * sy);
+    Vector::new_with(-slope_x, -slope_y, 1.0).normalize()
+}
+
+// Heitz, "Sampling the GGX Distribution of Visible Normals" (2018)
+fn trowbridge_reitz_sample_visible(wo: &Vector, ax: f32, ay: f32, u1: f32, u2: f32) -> Vector {
+    // Transform wo to the hemisphere configuration
+    let vh = Vector::new_with(ax * wo.x, ay * wo.y, wo.z).normalize();
+
+    // Orthonormal basis around vh
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0.0 {
+        Vector::new_with(-vh.y, vh.x, 0.0) / lensq.sqrt()
+    } else {
+        Vector::new_with(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross_with(&t1);
+
+    // Sample the projected area of the hemisphere
+    let r = u1.sqrt();
+    let phi = 2.0 * ::std::f32::consts::PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+
+    // Reproject onto the hemisphere and transform back to the ellipsoid
+    let pz = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    let nh = t1 * p1 + t2 * p2 + vh * pz;
+    Vector::new_with(ax * nh.x, ay * nh.y, nh.z.max(1e-6)).normalize()
+}
+
+// Abramowitz and Stegun 7.1.26
+fn erf(x: f32) -> f32 {
+    let coeffs = [1.061405429, -1.453152027, 1.421413741, -0.284496736, 0.254829592];
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = coeffs.iter().fold(0.0, |acc, c| acc * t + c) * t;
+    let y = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 { -y } else { y }
+}
+
+// Giles, "Approximating the erfinv function" (2010)
+fn erf_inv(x: f32) -> f32 {
+    let x = x.max(-0.99999).min(0.99999);
+    let w = -((1.0 - x) * (1.0 + x)).ln();
+    let p = if w < 5.0 {
+        let coeffs = [2.81022636e-08, 3.43273939e-07, -3.5233877e-06, -4.39150654e-06,
+                      0.00021858087, -0.00125372503, -0.00417768164, 0.246640727, 1.50140941];
+        let w = w - 2.5;
+        coeffs.iter().fold(0.0, |acc, c| acc * w + c)
+    } else {
+        let coeffs = [-0.000200214257, 0.000100950558, 0.00134934322, -0.00367342844,
+                      0.00573950773, -0.0076224613, 0.00943887047, 1.00167406, 2.83297682];
+        let w = w.sqrt() - 3.0;
+        coeffs.iter().fold(0.0, |acc, c| acc * w + c)
+    };
+    p * x
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -66,16 +366,6 @@ impl Microfacet {
             fresnel: f
         }
     }
-
-    fn g(&self, wo: &Vector, wi: &Vector, wh: &Vector) -> f32 {
-        let ndotwh = abs_cos_theta(wh);
-        let ndotwo = abs_cos_theta(wo);
-        let ndotwi = abs_cos_theta(wi);
-        let wodotwh = wo.abs_dot(wh);
-        (2.0 * ndotwh * ndotwo / wodotwh)
-            .min(2.0 * ndotwh * ndotwi / wodotwh)
-            .min(1.0)
-    }
 }
 
 impl BxDF for Microfacet {
@@ -94,12 +384,324 @@ impl BxDF for Microfacet {
         let wh = (wo + wi).normalize();
         let cos_theta_h = wi.dot(&wh);
         let f = self.fresnel.evaluate(cos_theta_h);
-        (self.r * self.distribution.d(&wh) * self.g(&wo, &wi, &wh) * f) /
+        (self.r * self.distribution.d(&wh) * self.distribution.g(&wo, &wi, &wh) * f) /
             (4.0 * cos_theta_i * cos_theta_o)
     }
 
-    fn sample_f(&self, _: &Vector, _: f32, _: f32) -> (Vector, f32, Spectrum) {
-        unimplemented!()
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        if wo.z == 0.0 {
+            return (Vector::new(), 0.0, Spectrum::from(0.0));
+        }
+
+        // Sample microfacet orientation and reflect wo about it
+        let wh = self.distribution.sample_wh(wo, u1, u2);
+        let wi = reflect(wo, &wh);
+        if !same_hemisphere(wo, &wi) {
+            return (wi, 0.0, Spectrum::from(0.0));
+        }
+
+        let pdf = self.distribution.pdf(wo, &wh) / (4.0 * wo.dot(&wh));
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        let wh = (wo + wi).normalize();
+        self.distribution.pdf(wo, &wh) / (4.0 * wo.dot(&wh))
+    }
+}
+
+// Rough dielectric interface that refracts through the microfacets
+#[derive(Debug, Clone, PartialEq)]
+pub struct MicrofacetTransmission {
+    t: Spectrum,
+    distribution: MicrofacetDistribution,
+    eta_a: f32,
+    eta_b: f32,
+    fresnel: Fresnel
+}
+
+impl MicrofacetTransmission {
+    // eta_a is the index of refraction above the surface and
+    // eta_b the one below it
+    pub fn new(t: Spectrum, dist: MicrofacetDistribution,
+               eta_a: f32, eta_b: f32) -> MicrofacetTransmission {
+        MicrofacetTransmission {
+            t: t,
+            distribution: dist,
+            eta_a: eta_a,
+            eta_b: eta_b,
+            fresnel: Fresnel::dielectric(eta_a, eta_b)
+        }
+    }
+
+    // Generalized half vector for refraction, facing up, along with
+    // the relative index of refraction of the side of wi
+    fn half_vector(&self, wo: &Vector, wi: &Vector) -> Option<(Vector, f32)> {
+        let eta = if cos_theta(wo) > 0.0 {
+            self.eta_b / self.eta_a
+        } else {
+            self.eta_a / self.eta_b
+        };
+
+        let wh = (wo + wi * eta).normalize();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+
+        // Both directions need to be on opposite sides of the microfacet,
+        // with wo on the side that it faces
+        if wo.dot(&wh) * cos_theta(wo) <= 0.0 || wi.dot(&wh) * cos_theta(wo) >= 0.0 {
+            None
+        } else {
+            Some((wh, eta))
+        }
+    }
+}
+
+impl BxDF for MicrofacetTransmission {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_TRANSMISSION | bsdf::BSDF_GLOSSY).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        if same_hemisphere(wo, wi) {
+            return Spectrum::from(0.0);
+        }
+
+        let cos_theta_o = cos_theta(wo);
+        let cos_theta_i = cos_theta(wi);
+        if cos_theta_o == 0.0 || cos_theta_i == 0.0 {
+            return Spectrum::from(0.0);
+        }
+
+        match self.half_vector(wo, wi) {
+            None => Spectrum::from(0.0),
+            Some((wh, eta)) => {
+                let f = self.fresnel.evaluate(wo.dot(&wh));
+                let sqrt_denom = wo.dot(&wh) + eta * wi.dot(&wh);
+
+                // The eta^2 of the change of measure cancels out with
+                // the 1 / eta^2 scaling of radiance across the interface
+                let v = self.distribution.d(&wh) * self.distribution.g(wo, wi, &wh) *
+                    wi.dot(&wh) * wo.dot(&wh) /
+                    (cos_theta_i * cos_theta_o * sqrt_denom * sqrt_denom);
+                (Spectrum::from(1.0) - f) * self.t * v.abs()
+            }
+        }
+    }
+
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        if wo.z == 0.0 {
+            return (Vector::new(), 0.0, Spectrum::from(0.0));
+        }
+
+        let wh = self.distribution.sample_wh(wo, u1, u2);
+        if wo.dot(&wh) <= 0.0 {
+            // Microfacets facing away from wo don't refract it
+            return (Vector::new(), 0.0, Spectrum::from(0.0));
+        }
+
+        let eta = if cos_theta(wo) > 0.0 {
+            self.eta_a / self.eta_b
+        } else {
+            self.eta_b / self.eta_a
+        };
+
+        match refract(wo, &wh, eta) {
+            None => (Vector::new(), 0.0, Spectrum::from(0.0)),
+            Some(wi) => {
+                let pdf = self.pdf(wo, &wi);
+                let f = self.f(wo, &wi);
+                (wi, pdf, f)
+            }
+        }
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        if same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        match self.half_vector(wo, wi) {
+            None => 0.0,
+            Some((wh, eta)) => {
+                // Change of variables from wh to wi
+                let sqrt_denom = wo.dot(&wh) + eta * wi.dot(&wh);
+                let dwh_dwi = (eta * eta * wi.dot(&wh)).abs() / (sqrt_denom * sqrt_denom);
+                let wh = if wo.z < 0.0 { -wh } else { wh };
+                self.distribution.pdf(wo, &wh) * dwh_dwi
+            }
+        }
+    }
+}
+
+// Rough dielectric interface that both reflects and refracts through its
+// microfacets, choosing between the two with the Fresnel reflectance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoughDielectric {
+    r: Spectrum,
+    t: Spectrum,
+    distribution: MicrofacetDistribution,
+    eta: f32,
+    fresnel: Fresnel
+}
+
+impl RoughDielectric {
+    // eta is the index of refraction below the surface relative to the
+    // one above it
+    pub fn new(r: Spectrum, t: Spectrum, dist: MicrofacetDistribution,
+               eta: f32) -> RoughDielectric {
+        RoughDielectric {
+            r: r,
+            t: t,
+            distribution: dist,
+            eta: eta,
+            fresnel: Fresnel::dielectric(1.0, eta)
+        }
+    }
+
+    // Ratio of the index of refraction on the other side of the
+    // interface to the one on the side of w
+    fn relative_eta(&self, w: &Vector) -> f32 {
+        if cos_theta(w) > 0.0 { self.eta } else { 1.0 / self.eta }
+    }
+
+    // Fresnel reflectance with respect to the macro surface
+    pub fn reflectance(&self, wo: &Vector) -> f32 {
+        self.fresnel.evaluate(cos_theta(wo))[0]
+    }
+
+    // Probability of sampling reflection rather than transmission, based
+    // on the Fresnel reflectance with respect to the macro surface
+    fn reflect_probability(&self, wo: &Vector, sample_reflection: bool,
+                           sample_transmission: bool) -> f32 {
+        match (sample_reflection, sample_transmission) {
+            (true, false) => 1.0,
+            (false, true) => 0.0,
+            (false, false) => 0.0,
+            (true, true) => {
+                let fr = self.reflectance(wo);
+                let r = fr * self.r.y();
+                let t = (1.0 - fr) * self.t.y();
+                if r + t == 0.0 { 0.5 } else { (r / (r + t)).max(0.05).min(0.95) }
+            }
+        }
+    }
+
+    // Microfacet normal for the pair of directions, facing up
+    fn half_vector(&self, wo: &Vector, wi: &Vector) -> Option<Vector> {
+        let etap = if same_hemisphere(wo, wi) { 1.0 } else { self.relative_eta(wo) };
+        let wm = wo + wi * etap;
+        if wo.z == 0.0 || wi.z == 0.0 || wm.length_squared() == 0.0 {
+            return None;
+        }
+
+        let wm = wm.normalize();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+
+        // Discard back facing microfacets
+        if wm.dot(wi) * cos_theta(wi) < 0.0 || wm.dot(wo) * cos_theta(wo) < 0.0 {
+            None
+        } else {
+            Some(wm)
+        }
+    }
+
+    // Samples only the requested lobes of the interface
+    pub fn sample_lobes(&self, wo: &Vector, u1: f32, u2: f32, sample_reflection: bool,
+                        sample_transmission: bool) -> Option<(Vector, f32, Spectrum)> {
+        let pr = self.reflect_probability(wo, sample_reflection, sample_transmission);
+        if wo.z == 0.0 || (!sample_reflection && !sample_transmission) {
+            return None;
+        }
+
+        let (reflected, u) = if u1 < pr {
+            (true, u1 / pr)
+        } else {
+            (false, (u1 - pr) / (1.0 - pr))
+        };
+
+        let wm = self.distribution.sample_wh(wo, u.min(0.99999), u2);
+        let wi = if reflected {
+            let wi = reflect(wo, &wm);
+            if !same_hemisphere(wo, &wi) { return None; }
+            wi
+        } else {
+            match refract(wo, &wm, 1.0 / self.relative_eta(wo)) {
+                Some(ref wi) if !same_hemisphere(wo, wi) && wi.z != 0.0 => wi.clone(),
+                _ => return None
+            }
+        };
+
+        let pdf = self.pdf_lobes(wo, &wi, sample_reflection, sample_transmission);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some((wi.clone(), pdf, self.f(wo, &wi)))
+    }
+
+    pub fn pdf_lobes(&self, wo: &Vector, wi: &Vector, sample_reflection: bool,
+                     sample_transmission: bool) -> f32 {
+        let wm = match self.half_vector(wo, wi) {
+            Some(wm) => wm,
+            None => return 0.0
+        };
+
+        let pr = self.reflect_probability(wo, sample_reflection, sample_transmission);
+        let wm_o = if wo.z < 0.0 { -&wm } else { wm.clone() };
+        if same_hemisphere(wo, wi) {
+            pr * self.distribution.pdf(wo, &wm_o) / (4.0 * wo.abs_dot(&wm))
+        } else {
+            // Change of variables from wm to wi
+            let etap = self.relative_eta(wo);
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            let dwm_dwi = wi.abs_dot(&wm) / (denom * denom);
+            (1.0 - pr) * self.distribution.pdf(wo, &wm_o) * dwm_dwi
+        }
+    }
+}
+
+impl BxDF for RoughDielectric {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_TRANSMISSION | bsdf::BSDF_GLOSSY).contains(ty)
+    }
+
+    fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
+        let wm = match self.half_vector(wo, wi) {
+            Some(wm) => wm,
+            None => return Spectrum::from(0.0)
+        };
+
+        let cos_theta_o = cos_theta(wo);
+        let cos_theta_i = cos_theta(wi);
+        let f = self.fresnel.evaluate(wo.dot(&wm));
+        let dg = self.distribution.d(&wm) * self.distribution.g(wo, wi, &wm);
+        if same_hemisphere(wo, wi) {
+            self.r * f * (dg / (4.0 * cos_theta_i * cos_theta_o).abs())
+        } else {
+            // Same as MicrofacetTransmission, written in terms of etap
+            // and with the radiance scaling by 1 / etap^2
+            let etap = self.relative_eta(wo);
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            let v = dg * wi.dot(&wm) * wo.dot(&wm) /
+                (denom * denom * cos_theta_i * cos_theta_o);
+            (Spectrum::from(1.0) - f) * self.t * (v.abs() / (etap * etap))
+        }
+    }
+
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        match self.sample_lobes(wo, u1, u2, true, true) {
+            Some(s) => s,
+            None => (Vector::new(), 0.0, Spectrum::from(0.0))
+        }
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        self.pdf_lobes(wo, wi, true, true)
     }
 }
 
@@ -143,7 +745,180 @@ impl BxDF for FresnelBlend {
         diffuse + specular
     }
 
-    fn sample_f(&self, _: &Vector, _: f32, _: f32) -> (Vector, f32, Spectrum) {
-        unimplemented!()
+    // Half of the samples follow the diffuse term with a cosine
+    // distribution and the other half follow the microfacet distribution
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        let wi = if u1 < 0.5 {
+            let mut wi = cosine_sample_hemisphere(2.0 * u1, u2);
+            if wo.z < 0.0 {
+                wi.z *= -1.0;
+            }
+            wi
+        } else {
+            let wh = self.distribution.sample_wh(wo, 2.0 * (u1 - 0.5), u2);
+            let wi = reflect(wo, &wh);
+            if !same_hemisphere(wo, &wi) {
+                return (wi, 0.0, Spectrum::from(0.0));
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, &wi);
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+
+        let wh = (wo + wi).normalize();
+        let microfacet_pdf = self.distribution.pdf(wo, &wh) / (4.0 * wo.abs_dot(&wh));
+        0.5 * (abs_cos_theta(wi) / ::std::f32::consts::PI + microfacet_pdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::BxDF;
+    use bsdf::fresnel::Fresnel;
+    use bsdf::validation::chi_square_test;
+    use geometry::vector::Vector;
+    use geometry::normal::Normalize;
+    use rng::RNG;
+    use spectrum::Spectrum;
+
+    fn distributions() -> Vec<MicrofacetDistribution> {
+        vec![MicrofacetDistribution::blinn(1.0),
+             MicrofacetDistribution::blinn(10.0),
+             MicrofacetDistribution::blinn(100.0),
+             MicrofacetDistribution::anisotropic(10.0, 10.0),
+             MicrofacetDistribution::anisotropic(2.0, 50.0),
+             MicrofacetDistribution::anisotropic(80.0, 5.0),
+             MicrofacetDistribution::beckmann(0.3, 0.3),
+             MicrofacetDistribution::beckmann(0.1, 0.5),
+             MicrofacetDistribution::trowbridge_reitz(0.3, 0.3),
+             MicrofacetDistribution::trowbridge_reitz(0.2, 0.6)]
+    }
+
+    fn outgoing_directions() -> Vec<Vector> {
+        vec![Vector::new_with(0.0, 0.0, 1.0),
+             Vector::new_with(0.5, 0.2, 0.8).normalize(),
+             Vector::new_with(-0.8, 0.3, 0.2).normalize()]
+    }
+
+    #[test]
+    fn it_samples_half_vectors_proportional_to_the_pdf() {
+        let mut rng = RNG::new(0);
+        for dist in distributions() {
+            // Integrate the density of half vectors over the hemisphere
+            let n = 256;
+            let dtheta = 0.5 * ::std::f32::consts::PI / (n as f32);
+            let dphi = 2.0 * ::std::f32::consts::PI / ((4 * n) as f32);
+            for wo in outgoing_directions() {
+                let mut sum = 0.0;
+                for i in 0..n {
+                    for j in 0..(4 * n) {
+                        let theta = ((i as f32) + 0.5) * dtheta;
+                        let phi = ((j as f32) + 0.5) * dphi;
+                        let wh = spherical_direction(theta.sin(), theta.cos(), phi);
+                        sum += dist.pdf(&wo, &wh) * theta.sin();
+                    }
+                }
+                assert!((sum * dtheta * dphi - 1.0).abs() < 0.02,
+                        "{:?} pdf is not normalized for {:?}", dist, wo);
+            }
+
+            for _ in 0..100 {
+                let wo = Vector::new_with(0.0, 0.0, 1.0);
+                let wh = dist.sample_wh(&wo, rng.random_float(), rng.random_float());
+                assert!(wh.z >= 0.0);
+                assert!((wh.length_squared() - 1.0).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_directions_match_pdf() {
+        let mut rng = RNG::new(1);
+        for dist in distributions() {
+            let brdf = Microfacet::new(Spectrum::from(1.0), Fresnel::noop(), dist);
+            let blend = FresnelBlend::new(Spectrum::from(0.5), Spectrum::from(0.2), dist);
+            for wo in outgoing_directions() {
+                let p = chi_square_test(&brdf, &wo, 16, 32, 100000, &mut rng);
+                assert!(p > 1e-3, "microfacet {:?} failed for {:?}: p = {}", dist, wo, p);
+
+                let p = chi_square_test(&blend, &wo, 16, 32, 100000, &mut rng);
+                assert!(p > 1e-3, "fresnel blend {:?} failed for {:?}: p = {}", dist, wo, p);
+            }
+        }
+    }
+
+    #[test]
+    fn smith_masking_is_bounded() {
+        let dists = [MicrofacetDistribution::beckmann(0.4, 0.1),
+                     MicrofacetDistribution::trowbridge_reitz(0.4, 0.1)];
+        for dist in dists.iter() {
+            // Nothing is masked at normal incidence
+            let n = Vector::new_with(0.0, 0.0, 1.0);
+            assert!((dist.g1(&n) - 1.0).abs() < 1e-6);
+
+            let wo = Vector::new_with(0.6, 0.3, 0.2).normalize();
+            let wi = Vector::new_with(-0.1, 0.7, 0.4).normalize();
+            let wh = (&wo + &wi).normalize();
+            let g = dist.g(&wo, &wi, &wh);
+            assert!(g > 0.0 && g <= dist.g1(&wo).min(dist.g1(&wi)));
+            assert!((g - dist.g(&wi, &wo, &wh)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn it_maps_roughness_to_alpha() {
+        assert_eq!(roughness_to_alpha(0.5), 0.25);
+        assert_eq!(roughness_to_alpha(1.0), 1.0);
+        assert!(roughness_to_alpha(0.0) > 0.0);
+    }
+
+    #[test]
+    fn rough_transmission_samples_match_pdf() {
+        let mut rng = RNG::new(2);
+        let dists = [MicrofacetDistribution::beckmann(0.3, 0.3),
+                     MicrofacetDistribution::trowbridge_reitz(0.3, 0.3),
+                     MicrofacetDistribution::trowbridge_reitz(0.2, 0.5)];
+        for dist in dists.iter() {
+            let btdf = MicrofacetTransmission::new(Spectrum::from(1.0), *dist, 1.0, 1.5);
+            for wo in outgoing_directions() {
+                let p = chi_square_test(&btdf, &wo, 16, 32, 100000, &mut rng);
+                assert!(p > 1e-3, "transmission {:?} failed for {:?}: p = {}", dist, wo, p);
+
+                // Leaving the dielectric
+                let wo = -wo;
+                let p = chi_square_test(&btdf, &wo, 16, 32, 100000, &mut rng);
+                assert!(p > 1e-3, "transmission {:?} failed for {:?}: p = {}", dist, wo, p);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_transmission_refracts_through_the_surface() {
+        let mut rng = RNG::new(3);
+        let btdf = MicrofacetTransmission::new(
+            Spectrum::from(1.0), MicrofacetDistribution::trowbridge_reitz(0.1, 0.1), 1.0, 1.5);
+        let wo = Vector::new_with(0.3, 0.0, 1.0).normalize();
+        for _ in 0..100 {
+            let (wi, pdf, f) = btdf.sample_f(&wo, rng.random_float(), rng.random_float());
+            if pdf > 0.0 {
+                assert!(wi.z < 0.0);
+                assert!(!f.is_black());
+                assert!(!f.has_nans());
+            }
+        }
+
+        // No light is transmitted back into the same hemisphere
+        let wi = Vector::new_with(0.0, 0.3, 1.0).normalize();
+        assert!(btdf.f(&wo, &wi).is_black());
+        assert_eq!(btdf.pdf(&wo, &wi), 0.0);
     }
 }
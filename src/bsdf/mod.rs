@@ -1,17 +1,28 @@
 mod utils;
 pub mod bssrdf;
+pub mod disney;
 pub mod fresnel;
+pub mod hair;
 pub mod lambertian;
+pub mod layered;
 pub mod measured;
 pub mod microfacet;
 pub mod orennayar;
 pub mod specular;
 
+// Numerical checks of BxDFs, only needed by the tests
+#[cfg(test)]
+pub mod validation;
+
 use bsdf::utils::*;
 use diff_geom::DifferentialGeometry;
 use geometry::vector::*;
 use geometry::normal::*;
+use montecarlo::cosine_sample_hemisphere;
+use montecarlo::uniform_hemisphere_pdf;
+use montecarlo::uniform_sample_hemisphere;
 use rng::RNG;
+use sampler::sample::Sample;
 use spectrum::Spectrum;
 use utils::Clamp;
 
@@ -40,20 +51,141 @@ bitflags! {
 pub trait BxDF : Debug + 'static {
     fn matches_flags(&self, BxDFType) -> bool;
     fn f(&self, &Vector, &Vector) -> Spectrum;
-    fn sample_f(&self, &Vector, f32, f32) -> (Vector, f32, Spectrum);
 
-    fn rho_hd(&self, v: &Vector, samples: &[f32]) -> Spectrum {
-        unimplemented!()
+    // All of the flags that describe this BxDF, which are exactly the
+    // ones that it matches on their own.
+    fn get_type(&self) -> BxDFType {
+        [BSDF_REFLECTION, BSDF_TRANSMISSION, BSDF_DIFFUSE,
+         BSDF_GLOSSY, BSDF_SPECULAR].iter().fold(BxDFType::empty(), |ty, &flag| {
+            if self.matches_flags(flag) { ty | flag } else { ty }
+        })
+    }
+
+    // By default sample directions from a cosine-weighted hemisphere on
+    // the same side as wo, which is a good match for most diffuse BxDFs.
+    fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+        let mut wi = cosine_sample_hemisphere(u1, u2);
+        if wo.z < 0.0 {
+            wi.z *= -1.0;
+        }
+
+        let pdf = self.pdf(wo, &wi);
+        let f = self.f(wo, &wi);
+        (wi, pdf, f)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        if same_hemisphere(wo, wi) {
+            abs_cos_theta(wi) / ::std::f32::consts::PI
+        } else {
+            0.0
+        }
+    }
+
+    // Hemispherical-directional reflectance: the total reflection in
+    // direction w due to constant illumination over the hemisphere. Each
+    // pair of values in samples is used to sample one direction.
+    fn rho_hd(&self, w: &Vector, samples: &[f32]) -> Spectrum {
+        estimate_rho_hd(self, w, samples)
     }
 
+    // Hemispherical-hemispherical reflectance: the fraction of incident
+    // light reflected when the incident light is constant over the
+    // hemisphere. samples1 is used to pick outgoing directions and
+    // samples2 to pick incident directions.
     fn rho_hh(&self, samples1: &[f32], samples2: &[f32]) -> Spectrum {
-        unimplemented!()
+        estimate_rho_hh(self, samples1, samples2)
+    }
+}
+
+// Monte Carlo estimates of rho_hd and rho_hh using the BxDF's sampling
+// routines. These are what BxDFs without a closed form use.
+pub fn estimate_rho_hd<T: BxDF + ?Sized>(bxdf: &T, w: &Vector, samples: &[f32]) -> Spectrum {
+    let num_samples = samples.len() / 2;
+    if num_samples == 0 {
+        return Spectrum::from(0.0);
+    }
+
+    let r = samples.chunks(2).fold(Spectrum::from(0.0), |r, s| {
+        let (wi, pdf, f) = bxdf.sample_f(w, s[0], s[1]);
+        if pdf > 0.0 { r + f * abs_cos_theta(&wi) / pdf } else { r }
+    });
+
+    r / (num_samples as f32)
+}
+
+pub fn estimate_rho_hh<T: BxDF + ?Sized>(bxdf: &T, samples1: &[f32],
+                                         samples2: &[f32]) -> Spectrum {
+    assert_eq!(samples1.len(), samples2.len());
+    let num_samples = samples1.len() / 2;
+    if num_samples == 0 {
+        return Spectrum::from(0.0);
+    }
+
+    let pdf_o = uniform_hemisphere_pdf();
+    let r = samples1.chunks(2).zip(samples2.chunks(2)).fold(Spectrum::from(0.0), |r, (s1, s2)| {
+        let wo = uniform_sample_hemisphere(s1[0], s1[1]);
+        let (wi, pdf_i, f) = bxdf.sample_f(&wo, s2[0], s2[1]);
+        if pdf_i > 0.0 {
+            r + f * abs_cos_theta(&wi) * abs_cos_theta(&wo) / (pdf_o * pdf_i)
+        } else {
+            r
+        }
+    });
+
+    r / (::std::f32::consts::PI * (num_samples as f32))
+}
+
+// Sample offsets into a Sample for use with BSDFSample::from_sample,
+// requested by integrators ahead of time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BSDFSampleOffsets {
+    pub num_samples: usize,
+    pub component_offset: usize,
+    pub dir_offset: usize
+}
+
+impl BSDFSampleOffsets {
+    pub fn new(count: usize, sample: &mut Sample) -> BSDFSampleOffsets {
+        BSDFSampleOffsets {
+            num_samples: count,
+            component_offset: sample.add_1d(count),
+            dir_offset: sample.add_2d(count)
+        }
     }
 }
 
-pub struct BSDFSample;
+#[derive(Debug, Clone, PartialEq)]
+pub struct BSDFSample {
+    pub u_dir: [f32; 2],
+    pub u_component: f32
+}
+
 impl BSDFSample {
-    pub fn new(rng: &mut RNG) -> BSDFSample { BSDFSample }
+    pub fn new(rng: &mut RNG) -> BSDFSample {
+        let u_dir = [rng.random_float(), rng.random_float()];
+        BSDFSample::new_with(u_dir[0], u_dir[1], rng.random_float())
+    }
+
+    pub fn new_with(up0: f32, up1: f32, ucomp: f32) -> BSDFSample {
+        assert!(up0 >= 0.0 && up0 < 1.0);
+        assert!(up1 >= 0.0 && up1 < 1.0);
+        assert!(ucomp >= 0.0 && ucomp < 1.0);
+        BSDFSample {
+            u_dir: [up0, up1],
+            u_component: ucomp
+        }
+    }
+
+    // Uses the n-th set of values that were requested with offsets
+    pub fn from_sample(sample: &Sample, offsets: &BSDFSampleOffsets,
+                       n: usize) -> BSDFSample {
+        assert!(n < offsets.num_samples);
+        let dir = sample.offset_2d[offsets.dir_offset] + 2 * n;
+        let comp = sample.offset_1d[offsets.component_offset] + n;
+        BSDFSample::new_with(sample.samples[dir], sample.samples[dir + 1],
+                             sample.samples[comp])
+    }
 }
 
 #[derive(Debug)]
@@ -96,13 +228,7 @@ impl BSDF {
 
     pub fn num_components(&self) -> usize { self.bxdfs.len() }
     pub fn num_components_matching(&self, flags: BxDFType) -> usize {
-        self.bxdfs.iter().fold(0, |acc, bxdf| {
-            if bxdf.matches_flags(flags) {
-                acc + 1
-            } else {
-                acc
-            }
-        })
+        self.bxdfs.iter().filter(|bxdf| flags.contains(bxdf.get_type())).count()
     }
 
     pub fn world_to_local(&self, v: Vector) -> Vector {
@@ -130,18 +256,21 @@ impl BSDF {
         ret
     }
 
-    pub fn f(&self, wo_w: Vector, wi_w: Vector, in_flags: BxDFType) -> Spectrum {
-        let flags = if wi_w.dot(&self.ng) * wo_w.dot(&self.ng) > 0.0 {
-            in_flags & !BSDF_TRANSMISSION
+    pub fn f(&self, wo_w: Vector, wi_w: Vector, flags: BxDFType) -> Spectrum {
+        // Only consider the components that scatter into the hemisphere
+        // of wi, some BxDFs do both reflection and transmission
+        let hemisphere = if wi_w.dot(&self.ng) * wo_w.dot(&self.ng) > 0.0 {
+            BSDF_REFLECTION
         } else {
-            in_flags & !BSDF_REFLECTION
+            BSDF_TRANSMISSION
         };
 
         let wo = self.world_to_local(wo_w);
         let wi = self.world_to_local(wi_w);
 
         self.bxdfs.iter().fold(Spectrum::from(0.0), |f, bxdf| {
-            if bxdf.matches_flags(flags) {
+            let ty = bxdf.get_type();
+            if flags.contains(ty) && ty.intersects(hemisphere) {
                 f + bxdf.f(&wo, &wi)
             } else {
                 f
@@ -149,9 +278,58 @@ impl BSDF {
         })
     }
 
-    pub fn sample_f(&self, vo: &Vector, sample: BSDFSample,
-                    bxdf_type: BxDFType) -> (Vector, f32, Spectrum) {
-        unimplemented!()
+    // Samples one of the components that match flags uniformly, and returns
+    // the sampled direction, its pdf with respect to all of the matching
+    // components, the value of the BSDF and the type of the sampled component.
+    pub fn sample_f(&self, wo_w: &Vector, sample: BSDFSample,
+                    flags: BxDFType) -> (Vector, f32, Spectrum, BxDFType) {
+        let matching: Vec<&Box<BxDF>> = self.bxdfs.iter()
+            .filter(|bxdf| flags.contains(bxdf.get_type()))
+            .collect();
+
+        let num_matching = matching.len();
+        if num_matching == 0 {
+            return (Vector::new(), 0.0, Spectrum::from(0.0), BxDFType::empty());
+        }
+
+        let which = ((sample.u_component * (num_matching as f32)).floor() as usize)
+            .min(num_matching - 1);
+        let bxdf = matching[which];
+        let ty = bxdf.get_type();
+
+        // Sample chosen BxDF
+        let wo = self.world_to_local(wo_w.clone());
+        let (wi, pdf, f) = bxdf.sample_f(&wo, sample.u_dir[0], sample.u_dir[1]);
+        if pdf == 0.0 {
+            return (Vector::new(), 0.0, Spectrum::from(0.0), ty);
+        }
+
+        let wi_w = self.local_to_world(wi.clone());
+
+        // Specular components are delta distributions, so the
+        // other components couldn't have sampled wi.
+        if ty.contains(BSDF_SPECULAR) {
+            return (wi_w, pdf / (num_matching as f32), f, ty);
+        }
+
+        // Compute overall PDF with all matching BxDFs
+        let total_pdf = matching.iter().enumerate().fold(pdf, |acc, (i, other)| {
+            if i == which { acc } else { acc + other.pdf(&wo, &wi) }
+        }) / (num_matching as f32);
+
+        let f = self.f(wo_w.clone(), wi_w.clone(), flags);
+        (wi_w, total_pdf, f, ty)
+    }
+
+    pub fn pdf(&self, wo_w: Vector, wi_w: Vector, flags: BxDFType) -> f32 {
+        let wo = self.world_to_local(wo_w);
+        let wi = self.world_to_local(wi_w);
+
+        let (num_matching, pdf) = self.bxdfs.iter()
+            .filter(|bxdf| flags.contains(bxdf.get_type()))
+            .fold((0, 0.0), |(n, pdf), bxdf| (n + 1, pdf + bxdf.pdf(&wo, &wi)));
+
+        if num_matching > 0 { pdf / (num_matching as f32) } else { 0.0 }
     }
 }
 
@@ -170,14 +348,25 @@ fn other_hemi(v: &Vector) -> Vector {
 
 impl<T: BxDF> BxDF for BRDFtoBTDF<T> {
     fn matches_flags(&self, ty: BxDFType) -> bool {
-        let flags = BSDF_REFLECTION | BSDF_TRANSMISSION;
-        self.brdf.matches_flags(ty ^ flags)
+        self.get_type().contains(ty)
+    }
+
+    fn get_type(&self) -> BxDFType {
+        // Swap reflection for transmission and vice versa, BxDFs
+        // that already do both stay the same
+        let ty = self.brdf.get_type();
+        let hemi = BSDF_REFLECTION | BSDF_TRANSMISSION;
+        if ty.intersects(hemi) && !ty.contains(hemi) { ty ^ hemi } else { ty }
     }
 
     fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
         self.brdf.f(wo, &other_hemi(wi))
     }
 
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        self.brdf.pdf(wo, &other_hemi(wi))
+    }
+
     fn sample_f(&self, wo: &Vector, u1: f32,
                 u2: f32) -> (Vector, f32, Spectrum) {
         let (wi, pdf, v) = self.brdf.sample_f(wo, u1, u2);
@@ -213,10 +402,16 @@ impl BxDF for ScaledBxDF {
         self.bxdf.matches_flags(ty)
     }
 
+    fn get_type(&self) -> BxDFType { self.bxdf.get_type() }
+
     fn f(&self, wo: &Vector, wi: &Vector) -> Spectrum {
         self.bxdf.f(wo, wi) * self.scale
     }
 
+    fn pdf(&self, wo: &Vector, wi: &Vector) -> f32 {
+        self.bxdf.pdf(wo, wi)
+    }
+
     fn sample_f(&self, wo: &Vector, u1: f32,
                 u2: f32) -> (Vector, f32, Spectrum) {
         let (wi, pdf, v) = self.bxdf.sample_f(wo, u1, u2);
@@ -231,3 +426,109 @@ impl BxDF for ScaledBxDF {
         self.bxdf.rho_hh(samples1, samples2) * self.scale
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::fresnel::Fresnel;
+    use bsdf::lambertian::Lambertian;
+    use bsdf::specular::SpecularReflection;
+    use diff_geom::DifferentialGeometry;
+    use geometry::normal::Normal;
+    use geometry::normal::Normalize;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use rng::RNG;
+    use sampler::sample::Sample;
+    use spectrum::Spectrum;
+
+    fn flat_bsdf() -> BSDF {
+        let dg = DifferentialGeometry::new_with(
+            Point::new(), Vector::new_with(1.0, 0.0, 0.0), Vector::new_with(0.0, 1.0, 0.0),
+            Normal::new(), Normal::new(), 0.0, 0.0, None);
+        let ng = dg.nn.clone();
+
+        let mut bsdf = BSDF::new(dg, ng);
+        bsdf.add_bxdf(Lambertian::new(Spectrum::from(0.5)));
+        bsdf.add_bxdf(SpecularReflection::new(Spectrum::from(1.0), Fresnel::noop()));
+        bsdf
+    }
+
+    #[test]
+    fn bxdfs_know_their_type() {
+        let brdf = Lambertian::new(Spectrum::from(0.5));
+        assert_eq!(brdf.get_type(), BSDF_REFLECTION | BSDF_DIFFUSE);
+
+        let btdf = BRDFtoBTDF::new(Lambertian::new(Spectrum::from(0.5)));
+        assert_eq!(btdf.get_type(), BSDF_TRANSMISSION | BSDF_DIFFUSE);
+        assert!(btdf.matches_flags(BSDF_TRANSMISSION));
+        assert!(!btdf.matches_flags(BSDF_REFLECTION));
+    }
+
+    #[test]
+    fn it_only_uses_matching_components() {
+        let bsdf = flat_bsdf();
+        assert_eq!(bsdf.num_components_matching(BSDF_ALL), 2);
+        assert_eq!(bsdf.num_components_matching(BSDF_ALL_REFLECTION), 2);
+        assert_eq!(bsdf.num_components_matching(BSDF_REFLECTION | BSDF_DIFFUSE), 1);
+        assert_eq!(bsdf.num_components_matching(BSDF_ALL_TRANSMISSION), 0);
+
+        let wo = Vector::new_with(0.0, 0.6, 0.8);
+        let wi = Vector::new_with(0.6, 0.0, 0.8);
+        let f = bsdf.f(wo.clone(), wi.clone(), BSDF_ALL);
+        assert!((f[0] - 0.5 / ::std::f32::consts::PI).abs() < 1e-6);
+        assert!(bsdf.f(wo, wi, BSDF_ALL_TRANSMISSION).is_black());
+    }
+
+    #[test]
+    fn it_samples_components_uniformly() {
+        let bsdf = flat_bsdf();
+        let wo = Vector::new_with(0.0, 0.6, 0.8);
+
+        // Diffuse component, which needs to account for the
+        // specular component when computing its pdf
+        let (wi, pdf, f, ty) = bsdf.sample_f(&wo, BSDFSample::new_with(0.3, 0.7, 0.1), BSDF_ALL);
+        assert_eq!(ty, BSDF_REFLECTION | BSDF_DIFFUSE);
+        assert!(wi.z > 0.0);
+        assert!((pdf - 0.5 * wi.z / ::std::f32::consts::PI).abs() < 1e-5);
+        assert!((pdf - bsdf.pdf(wo.clone(), wi.clone(), BSDF_ALL)).abs() < 1e-5);
+        assert!((f[0] - 0.5 / ::std::f32::consts::PI).abs() < 1e-6);
+
+        // Specular component
+        let (wi, pdf, _, ty) = bsdf.sample_f(&wo, BSDFSample::new_with(0.3, 0.7, 0.9), BSDF_ALL);
+        assert_eq!(ty, BSDF_REFLECTION | BSDF_SPECULAR);
+        assert!((wi.y + 0.6).abs() < 1e-6 && (wi.z - 0.8).abs() < 1e-6);
+        assert_eq!(pdf, 0.5);
+
+        // Only one component matches, so no need to split the pdf
+        let flags = BSDF_REFLECTION | BSDF_DIFFUSE;
+        let (wi, pdf, _, _) = bsdf.sample_f(&wo, BSDFSample::new_with(0.3, 0.7, 0.9), flags);
+        assert!((pdf - wi.z / ::std::f32::consts::PI).abs() < 1e-5);
+
+        let (_, pdf, f, ty) = bsdf.sample_f(&wo, BSDFSample::new_with(0.3, 0.7, 0.9),
+                                            BSDF_ALL_TRANSMISSION);
+        assert_eq!(pdf, 0.0);
+        assert!(f.is_black());
+        assert!(ty.is_empty());
+    }
+
+    #[test]
+    fn it_can_be_sampled_from_samples_and_rngs() {
+        let mut sample = Sample::empty();
+        let offsets = BSDFSampleOffsets::new(2, &mut sample);
+
+        // Lay the 2D samples out after the 1D samples like Sample::new does
+        sample.offset_2d[offsets.dir_offset] = 2;
+        sample.samples = vec![0.25, 0.75, 0.1, 0.2, 0.3, 0.4];
+
+        let s = BSDFSample::from_sample(&sample, &offsets, 1);
+        assert_eq!(s.u_component, 0.75);
+        assert_eq!(s.u_dir, [0.3, 0.4]);
+
+        let mut rng = RNG::new(0);
+        for _ in 0..100 {
+            let s = BSDFSample::new(&mut rng);
+            assert!(s.u_component >= 0.0 && s.u_component < 1.0);
+        }
+    }
+}
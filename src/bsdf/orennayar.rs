@@ -58,8 +58,4 @@ impl BxDF for OrenNayar {
         let invpi = 1.0 / ::std::f32::consts::PI;
         self.r * invpi * (self.a + self.b * maxcos * sinalpha * tanbeta)
     }
-
-    fn sample_f(&self, _: &Vector, _: f32, _: f32) -> (Vector, f32, Spectrum) {
-        unimplemented!()
-    }
 }
@@ -12,7 +12,7 @@ pub struct SpecularReflection {
 }
 
 impl SpecularReflection {
-    fn new(_r: Spectrum, _f: Fresnel) -> SpecularReflection {
+    pub fn new(_r: Spectrum, _f: Fresnel) -> SpecularReflection {
         SpecularReflection {
             r: _r,
             fresnel: _f
@@ -31,6 +31,8 @@ impl BxDF for SpecularReflection {
         Spectrum::from(0f32)
     }
 
+    fn pdf(&self, _: &Vector, _: &Vector) -> f32 { 0.0 }
+
     fn sample_f(&self, wo: &Vector, u1: f32,
                 u2: f32) -> (Vector, f32, Spectrum) {
         // Compute perfect specular reflection direction
@@ -49,7 +51,7 @@ pub struct SpecularTransmission {
 }
 
 impl SpecularTransmission {
-    fn new(_t: Spectrum, _etai: f32, _etat: f32) -> SpecularTransmission {
+    pub fn new(_t: Spectrum, _etai: f32, _etat: f32) -> SpecularTransmission {
         SpecularTransmission {
             t: _t,
             etai: _etai,
@@ -69,6 +71,8 @@ impl BxDF for SpecularTransmission {
         Spectrum::from(0f32)
     }
 
+    fn pdf(&self, _: &Vector, _: &Vector) -> f32 { 0.0 }
+
     fn sample_f(&self, wo: &Vector, u1: f32,
                 u2: f32) -> (Vector, f32, Spectrum) {
         let ct = cos_theta(&wo);
@@ -98,11 +102,81 @@ impl BxDF for SpecularTransmission {
 
         let pdf = 1f32;
         let f = self.fresnel.evaluate(ct);
-        let v = (et * et) / (ei * ei) * (Spectrum::from(1f32) - self.t);
+        let v = (et * et) / (ei * ei) * (Spectrum::from(1f32) - f) * self.t;
         (wi.clone(), pdf, v / abs_cos_theta(&wi))
     }
 }
 
+// A thin slab of dielectric, like a window pane, where the light that is
+// refracted into the slab bounces between both sides before leaving. The
+// slab is thin enough that every path leaves at the point it entered, so
+// the internal reflections can be summed up as a geometric series.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThinDielectric {
+    r: Spectrum,
+    t: Spectrum,
+    fresnel: Fresnel
+}
+
+impl ThinDielectric {
+    pub fn new(r: Spectrum, t: Spectrum, eta: f32) -> ThinDielectric {
+        ThinDielectric {
+            r: r,
+            t: t,
+            fresnel: Fresnel::dielectric(1.0, eta)
+        }
+    }
+
+    // Total reflectance and transmittance of the slab
+    fn reflect_transmit(&self, wo: &Vector) -> (f32, f32) {
+        // Both sides of the slab look the same from outside
+        let r = self.fresnel.evaluate(abs_cos_theta(wo))[0];
+        if r < 1.0 {
+            // R + T^2 R + T^2 R^3 + ... where T = 1 - R
+            let t = 1.0 - r;
+            let total_r = r + t * t * r / (1.0 - r * r);
+            (total_r, 1.0 - total_r)
+        } else {
+            (1.0, 0.0)
+        }
+    }
+}
+
+impl BxDF for ThinDielectric {
+    fn matches_flags(&self, ty: bsdf::BxDFType) -> bool {
+        (bsdf::BSDF_REFLECTION | bsdf::BSDF_TRANSMISSION | bsdf::BSDF_SPECULAR).contains(ty)
+    }
+
+    fn f(&self, _: &Vector, _: &Vector) -> Spectrum {
+        Spectrum::from(0f32)
+    }
+
+    fn pdf(&self, _: &Vector, _: &Vector) -> f32 { 0.0 }
+
+    fn sample_f(&self, wo: &Vector, u1: f32,
+                _: f32) -> (Vector, f32, Spectrum) {
+        let (r, t) = self.reflect_transmit(wo);
+        let pr = r * self.r.y();
+        let pt = t * self.t.y();
+        if pr + pt == 0.0 {
+            return (Vector::new(), 0.0, Spectrum::from(0f32));
+        }
+
+        // Choose between reflection and transmission proportionally
+        // to the amount of light carried by each
+        if u1 < pr / (pr + pt) {
+            let wi = Vector::new_with(-wo.x, -wo.y, wo.z);
+            let f = self.r * r / abs_cos_theta(&wi);
+            (wi, pr / (pr + pt), f)
+        } else {
+            // The slab doesn't bend light passing straight through
+            let wi = -wo;
+            let f = self.t * t / abs_cos_theta(&wi);
+            (wi, pt / (pr + pt), f)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -210,4 +284,29 @@ mod tests {
                                         0.0, 0.0);
         assert!((wi4 - Vector::new_with(-0.5, 0.0, 3f32.sqrt() / 2.0)).length_squared() < 1e-6);
     }
+
+    #[test]
+    fn thin_dielectric_sums_internal_reflections() {
+        let slab = ThinDielectric::new(Spectrum::from(1f32), Spectrum::from(1f32), 1.5);
+        assert!(slab.matches_flags(bsdf::BSDF_REFLECTION));
+        assert!(slab.matches_flags(bsdf::BSDF_TRANSMISSION));
+        assert!(slab.matches_flags(bsdf::BSDF_SPECULAR));
+        assert!(!slab.matches_flags(bsdf::BSDF_GLOSSY));
+
+        // A single interface of glass reflects 4% at normal incidence,
+        // and the slab reflects ~7.7% by bouncing between both sides
+        let wo = Vector::new_with(0.0, 0.0, 1.0);
+        let (r, t) = slab.reflect_transmit(&wo);
+        assert!((r - 0.08 / 1.04).abs() < 1e-4);
+        assert!((r + t - 1.0).abs() < 1e-6);
+
+        let (wi, pdf, f) = slab.sample_f(&wo, 0.0, 0.5);
+        assert_eq!(wi, Vector::new_with(0.0, 0.0, 1.0));
+        assert!((f[0] * abs_cos_theta(&wi) / pdf - 1.0).abs() < 1e-4);
+
+        let wo = Vector::new_with(0.6, 0.0, 0.8);
+        let (wi, pdf, f) = slab.sample_f(&wo, 0.99, 0.5);
+        assert_eq!(wi, Vector::new_with(-0.6, 0.0, -0.8));
+        assert!((f[0] * abs_cos_theta(&wi) / pdf - 1.0).abs() < 1e-4);
+    }
 }
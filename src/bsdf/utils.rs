@@ -27,3 +27,31 @@ pub fn sin_phi(v: &Vector) -> f32 {
         (vy / sintheta).clamp(-1.0, 1.0)
     }
 }
+
+pub fn tan_theta2(v: &Vector) -> f32 { sin_theta2(v) / (v.z * v.z) }
+
+pub fn same_hemisphere(w: &Vector, wp: &Vector) -> bool { w.z * wp.z > 0.0 }
+
+pub fn reflect(wo: &Vector, n: &Vector) -> Vector {
+    let d = 2.0 * (wo.x * n.x + wo.y * n.y + wo.z * n.z);
+    Vector::new_with(d * n.x - wo.x, d * n.y - wo.y, d * n.z - wo.z)
+}
+
+// Refracts wi about n where eta is the ratio of the index of refraction
+// on wi's side to the one on the other side. Returns None on total
+// internal reflection.
+pub fn refract(wi: &Vector, n: &Vector, eta: f32) -> Option<Vector> {
+    let cos_theta_i = wi.x * n.x + wi.y * n.y + wi.z * n.z;
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi * eta + n * (eta * cos_theta_i - cos_theta_t))
+}
+
+pub fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
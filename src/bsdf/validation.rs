@@ -0,0 +1,424 @@
+use bsdf::BxDF;
+use geometry::vector::Vector;
+use geometry::vector::spherical_direction;
+use geometry::vector::spherical_phi;
+use geometry::vector::spherical_theta;
+use montecarlo::uniform_sample_hemisphere;
+use rng::RNG;
+use spectrum::Spectrum;
+
+// Numerical checks that BxDFs should pass in order to be used for
+// rendering: that they don't create energy, that they satisfy Helmholtz
+// reciprocity, and that their sampling routines actually generate
+// directions with the density that they report.
+
+// Estimates the albedo of the BxDF for light leaving in direction wo
+pub fn albedo<T: BxDF + ?Sized>(bxdf: &T, wo: &Vector, num_samples: usize,
+                                rng: &mut RNG) -> Spectrum {
+    let samples: Vec<f32> = (0..(2 * num_samples)).map(|_| rng.random_float()).collect();
+    bxdf.rho_hd(wo, &samples)
+}
+
+// Largest relative difference between f(wo, wi) and f(wi, wo) across
+// all channels.
+pub fn reciprocity_error<T: BxDF + ?Sized>(bxdf: &T, wo: &Vector, wi: &Vector) -> f32 {
+    let a = bxdf.f(wo, wi).to_rgb();
+    let b = bxdf.f(wi, wo).to_rgb();
+    (0..3).fold(0.0, |err, i| {
+        let scale = a[i].abs().max(b[i].abs());
+        if scale == 0.0 { err } else { err.max((a[i] - b[i]).abs() / scale) }
+    })
+}
+
+// Integrates the pdf of the BxDF over bin (i, j) of a theta_res x phi_res
+// table covering the whole sphere, using the midpoint rule with n_theta x
+// n_phi points.
+fn integrate_bin<T: BxDF + ?Sized>(bxdf: &T, wo: &Vector, theta_res: usize, phi_res: usize,
+                                   i: usize, j: usize, n_theta: usize, n_phi: usize) -> f64 {
+    let theta_step = ::std::f32::consts::PI / (theta_res as f32);
+    let phi_step = 2.0 * ::std::f32::consts::PI / (phi_res as f32);
+    let sub_theta = theta_step / (n_theta as f32);
+    let sub_phi = phi_step / (n_phi as f32);
+
+    let mut sum = 0f64;
+    for si in 0..n_theta {
+        for sj in 0..n_phi {
+            let theta = (i as f32) * theta_step + ((si as f32) + 0.5) * sub_theta;
+            let phi = (j as f32) * phi_step + ((sj as f32) + 0.5) * sub_phi;
+            let wi = spherical_direction(theta.sin(), theta.cos(), phi);
+            sum += (bxdf.pdf(wo, &wi) * theta.sin()) as f64;
+        }
+    }
+
+    sum * ((sub_theta * sub_phi) as f64)
+}
+
+// Integrates the pdf of the BxDF over each bin in a theta_res x phi_res
+// table covering the whole sphere. The result is scaled to the number of
+// samples that are expected to land in each bin.
+fn expected_frequencies<T: BxDF + ?Sized>(bxdf: &T, wo: &Vector, theta_res: usize,
+                                          phi_res: usize, sample_count: usize) -> Vec<f64> {
+    const SUBDIVISIONS: usize = 8;
+    const MAX_SUBDIVISIONS: usize = 128;
+    const EDGE_THETA_SUBDIVISIONS: usize = 256;
+    const EDGE_PHI_SUBDIVISIONS: usize = 32;
+
+    // Sharply peaked pdfs need many more points than smooth ones, so the
+    // number of points is doubled until the expected number of samples
+    // settles down to well within the noise of the test.
+    let count = sample_count as f64;
+    let mut table = vec![0f64; theta_res * phi_res];
+    for i in 0..theta_res {
+        for j in 0..phi_res {
+            let mut n = SUBDIVISIONS;
+            let mut v = integrate_bin(bxdf, wo, theta_res, phi_res, i, j, n, n);
+            while n < MAX_SUBDIVISIONS {
+                n *= 2;
+                let finer = integrate_bin(bxdf, wo, theta_res, phi_res, i, j, n, n);
+                let converged = (finer - v).abs() * count < 0.1 * (finer * count).max(1.0).sqrt();
+                v = finer;
+                if converged {
+                    break;
+                }
+            }
+
+            table[i * phi_res + j] = v;
+        }
+    }
+
+    // Where the support of the pdf ends with a hard edge, as it does for
+    // refraction through microfacets, a thin sliver of it can fall between
+    // the points of the coarse rule. Bins that look empty next to ones that
+    // aren't get integrated again much more finely in theta, which is the
+    // direction that such edges mostly run across, so that samples landing
+    // in such a sliver aren't taken for samples where the pdf is zero.
+    let coarse = table.clone();
+    for i in 0..theta_res {
+        for j in 0..phi_res {
+            if coarse[i * phi_res + j] != 0.0 {
+                continue;
+            }
+
+            let mut neighbors = vec![(i, (j + 1) % phi_res), (i, (j + phi_res - 1) % phi_res)];
+            if i > 0 {
+                neighbors.push((i - 1, j));
+            }
+            if i + 1 < theta_res {
+                neighbors.push((i + 1, j));
+            }
+
+            if neighbors.iter().any(|&(ni, nj)| coarse[ni * phi_res + nj] != 0.0) {
+                table[i * phi_res + j] =
+                    integrate_bin(bxdf, wo, theta_res, phi_res, i, j,
+                                  EDGE_THETA_SUBDIVISIONS, EDGE_PHI_SUBDIVISIONS);
+            }
+        }
+    }
+
+    table.iter().map(|&v| v * (sample_count as f64)).collect()
+}
+
+fn observed_frequencies<T: BxDF + ?Sized>(bxdf: &T, wo: &Vector, theta_res: usize,
+                                          phi_res: usize, sample_count: usize,
+                                          rng: &mut RNG) -> Vec<f64> {
+    let mut table = vec![0f64; theta_res * phi_res];
+    for _ in 0..sample_count {
+        let (wi, pdf, _) = bxdf.sample_f(wo, rng.random_float(), rng.random_float());
+        if pdf == 0.0 {
+            continue;
+        }
+
+        let (theta, phi) = (spherical_theta(&wi), spherical_phi(&wi));
+        let i = ((theta / ::std::f32::consts::PI * (theta_res as f32)) as usize)
+            .min(theta_res - 1);
+        let j = ((phi / (2.0 * ::std::f32::consts::PI) * (phi_res as f32)) as usize)
+            .min(phi_res - 1);
+        table[i * phi_res + j] += 1.0;
+    }
+
+    table
+}
+
+// ln(Gamma(x)) using the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    let coeffs = [76.18009172947146, -86.50532032941677, 24.01409824083091,
+                  -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let ser = coeffs.iter().enumerate().fold(1.000000000190015, |acc, (i, c)| {
+        acc + c / (x + 1.0 + (i as f64))
+    });
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+// Regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+
+    if x < a + 1.0 {
+        // Series representation of P(a, x)
+        let mut ap = a;
+        let mut del = 1.0 / a;
+        let mut sum = del;
+        for _ in 0..1000 {
+            ap += 1.0;
+            del *= x / ap;
+            sum += del;
+            if del.abs() < sum.abs() * 1e-12 {
+                break;
+            }
+        }
+        1.0 - sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+    } else {
+        // Continued fraction representation of Q(a, x)
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * ((i as f64) - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny { d = tiny; }
+            c = b + an / c;
+            if c.abs() < tiny { c = tiny; }
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+            if (del - 1.0).abs() < 1e-12 {
+                break;
+            }
+        }
+        (-x + a * x.ln() - ln_gamma(a)).exp() * h
+    }
+}
+
+// Pearson's chi-square test of observed against expected frequencies.
+// Bins with too few expected samples for the test to be meaningful are
+// pooled together. Returns the p-value of the test.
+pub fn chi_square(observed: &[f64], expected: &[f64], min_expected: f64) -> f64 {
+    assert_eq!(observed.len(), expected.len());
+
+    let mut order: Vec<usize> = (0..expected.len()).collect();
+    order.sort_by(|&a, &b| expected[a].partial_cmp(&expected[b]).unwrap());
+
+    let mut pooled_obs = 0.0;
+    let mut pooled_exp = 0.0;
+    let mut chsq = 0.0;
+    let mut num_bins = 0;
+    for &i in order.iter() {
+        if expected[i] == 0.0 {
+            if observed[i] > 0.0 {
+                // Samples where the pdf says they can't be
+                return 0.0;
+            }
+        } else if expected[i] < min_expected {
+            pooled_obs += observed[i];
+            pooled_exp += expected[i];
+        } else if pooled_exp > 0.0 && pooled_exp < min_expected {
+            // Not enough pooled to make a bin on its own, so
+            // merge it into this one instead
+            let (o, e) = (observed[i] + pooled_obs, expected[i] + pooled_exp);
+            chsq += (o - e) * (o - e) / e;
+            pooled_obs = 0.0;
+            pooled_exp = 0.0;
+            num_bins += 1;
+        } else {
+            chsq += (observed[i] - expected[i]) * (observed[i] - expected[i]) / expected[i];
+            num_bins += 1;
+        }
+    }
+
+    if pooled_exp >= min_expected {
+        chsq += (pooled_obs - pooled_exp) * (pooled_obs - pooled_exp) / pooled_exp;
+        num_bins += 1;
+    }
+
+    if num_bins < 2 {
+        return 1.0;
+    }
+
+    let dof = (num_bins - 1) as f64;
+    gamma_q(dof / 2.0, chsq / 2.0)
+}
+
+// Runs a chi-square goodness of fit test of the directions generated by
+// sample_f against the BxDF's pdf for the outgoing direction wo.
+pub fn chi_square_test<T: BxDF + ?Sized>(bxdf: &T, wo: &Vector, theta_res: usize,
+                                         phi_res: usize, sample_count: usize,
+                                         rng: &mut RNG) -> f64 {
+    let expected = expected_frequencies(bxdf, wo, theta_res, phi_res, sample_count);
+    let observed = observed_frequencies(bxdf, wo, theta_res, phi_res, sample_count, rng);
+    chi_square(&observed, &expected, 5.0)
+}
+
+// Random direction in the upper hemisphere that isn't too close to the horizon
+pub fn random_direction(rng: &mut RNG) -> Vector {
+    let w = uniform_sample_hemisphere(rng.random_float(), rng.random_float());
+    let z = w.z.max(0.05);
+    let r = (1.0 - z * z).sqrt() / (w.x * w.x + w.y * w.y).sqrt().max(1e-6);
+    Vector::new_with(w.x * r, w.y * r, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::BxDF;
+    use bsdf::estimate_rho_hd;
+    use bsdf::estimate_rho_hh;
+    use bsdf::fresnel::Fresnel;
+    use bsdf::lambertian::Lambertian;
+    use bsdf::measured::IrregIsotropic;
+    use bsdf::measured::IrregIsotropicSample;
+    use bsdf::measured::RegularHalfangle;
+    use bsdf::measured::brdf_remap;
+    use bsdf::microfacet::FresnelBlend;
+    use bsdf::microfacet::Microfacet;
+    use bsdf::microfacet::MicrofacetDistribution;
+    use bsdf::orennayar::OrenNayar;
+    use bsdf::specular::SpecularReflection;
+    use geometry::vector::Vector;
+    use rng::RNG;
+    use spectrum::Spectrum;
+    use utils::kdtree::KdTree;
+
+    use std::sync::Arc;
+
+    fn irregular_isotropic() -> IrregIsotropic {
+        let mut rng = RNG::new(1);
+        let samples: Vec<IrregIsotropicSample> = (0..2000).map(|_| {
+            let wo = uniform_sample_hemisphere(rng.random_float(), rng.random_float());
+            let wi = uniform_sample_hemisphere(rng.random_float(), rng.random_float());
+            IrregIsotropicSample::new(&brdf_remap(&wo, &wi), &Spectrum::from(0.1))
+        }).collect();
+        IrregIsotropic::new(Arc::new(KdTree::new(&samples)))
+    }
+
+    fn test_bxdfs() -> Vec<(&'static str, Box<BxDF>)> {
+        let r = Spectrum::from_rgb([0.9, 0.5, 0.1]);
+        vec![
+            ("lambertian", Box::new(Lambertian::new(r))),
+            ("oren-nayar", Box::new(OrenNayar::new(r, 20.0))),
+            ("microfacet", Box::new(Microfacet::new(
+                Spectrum::from(1.0), Fresnel::noop(),
+                MicrofacetDistribution::trowbridge_reitz(0.5, 0.5)))),
+            ("anisotropic microfacet", Box::new(Microfacet::new(
+                r, Fresnel::dielectric(1.0, 1.5),
+                MicrofacetDistribution::trowbridge_reitz(0.3, 0.7)))),
+            ("fresnel blend", Box::new(FresnelBlend::new(
+                r * 0.5, Spectrum::from(0.05), MicrofacetDistribution::blinn(10.0)))),
+            ("specular reflection", Box::new(SpecularReflection::new(
+                r, Fresnel::dielectric(1.0, 1.5)))),
+            ("regular halfangle", Box::new(RegularHalfangle::new(
+                2, 2, 2, Arc::new(vec![0.2; 24])))),
+            ("irregular isotropic", Box::new(irregular_isotropic()))]
+    }
+
+    #[test]
+    fn lambertian_estimates_match_closed_form() {
+        let r = Spectrum::from_rgb([0.9, 0.5, 0.1]);
+        let brdf = Lambertian::new(r);
+        let mut rng = RNG::new(0);
+
+        let samples1: Vec<f32> = (0..8192).map(|_| rng.random_float()).collect();
+        let samples2: Vec<f32> = (0..8192).map(|_| rng.random_float()).collect();
+        let wo = Vector::new_with(0.0, 0.6, 0.8);
+
+        let hd = estimate_rho_hd(&brdf, &wo, &samples1).to_rgb();
+        let hh = estimate_rho_hh(&brdf, &samples1, &samples2).to_rgb();
+        let exact = brdf.rho_hd(&wo, &samples1).to_rgb();
+        assert_eq!(brdf.rho_hh(&samples1, &samples2), r);
+        for i in 0..3 {
+            // Cosine sampling the lambertian is a perfect importance sampler
+            assert!((hd[i] - exact[i]).abs() < 1e-4);
+            assert!((hh[i] - exact[i]).abs() < 0.02 * exact[i]);
+        }
+    }
+
+    #[test]
+    fn bxdfs_conserve_energy() {
+        let mut rng = RNG::new(2);
+        for &(name, ref bxdf) in test_bxdfs().iter() {
+            for _ in 0..8 {
+                let wo = random_direction(&mut rng);
+                let a = albedo(bxdf.as_ref(), &wo, 4096, &mut rng);
+                assert!(!a.has_nans(), "{} has NaN albedo", name);
+                for c in a.to_rgb().iter() {
+                    assert!(*c <= 1.01, "{} reflects more than it receives: {}", name, c);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bxdfs_are_reciprocal() {
+        let mut rng = RNG::new(3);
+        for &(name, ref bxdf) in test_bxdfs().iter() {
+            for _ in 0..64 {
+                let wo = random_direction(&mut rng);
+                let wi = random_direction(&mut rng);
+                let err = reciprocity_error(bxdf.as_ref(), &wo, &wi);
+                assert!(err < 1e-3, "{} isn't reciprocal: {}", name, err);
+            }
+        }
+    }
+
+    #[test]
+    fn sampling_matches_pdf() {
+        let mut rng = RNG::new(4);
+        for &(name, ref bxdf) in test_bxdfs().iter() {
+            // Delta distributions have no pdf to compare against
+            if bxdf.get_type().contains(::bsdf::BSDF_SPECULAR) {
+                continue;
+            }
+
+            for _ in 0..2 {
+                let wo = random_direction(&mut rng);
+                let p = chi_square_test(bxdf.as_ref(), &wo, 10, 20, 20000, &mut rng);
+                assert!(p > 1e-3, "{} failed the chi-square test: p = {}", name, p);
+            }
+        }
+    }
+
+    // Samples uniformly but claims to be cosine weighted
+    #[derive(Debug)]
+    struct LyingBxDF;
+
+    impl BxDF for LyingBxDF {
+        fn matches_flags(&self, ty: ::bsdf::BxDFType) -> bool {
+            (::bsdf::BSDF_REFLECTION | ::bsdf::BSDF_DIFFUSE).contains(ty)
+        }
+
+        fn f(&self, _: &Vector, _: &Vector) -> Spectrum {
+            Spectrum::from(0.5 / ::std::f32::consts::PI)
+        }
+
+        fn sample_f(&self, wo: &Vector, u1: f32, u2: f32) -> (Vector, f32, Spectrum) {
+            let wi = uniform_sample_hemisphere(u1, u2);
+            let pdf = self.pdf(wo, &wi);
+            let f = self.f(wo, &wi);
+            (wi, pdf, f)
+        }
+    }
+
+    #[test]
+    fn chi_square_catches_bad_samplers() {
+        let mut rng = RNG::new(5);
+        let wo = Vector::new_with(0.0, 0.0, 1.0);
+        let p = chi_square_test(&LyingBxDF, &wo, 10, 20, 20000, &mut rng);
+        assert!(p < 1e-6);
+    }
+
+    #[test]
+    fn incomplete_gamma_is_sane() {
+        // Q(1, x) = exp(-x)
+        for &x in [0.1, 1.0, 2.5, 10.0].iter() {
+            assert!((gamma_q(1.0, x) - (-x as f64).exp()).abs() < 1e-9);
+        }
+
+        assert_eq!(gamma_q(2.0, 0.0), 1.0);
+    }
+}
@@ -194,6 +194,17 @@ impl Camera {
         }
     }
 
+    // Where the camera is at the start of the shutter
+    pub fn world_to_camera(&self) -> Option<Transform> {
+        self.proj().map(|_| {
+            self.base().cam_to_world.interpolate(self.base().shutter_open).inverse()
+        })
+    }
+
+    pub fn camera_to_raster(&self) -> Option<Transform> {
+        self.proj().map(|proj| proj.screen_to_raster() * proj.camera_to_screen())
+    }
+
     pub fn film(&self) -> &Film { &(self.base().film) }
     pub fn film_mut(&mut self) -> &mut Film { &mut self.base_mut().film }
 
@@ -4,13 +4,11 @@ use camera::film::Film;
 use geometry::normal::Normalize;
 use geometry::point::Point;
 use geometry::vector::Vector;
+use montecarlo::concentric_sample_disk;
 use ray::Ray;
 use transform::animated::AnimatedTransform;
 use transform::transform::Transform;
 
-// !FIXME! This doesn't belong here!
-fn concentric_sample_disk(x: f32, y: f32) -> (f32, f32) { (x, y) }
-
 #[derive(Debug, Clone)]
 pub struct Projection {
     camera_to_screen: Transform,
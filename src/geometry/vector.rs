@@ -46,6 +46,10 @@ impl Vector {
     pub fn length(&self) -> f32 {
         self.length_squared().sqrt()
     }
+
+    pub fn abs(&self) -> Vector {
+        Vector::new_with(self.x.abs(), self.y.abs(), self.z.abs())
+    }
 }
 
 impl<'a, 'b> ::std::ops::Sub<&'b Vector> for &'a Vector {
@@ -407,6 +411,13 @@ mod tests {
         assert!((-(&nanvec)).z.is_nan());
     }
 
+    #[test]
+    fn it_can_take_the_absolute_value() {
+        assert_eq!(Vector::new_with(-1.0, 2.0, -0.0).abs(),
+                   Vector::new_with(1.0, 2.0, 0.0));
+        assert!(Vector::new_with(f32::NAN, 0.0, 0.0).abs().x.is_nan());
+    }
+
     #[test]
     fn it_can_be_indexed() {
         let mut v = Vector::new_with(-1f32, -1f32, 0f32);
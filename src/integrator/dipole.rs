@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use bbox::Union;
+use bsdf::BSDF_ALL;
+use bsdf::bssrdf::BSSRDF;
+use bsdf::bssrdf::DiffusionReflectance;
+use bsdf::bssrdf::fdr;
+use bsdf::fresnel::Fresnel;
+use camera::Camera;
+use geometry::normal::Normal;
+use geometry::point::Point;
+use geometry::vector::Dot;
+use geometry::vector::Vector;
+use intersection::Intersectable;
+use intersection::Intersection;
+use light::LightSample;
+use montecarlo::uniform_sample_sphere;
+use ray::Ray;
+use ray::RayDifferential;
+use renderer::Renderer;
+use rng::RNG;
+use sampler::sample::Sample;
+use scene::Scene;
+use spectrum::Spectrum;
+use time::Time;
+
+use integrator::specular_reflect;
+use integrator::specular_transmit;
+
+// Number of consecutive candidate points that may be rejected before we
+// decide that the translucent surfaces in the scene are covered.
+const MAX_CONSECUTIVE_FAILS: usize = 2000;
+
+// Number of surfaces a single probe ray may pass through while looking
+// for candidate points.
+const MAX_PROBE_DEPTH: usize = 16;
+
+// Number of light samples used to estimate irradiance at each point.
+const IRRADIANCE_SAMPLES: usize = 4;
+
+const MAX_LEAF_POINTS: usize = 8;
+const MAX_OCTREE_DEPTH: usize = 16;
+
+#[derive(Clone, Debug)]
+struct IrradiancePoint {
+    p: Point,
+    n: Normal,
+    e: Spectrum,
+    area: f32,
+    p_error: Vector
+}
+
+#[derive(Clone, Debug)]
+enum OctreeChildren {
+    Leaf(Vec<usize>),
+    Interior(Vec<(BBox, SubsurfaceOctreeNode)>)
+}
+
+// Each node of the octree stores an aggregate representation of all of
+// the irradiance points below it: their total area, the area-weighted
+// average irradiance and the irradiance-weighted average position.
+#[derive(Clone, Debug)]
+struct SubsurfaceOctreeNode {
+    p: Point,
+    e: Spectrum,
+    sum_area: f32,
+    children: OctreeChildren
+}
+
+impl SubsurfaceOctreeNode {
+    fn build(bound: &BBox, idxs: Vec<usize>, pts: &[IrradiancePoint],
+             depth: usize) -> SubsurfaceOctreeNode {
+        let sum_area = idxs.iter().fold(0.0, |acc, &i| acc + pts[i].area);
+        let e = idxs.iter().fold(Spectrum::from(0.0), |acc, &i| {
+            acc + pts[i].e * pts[i].area
+        }) / sum_area;
+
+        let sum_wt = idxs.iter().fold(0.0, |acc, &i| acc + pts[i].e.y());
+        let p = if sum_wt > 0.0 {
+            idxs.iter().fold(Vector::new(), |acc, &i| {
+                acc + Vector::from(pts[i].p.clone()) * (pts[i].e.y() / sum_wt)
+            })
+        } else {
+            idxs.iter().fold(Vector::new(), |acc, &i| {
+                acc + Vector::from(pts[i].p.clone()) / (idxs.len() as f32)
+            })
+        };
+
+        let children = if idxs.len() <= MAX_LEAF_POINTS || depth >= MAX_OCTREE_DEPTH {
+            OctreeChildren::Leaf(idxs)
+        } else {
+            // Partition the points into the octants around the center
+            let mid = bound.lerp_point(0.5, 0.5, 0.5);
+            let mut octants: Vec<Vec<usize>> = (0..8).map(|_| Vec::new()).collect();
+            for i in idxs.into_iter() {
+                let o = (if pts[i].p.x > mid.x { 4 } else { 0 }) +
+                    (if pts[i].p.y > mid.y { 2 } else { 0 }) +
+                    (if pts[i].p.z > mid.z { 1 } else { 0 });
+                octants[o].push(i);
+            }
+
+            OctreeChildren::Interior(octants.into_iter().enumerate()
+                .filter(|&(_, ref o)| o.len() > 0)
+                .map(|(o, child_idxs)| {
+                    let child_bound = BBox::new_with(
+                        Point::new_with(
+                            if o & 4 != 0 { mid.x } else { bound.p_min.x },
+                            if o & 2 != 0 { mid.y } else { bound.p_min.y },
+                            if o & 1 != 0 { mid.z } else { bound.p_min.z }),
+                        Point::new_with(
+                            if o & 4 != 0 { bound.p_max.x } else { mid.x },
+                            if o & 2 != 0 { bound.p_max.y } else { mid.y },
+                            if o & 1 != 0 { bound.p_max.z } else { mid.z }));
+                    let child = SubsurfaceOctreeNode::build(
+                        &child_bound, child_idxs, pts, depth + 1);
+                    (child_bound, child)
+                }).collect())
+        };
+
+        SubsurfaceOctreeNode {
+            p: Point::new_with(p.x, p.y, p.z),
+            e: e,
+            sum_area: sum_area,
+            children: children
+        }
+    }
+
+    fn mo(&self, bound: &BBox, pi: &Point, rd: &DiffusionReflectance,
+          max_error: f32, pts: &[IrradiancePoint]) -> Spectrum {
+        // If the node is far enough away from the point being shaded
+        // then its aggregate is a good enough stand in for its children
+        let d2 = pi.distance_squared(&self.p);
+        if d2 > 0.0 && self.sum_area / d2 < max_error && !bound.inside(pi) {
+            return rd.evaluate(d2) * self.e * self.sum_area;
+        }
+
+        match &self.children {
+            &OctreeChildren::Leaf(ref idxs) => {
+                idxs.iter().fold(Spectrum::from(0.0), |acc, &i| {
+                    let ip = &pts[i];
+                    acc + rd.evaluate(pi.distance_squared(&ip.p)) * ip.e * ip.area
+                })
+            },
+            &OctreeChildren::Interior(ref children) => {
+                children.iter().fold(Spectrum::from(0.0), |acc, &(ref b, ref c)| {
+                    acc + c.mo(b, pi, rd, max_error, pts)
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SubsurfaceOctree {
+    bound: BBox,
+    root: SubsurfaceOctreeNode
+}
+
+impl SubsurfaceOctree {
+    fn new(pts: &[IrradiancePoint]) -> Option<SubsurfaceOctree> {
+        if pts.len() == 0 {
+            return None;
+        }
+
+        let bound = pts.iter().fold(BBox::new(), |b, ip| b.union(&ip.p));
+        let idxs = (0..pts.len()).collect();
+        let root = SubsurfaceOctreeNode::build(&bound, idxs, pts, 0);
+        Some(SubsurfaceOctree { bound: bound, root: root })
+    }
+
+    // Radiant exitance at pi due to all of the irradiance samples
+    fn mo(&self, pi: &Point, rd: &DiffusionReflectance, max_error: f32,
+          pts: &[IrradiancePoint]) -> Spectrum {
+        self.root.mo(&self.bound, pi, rd, max_error, pts)
+    }
+}
+
+// Accepts points only if they are at least min_dist away from all of
+// the points accepted so far by bucketing them into a uniform grid.
+struct PoissonGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32, i32), Vec<Point>>
+}
+
+impl PoissonGrid {
+    fn new(min_dist: f32) -> PoissonGrid {
+        PoissonGrid { cell_size: min_dist, cells: HashMap::new() }
+    }
+
+    fn cell(&self, p: &Point) -> (i32, i32, i32) {
+        ((p.x / self.cell_size).floor() as i32,
+         (p.y / self.cell_size).floor() as i32,
+         (p.z / self.cell_size).floor() as i32)
+    }
+
+    fn try_insert(&mut self, p: &Point) -> bool {
+        let (cx, cy, cz) = self.cell(p);
+        let min_dist2 = self.cell_size * self.cell_size;
+        for x in (cx - 1)..(cx + 2) {
+            for y in (cy - 1)..(cy + 2) {
+                for z in (cz - 1)..(cz + 2) {
+                    if let Some(pts) = self.cells.get(&(x, y, z)) {
+                        if pts.iter().any(|q| q.distance_squared(p) < min_dist2) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.cells.entry((cx, cy, cz)).or_insert(Vec::new()).push(p.clone());
+        true
+    }
+}
+
+// Renders translucent materials using the dipole diffusion approximation
+// as described by Jensen and Buhler, "A Rapid Hierarchical Rendering
+// Technique for Translucent Materials" (2002).
+#[derive(Clone, Debug)]
+pub struct DipoleSubsurfaceIntegrator {
+    max_depth: usize,
+    max_error: f32,
+    min_sample_dist: f32,
+    points: Vec<IrradiancePoint>,
+    octree: Option<SubsurfaceOctree>
+}
+
+impl DipoleSubsurfaceIntegrator {
+    pub fn new(md: usize, merr: f32, mindist: f32) -> DipoleSubsurfaceIntegrator {
+        DipoleSubsurfaceIntegrator {
+            max_depth: md,
+            max_error: merr,
+            min_sample_dist: mindist,
+            points: Vec::new(),
+            octree: None
+        }
+    }
+
+    pub fn num_irradiance_points(&self) -> usize { self.points.len() }
+
+    // Finds a Poisson-disk distributed set of points over all of the
+    // surfaces in the scene that have a BSSRDF by shooting probe rays
+    // through the scene from random points in its bounds.
+    fn find_surface_points(&self, scene: &Scene, rng: &mut RNG)
+                           -> Vec<(Point, Normal, Vector)> {
+        let bound = scene.world_bound();
+        let mut grid = PoissonGrid::new(self.min_sample_dist);
+        let mut found = Vec::new();
+        let mut fails = 0;
+
+        while fails < MAX_CONSECUTIVE_FAILS {
+            let o = bound.lerp_point(rng.random_float(), rng.random_float(),
+                                     rng.random_float());
+            let d = uniform_sample_sphere(rng.random_float(), rng.random_float());
+            let mut ray = RayDifferential::new_with(o, d, 0.0);
+
+            fails += 1;
+            for _ in 0..MAX_PROBE_DEPTH {
+                let isect = match scene.intersect(&ray.ray) {
+                    Some(isect) => isect,
+                    None => break
+                };
+
+                let p = isect.dg.p.clone();
+                if isect.get_bssrdf(&ray).is_some() && grid.try_insert(&p) {
+                    found.push((p.clone(), isect.dg.nn.clone(), isect.p_error.clone()));
+                    fails = 0;
+                }
+
+                // Keep going to find surfaces that aren't directly
+                // visible from the ray origin
+                ray = isect.spawn_ray(&ray, ray.ray.d.clone());
+            }
+        }
+
+        found
+    }
+
+    fn irradiance(&self, scene: &Scene, p: &Point, n: &Normal, p_error: &Vector,
+                  rng: &mut RNG) -> Spectrum {
+        let mut e = Spectrum::from(0.0);
+        for light in scene.lights().iter() {
+            for _ in 0..IRRADIANCE_SAMPLES {
+                let (li, wi, pdf, visibility) =
+                    light.sample_l(p, p_error, LightSample::new(rng), Time::from(0.0));
+                if li.is_black() || pdf == 0.0 || !visibility.unoccluded(scene) {
+                    continue;
+                }
+
+                e = e + li * wi.abs_dot(n) / pdf;
+            }
+        }
+
+        e / (IRRADIANCE_SAMPLES as f32)
+    }
+
+    pub fn preprocess(&mut self, scene: &Scene, camera: &Camera) {
+        if self.octree.is_some() {
+            return;
+        }
+
+        let mut rng = RNG::new(0);
+        let surface_points = self.find_surface_points(scene, &mut rng);
+
+        // Each point is responsible for the disk around it
+        // that no other point is allowed to be in
+        let r = 0.5 * self.min_sample_dist;
+        let area = ::std::f32::consts::PI * r * r;
+
+        let points: Vec<IrradiancePoint> = surface_points.into_iter().map(|(p, n, p_error)| {
+            let e = self.irradiance(scene, &p, &n, &p_error, &mut rng);
+            IrradiancePoint { p: p, n: n, e: e, area: area, p_error: p_error }
+        }).collect();
+
+        self.octree = SubsurfaceOctree::new(&points);
+        self.points = points;
+    }
+
+    // Evaluates the BSSRDF to find the light that scattered to p from
+    // within the material and leaves in direction wo. The light has to
+    // make it through the boundary twice, once on the way in, which is
+    // averaged over all directions, and once on the way out.
+    fn subsurface_li(&self, p: &Point, n: &Normal, wo: &Vector, bssrdf: &BSSRDF) -> Spectrum {
+        let octree = match self.octree.as_ref() {
+            Some(octree) => octree,
+            None => return Spectrum::from(0.0)
+        };
+
+        let rd = DiffusionReflectance::from_bssrdf(bssrdf);
+        let mo = octree.mo(p, &rd, self.max_error, &self.points);
+
+        // The side of the shading normal that wo is on doesn't change
+        // which side of the boundary the light is coming from
+        let ft = Spectrum::from(1.0) -
+            Fresnel::dielectric(1.0, bssrdf.eta()).evaluate(wo.abs_dot(n));
+        let fdt = 1.0 - fdr(bssrdf.eta());
+        ft * mo * (fdt / ::std::f32::consts::PI)
+    }
+
+    pub fn li<R: Renderer>(&self, scene: &Scene,
+                           renderer: &R,
+                           rayd: &RayDifferential,
+                           isect: &mut Intersection,
+                           sample: &Sample,
+                           rng: &mut RNG) -> Spectrum {
+        let ray = &rayd.ray;
+        let bsdf = if let Some(b) = isect.get_bsdf(rayd) { b } else {
+            return Spectrum::from(0.0)
+        };
+
+        let p = &(bsdf.dg_shading.p);
+        let n = &(bsdf.dg_shading.nn);
+        let wo = -(&ray.d);
+
+        let mut l = isect.le(&wo);
+
+        if let Some(bssrdf) = isect.get_bssrdf(rayd) {
+            l = l + self.subsurface_li(p, n, &wo, &bssrdf);
+        }
+
+        // Add direct lighting reflected off of the surface
+        l = scene.lights().iter().fold(l, |l_acc, ref light| {
+            let (li, wi, pdf, visibility) =
+                light.sample_l(p, &isect.p_error,
+                               LightSample::new(rng), ray.time.clone());
+            if li.is_black() || pdf == 0f32 { l_acc }
+            else {
+                let f = bsdf.f(wo.clone(), wi.clone(), BSDF_ALL);
+                if f.is_black() || !visibility.unoccluded(scene) { l_acc }
+                else {
+                    l_acc +
+                        f * li * wi.abs_dot(n) *
+                        visibility.transmittance(scene, renderer, sample, rng) / pdf
+                }
+            }
+        });
+
+        l + (
+            if ray.depth + 1 < self.max_depth {
+                let refl = specular_reflect(rayd, &bsdf, rng, isect,
+                                            renderer, scene, sample);
+                let tmit = specular_transmit(rayd, &bsdf, rng, isect,
+                                             renderer, scene, sample);
+                refl + tmit
+            } else { Spectrum::from(0f32) }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::bssrdf::DiffusionReflectance;
+    use geometry::normal::Normal;
+    use geometry::point::Point;
+    use rng::RNG;
+    use spectrum::Spectrum;
+
+    fn plane_of_points(rng: &mut RNG) -> Vec<IrradiancePoint> {
+        let mut grid = PoissonGrid::new(0.05);
+        let mut pts = Vec::new();
+        for _ in 0..20000 {
+            let p = Point::new_with(rng.random_float(), rng.random_float(), 0.0);
+            if grid.try_insert(&p) {
+                pts.push(IrradiancePoint {
+                    p: p,
+                    n: Normal::new_with(0.0, 0.0, 1.0),
+                    e: Spectrum::from(rng.random_float()),
+                    area: ::std::f32::consts::PI * 0.025 * 0.025,
+                    p_error: Vector::new()
+                });
+            }
+        }
+        pts
+    }
+
+    #[test]
+    fn poisson_points_are_spread_out() {
+        let mut rng = RNG::new(3);
+        let pts = plane_of_points(&mut rng);
+        assert!(pts.len() > 100);
+        for (i, a) in pts.iter().enumerate() {
+            for b in pts[(i + 1)..].iter() {
+                assert!(a.p.distance(&b.p) >= 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn octree_approximates_brute_force() {
+        let mut rng = RNG::new(11);
+        let pts = plane_of_points(&mut rng);
+        let octree = SubsurfaceOctree::new(&pts).unwrap();
+        let rd = DiffusionReflectance::new(&Spectrum::from_rgb([0.032, 0.17, 0.48]),
+                                           &Spectrum::from_rgb([0.74, 0.88, 1.01]),
+                                           1.3);
+
+        let pi = Point::new_with(0.5, 0.5, 0.0);
+        let exact = pts.iter().fold(Spectrum::from(0.0), |acc, ip| {
+            acc + rd.evaluate(pi.distance_squared(&ip.p)) * ip.e * ip.area
+        }).to_rgb();
+
+        // No error allowed means that we visit every point
+        let visited = octree.mo(&pi, &rd, 0.0, &pts).to_rgb();
+        let approx = octree.mo(&pi, &rd, 0.05, &pts).to_rgb();
+        for i in 0..3 {
+            assert!((visited[i] - exact[i]).abs() <= 1e-4 * exact[i]);
+            assert!((approx[i] - exact[i]).abs() <= 0.1 * exact[i]);
+        }
+    }
+
+    #[test]
+    fn uniformly_lit_slabs_match_the_total_diffuse_reflectance() {
+        let (sigma_a, sigma_prime_s, eta) = (1.0, 10.0, 1.3);
+        let bssrdf = BSSRDF::new(Spectrum::from(sigma_a), Spectrum::from(sigma_prime_s), eta);
+
+        // Unit irradiance over a slab that is wide enough for the dipole
+        // to have died off well before its edges
+        let h = 0.02;
+        let mut pts = Vec::new();
+        for i in 0..150 {
+            for j in 0..150 {
+                pts.push(IrradiancePoint {
+                    p: Point::new_with(((i as f32) - 74.5) * h, ((j as f32) - 74.5) * h, 0.0),
+                    n: Normal::new_with(0.0, 0.0, 1.0),
+                    e: Spectrum::from(1.0),
+                    area: h * h,
+                    p_error: Vector::new()
+                });
+            }
+        }
+
+        let mut integrator = DipoleSubsurfaceIntegrator::new(5, 0.0, h);
+        integrator.octree = SubsurfaceOctree::new(&pts);
+        integrator.points = pts;
+
+        // Jensen et al. 2001, equation 15
+        let alphap = sigma_prime_s / (sigma_a + sigma_prime_s);
+        let a = (1.0 + fdr(eta)) / (1.0 - fdr(eta));
+        let s = (3.0 * (1.0 - alphap)).sqrt();
+        let rd = 0.5 * alphap * (1.0 + (-4.0 / 3.0 * a * s).exp()) * (-s).exp();
+
+        let p = Point::new();
+        let n = Normal::new_with(0.0, 0.0, 1.0);
+        let wo = Vector::new_with(0.6, 0.0, 0.8);
+        let ft = 1.0 - Fresnel::dielectric(1.0, eta).evaluate(0.8)[0];
+        let expected = ft * (1.0 - fdr(eta)) * rd / ::std::f32::consts::PI;
+
+        let l = integrator.subsurface_li(&p, &n, &wo, &bssrdf);
+        assert!((l[0] - expected).abs() < 0.02 * expected, "{} vs. {}", l[0], expected);
+
+        // Shading normals facing away from wo see the same light
+        let back = integrator.subsurface_li(&p, &-n, &wo, &bssrdf);
+        assert!((back[0] - l[0]).abs() < 1e-6 * l[0]);
+    }
+
+    #[test]
+    fn empty_octrees_are_not_built() {
+        assert!(SubsurfaceOctree::new(&[]).is_none());
+    }
+}
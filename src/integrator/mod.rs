@@ -1,3 +1,4 @@
+mod dipole;
 mod whitted;
 
 use bsdf;
@@ -15,6 +16,7 @@ use sampler::Sampler;
 use scene::Scene;
 use spectrum::Spectrum;
 
+use integrator::dipole::DipoleSubsurfaceIntegrator;
 use integrator::whitted::WhittedIntegrator;
 
 fn process_specular<R: Renderer>(
@@ -24,13 +26,14 @@ fn process_specular<R: Renderer>(
     let wo = -(&ray.ray.d);
     let p = &(bsdf.dg_shading.p);
     let n = &(bsdf.dg_shading.nn);
-    let (wi, pdf, f) = bsdf.sample_f(
+    let (wi, pdf, f, _) = bsdf.sample_f(
         &wo, BSDFSample::new(rng), sample_type);
 
     let win = wi.abs_dot(n);
     if pdf > 0f32 && !f.is_black() && win != 0f32 {
         // Cmpute ray differential rd for specular reflection <512>
-        let rd = ray.clone(); // !FIXME! just to compile
+        // !FIXME! the differentials themselves are still missing
+        let rd = isect.spawn_ray(ray, wi);
         let li = renderer.li_simple(scene, &rd, sample, rng);
         f * li * win / pdf
     } else {
@@ -68,6 +71,10 @@ pub enum SurfaceIntegrator {
     Whitted {
         base: Integrator,
         surf: WhittedIntegrator
+    },
+    DipoleSubsurface {
+        base: Integrator,
+        surf: DipoleSubsurfaceIntegrator
     }
 }
 
@@ -79,15 +86,31 @@ impl SurfaceIntegrator {
         }
     }
 
-    pub fn li<R:Renderer>(&self, _: &Scene, _: &R, _: &RayDifferential,
-                          _: &mut Intersection, _: &Sample, _: &mut RNG) -> Spectrum {
-        unimplemented!()
+    pub fn dipole_subsurface(max_depth: usize, max_error: f32,
+                             min_sample_dist: f32) -> SurfaceIntegrator {
+        SurfaceIntegrator::DipoleSubsurface {
+            base: Integrator,
+            surf: DipoleSubsurfaceIntegrator::new(max_depth, max_error, min_sample_dist)
+        }
+    }
+
+    pub fn li<R:Renderer>(&self, scene: &Scene, renderer: &R, ray: &RayDifferential,
+                          isect: &mut Intersection, sample: &Sample,
+                          rng: &mut RNG) -> Spectrum {
+        match self {
+            &SurfaceIntegrator::Whitted { ref surf, .. } =>
+                surf.li(scene, renderer, ray, isect, sample, rng),
+            &SurfaceIntegrator::DipoleSubsurface { ref surf, .. } =>
+                surf.li(scene, renderer, ray, isect, sample, rng)
+        }
     }
 
     pub fn preprocess(&mut self, scene: &Scene, camera: &Camera) {
         match self {
             &mut SurfaceIntegrator::Whitted { ref mut base, .. } =>
-                base.preprocess(scene, camera)
+                base.preprocess(scene, camera),
+            &mut SurfaceIntegrator::DipoleSubsurface { ref mut surf, .. } =>
+                surf.preprocess(scene, camera)
         }
     }
 
@@ -28,7 +28,7 @@ impl WhittedIntegrator {
         }
     }
 
-    fn li<R : Renderer>(&self, scene: &Scene,
+    pub fn li<R : Renderer>(&self, scene: &Scene,
                         renderer: &R,
                         rayd: &RayDifferential,
                         isect: &mut Intersection,
@@ -51,7 +51,7 @@ impl WhittedIntegrator {
 
             // Add contribution of each light source
             let (li, wi, pdf, visibility) =
-                light.sample_l(p, isect.ray_epsilon,
+                light.sample_l(p, &isect.p_error,
                                LightSample::new(rng), ray.time.clone());
             if li.is_black() || pdf == 0f32 { l_acc }
             else {
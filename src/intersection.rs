@@ -8,6 +8,7 @@ use geometry::vector::Vector;
 use primitive::Primitive;
 use ray::Ray;
 use ray::RayDifferential;
+use ray::offset_ray_origin;
 use spectrum::Spectrum;
 use transform::transform::Transform;
 
@@ -19,13 +20,14 @@ pub struct Intersection {
     pub object_to_world: Transform,
     pub shape_id: usize,
     pub primitive_id: usize,
-    pub ray_epsilon: f32,
+    // Conservative bound on the error in dg.p
+    pub p_error: Vector,
 }
 
 impl Intersection {
     pub fn new_with(_dg: DifferentialGeometry, w2o: Transform,
                     o2w: Transform, sid: usize, pid: usize,
-                    ray_eps: f32) -> Intersection {
+                    p_err: Vector) -> Intersection {
         Intersection {
             dg: _dg.clone(),
             primitive: None,
@@ -33,10 +35,19 @@ impl Intersection {
             object_to_world: o2w,
             shape_id: sid,
             primitive_id: pid,
-            ray_epsilon: ray_eps,
+            p_error: p_err,
         }
     }
 
+    // Spawns a ray continuing on from ray in the direction d, starting far
+    // enough away from the surface that it won't hit it again.
+    pub fn spawn_ray(&self, ray: &RayDifferential, d: Vector) -> RayDifferential {
+        let o = offset_ray_origin(&self.dg.p, &self.p_error, &self.dg.nn, &d);
+        let spawned = ray.clone().into(o, d, 0.0);
+        spawned.ray.set_maxt(::std::f32::MAX);
+        spawned
+    }
+
     pub fn get_bsdf(&self, ray: &RayDifferential) -> Option<BSDF> {
         let mut new_dg = self.dg.clone();
         new_dg.compute_differentials(ray);
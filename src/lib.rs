@@ -2,6 +2,7 @@
 #[macro_use]
 extern crate bitflags;
 
+extern crate num_cpus;
 extern crate scoped_threadpool;
 
 pub mod area_light;
@@ -16,6 +17,8 @@ pub mod integrator;
 pub mod light;
 pub mod material;
 pub mod montecarlo;
+pub mod obj;
+pub mod paramset;
 pub mod primitive;
 pub mod quaternion;
 pub mod ray;
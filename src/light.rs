@@ -19,7 +19,7 @@ impl Light {
         unimplemented!()
     }
 
-    pub fn sample_l(&self, p: &Point, eps: f32,
+    pub fn sample_l(&self, p: &Point, p_error: &Vector,
                     sample: LightSample, time: Time) ->
         (Spectrum, Vector, f32, VisibilityTester) {
             unimplemented!()
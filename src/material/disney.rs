@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::disney::*;
+use bsdf::fresnel::Fresnel;
+use bsdf::microfacet::Microfacet;
+use bsdf::microfacet::MicrofacetDistribution;
+use bsdf::microfacet::MicrofacetTransmission;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+use utils::Lerp;
+
+use material::bump;
+
+// The "specular" parameter is a remapping of normal incidence
+// reflectance into [0, 1] such that 0.5 corresponds to an IOR of 1.5
+fn specular_to_r0(specular: f32) -> f32 { 0.08 * specular }
+
+fn r0_to_eta(r0: f32) -> f32 {
+    let sr0 = r0.min(0.99).sqrt();
+    (1.0 + sr0) / (1.0 - sr0)
+}
+
+// Transmission goes through the same microfacets as the specular reflection
+fn spec_trans_lobe(c: &Spectrum, strans: f32, distribution: MicrofacetDistribution,
+                   eta: f32) -> MicrofacetTransmission {
+    MicrofacetTransmission::new(c.sqrt() * strans, distribution, 1.0, eta)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DisneyMaterial {
+    color: Arc<Texture<Spectrum>>,
+    metallic: Arc<Texture<f32>>,
+    specular: Arc<Texture<f32>>,
+    roughness: Arc<Texture<f32>>,
+    specular_tint: Arc<Texture<f32>>,
+    anisotropic: Arc<Texture<f32>>,
+    sheen: Arc<Texture<f32>>,
+    sheen_tint: Arc<Texture<f32>>,
+    clearcoat: Arc<Texture<f32>>,
+    clearcoat_gloss: Arc<Texture<f32>>,
+    spec_trans: Arc<Texture<f32>>,
+    subsurface: Arc<Texture<f32>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl DisneyMaterial {
+    pub fn new(color: Arc<Texture<Spectrum>>,
+               metallic: Arc<Texture<f32>>,
+               specular: Arc<Texture<f32>>,
+               roughness: Arc<Texture<f32>>,
+               specular_tint: Arc<Texture<f32>>,
+               anisotropic: Arc<Texture<f32>>,
+               sheen: Arc<Texture<f32>>,
+               sheen_tint: Arc<Texture<f32>>,
+               clearcoat: Arc<Texture<f32>>,
+               clearcoat_gloss: Arc<Texture<f32>>,
+               spec_trans: Arc<Texture<f32>>,
+               subsurface: Arc<Texture<f32>>,
+               bm: Option<Arc<Texture<f32>>>) -> DisneyMaterial {
+        DisneyMaterial {
+            color: color,
+            metallic: metallic,
+            specular: specular,
+            roughness: roughness,
+            specular_tint: specular_tint,
+            anisotropic: anisotropic,
+            sheen: sheen,
+            sheen_tint: sheen_tint,
+            clearcoat: clearcoat,
+            clearcoat_gloss: clearcoat_gloss,
+            spec_trans: spec_trans,
+            subsurface: subsurface,
+            bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> DisneyMaterial {
+        DisneyMaterial::new(
+            mp.get_spectrum_texture("color", Spectrum::from(0.5)),
+            mp.get_float_texture("metallic", 0.0),
+            mp.get_float_texture("specular", 0.5),
+            mp.get_float_texture("roughness", 0.5),
+            mp.get_float_texture("speculartint", 0.0),
+            mp.get_float_texture("anisotropic", 0.0),
+            mp.get_float_texture("sheen", 0.0),
+            mp.get_float_texture("sheentint", 0.5),
+            mp.get_float_texture("clearcoat", 0.0),
+            mp.get_float_texture("clearcoatgloss", 1.0),
+            mp.get_float_texture("spectrans", 0.0),
+            mp.get_float_texture("subsurface", 0.0),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let c = self.color.evaluate(&dgs).clamp(0.0, 1.0);
+        let metallic = self.metallic.evaluate(&dgs).clamp(0.0, 1.0);
+        let rough = self.roughness.evaluate(&dgs).clamp(0.0, 1.0);
+        let spec_tint = self.specular_tint.evaluate(&dgs).clamp(0.0, 1.0);
+        let strans = self.spec_trans.evaluate(&dgs).clamp(0.0, 1.0);
+        let r0 = specular_to_r0(self.specular.evaluate(&dgs).clamp(0.0, 1.0));
+        let eta = r0_to_eta(r0);
+
+        let mut bsdf = BSDF::new_with_eta(dgs.clone(), dg_geom.nn, eta);
+        let mut lobes = DisneyPrincipled::new();
+
+        // Normalize the color by its luminance to isolate hue and saturation
+        let lum = c.y();
+        let c_tint = if lum > 0.0 { c / lum } else { Spectrum::from(1.0) };
+
+        // Metals and transmissive surfaces have no diffuse component. Each
+        // lobe is sampled in proportion to a rough estimate of its albedo.
+        let diffuse_weight = (1.0 - metallic) * (1.0 - strans);
+        if diffuse_weight > 0.0 {
+            let sd = self.subsurface.evaluate(&dgs).clamp(0.0, 1.0);
+            let dc = c * diffuse_weight;
+            let dlum = lum * diffuse_weight;
+
+            lobes.add_lobe(DisneyLobe::Diffuse(DisneyDiffuse::new(dc * (1.0 - sd))),
+                           dlum * (1.0 - sd));
+            lobes.add_lobe(DisneyLobe::FakeSS(DisneyFakeSS::new(dc * sd, rough)),
+                           dlum * sd);
+            lobes.add_lobe(DisneyLobe::Retro(DisneyRetro::new(dc, rough)),
+                           dlum * rough * 0.5);
+
+            let sheen = self.sheen.evaluate(&dgs);
+            if sheen > 0.0 {
+                let sheen_tint = self.sheen_tint.evaluate(&dgs).clamp(0.0, 1.0);
+                let c_sheen = Spectrum::from(1.0).lerp(&c_tint, sheen_tint);
+                let s = c_sheen * (sheen * diffuse_weight);
+                lobes.add_lobe(DisneyLobe::Sheen(DisneySheen::new(s)), s.y());
+            }
+        }
+
+        // Stretch the specular highlight along one of the tangent
+        // directions for anisotropic surfaces
+        let aspect = (1.0 - self.anisotropic.evaluate(&dgs).clamp(0.0, 1.0) * 0.9).sqrt();
+        let ax = (rough * rough / aspect).max(0.001);
+        let ay = (rough * rough * aspect).max(0.001);
+
+        let c_spec0 = (Spectrum::from(1.0).lerp(&c_tint, spec_tint) * r0).lerp(&c, metallic);
+        let fresnel = Fresnel::disney(&c_spec0, metallic, eta);
+        let distribution = MicrofacetDistribution::trowbridge_reitz(ax, ay);
+        lobes.add_lobe(DisneyLobe::Specular(Microfacet::new(
+            Spectrum::from(1.0), fresnel, distribution)),
+                       0.5 * (1.0 + c_spec0.y()));
+
+        let cc = self.clearcoat.evaluate(&dgs);
+        if cc > 0.0 {
+            let gloss = self.clearcoat_gloss.evaluate(&dgs).clamp(0.0, 1.0);
+            lobes.add_lobe(DisneyLobe::Clearcoat(DisneyClearcoat::new(
+                cc, 0.1f32.lerp(&0.001, gloss))), 0.25 * cc);
+        }
+
+        bsdf.add_bxdf(lobes);
+
+        if strans > 0.0 {
+            bsdf.add_bxdf(spec_trans_lobe(&c, strans, distribution, eta));
+        }
+
+        Some(bsdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf::validation;
+    use rng::RNG;
+
+    #[test]
+    fn it_maps_specular_to_ior() {
+        assert!((r0_to_eta(specular_to_r0(0.5)) - 1.5).abs() < 1e-5);
+        assert!(r0_to_eta(specular_to_r0(1.0)) > 1.5);
+        assert_eq!(r0_to_eta(0.0), 1.0);
+    }
+
+    #[test]
+    fn spec_trans_samples_match_pdf() {
+        let mut rng = RNG::new(5);
+        let c = Spectrum::from_rgb([0.9, 0.6, 0.3]);
+        let eta = r0_to_eta(specular_to_r0(0.5));
+        let dists = [MicrofacetDistribution::trowbridge_reitz(0.25, 0.25),
+                     MicrofacetDistribution::trowbridge_reitz(0.1, 0.4)];
+        for dist in dists.iter() {
+            let lobe = spec_trans_lobe(&c, 0.8, *dist, eta);
+            for _ in 0..4 {
+                // From either side of the surface
+                let wo = validation::random_direction(&mut rng);
+                let p = validation::chi_square_test(&lobe, &wo, 16, 32, 100000, &mut rng);
+                assert!(p > 1e-3, "{:?} failed for {:?}: p = {}", dist, wo, p);
+
+                let wo = -wo;
+                let p = validation::chi_square_test(&lobe, &wo, 16, 32, 100000, &mut rng);
+                assert!(p > 1e-3, "{:?} failed for {:?}: p = {}", dist, wo, p);
+            }
+        }
+    }
+}
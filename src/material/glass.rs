@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::microfacet::Microfacet;
+use bsdf::microfacet::MicrofacetDistribution;
+use bsdf::microfacet::MicrofacetTransmission;
+use bsdf::microfacet::roughness_to_alpha;
+use bsdf::specular::SpecularReflection;
+use bsdf::specular::SpecularTransmission;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct GlassMaterial {
+    k_r: Arc<Texture<Spectrum>>,
+    k_t: Arc<Texture<Spectrum>>,
+    index: Arc<Texture<f32>>,
+    u_roughness: Arc<Texture<f32>>,
+    v_roughness: Arc<Texture<f32>>,
+    beckmann: bool,
+    remap_roughness: bool,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl GlassMaterial {
+    pub fn new(kr: Arc<Texture<Spectrum>>,
+               kt: Arc<Texture<Spectrum>>,
+               idx: Arc<Texture<f32>>,
+               bm: Option<Arc<Texture<f32>>>) -> GlassMaterial {
+        let smooth = Arc::new(Texture::new(0.0));
+        GlassMaterial::rough(kr, kt, idx, smooth.clone(), smooth, false, true, bm)
+    }
+
+    // Glass with a microfacet interface. A roughness of zero in both
+    // directions falls back to perfectly specular glass.
+    pub fn rough(kr: Arc<Texture<Spectrum>>,
+                 kt: Arc<Texture<Spectrum>>,
+                 idx: Arc<Texture<f32>>,
+                 urough: Arc<Texture<f32>>,
+                 vrough: Arc<Texture<f32>>,
+                 beckmann: bool, remap: bool,
+                 bm: Option<Arc<Texture<f32>>>) -> GlassMaterial {
+        GlassMaterial {
+            k_r: kr, k_t: kt, index: idx,
+            u_roughness: urough, v_roughness: vrough,
+            beckmann: beckmann, remap_roughness: remap,
+            bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> GlassMaterial {
+        let roughness = mp.find_float("roughness", 0.0);
+        GlassMaterial::rough(mp.get_spectrum_texture("Kr", Spectrum::from(1.0)),
+                             mp.get_spectrum_texture("Kt", Spectrum::from(1.0)),
+                             mp.get_float_texture("index", 1.5),
+                             mp.get_float_texture("uroughness", roughness),
+                             mp.get_float_texture("vroughness", roughness),
+                             mp.find_string("distribution", "ggx") == "beckmann",
+                             mp.find_string("remaproughness", "true") == "true",
+                             mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    fn distribution(&self, urough: f32, vrough: f32) -> MicrofacetDistribution {
+        let (ax, ay) = if self.remap_roughness {
+            (roughness_to_alpha(urough), roughness_to_alpha(vrough))
+        } else {
+            (urough, vrough)
+        };
+
+        if self.beckmann {
+            MicrofacetDistribution::beckmann(ax, ay)
+        } else {
+            MicrofacetDistribution::trowbridge_reitz(ax, ay)
+        }
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let ior = self.index.evaluate(&dgs);
+        let mut bsdf = BSDF::new_with_eta(dgs.clone(), dg_geom.nn, ior);
+
+        let r = self.k_r.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        let t = self.k_t.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+
+        let urough = self.u_roughness.evaluate(&dgs);
+        let vrough = self.v_roughness.evaluate(&dgs);
+        if urough == 0.0 && vrough == 0.0 {
+            if !r.is_black() {
+                bsdf.add_bxdf(SpecularReflection::new(r, Fresnel::dielectric(1.0, ior)));
+            }
+
+            if !t.is_black() {
+                bsdf.add_bxdf(SpecularTransmission::new(t, 1.0, ior));
+            }
+        } else {
+            let dist = self.distribution(urough, vrough);
+            if !r.is_black() {
+                bsdf.add_bxdf(Microfacet::new(r, Fresnel::dielectric(1.0, ior), dist));
+            }
+
+            if !t.is_black() {
+                bsdf.add_bxdf(MicrofacetTransmission::new(t, dist, 1.0, ior));
+            }
+        }
+
+        Some(bsdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf;
+    use paramset::ParamSet;
+
+    #[test]
+    fn it_can_be_rough() {
+        let dg = DifferentialGeometry::new();
+        let glass = GlassMaterial::create(&ParamSet::new());
+        let smooth = glass.get_bsdf(dg.clone(), dg.clone()).unwrap();
+        assert_eq!(smooth.num_components_matching(bsdf::BSDF_ALL), 2);
+        assert_eq!(smooth.num_components_matching(bsdf::BSDF_ALL_TRANSMISSION), 1);
+
+        let mut mp = ParamSet::new();
+        mp.add_float("roughness", 0.3);
+        mp.add_string("distribution", "beckmann");
+        let rough = GlassMaterial::create(&mp).get_bsdf(dg.clone(), dg).unwrap();
+        assert_eq!(rough.num_components_matching(bsdf::BSDF_ALL), 2);
+        assert_eq!(rough.num_components_matching(bsdf::BSDF_REFLECTION | bsdf::BSDF_GLOSSY), 1);
+        assert_eq!(rough.num_components_matching(bsdf::BSDF_TRANSMISSION | bsdf::BSDF_GLOSSY), 1);
+        assert_eq!(rough.num_components_matching(bsdf::BSDF_ALL_REFLECTION), 1);
+    }
+}
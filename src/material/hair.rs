@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::hair::HairBSDF;
+use bsdf::hair::sigma_a_from_concentration;
+use bsdf::hair::sigma_a_from_reflectance;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+// How the absorption inside of the fiber is specified
+#[derive(Clone, PartialEq, Debug)]
+pub enum HairColor {
+    // Absorption coefficient directly
+    SigmaA(Arc<Texture<Spectrum>>),
+    // Color of the hair after many scattering events
+    Reflectance(Arc<Texture<Spectrum>>),
+    // Concentrations of eumelanin and pheomelanin
+    Melanin(Arc<Texture<f32>>, Arc<Texture<f32>>)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HairMaterial {
+    color: HairColor,
+    eta: Arc<Texture<f32>>,
+    beta_m: Arc<Texture<f32>>,
+    beta_n: Arc<Texture<f32>>,
+    alpha: Arc<Texture<f32>>
+}
+
+impl HairMaterial {
+    pub fn new(color: HairColor,
+               eta: Arc<Texture<f32>>,
+               beta_m: Arc<Texture<f32>>,
+               beta_n: Arc<Texture<f32>>,
+               alpha: Arc<Texture<f32>>) -> HairMaterial {
+        HairMaterial {
+            color: color,
+            eta: eta,
+            beta_m: beta_m,
+            beta_n: beta_n,
+            alpha: alpha
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> HairMaterial {
+        // Default to brown hair if nothing else is given
+        let color = if let Some(sigma_a) = mp.get_spectrum_texture_or_none("sigma_a") {
+            HairColor::SigmaA(sigma_a)
+        } else if let Some(c) = mp.get_spectrum_texture_or_none("color") {
+            HairColor::Reflectance(c)
+        } else {
+            HairColor::Melanin(mp.get_float_texture("eumelanin", 1.3),
+                               mp.get_float_texture("pheomelanin", 0.0))
+        };
+
+        HairMaterial::new(color,
+                          mp.get_float_texture("eta", 1.55),
+                          mp.get_float_texture("beta_m", 0.3),
+                          mp.get_float_texture("beta_n", 0.3),
+                          mp.get_float_texture("alpha", 2.0))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        let dgs = dg_shading;
+        let beta_m = self.beta_m.evaluate(&dgs).clamp(0.0, 1.0);
+        let beta_n = self.beta_n.evaluate(&dgs).clamp(0.0, 1.0);
+        let sigma_a = match self.color {
+            HairColor::SigmaA(ref s) => s.evaluate(&dgs).clamp(0.0, ::std::f32::MAX),
+            HairColor::Reflectance(ref c) => {
+                let c = c.evaluate(&dgs).clamp(1e-4, 1.0);
+                sigma_a_from_reflectance(&c, beta_n)
+            }
+            HairColor::Melanin(ref ce, ref cp) => {
+                let ce = ce.evaluate(&dgs).max(0.0);
+                let cp = cp.evaluate(&dgs).max(0.0);
+                sigma_a_from_concentration(ce, cp)
+            }
+        };
+
+        // Curves are parameterized with v going across the fiber
+        let h = (-1.0 + 2.0 * dgs.v).clamp(-1.0, 1.0);
+        let eta = self.eta.evaluate(&dgs);
+        let alpha = self.alpha.evaluate(&dgs);
+
+        let mut bsdf = BSDF::new_with_eta(dgs.clone(), dg_geom.nn, eta);
+        bsdf.add_bxdf(HairBSDF::new(h, eta, sigma_a, beta_m, beta_n, alpha));
+        Some(bsdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsdf;
+    use diff_geom::DifferentialGeometry;
+    use paramset::ParamSet;
+    use spectrum::Spectrum;
+
+    #[test]
+    fn it_can_be_created() {
+        let mp = ParamSet::new();
+        match HairMaterial::create(&mp).color {
+            HairColor::Melanin(_, _) => (),
+            _ => panic!("Expected the hair to be colored by melanin")
+        }
+
+        let mut mp = ParamSet::new();
+        mp.add_spectrum("color", Spectrum::from_rgb([0.5, 0.3, 0.1]));
+        match HairMaterial::create(&mp).color {
+            HairColor::Reflectance(_) => (),
+            _ => panic!("Expected the hair to be colored by reflectance")
+        }
+    }
+
+    #[test]
+    fn it_has_a_single_glossy_lobe() {
+        let mut dg = DifferentialGeometry::new();
+        dg.v = 0.25;
+        let bsdf = HairMaterial::create(&ParamSet::new())
+            .get_bsdf(dg.clone(), dg).unwrap();
+        assert_eq!(bsdf.num_components(), 1);
+        assert_eq!(bsdf.num_components_matching(bsdf::BSDF_ALL_REFLECTION), 0);
+        assert_eq!(bsdf.num_components_matching(bsdf::BSDF_ALL), 1);
+    }
+}
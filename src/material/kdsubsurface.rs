@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::bssrdf::BSSRDF;
+use bsdf::bssrdf::subsurface_from_diffuse;
+use bsdf::fresnel::Fresnel;
+use bsdf::specular::SpecularReflection;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+// A subsurface scattering material that is parameterized by the more
+// intuitive diffuse reflectance and mean free path rather than by the
+// scattering coefficients themselves.
+#[derive(Clone, PartialEq, Debug)]
+pub struct KdSubsurfaceMaterial {
+    k_d: Arc<Texture<Spectrum>>,
+    k_r: Arc<Texture<Spectrum>>,
+    mean_free_path: Arc<Texture<f32>>,
+    eta: f32,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl KdSubsurfaceMaterial {
+    pub fn new(kd: Arc<Texture<Spectrum>>,
+               kr: Arc<Texture<Spectrum>>,
+               mfp: Arc<Texture<f32>>,
+               et: f32, bm: Option<Arc<Texture<f32>>>) -> KdSubsurfaceMaterial {
+        KdSubsurfaceMaterial {
+            k_d: kd,
+            k_r: kr,
+            mean_free_path: mfp,
+            eta: et,
+            bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> KdSubsurfaceMaterial {
+        KdSubsurfaceMaterial::new(
+            mp.get_spectrum_texture("Kd", Spectrum::from(0.5)),
+            mp.get_spectrum_texture("Kr", Spectrum::from(1.0)),
+            mp.get_float_texture("meanfreepath", 1.0),
+            mp.find_float("index", 1.3),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    fn shading_geometry(&self, dg_geom: &DifferentialGeometry,
+                        dg_shading: DifferentialGeometry) -> DifferentialGeometry {
+        if let Some(ref tex) = self.bump_map {
+            bump(tex, dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        }
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = self.shading_geometry(&dg_geom, dg_shading);
+        let mut bsdf = BSDF::new_with_eta(dgs.clone(), dg_geom.nn, self.eta);
+
+        let r = self.k_r.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !r.is_black() {
+            bsdf.add_bxdf(SpecularReflection::new(r, Fresnel::dielectric(1.0, self.eta)));
+        }
+
+        Some(bsdf)
+    }
+
+    pub fn get_bssrdf(&self, dg_geom: DifferentialGeometry,
+                      dg_shading: DifferentialGeometry) -> Option<BSSRDF> {
+        let dgs = self.shading_geometry(&dg_geom, dg_shading);
+        let kd = self.k_d.evaluate(&dgs).clamp(0.0, 1.0);
+        let mfp = self.mean_free_path.evaluate(&dgs);
+        let (sa, sps) = subsurface_from_diffuse(&kd, mfp, self.eta);
+        Some(BSSRDF::new(sa, sps, self.eta))
+    }
+}
@@ -4,6 +4,7 @@ use bsdf::BSDF;
 use bsdf::lambertian::Lambertian;
 use bsdf::orennayar::OrenNayar;
 use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
 use spectrum::Spectrum;
 use texture::Texture;
 use utils::Clamp;
@@ -28,6 +29,12 @@ impl MatteMaterial {
         }
     }
 
+    pub fn create(mp: &ParamSet) -> MatteMaterial {
+        MatteMaterial::new(mp.get_spectrum_texture("Kd", Spectrum::from(0.5)),
+                           mp.get_float_texture("sigma", 0.0),
+                           mp.get_float_texture_or_none("bumpmap"))
+    }
+
     pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
                     dg_shading: DifferentialGeometry) -> Option<BSDF> {
         // Allocate bsdf possibly doing bump mapping with bump map
@@ -1,32 +1,235 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
 
 use bsdf::BSDF;
 use bsdf::measured::IrregIsotropic;
 use bsdf::measured::IrregIsotropicSample;
 use bsdf::measured::RegularHalfangle;
+use bsdf::measured::brdf_remap;
 use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use geometry::vector::spherical_direction;
 use spectrum::Spectrum;
 use texture::Texture;
 use utils::kdtree::KdTree;
 
 use material::bump;
 
+#[derive(Debug)]
+pub enum MeasuredError {
+    Io(io::Error),
+    Format(String)
+}
+
+impl ::std::fmt::Display for MeasuredError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            &MeasuredError::Io(ref e) => write!(f, "I/O error: {}", e),
+            &MeasuredError::Format(ref s) => write!(f, "Malformed BRDF data: {}", s)
+        }
+    }
+}
+
+impl ::std::error::Error for MeasuredError {
+    fn description(&self) -> &str {
+        match self {
+            &MeasuredError::Io(ref e) => e.description(),
+            &MeasuredError::Format(ref s) => s
+        }
+    }
+}
+
+impl ::std::convert::From<io::Error> for MeasuredError {
+    fn from(e: io::Error) -> MeasuredError { MeasuredError::Io(e) }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum MeasuredData {
+    ThetaPhi(Arc<KdTree<IrregIsotropicSample>>),
+    RegularHalfangle {
+        num_theta_h: usize,
+        num_theta_d: usize,
+        num_phi_d: usize,
+        data: Arc<Vec<f32>>
+    }
+}
+
+// Measured data files are big and the same file is usually referenced by
+// many materials in a scene, so we only ever want to load each one once.
+fn loaded_data() -> &'static Mutex<HashMap<String, Arc<MeasuredData>>> {
+    static LOADED: OnceLock<Mutex<HashMap<String, Arc<MeasuredData>>>> = OnceLock::new();
+    LOADED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) | ((bytes[1] as u32) << 8) |
+    ((bytes[2] as u32) << 16) | ((bytes[3] as u32) << 24)
+}
+
+fn read_f64_le(bytes: &[u8]) -> f64 {
+    let bits = (0..8).fold(0u64, |acc, i| acc | ((bytes[i] as u64) << (8 * i)));
+    unsafe { ::std::mem::transmute::<u64, f64>(bits) }
+}
+
+// Loads a MERL BRDF database file. The file is a header of three 32-bit
+// table dimensions followed by all of the red values, then all of the
+// green values, and then all of the blue values as doubles.
+fn load_merl<R: Read>(filename: &str, f: R) -> Result<MeasuredData, MeasuredError> {
+    let mut reader = BufReader::new(f);
+
+    let mut header = [0u8; 12];
+    try!(reader.read_exact(&mut header));
+    let dims = [read_u32_le(&header[0..4]) as usize,
+                read_u32_le(&header[4..8]) as usize,
+                read_u32_le(&header[8..12]) as usize];
+
+    let n = dims[0] * dims[1] * dims[2];
+    if n != 90 * 90 * 180 {
+        return Err(MeasuredError::Format(format!(
+            "{}: dimensions don't match expected MERL sizes: {} x {} x {}",
+            filename, dims[0], dims[1], dims[2])));
+    }
+
+    let scales = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+    let mut data = vec![0f32; 3 * n];
+
+    // Read the data one phi_d row at a time rather than buffering the
+    // whole file, and interleave the channels as we go.
+    let chunk_size = 2 * dims[2];
+    let mut chunk = vec![0u8; 8 * chunk_size];
+    for c in 0..3 {
+        let mut offset = 0;
+        for _ in 0..(n / chunk_size) {
+            try!(reader.read_exact(&mut chunk));
+            for j in 0..chunk_size {
+                let v = read_f64_le(&chunk[(8 * j)..(8 * j + 8)]) * scales[c];
+                data[3 * offset + c] = v.max(0.0) as f32;
+                offset += 1;
+            }
+        }
+    }
+
+    Ok(MeasuredData::RegularHalfangle {
+        num_theta_h: dims[0],
+        num_theta_d: dims[1],
+        num_phi_d: dims[2],
+        data: Arc::new(data)
+    })
+}
+
+fn read_float_file<R: Read>(filename: &str, f: R) -> Result<Vec<f32>, MeasuredError> {
+    let mut contents = String::new();
+    try!(BufReader::new(f).read_to_string(&mut contents));
+
+    let mut values = Vec::new();
+    for (line_num, line) in contents.lines().enumerate() {
+        // Everything after a '#' is a comment
+        let data = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line
+        };
+
+        for tok in data.split_whitespace() {
+            match tok.parse::<f32>() {
+                Ok(v) => values.push(v),
+                Err(_) => return Err(MeasuredError::Format(format!(
+                    "{}:{}: unexpected token '{}'", filename, line_num + 1, tok)))
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+// Loads irregularly sampled isotropic BRDF data as written out by PBRT-v2:
+// the number of wavelengths, the wavelengths themselves, and then for each
+// sample (theta_i, phi_i, theta_o, phi_o) followed by one value for each of
+// the wavelengths.
+fn load_irregular_isotropic<R: Read>(filename: &str, f: R)
+                                     -> Result<MeasuredData, MeasuredError> {
+    let values = try!(read_float_file(filename, f));
+    if values.len() == 0 {
+        return Err(MeasuredError::Format(format!("{}: no data", filename)));
+    }
+
+    let num_wls = values[0] as usize;
+    if values[0] < 1.0 || values.len() < 1 + num_wls ||
+        (values.len() - 1 - num_wls) % (4 + num_wls) != 0 {
+        return Err(MeasuredError::Format(format!(
+            "{}: excess or insufficient data in file", filename)));
+    }
+
+    let wls = &values[1..(1 + num_wls)];
+    let samples: Vec<IrregIsotropicSample> =
+        values[(1 + num_wls)..].chunks(4 + num_wls).map(|s| {
+            let (theta_i, phi_i, theta_o, phi_o) = (s[0], s[1], s[2], s[3]);
+            let wo = spherical_direction(theta_o.sin(), theta_o.cos(), phi_o);
+            let wi = spherical_direction(theta_i.sin(), theta_i.cos(), phi_i);
+
+            let spd: Vec<(f32, f32)> =
+                wls.iter().cloned().zip(s[4..].iter().cloned()).collect();
+            let v = Spectrum::from_samples(&spd).into_rgb_spectrum();
+            IrregIsotropicSample::new(&brdf_remap(&wo, &wi), &v)
+        }).collect();
+
+    if samples.len() == 0 {
+        return Err(MeasuredError::Format(format!("{}: no BRDF samples", filename)));
+    }
+
+    Ok(MeasuredData::ThetaPhi(Arc::new(KdTree::new(&samples))))
+}
+
+fn load_measured_data(filename: &str) -> Result<Arc<MeasuredData>, MeasuredError> {
+    if let Some(data) = loaded_data().lock().unwrap().get(filename) {
+        return Ok(data.clone());
+    }
+
+    // Loading takes a while, so don't hold up materials that want other
+    // files in the meantime
+    let f = try!(File::open(filename));
+    let is_merl = match Path::new(filename).extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("binary") || ext.eq_ignore_ascii_case("brdf"),
+        None => false
+    };
+
+    let data = if is_merl {
+        try!(load_merl(filename, f))
+    } else {
+        try!(load_irregular_isotropic(filename, f))
+    };
+
+    // If somebody else loaded the same file while we were at it, share
+    // their copy rather than keeping two around
+    let mut cache = loaded_data().lock().unwrap();
+    Ok(cache.entry(filename.to_string()).or_insert_with(|| Arc::new(data)).clone())
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct MeasuredMaterial {
-    theta_phi_data: KdTree<IrregIsotropicSample>,
-    regular_halfangle_data: Vec<f32>,
-    num_theta_h: usize,
-    num_theta_d: usize,
-    num_phi_d: usize,
+    data: Arc<MeasuredData>,
     bump_map: Option<Arc<Texture<f32>>>
 }
 
 impl MeasuredMaterial {
-    pub fn new(filename: String, b: Option<Arc<Texture<f32>>>) -> MeasuredMaterial {
-        // If we want to follow the datatype for the measured brdf data
-        // used in PBRT-v2, then we need to follow the code given in
-        // materials/measured.cpp...
-        unimplemented!()
+    pub fn new(filename: String, b: Option<Arc<Texture<f32>>>)
+               -> Result<MeasuredMaterial, MeasuredError> {
+        let data = try!(load_measured_data(&filename));
+        Ok(MeasuredMaterial {
+            data: data,
+            bump_map: b
+        })
+    }
+
+    pub fn create(mp: &ParamSet) -> Result<MeasuredMaterial, MeasuredError> {
+        MeasuredMaterial::new(mp.find_string("filename", ""),
+                              mp.get_float_texture_or_none("bumpmap"))
     }
 
     pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
@@ -40,15 +243,66 @@ impl MeasuredMaterial {
 
         let mut bsdf = BSDF::new(dgs.clone(), dg_geom.nn);
 
-        if self.regular_halfangle_data.len() > 0 {
-            let data = self.regular_halfangle_data.clone();
-            bsdf.add_bxdf(RegularHalfangle::new(self.num_theta_h,
-                                                self.num_theta_d,
-                                                self.num_phi_d, data));
-        } else {
-            bsdf.add_bxdf(IrregIsotropic::new(self.theta_phi_data.clone()));
+        match &*self.data {
+            &MeasuredData::RegularHalfangle { num_theta_h, num_theta_d,
+                                              num_phi_d, ref data } => {
+                bsdf.add_bxdf(RegularHalfangle::new(num_theta_h, num_theta_d,
+                                                    num_phi_d, data.clone()));
+            },
+            &MeasuredData::ThetaPhi(ref data) => {
+                bsdf.add_bxdf(IrregIsotropic::new(data.clone()));
+            }
         }
 
         Some(bsdf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_can_read_irregular_isotropic_data() {
+        let file = "# Two wavelengths\n\
+                    2 400 700\n\
+                    0.1 0.0 0.2 1.0   0.5 0.5 # first sample\n\
+                    0.3 0.0 0.4 2.0   0.25 0.25\n";
+        match load_irregular_isotropic("test.brdf", file.as_bytes()).unwrap() {
+            MeasuredData::ThetaPhi(tree) => assert_eq!(tree.size(), 2),
+            _ => panic!("Expected irregular isotropic data")
+        }
+    }
+
+    #[test]
+    fn it_reports_malformed_irregular_data() {
+        // Second sample is missing a value
+        let file = "2 400 700\n0.1 0.0 0.2 1.0 0.5 0.5\n0.3 0.0 0.4 2.0 0.25\n";
+        assert!(load_irregular_isotropic("bad", file.as_bytes()).is_err());
+
+        let garbage = "2 400 700\n0.1 0.0 zero 1.0 0.5 0.5\n";
+        match load_irregular_isotropic("bad", garbage.as_bytes()) {
+            Err(MeasuredError::Format(msg)) => assert!(msg.contains("bad:2")),
+            _ => panic!("Expected a format error")
+        }
+    }
+
+    #[test]
+    fn it_reports_truncated_merl_data() {
+        let mut bytes = Vec::new();
+        for d in [90u32, 90, 180].iter() {
+            bytes.extend_from_slice(&[*d as u8, (*d >> 8) as u8, 0, 0]);
+        }
+        bytes.extend_from_slice(&[0u8; 64]);
+        match load_merl("short.binary", &bytes[..]) {
+            Err(MeasuredError::Io(_)) => (),
+            _ => panic!("Expected an I/O error")
+        }
+    }
+
+    #[test]
+    fn it_reports_missing_files() {
+        let r = MeasuredMaterial::new("this/file/does/not/exist.binary".to_string(), None);
+        assert!(r.is_err());
+    }
+}
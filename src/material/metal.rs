@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::microfacet::Microfacet;
+use bsdf::microfacet::MicrofacetDistribution;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+
+use material::bump;
+
+// Measured complex index of refraction for a handful of common metals,
+// given as (wavelength in nm, value) pairs. The copper data is the part of
+// the table that ships with PBRT-v2 that falls in the visible range; the
+// others are resampled to cover the same range.
+
+const COPPER_N: [(f32, f32); 29] = [
+    (393.6, 1.174375), (399.9, 1.175), (406.5, 1.1775), (413.3, 1.18),
+    (420.3, 1.178125), (427.5, 1.175), (435.0, 1.172812), (442.8, 1.17),
+    (450.9, 1.165312), (459.2, 1.16), (467.9, 1.155312), (476.9, 1.15),
+    (486.2, 1.142812), (495.9, 1.135), (506.1, 1.131562), (516.6, 1.12),
+    (527.6, 1.092437), (539.1, 1.04), (551.0, 0.950375), (563.6, 0.826),
+    (576.7, 0.645875), (590.4, 0.468), (604.8, 0.35125), (619.9, 0.272),
+    (635.8, 0.230813), (652.5, 0.214), (670.2, 0.20925), (688.8, 0.213),
+    (708.5, 0.21625)];
+
+const COPPER_K: [(f32, f32); 29] = [
+    (393.6, 2.177188), (399.9, 2.13), (406.5, 2.160063), (413.3, 2.21),
+    (420.3, 2.249938), (427.5, 2.289), (435.0, 2.326), (442.8, 2.362),
+    (450.9, 2.397625), (459.2, 2.433), (467.9, 2.469187), (476.9, 2.504),
+    (486.2, 2.535875), (495.9, 2.564), (506.1, 2.589625), (516.6, 2.605),
+    (527.6, 2.595562), (539.1, 2.583), (551.0, 2.5765), (563.6, 2.599),
+    (576.7, 2.678062), (590.4, 2.809), (604.8, 3.01075), (619.9, 3.24),
+    (635.8, 3.458187), (652.5, 3.67), (670.2, 3.863125), (688.8, 4.05),
+    (708.5, 4.239563)];
+
+const GOLD_N: [(f32, f32); 13] = [
+    (400.0, 1.470), (425.0, 1.460), (450.0, 1.380), (475.0, 1.220),
+    (500.0, 0.950), (525.0, 0.600), (550.0, 0.400), (575.0, 0.290),
+    (600.0, 0.220), (625.0, 0.190), (650.0, 0.170), (675.0, 0.160),
+    (700.0, 0.160)];
+
+const GOLD_K: [(f32, f32); 13] = [
+    (400.0, 1.950), (425.0, 1.950), (450.0, 1.910), (475.0, 1.850),
+    (500.0, 1.840), (525.0, 2.130), (550.0, 2.450), (575.0, 2.760),
+    (600.0, 2.990), (625.0, 3.220), (650.0, 3.450), (675.0, 3.670),
+    (700.0, 3.900)];
+
+const SILVER_N: [(f32, f32); 13] = [
+    (400.0, 0.050), (425.0, 0.045), (450.0, 0.040), (475.0, 0.045),
+    (500.0, 0.050), (525.0, 0.055), (550.0, 0.060), (575.0, 0.058),
+    (600.0, 0.055), (625.0, 0.052), (650.0, 0.050), (675.0, 0.045),
+    (700.0, 0.040)];
+
+const SILVER_K: [(f32, f32); 13] = [
+    (400.0, 2.100), (425.0, 2.380), (450.0, 2.650), (475.0, 2.870),
+    (500.0, 3.090), (525.0, 3.340), (550.0, 3.590), (575.0, 3.800),
+    (600.0, 4.000), (625.0, 4.200), (650.0, 4.400), (675.0, 4.600),
+    (700.0, 4.800)];
+
+const ALUMINUM_N: [(f32, f32); 13] = [
+    (400.0, 0.490), (425.0, 0.555), (450.0, 0.620), (475.0, 0.695),
+    (500.0, 0.770), (525.0, 0.865), (550.0, 0.960), (575.0, 1.080),
+    (600.0, 1.200), (625.0, 1.345), (650.0, 1.490), (675.0, 1.660),
+    (700.0, 1.830)];
+
+const ALUMINUM_K: [(f32, f32); 13] = [
+    (400.0, 4.860), (425.0, 5.165), (450.0, 5.470), (475.0, 5.775),
+    (500.0, 6.080), (525.0, 6.385), (550.0, 6.690), (575.0, 6.975),
+    (600.0, 7.260), (625.0, 7.540), (650.0, 7.820), (675.0, 8.065),
+    (700.0, 8.310)];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metal {
+    Copper,
+    Gold,
+    Silver,
+    Aluminum
+}
+
+impl Metal {
+    fn samples(&self) -> (&'static [(f32, f32)], &'static [(f32, f32)]) {
+        match self {
+            &Metal::Copper => (&COPPER_N, &COPPER_K),
+            &Metal::Gold => (&GOLD_N, &GOLD_K),
+            &Metal::Silver => (&SILVER_N, &SILVER_K),
+            &Metal::Aluminum => (&ALUMINUM_N, &ALUMINUM_K)
+        }
+    }
+
+    // The rest of the BSDF code does arithmetic with RGB spectra, so
+    // we bring the tabulated data over once here rather than at
+    // every shading point.
+    pub fn eta(&self) -> Spectrum {
+        Spectrum::from_samples(self.samples().0).into_rgb_spectrum()
+    }
+
+    pub fn k(&self) -> Spectrum {
+        Spectrum::from_samples(self.samples().1).into_rgb_spectrum()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MetalMaterial {
+    eta: Arc<Texture<Spectrum>>,
+    k: Arc<Texture<Spectrum>>,
+    roughness: Arc<Texture<f32>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl MetalMaterial {
+    pub fn new(eta: Arc<Texture<Spectrum>>,
+               k: Arc<Texture<Spectrum>>,
+               rough: Arc<Texture<f32>>,
+               bm: Option<Arc<Texture<f32>>>) -> MetalMaterial {
+        MetalMaterial {
+            eta: eta, k: k, roughness: rough, bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> MetalMaterial {
+        MetalMaterial::new(mp.get_spectrum_texture("eta", Metal::Copper.eta()),
+                           mp.get_spectrum_texture("k", Metal::Copper.k()),
+                           mp.get_float_texture("roughness", 0.01),
+                           mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let mut bsdf = BSDF::new(dgs.clone(), dg_geom.nn);
+
+        let rough = self.roughness.evaluate(&dgs);
+        let fresnel = Fresnel::conductor(&self.eta.evaluate(&dgs),
+                                         &self.k.evaluate(&dgs));
+        bsdf.add_bxdf(Microfacet::new(Spectrum::from(1.0), fresnel,
+                                      MicrofacetDistribution::blinn(1.0 / rough)));
+
+        Some(bsdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metals_have_sensible_indices() {
+        for m in [Metal::Copper, Metal::Gold, Metal::Silver, Metal::Aluminum].iter() {
+            let eta = m.eta();
+            let k = m.k();
+            assert!(!eta.has_nans());
+            assert!(!k.has_nans());
+
+            // Metals are strongly absorbing across the visible range
+            for i in 0..3 {
+                assert!(k[i] > 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn copper_is_reddish() {
+        let fr = Fresnel::conductor(&Metal::Copper.eta(), &Metal::Copper.k());
+        let r = fr.evaluate(1.0).to_rgb();
+        assert!(r[0] > r[1]);
+        assert!(r[0] > r[2]);
+    }
+}
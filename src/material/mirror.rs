@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::specular::SpecularReflection;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MirrorMaterial {
+    k_r: Arc<Texture<Spectrum>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl MirrorMaterial {
+    pub fn new(kr: Arc<Texture<Spectrum>>,
+               bm: Option<Arc<Texture<f32>>>) -> MirrorMaterial {
+        MirrorMaterial { k_r: kr, bump_map: bm }
+    }
+
+    pub fn create(mp: &ParamSet) -> MirrorMaterial {
+        MirrorMaterial::new(mp.get_spectrum_texture("Kr", Spectrum::from(0.9)),
+                            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let mut bsdf = BSDF::new(dgs.clone(), dg_geom.nn);
+
+        // A perfect mirror reflects everything regardless of angle
+        let r = self.k_r.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !r.is_black() {
+            bsdf.add_bxdf(SpecularReflection::new(r, Fresnel::noop()));
+        }
+
+        Some(bsdf)
+    }
+}
@@ -1,19 +1,49 @@
+mod disney;
+mod glass;
+mod hair;
+mod kdsubsurface;
 mod matte;
 mod measured;
+mod metal;
+mod mirror;
 mod mix;
 mod plastic;
+mod shinymetal;
+mod substrate;
+mod subsurface;
+mod translucent;
+mod uber;
 
 use std::sync::Arc;
 
 use bsdf::BSDF;
 use bsdf::bssrdf::BSSRDF;
 use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
 use geometry::vector::*;
 use geometry::normal::*;
 use spectrum::Spectrum;
 use texture::Texture;
 
+use material::disney::DisneyMaterial;
+use material::glass::GlassMaterial;
+use material::hair::HairMaterial;
+use material::kdsubsurface::KdSubsurfaceMaterial;
 use material::matte::MatteMaterial;
+use material::measured::MeasuredMaterial;
+use material::metal::MetalMaterial;
+use material::mirror::MirrorMaterial;
+use material::plastic::PlasticMaterial;
+use material::shinymetal::ShinyMetalMaterial;
+use material::substrate::SubstrateMaterial;
+use material::subsurface::SubsurfaceMaterial;
+use material::translucent::TranslucentMaterial;
+use material::uber::UberMaterial;
+
+pub use material::hair::HairColor;
+pub use material::measured::MeasuredError;
+pub use material::metal::Metal;
+pub use material::subsurface::ScatteringMedium;
 
 pub fn bump(d: &Texture<f32>, dg_geom: &DifferentialGeometry,
             dg_shading: &DifferentialGeometry) -> DifferentialGeometry {
@@ -69,6 +99,19 @@ pub fn bump(d: &Texture<f32>, dg_geom: &DifferentialGeometry,
 #[derive(Clone, PartialEq, Debug)]
 pub enum Material {
     Matte(MatteMaterial),
+    Glass(GlassMaterial),
+    Mirror(MirrorMaterial),
+    Metal(MetalMaterial),
+    Measured(MeasuredMaterial),
+    Plastic(PlasticMaterial),
+    Substrate(SubstrateMaterial),
+    Uber(UberMaterial),
+    Translucent(TranslucentMaterial),
+    ShinyMetal(ShinyMetalMaterial),
+    Disney(DisneyMaterial),
+    Subsurface(SubsurfaceMaterial),
+    KdSubsurface(KdSubsurfaceMaterial),
+    Hair(HairMaterial),
     Broken
 }
 
@@ -79,6 +122,155 @@ impl Material {
         Material::Matte(MatteMaterial::new(kd, sig, bump_map))
     }
 
+    pub fn glass(kr: Arc<Texture<Spectrum>>,
+                 kt: Arc<Texture<Spectrum>>,
+                 index: Arc<Texture<f32>>,
+                 bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Glass(GlassMaterial::new(kr, kt, index, bump_map))
+    }
+
+    pub fn mirror(kr: Arc<Texture<Spectrum>>,
+                  bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Mirror(MirrorMaterial::new(kr, bump_map))
+    }
+
+    pub fn metal(eta: Arc<Texture<Spectrum>>,
+                 k: Arc<Texture<Spectrum>>,
+                 rough: Arc<Texture<f32>>,
+                 bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Metal(MetalMaterial::new(eta, k, rough, bump_map))
+    }
+
+    pub fn measured(filename: String, bump_map: Option<Arc<Texture<f32>>>)
+                    -> Result<Material, MeasuredError> {
+        MeasuredMaterial::new(filename, bump_map).map(Material::Measured)
+    }
+
+    pub fn plastic(kd: Arc<Texture<Spectrum>>,
+                   ks: Arc<Texture<Spectrum>>,
+                   rough: Arc<Texture<f32>>,
+                   bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Plastic(PlasticMaterial::new(kd, ks, rough, bump_map))
+    }
+
+    pub fn substrate(kd: Arc<Texture<Spectrum>>,
+                     ks: Arc<Texture<Spectrum>>,
+                     urough: Arc<Texture<f32>>,
+                     vrough: Arc<Texture<f32>>,
+                     bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Substrate(SubstrateMaterial::new(kd, ks, urough, vrough, bump_map))
+    }
+
+    pub fn uber(kd: Arc<Texture<Spectrum>>,
+                ks: Arc<Texture<Spectrum>>,
+                kr: Arc<Texture<Spectrum>>,
+                kt: Arc<Texture<Spectrum>>,
+                rough: Arc<Texture<f32>>,
+                opacity: Arc<Texture<Spectrum>>,
+                eta: Arc<Texture<f32>>,
+                bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Uber(UberMaterial::new(kd, ks, kr, kt, rough, opacity, eta, bump_map))
+    }
+
+    pub fn translucent(kd: Arc<Texture<Spectrum>>,
+                       ks: Arc<Texture<Spectrum>>,
+                       rough: Arc<Texture<f32>>,
+                       reflect: Arc<Texture<Spectrum>>,
+                       transmit: Arc<Texture<Spectrum>>,
+                       bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Translucent(TranslucentMaterial::new(kd, ks, rough, reflect,
+                                                       transmit, bump_map))
+    }
+
+    pub fn shiny_metal(ks: Arc<Texture<Spectrum>>,
+                       rough: Arc<Texture<f32>>,
+                       kr: Arc<Texture<Spectrum>>,
+                       bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::ShinyMetal(ShinyMetalMaterial::new(ks, rough, kr, bump_map))
+    }
+
+    pub fn disney(color: Arc<Texture<Spectrum>>,
+                  metallic: Arc<Texture<f32>>,
+                  specular: Arc<Texture<f32>>,
+                  roughness: Arc<Texture<f32>>,
+                  specular_tint: Arc<Texture<f32>>,
+                  anisotropic: Arc<Texture<f32>>,
+                  sheen: Arc<Texture<f32>>,
+                  sheen_tint: Arc<Texture<f32>>,
+                  clearcoat: Arc<Texture<f32>>,
+                  clearcoat_gloss: Arc<Texture<f32>>,
+                  spec_trans: Arc<Texture<f32>>,
+                  subsurface: Arc<Texture<f32>>,
+                  bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Disney(DisneyMaterial::new(
+            color, metallic, specular, roughness, specular_tint, anisotropic,
+            sheen, sheen_tint, clearcoat, clearcoat_gloss, spec_trans,
+            subsurface, bump_map))
+    }
+
+    pub fn subsurface(scale: f32,
+                      kr: Arc<Texture<Spectrum>>,
+                      sigma_a: Arc<Texture<Spectrum>>,
+                      sigma_prime_s: Arc<Texture<Spectrum>>,
+                      eta: f32,
+                      bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Subsurface(SubsurfaceMaterial::new(scale, kr, sigma_a, sigma_prime_s,
+                                                     eta, bump_map))
+    }
+
+    // Subsurface scattering using one of the measured media such as
+    // skin, marble or ketchup. The coefficients are given in inverse
+    // millimeters so scale converts them to scene units.
+    pub fn subsurface_medium(medium: ScatteringMedium,
+                             scale: f32,
+                             kr: Arc<Texture<Spectrum>>,
+                             eta: f32,
+                             bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::Subsurface(SubsurfaceMaterial::from_medium(medium, scale, kr,
+                                                             eta, bump_map))
+    }
+
+    pub fn kd_subsurface(kd: Arc<Texture<Spectrum>>,
+                         kr: Arc<Texture<Spectrum>>,
+                         mean_free_path: Arc<Texture<f32>>,
+                         eta: f32,
+                         bump_map: Option<Arc<Texture<f32>>>) -> Material {
+        Material::KdSubsurface(KdSubsurfaceMaterial::new(kd, kr, mean_free_path,
+                                                         eta, bump_map))
+    }
+
+    pub fn hair(color: HairColor,
+                eta: Arc<Texture<f32>>,
+                beta_m: Arc<Texture<f32>>,
+                beta_n: Arc<Texture<f32>>,
+                alpha: Arc<Texture<f32>>) -> Material {
+        Material::Hair(HairMaterial::new(color, eta, beta_m, beta_n, alpha))
+    }
+
+    // Creates a material from the parameters given to a Material or
+    // MakeNamedMaterial directive in a scene description.
+    pub fn create(name: &str, mp: &ParamSet) -> Result<Material, String> {
+        match name {
+            "matte" => Ok(Material::Matte(MatteMaterial::create(mp))),
+            "plastic" => Ok(Material::Plastic(PlasticMaterial::create(mp))),
+            "glass" => Ok(Material::Glass(GlassMaterial::create(mp))),
+            "mirror" => Ok(Material::Mirror(MirrorMaterial::create(mp))),
+            "metal" => Ok(Material::Metal(MetalMaterial::create(mp))),
+            "measured" => MeasuredMaterial::create(mp)
+                .map(Material::Measured)
+                .map_err(|e| format!("{}", e)),
+            "substrate" => Ok(Material::Substrate(SubstrateMaterial::create(mp))),
+            "uber" => Ok(Material::Uber(UberMaterial::create(mp))),
+            "translucent" => Ok(Material::Translucent(TranslucentMaterial::create(mp))),
+            "shinymetal" => Ok(Material::ShinyMetal(ShinyMetalMaterial::create(mp))),
+            "disney" => Ok(Material::Disney(DisneyMaterial::create(mp))),
+            "subsurface" => Ok(Material::Subsurface(SubsurfaceMaterial::create(mp))),
+            "kdsubsurface" => Ok(Material::KdSubsurface(KdSubsurfaceMaterial::create(mp))),
+            "hair" => Ok(Material::Hair(HairMaterial::create(mp))),
+            _ => Err(format!("Material \"{}\" unknown.", name))
+        }
+    }
+
     // !FIXME!
     pub fn broken() -> Material { Material::Broken }
 
@@ -86,12 +278,99 @@ impl Material {
                     dgs: DifferentialGeometry) -> Option<BSDF> {
         match self {
             &Material::Matte(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Glass(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Mirror(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Metal(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Measured(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Plastic(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Substrate(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Uber(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Translucent(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::ShinyMetal(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Disney(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Subsurface(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::KdSubsurface(ref mat) => mat.get_bsdf(dg, dgs),
+            &Material::Hair(ref mat) => mat.get_bsdf(dg, dgs),
             _ => unimplemented!()
         }
     }
 
     pub fn get_bssrdf(&self, dg: DifferentialGeometry,
                       dgs: DifferentialGeometry) -> Option<BSSRDF> {
-        None
+        match self {
+            &Material::Subsurface(ref mat) => mat.get_bssrdf(dg, dgs),
+            &Material::KdSubsurface(ref mat) => mat.get_bssrdf(dg, dgs),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diff_geom::DifferentialGeometry;
+    use paramset::ParamSet;
+    use spectrum::Spectrum;
+
+    #[test]
+    fn it_can_be_created_from_params() {
+        let mut mp = ParamSet::new();
+        mp.add_spectrum("Kd", Spectrum::from(0.8));
+        mp.add_float("roughness", 0.05);
+
+        for name in ["matte", "plastic", "glass", "mirror", "metal",
+                     "substrate", "uber", "translucent", "shinymetal", "disney", "subsurface", "kdsubsurface", "hair"].iter() {
+            assert!(Material::create(name, &mp).is_ok());
+        }
+
+        match Material::create("substrate", &mp) {
+            Ok(Material::Substrate(_)) => (),
+            _ => panic!("Expected a substrate material")
+        }
+
+        assert!(Material::create("velvet", &mp).is_err());
+        assert!(Material::create("measured", &mp).is_err());
+    }
+
+    #[test]
+    fn created_materials_have_expected_lobes() {
+        let dg = DifferentialGeometry::new();
+        let components = |name: &str, mp: &ParamSet| {
+            Material::create(name, mp).unwrap()
+                .get_bsdf(dg.clone(), dg.clone()).unwrap().num_components()
+        };
+
+        let mp = ParamSet::new();
+        assert_eq!(components("substrate", &mp), 1);
+        assert_eq!(components("shinymetal", &mp), 2);
+        assert_eq!(components("translucent", &mp), 4);
+
+        // Default uber is opaque with only diffuse and glossy lobes
+        assert_eq!(components("uber", &mp), 2);
+
+        let mut translucent = ParamSet::new();
+        translucent.add_spectrum("opacity", Spectrum::from(0.5));
+        assert_eq!(components("uber", &translucent), 3);
+
+        // All of the principled lobes are folded into a single component
+        assert_eq!(components("disney", &mp), 1);
+        let mut glassy = ParamSet::new();
+        glassy.add_float("spectrans", 0.8);
+        assert_eq!(components("disney", &glassy), 2);
+    }
+
+    #[test]
+    fn only_translucent_materials_have_bssrdfs() {
+        let dg = DifferentialGeometry::new();
+        let mp = ParamSet::new();
+        let has_bssrdf = |name: &str| {
+            Material::create(name, &mp).unwrap()
+                .get_bssrdf(dg.clone(), dg.clone()).is_some()
+        };
+
+        assert!(has_bssrdf("subsurface"));
+        assert!(has_bssrdf("kdsubsurface"));
+        assert!(!has_bssrdf("matte"));
+        assert!(!has_bssrdf("disney"));
     }
 }
@@ -6,6 +6,7 @@ use bsdf::lambertian::Lambertian;
 use bsdf::microfacet::Microfacet;
 use bsdf::microfacet::MicrofacetDistribution;
 use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
 use spectrum::Spectrum;
 use texture::Texture;
 use utils::Clamp;
@@ -30,6 +31,13 @@ impl PlasticMaterial {
         }
     }
 
+    pub fn create(mp: &ParamSet) -> PlasticMaterial {
+        PlasticMaterial::new(mp.get_spectrum_texture("Kd", Spectrum::from(0.25)),
+                             mp.get_spectrum_texture("Ks", Spectrum::from(0.25)),
+                             mp.get_float_texture("roughness", 0.1),
+                             mp.get_float_texture_or_none("bumpmap"))
+    }
+
     pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
                     dg_shading: DifferentialGeometry) -> Option<BSDF> {
         // Allocate bsdf possibly doing bump mapping with bump map
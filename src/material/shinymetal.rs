@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::fresnel::approx_eta;
+use bsdf::microfacet::Microfacet;
+use bsdf::microfacet::MicrofacetDistribution;
+use bsdf::specular::SpecularReflection;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShinyMetalMaterial {
+    k_s: Arc<Texture<Spectrum>>,
+    k_r: Arc<Texture<Spectrum>>,
+    roughness: Arc<Texture<f32>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl ShinyMetalMaterial {
+    pub fn new(ks: Arc<Texture<Spectrum>>,
+               rough: Arc<Texture<f32>>,
+               kr: Arc<Texture<Spectrum>>,
+               bm: Option<Arc<Texture<f32>>>) -> ShinyMetalMaterial {
+        ShinyMetalMaterial {
+            k_s: ks, k_r: kr, roughness: rough, bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> ShinyMetalMaterial {
+        ShinyMetalMaterial::new(
+            mp.get_spectrum_texture("Ks", Spectrum::from(1.0)),
+            mp.get_float_texture("roughness", 0.1),
+            mp.get_spectrum_texture("Kr", Spectrum::from(1.0)),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let mut bsdf = BSDF::new(dgs.clone(), dg_geom.nn);
+
+        let spec = self.k_s.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        let rough = self.roughness.evaluate(&dgs);
+        let r = self.k_r.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+
+        // Rather than asking for measured data, treat the given colors as
+        // normal incidence reflectance of a non-absorbing conductor.
+        let k = Spectrum::from(0.0);
+        let fr_mf = Fresnel::conductor(&approx_eta(&spec), &k);
+        let fr_sr = Fresnel::conductor(&approx_eta(&r), &k);
+
+        bsdf.add_bxdf(Microfacet::new(Spectrum::from(1.0), fr_mf,
+                                      MicrofacetDistribution::blinn(1.0 / rough)));
+        bsdf.add_bxdf(SpecularReflection::new(Spectrum::from(1.0), fr_sr));
+
+        Some(bsdf)
+    }
+}
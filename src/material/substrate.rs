@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::microfacet::FresnelBlend;
+use bsdf::microfacet::MicrofacetDistribution;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SubstrateMaterial {
+    k_d: Arc<Texture<Spectrum>>,
+    k_s: Arc<Texture<Spectrum>>,
+    nu: Arc<Texture<f32>>,
+    nv: Arc<Texture<f32>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl SubstrateMaterial {
+    pub fn new(kd: Arc<Texture<Spectrum>>,
+               ks: Arc<Texture<Spectrum>>,
+               u: Arc<Texture<f32>>,
+               v: Arc<Texture<f32>>,
+               bm: Option<Arc<Texture<f32>>>) -> SubstrateMaterial {
+        SubstrateMaterial {
+            k_d: kd, k_s: ks, nu: u, nv: v, bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> SubstrateMaterial {
+        SubstrateMaterial::new(
+            mp.get_spectrum_texture("Kd", Spectrum::from(0.5)),
+            mp.get_spectrum_texture("Ks", Spectrum::from(0.5)),
+            mp.get_float_texture("uroughness", 0.1),
+            mp.get_float_texture("vroughness", 0.1),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let mut bsdf = BSDF::new(dgs.clone(), dg_geom.nn);
+
+        let d = self.k_d.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        let s = self.k_s.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        let u = self.nu.evaluate(&dgs);
+        let v = self.nv.evaluate(&dgs);
+
+        if !d.is_black() || !s.is_black() {
+            let dist = MicrofacetDistribution::anisotropic(1.0 / u, 1.0 / v);
+            bsdf.add_bxdf(FresnelBlend::new(d, s, dist));
+        }
+
+        Some(bsdf)
+    }
+}
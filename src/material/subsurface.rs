@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::bssrdf::BSSRDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::specular::SpecularReflection;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+// Measured scattering coefficients from Jensen et al. "A Practical Model
+// for Subsurface Light Transport" (2001), in inverse millimeters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScatteringMedium {
+    Apple,
+    Chicken1,
+    Chicken2,
+    Cream,
+    Ketchup,
+    Marble,
+    Potato,
+    Skimmilk,
+    Skin1,
+    Skin2,
+    Spectralon,
+    Wholemilk
+}
+
+impl ScatteringMedium {
+    pub fn from_name(name: &str) -> Option<ScatteringMedium> {
+        match name {
+            "Apple" => Some(ScatteringMedium::Apple),
+            "Chicken1" => Some(ScatteringMedium::Chicken1),
+            "Chicken2" => Some(ScatteringMedium::Chicken2),
+            "Cream" => Some(ScatteringMedium::Cream),
+            "Ketchup" => Some(ScatteringMedium::Ketchup),
+            "Marble" => Some(ScatteringMedium::Marble),
+            "Potato" => Some(ScatteringMedium::Potato),
+            "Skimmilk" => Some(ScatteringMedium::Skimmilk),
+            "Skin1" => Some(ScatteringMedium::Skin1),
+            "Skin2" => Some(ScatteringMedium::Skin2),
+            "Spectralon" => Some(ScatteringMedium::Spectralon),
+            "Wholemilk" => Some(ScatteringMedium::Wholemilk),
+            _ => None
+        }
+    }
+
+    fn coefficients(&self) -> ([f32; 3], [f32; 3]) {
+        // (sigma_prime_s, sigma_a)
+        match self {
+            &ScatteringMedium::Apple => ([2.29, 2.39, 1.97], [0.0030, 0.0034, 0.046]),
+            &ScatteringMedium::Chicken1 => ([0.15, 0.21, 0.38], [0.015, 0.077, 0.19]),
+            &ScatteringMedium::Chicken2 => ([0.19, 0.25, 0.32], [0.018, 0.088, 0.20]),
+            &ScatteringMedium::Cream => ([7.38, 5.47, 3.15], [0.0002, 0.0028, 0.0163]),
+            &ScatteringMedium::Ketchup => ([0.18, 0.07, 0.03], [0.061, 0.97, 1.45]),
+            &ScatteringMedium::Marble => ([2.19, 2.62, 3.00], [0.0021, 0.0041, 0.0071]),
+            &ScatteringMedium::Potato => ([0.68, 0.70, 0.55], [0.0024, 0.0090, 0.12]),
+            &ScatteringMedium::Skimmilk => ([0.70, 1.22, 1.90], [0.0014, 0.0025, 0.0142]),
+            &ScatteringMedium::Skin1 => ([0.74, 0.88, 1.01], [0.032, 0.17, 0.48]),
+            &ScatteringMedium::Skin2 => ([1.09, 1.59, 1.79], [0.013, 0.070, 0.145]),
+            &ScatteringMedium::Spectralon => ([11.6, 20.4, 14.9], [0.00, 0.00, 0.00]),
+            &ScatteringMedium::Wholemilk => ([2.55, 3.21, 3.77], [0.0011, 0.0024, 0.014])
+        }
+    }
+
+    pub fn sigma_a(&self) -> Spectrum { Spectrum::from_rgb(self.coefficients().1) }
+    pub fn sigma_prime_s(&self) -> Spectrum { Spectrum::from_rgb(self.coefficients().0) }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SubsurfaceMaterial {
+    scale: f32,
+    eta: f32,
+    k_r: Arc<Texture<Spectrum>>,
+    sigma_a: Arc<Texture<Spectrum>>,
+    sigma_prime_s: Arc<Texture<Spectrum>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl SubsurfaceMaterial {
+    pub fn new(sc: f32, kr: Arc<Texture<Spectrum>>,
+               sa: Arc<Texture<Spectrum>>,
+               sps: Arc<Texture<Spectrum>>,
+               et: f32, bm: Option<Arc<Texture<f32>>>) -> SubsurfaceMaterial {
+        SubsurfaceMaterial {
+            scale: sc,
+            eta: et,
+            k_r: kr,
+            sigma_a: sa,
+            sigma_prime_s: sps,
+            bump_map: bm
+        }
+    }
+
+    pub fn from_medium(medium: ScatteringMedium, sc: f32,
+                       kr: Arc<Texture<Spectrum>>, et: f32,
+                       bm: Option<Arc<Texture<f32>>>) -> SubsurfaceMaterial {
+        SubsurfaceMaterial::new(sc, kr,
+                                Arc::new(Texture::new(medium.sigma_a())),
+                                Arc::new(Texture::new(medium.sigma_prime_s())),
+                                et, bm)
+    }
+
+    pub fn create(mp: &ParamSet) -> SubsurfaceMaterial {
+        // Default to the coefficients for whole milk unless a
+        // named medium was given
+        let medium = ScatteringMedium::from_name(&mp.find_string("name", ""))
+            .unwrap_or(ScatteringMedium::Wholemilk);
+
+        SubsurfaceMaterial::new(
+            mp.find_float("scale", 1.0),
+            mp.get_spectrum_texture("Kr", Spectrum::from(1.0)),
+            mp.get_spectrum_texture("sigma_a", medium.sigma_a()),
+            mp.get_spectrum_texture("sigma_prime_s", medium.sigma_prime_s()),
+            mp.find_float("index", 1.33),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    fn shading_geometry(&self, dg_geom: &DifferentialGeometry,
+                        dg_shading: DifferentialGeometry) -> DifferentialGeometry {
+        if let Some(ref tex) = self.bump_map {
+            bump(tex, dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        }
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = self.shading_geometry(&dg_geom, dg_shading);
+        let mut bsdf = BSDF::new_with_eta(dgs.clone(), dg_geom.nn, self.eta);
+
+        let r = self.k_r.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !r.is_black() {
+            bsdf.add_bxdf(SpecularReflection::new(r, Fresnel::dielectric(1.0, self.eta)));
+        }
+
+        Some(bsdf)
+    }
+
+    pub fn get_bssrdf(&self, dg_geom: DifferentialGeometry,
+                      dg_shading: DifferentialGeometry) -> Option<BSSRDF> {
+        let dgs = self.shading_geometry(&dg_geom, dg_shading);
+        let sa = self.sigma_a.evaluate(&dgs) * self.scale;
+        let sps = self.sigma_prime_s.evaluate(&dgs) * self.scale;
+        Some(BSSRDF::new(sa, sps, self.eta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paramset::ParamSet;
+
+    #[test]
+    fn it_knows_measured_media() {
+        let skin = ScatteringMedium::from_name("Skin1").unwrap();
+        assert_eq!(skin, ScatteringMedium::Skin1);
+        assert!(ScatteringMedium::from_name("Jello").is_none());
+
+        // Ketchup absorbs green and blue much more than red
+        let ketchup = ScatteringMedium::Ketchup.sigma_a().to_rgb();
+        assert!(ketchup[0] < ketchup[1] && ketchup[0] < ketchup[2]);
+    }
+
+    #[test]
+    fn it_scales_coefficients() {
+        let mut mp = ParamSet::new();
+        mp.add_string("name", "Marble");
+        mp.add_float("scale", 10.0);
+
+        let dg = DifferentialGeometry::new();
+        let bssrdf = SubsurfaceMaterial::create(&mp).get_bssrdf(dg.clone(), dg).unwrap();
+        let sps = bssrdf.sigma_prime_s().to_rgb();
+        assert!((sps[0] - 21.9).abs() < 1e-4);
+        assert_eq!(bssrdf.eta(), 1.33);
+    }
+}
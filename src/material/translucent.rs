@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::BRDFtoBTDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::lambertian::Lambertian;
+use bsdf::microfacet::Microfacet;
+use bsdf::microfacet::MicrofacetDistribution;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TranslucentMaterial {
+    k_d: Arc<Texture<Spectrum>>,
+    k_s: Arc<Texture<Spectrum>>,
+    roughness: Arc<Texture<f32>>,
+    reflect: Arc<Texture<Spectrum>>,
+    transmit: Arc<Texture<Spectrum>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl TranslucentMaterial {
+    pub fn new(kd: Arc<Texture<Spectrum>>,
+               ks: Arc<Texture<Spectrum>>,
+               rough: Arc<Texture<f32>>,
+               refl: Arc<Texture<Spectrum>>,
+               trans: Arc<Texture<Spectrum>>,
+               bm: Option<Arc<Texture<f32>>>) -> TranslucentMaterial {
+        TranslucentMaterial {
+            k_d: kd, k_s: ks, roughness: rough,
+            reflect: refl, transmit: trans, bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> TranslucentMaterial {
+        TranslucentMaterial::new(
+            mp.get_spectrum_texture("Kd", Spectrum::from(0.25)),
+            mp.get_spectrum_texture("Ks", Spectrum::from(0.25)),
+            mp.get_float_texture("roughness", 0.1),
+            mp.get_spectrum_texture("reflect", Spectrum::from(0.5)),
+            mp.get_spectrum_texture("transmit", Spectrum::from(0.5)),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let ior = 1.5;
+        let mut bsdf = BSDF::new_with_eta(dgs.clone(), dg_geom.nn, ior);
+
+        let r = self.reflect.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        let t = self.transmit.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if r.is_black() && t.is_black() {
+            return Some(bsdf);
+        }
+
+        let kd = self.k_d.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !kd.is_black() {
+            if !r.is_black() {
+                bsdf.add_bxdf(Lambertian::new(r * kd));
+            }
+
+            if !t.is_black() {
+                bsdf.add_bxdf(BRDFtoBTDF::new(Lambertian::new(t * kd)));
+            }
+        }
+
+        let ks = self.k_s.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !ks.is_black() {
+            let rough = self.roughness.evaluate(&dgs);
+            if !r.is_black() {
+                bsdf.add_bxdf(Microfacet::new(r * ks, Fresnel::dielectric(ior, 1.0),
+                                              MicrofacetDistribution::blinn(1.0 / rough)));
+            }
+
+            if !t.is_black() {
+                let spec = Microfacet::new(t * ks, Fresnel::dielectric(ior, 1.0),
+                                           MicrofacetDistribution::blinn(1.0 / rough));
+                bsdf.add_bxdf(BRDFtoBTDF::new(spec));
+            }
+        }
+
+        Some(bsdf)
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use bsdf::BSDF;
+use bsdf::fresnel::Fresnel;
+use bsdf::lambertian::Lambertian;
+use bsdf::microfacet::Microfacet;
+use bsdf::microfacet::MicrofacetDistribution;
+use bsdf::specular::SpecularReflection;
+use bsdf::specular::SpecularTransmission;
+use diff_geom::DifferentialGeometry;
+use paramset::ParamSet;
+use spectrum::Spectrum;
+use texture::Texture;
+use utils::Clamp;
+
+use material::bump;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct UberMaterial {
+    k_d: Arc<Texture<Spectrum>>,
+    k_s: Arc<Texture<Spectrum>>,
+    k_r: Arc<Texture<Spectrum>>,
+    k_t: Arc<Texture<Spectrum>>,
+    opacity: Arc<Texture<Spectrum>>,
+    roughness: Arc<Texture<f32>>,
+    eta: Arc<Texture<f32>>,
+    bump_map: Option<Arc<Texture<f32>>>
+}
+
+impl UberMaterial {
+    pub fn new(kd: Arc<Texture<Spectrum>>,
+               ks: Arc<Texture<Spectrum>>,
+               kr: Arc<Texture<Spectrum>>,
+               kt: Arc<Texture<Spectrum>>,
+               rough: Arc<Texture<f32>>,
+               op: Arc<Texture<Spectrum>>,
+               e: Arc<Texture<f32>>,
+               bm: Option<Arc<Texture<f32>>>) -> UberMaterial {
+        UberMaterial {
+            k_d: kd, k_s: ks, k_r: kr, k_t: kt,
+            opacity: op, roughness: rough, eta: e, bump_map: bm
+        }
+    }
+
+    pub fn create(mp: &ParamSet) -> UberMaterial {
+        UberMaterial::new(
+            mp.get_spectrum_texture("Kd", Spectrum::from(0.25)),
+            mp.get_spectrum_texture("Ks", Spectrum::from(0.25)),
+            mp.get_spectrum_texture("Kr", Spectrum::from(0.0)),
+            mp.get_spectrum_texture("Kt", Spectrum::from(0.0)),
+            mp.get_float_texture("roughness", 0.1),
+            mp.get_spectrum_texture("opacity", Spectrum::from(1.0)),
+            mp.get_float_texture("index", 1.5),
+            mp.get_float_texture_or_none("bumpmap"))
+    }
+
+    pub fn get_bsdf(&self, dg_geom: DifferentialGeometry,
+                    dg_shading: DifferentialGeometry) -> Option<BSDF> {
+        // Allocate bsdf possibly doing bump mapping with bump map
+        let dgs = if let Some(ref tex) = self.bump_map {
+            bump(tex, &dg_geom, &dg_shading)
+        } else {
+            dg_shading
+        };
+
+        let mut bsdf = BSDF::new(dgs.clone(), dg_geom.nn);
+
+        // Anything that isn't opaque lets light straight through
+        let op = self.opacity.evaluate(&dgs).clamp(0.0, 1.0);
+        if op != Spectrum::from(1.0) {
+            bsdf.add_bxdf(SpecularTransmission::new(Spectrum::from(1.0) - op, 1.0, 1.0));
+        }
+
+        let kd = op * self.k_d.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !kd.is_black() {
+            bsdf.add_bxdf(Lambertian::new(kd));
+        }
+
+        let e = self.eta.evaluate(&dgs);
+        let ks = op * self.k_s.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !ks.is_black() {
+            let rough = self.roughness.evaluate(&dgs);
+            bsdf.add_bxdf(Microfacet::new(ks, Fresnel::dielectric(e, 1.0),
+                                          MicrofacetDistribution::blinn(1.0 / rough)));
+        }
+
+        let kr = op * self.k_r.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !kr.is_black() {
+            bsdf.add_bxdf(SpecularReflection::new(kr, Fresnel::dielectric(e, 1.0)));
+        }
+
+        let kt = op * self.k_t.evaluate(&dgs).clamp(0.0, ::std::f32::MAX);
+        if !kt.is_black() {
+            bsdf.add_bxdf(SpecularTransmission::new(kt, e, 1.0));
+        }
+
+        Some(bsdf)
+    }
+}
@@ -1,5 +1,6 @@
 extern crate primal;
 
+use geometry::vector::Vector;
 use rng::RNG;
 
 use std::ops::Deref;
@@ -127,6 +128,80 @@ pub fn stratified_sample_2d(samples: &mut [f32], nx: usize, ny: usize,
     }
 }
 
+pub fn concentric_sample_disk(u1: f32, u2: f32) -> (f32, f32) {
+    // Map uniform random numbers to [-1, 1]^2
+    let sx = 2.0 * u1 - 1.0;
+    let sy = 2.0 * u2 - 1.0;
+
+    // Handle degeneracy at the origin
+    if sx == 0.0 && sy == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    // Map square to (r, theta)
+    let quarter_pi = ::std::f32::consts::PI / 4.0;
+    let (r, theta) = if sx >= -sy {
+        if sx > sy {
+            // Handle first region of disk
+            (sx, if sy > 0.0 { sy / sx } else { 8.0 + sy / sx })
+        } else {
+            // Handle second region of disk
+            (sy, 2.0 - sx / sy)
+        }
+    } else {
+        if sx <= sy {
+            // Handle third region of disk
+            (-sx, 4.0 - sy / -sx)
+        } else {
+            // Handle fourth region of disk
+            (-sy, 6.0 + sx / -sy)
+        }
+    };
+
+    let t = theta * quarter_pi;
+    (r * t.cos(), r * t.sin())
+}
+
+pub fn cosine_sample_hemisphere(u1: f32, u2: f32) -> Vector {
+    let (x, y) = concentric_sample_disk(u1, u2);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    Vector::new_with(x, y, z)
+}
+
+pub fn cosine_hemisphere_pdf(costheta: f32) -> f32 {
+    costheta / ::std::f32::consts::PI
+}
+
+pub fn uniform_sample_hemisphere(u1: f32, u2: f32) -> Vector {
+    let z = u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * ::std::f32::consts::PI * u2;
+    Vector::new_with(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_hemisphere_pdf() -> f32 {
+    1.0 / (2.0 * ::std::f32::consts::PI)
+}
+
+pub fn uniform_sample_sphere(u1: f32, u2: f32) -> Vector {
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * ::std::f32::consts::PI * u2;
+    Vector::new_with(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * ::std::f32::consts::PI)
+}
+
+// Weight for a sample taken from f when combining nf samples from f
+// with ng samples from g using multiple importance sampling.
+pub fn power_heuristic(nf: usize, f_pdf: f32, ng: usize, g_pdf: f32) -> f32 {
+    let f = (nf as f32) * f_pdf;
+    let g = (ng as f32) * g_pdf;
+    if f == 0.0 && g == 0.0 { 0.0 } else { (f * f) / (f * f + g * g) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -310,4 +385,34 @@ mod tests {
     #[test]
     fn it_can_generate_latin_hypercube() {
     }
+
+    #[test]
+    fn it_can_sample_disks() {
+        assert_eq!(concentric_sample_disk(0.5, 0.5), (0.0, 0.0));
+
+        let mut rng = RNG::new(3);
+        for _ in 0..100 {
+            let (x, y) = concentric_sample_disk(rng.random_float(), rng.random_float());
+            assert!(x * x + y * y <= 1.0 + 1e-6);
+        }
+
+        // Corners of the square map to the edge of the disk
+        let (x, y) = concentric_sample_disk(1.0, 0.5);
+        assert!((x - 1.0).abs() < 1e-6);
+        assert!(y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_can_sample_hemispheres() {
+        let mut rng = RNG::new(5);
+        for _ in 0..100 {
+            let v = cosine_sample_hemisphere(rng.random_float(), rng.random_float());
+            assert!(v.z >= 0.0);
+            assert!((v.length() - 1.0).abs() < 1e-5);
+
+            let w = uniform_sample_hemisphere(rng.random_float(), rng.random_float());
+            assert!(w.z >= 0.0);
+            assert!((w.length() - 1.0).abs() < 1e-5);
+        }
+    }
 }
@@ -0,0 +1,604 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use geometry::normal::Normal;
+use geometry::point::Point;
+use material::Material;
+use primitive::Primitive;
+use shape::MeshData;
+use shape::Shape;
+use spectrum::Spectrum;
+use texture::Texture;
+use transform::transform::Transform;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Format(String)
+}
+
+impl ::std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            &ObjError::Io(ref e) => write!(f, "I/O error: {}", e),
+            &ObjError::Format(ref s) => write!(f, "Malformed OBJ data: {}", s)
+        }
+    }
+}
+
+impl ::std::error::Error for ObjError {
+    fn description(&self) -> &str {
+        match self {
+            &ObjError::Io(ref e) => e.description(),
+            &ObjError::Format(ref s) => s
+        }
+    }
+}
+
+impl ::std::convert::From<io::Error> for ObjError {
+    fn from(e: io::Error) -> ObjError { ObjError::Io(e) }
+}
+
+fn format_error(filename: &str, line_num: usize, msg: String) -> ObjError {
+    ObjError::Format(format!("{}:{}: {}", filename, line_num, msg))
+}
+
+// Splits the file into statements, dropping comments and joining lines
+// that end in a backslash. Each statement keeps the number of the line it
+// started on for error reporting.
+fn statements(contents: &str) -> Vec<(usize, Vec<&str>)> {
+    let mut stmts = Vec::new();
+    let mut current: Option<(usize, Vec<&str>)> = None;
+    for (line_num, line) in contents.lines().enumerate() {
+        let data = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line
+        };
+
+        let (data, continued) = {
+            let trimmed = data.trim_right();
+            if trimmed.ends_with('\\') {
+                (&trimmed[..(trimmed.len() - 1)], true)
+            } else {
+                (trimmed, false)
+            }
+        };
+
+        let mut stmt = current.take().unwrap_or((line_num + 1, Vec::new()));
+        stmt.1.extend(data.split_whitespace());
+        if continued {
+            current = Some(stmt);
+        } else if stmt.1.len() > 0 {
+            stmts.push(stmt);
+        }
+    }
+
+    if let Some(stmt) = current {
+        if stmt.1.len() > 0 {
+            stmts.push(stmt);
+        }
+    }
+
+    stmts
+}
+
+fn parse_floats(filename: &str, line_num: usize, toks: &[&str],
+                min: usize, max: usize) -> Result<Vec<f32>, ObjError> {
+    if toks.len() < min || toks.len() > max {
+        return Err(format_error(filename, line_num, format!(
+            "expected between {} and {} values, found {}", min, max, toks.len())));
+    }
+
+    let mut values = Vec::with_capacity(toks.len());
+    for tok in toks {
+        match tok.parse::<f32>() {
+            Ok(v) => values.push(v),
+            Err(_) => return Err(format_error(filename, line_num, format!(
+                "unexpected token '{}'", tok)))
+        }
+    }
+    Ok(values)
+}
+
+// OBJ indices start at one, and negative indices count backwards from the
+// most recently defined element.
+fn resolve_index(filename: &str, line_num: usize, tok: &str,
+                 count: usize) -> Result<usize, ObjError> {
+    let idx = match tok.parse::<isize>() {
+        Ok(i) => i,
+        Err(_) => return Err(format_error(filename, line_num, format!(
+            "unexpected token '{}'", tok)))
+    };
+
+    let resolved = if idx > 0 {
+        idx - 1
+    } else {
+        count as isize + idx
+    };
+
+    if idx == 0 || resolved < 0 || resolved >= count as isize {
+        return Err(format_error(filename, line_num, format!(
+            "index {} out of range ({} defined)", idx, count)));
+    }
+
+    Ok(resolved as usize)
+}
+
+// Material parameters as read from a .mtl file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub kd: Spectrum,
+    pub ks: Spectrum,
+    pub ns: f32,
+    pub d: f32
+}
+
+impl ObjMaterial {
+    pub fn new(name: &str) -> ObjMaterial {
+        ObjMaterial {
+            name: name.to_string(),
+            kd: Spectrum::from(0.5),
+            ks: Spectrum::from(0.0),
+            ns: 0.0,
+            d: 1.0
+        }
+    }
+
+    // Translates the Phong exponent into a microfacet roughness, which for
+    // our Blinn distribution is just its reciprocal.
+    pub fn roughness(&self) -> f32 {
+        if self.ns > 1.0 { 1.0 / self.ns } else { 1.0 }
+    }
+
+    pub fn to_material(&self) -> Material {
+        let kd = Arc::new(Texture::new(self.kd));
+        if self.d < 1.0 {
+            Material::uber(kd, Arc::new(Texture::new(self.ks)),
+                           Arc::new(Texture::new(Spectrum::from(0.0))),
+                           Arc::new(Texture::new(Spectrum::from(0.0))),
+                           Arc::new(Texture::new(self.roughness())),
+                           Arc::new(Texture::new(Spectrum::from(self.d))),
+                           Arc::new(Texture::new(1.5)), None)
+        } else if !self.ks.is_black() {
+            Material::plastic(kd, Arc::new(Texture::new(self.ks)),
+                              Arc::new(Texture::new(self.roughness())), None)
+        } else {
+            Material::matte(kd, Arc::new(Texture::new(0.0)), None)
+        }
+    }
+}
+
+pub fn parse_mtl<R: Read>(filename: &str, f: R) -> Result<Vec<ObjMaterial>, ObjError> {
+    let mut contents = String::new();
+    try!(BufReader::new(f).read_to_string(&mut contents));
+
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (line_num, toks) in statements(&contents) {
+        if toks[0] == "newmtl" {
+            if toks.len() != 2 {
+                return Err(format_error(filename, line_num,
+                                        String::from("expected a material name")));
+            }
+            materials.push(ObjMaterial::new(toks[1]));
+            continue;
+        }
+
+        let mtl = match materials.last_mut() {
+            Some(m) => m,
+            None => return Err(format_error(filename, line_num, format!(
+                "'{}' before any newmtl", toks[0])))
+        };
+
+        match toks[0] {
+            "Kd" | "Ks" => {
+                // A single value is a grey
+                let v = try!(parse_floats(filename, line_num, &toks[1..], 1, 3));
+                let s = if v.len() == 1 {
+                    Spectrum::from(v[0])
+                } else if v.len() == 3 {
+                    Spectrum::from_rgb([v[0], v[1], v[2]])
+                } else {
+                    return Err(format_error(filename, line_num, format!(
+                        "expected 1 or 3 values, found {}", v.len())));
+                };
+                if toks[0] == "Kd" { mtl.kd = s; } else { mtl.ks = s; }
+            },
+            "Ns" => mtl.ns = try!(parse_floats(filename, line_num, &toks[1..], 1, 1))[0],
+            "d" => mtl.d = try!(parse_floats(filename, line_num, &toks[1..], 1, 1))[0],
+            "Tr" => mtl.d = 1.0 - try!(parse_floats(filename, line_num, &toks[1..], 1, 1))[0],
+            // Everything else (Ka, Ke, illum, Ni, ...) has no counterpart
+            // here. That includes texture maps like map_Kd and bump, since
+            // there are no image textures to load them into.
+            _ => ()
+        }
+    }
+
+    Ok(materials)
+}
+
+// The triangles of one group of faces sharing a material
+#[derive(Clone, Debug)]
+pub struct ObjMesh {
+    pub group: String,
+    pub material: Option<String>,
+    pub data: MeshData
+}
+
+// A face vertex after resolving its position, texture coordinate and
+// normal indices against everything defined so far.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+struct GroupBuilder {
+    group: String,
+    material: Option<String>,
+    triangles: Vec<[FaceVertex; 3]>
+}
+
+impl GroupBuilder {
+    // OBJ indexes positions, uvs and normals separately, so every distinct
+    // combination becomes its own mesh vertex. Attributes only make it into
+    // the mesh if every vertex has one.
+    fn build(self, p: &[Point], uv: &[[f32; 2]], n: &[Normal]) -> ObjMesh {
+        let has_uv = self.triangles.iter().all(|t| t.iter().all(|v| v.1.is_some()));
+        let has_n = self.triangles.iter().all(|t| t.iter().all(|v| v.2.is_some()));
+
+        let mut vertices = HashMap::new();
+        let mut vi = Vec::with_capacity(self.triangles.len() * 3);
+        let mut mp = Vec::new();
+        let mut muv = Vec::new();
+        let mut mn = Vec::new();
+        for tri in self.triangles.iter() {
+            for &(pi, ti, ni) in tri.iter() {
+                let key = (pi, if has_uv { ti } else { None }, if has_n { ni } else { None });
+                let next = mp.len();
+                let idx = *vertices.entry(key).or_insert(next);
+                if idx == next {
+                    mp.push(p[pi].clone());
+                    if let Some(ti) = key.1 {
+                        muv.push(uv[ti][0]);
+                        muv.push(uv[ti][1]);
+                    }
+                    if let Some(ni) = key.2 {
+                        mn.push(n[ni].clone());
+                    }
+                }
+                vi.push(idx);
+            }
+        }
+
+        ObjMesh {
+            group: self.group,
+            material: self.material,
+            data: MeshData::new(&vi, &mp,
+                                if has_n { Some(&mn[..]) } else { None }, None,
+                                if has_uv { Some(&muv[..]) } else { None })
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjFile {
+    meshes: Vec<ObjMesh>,
+    material_libs: Vec<String>,
+    materials: HashMap<String, ObjMaterial>
+}
+
+impl ObjFile {
+    pub fn parse<R: Read>(filename: &str, f: R) -> Result<ObjFile, ObjError> {
+        let mut contents = String::new();
+        try!(BufReader::new(f).read_to_string(&mut contents));
+
+        let mut p = Vec::new();
+        let mut uv = Vec::new();
+        let mut n = Vec::new();
+        let mut libs = Vec::new();
+
+        // Faces are collected per (group, material) pair in the order that
+        // the pairs first show up.
+        let mut builders: Vec<GroupBuilder> = Vec::new();
+        let mut lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+        let mut group = String::from("default");
+        let mut material: Option<String> = None;
+
+        for (line_num, toks) in statements(&contents) {
+            match toks[0] {
+                "v" => {
+                    // Optional w or vertex colors are ignored
+                    let v = try!(parse_floats(filename, line_num, &toks[1..], 3, 6));
+                    p.push(Point::new_with(v[0], v[1], v[2]));
+                },
+                "vt" => {
+                    let v = try!(parse_floats(filename, line_num, &toks[1..], 1, 3));
+                    uv.push([v[0], if v.len() > 1 { v[1] } else { 0.0 }]);
+                },
+                "vn" => {
+                    let v = try!(parse_floats(filename, line_num, &toks[1..], 3, 3));
+                    n.push(Normal::new_with(v[0], v[1], v[2]));
+                },
+                "f" => {
+                    if toks.len() < 4 {
+                        return Err(format_error(filename, line_num, format!(
+                            "face needs at least 3 vertices, found {}", toks.len() - 1)));
+                    }
+
+                    let mut face = Vec::with_capacity(toks.len() - 1);
+                    for tok in toks[1..].iter() {
+                        let mut parts = tok.split('/');
+                        let pi = try!(resolve_index(filename, line_num,
+                                                    parts.next().unwrap_or(""), p.len()));
+                        let ti = match parts.next() {
+                            Some(t) if t.len() > 0 =>
+                                Some(try!(resolve_index(filename, line_num, t, uv.len()))),
+                            _ => None
+                        };
+                        let ni = match parts.next() {
+                            Some(t) if t.len() > 0 =>
+                                Some(try!(resolve_index(filename, line_num, t, n.len()))),
+                            _ => None
+                        };
+                        if parts.next().is_some() {
+                            return Err(format_error(filename, line_num, format!(
+                                "unexpected token '{}'", tok)));
+                        }
+                        face.push((pi, ti, ni));
+                    }
+
+                    let key = (group.clone(), material.clone());
+                    let next = builders.len();
+                    let idx = *lookup.entry(key).or_insert(next);
+                    if idx == next {
+                        builders.push(GroupBuilder {
+                            group: group.clone(),
+                            material: material.clone(),
+                            triangles: Vec::new()
+                        });
+                    }
+
+                    // Polygons are assumed convex and triangulated as a fan
+                    for i in 1..(face.len() - 1) {
+                        builders[idx].triangles.push([face[0], face[i], face[i + 1]]);
+                    }
+                },
+                "g" | "o" => {
+                    group = if toks.len() > 1 {
+                        toks[1..].join(" ")
+                    } else {
+                        String::from("default")
+                    };
+                },
+                "usemtl" => {
+                    if toks.len() != 2 {
+                        return Err(format_error(filename, line_num,
+                                                String::from("expected a material name")));
+                    }
+                    material = Some(toks[1].to_string());
+                },
+                "mtllib" => {
+                    if toks.len() < 2 {
+                        return Err(format_error(filename, line_num,
+                                                String::from("expected a filename")));
+                    }
+                    libs.extend(toks[1..].iter().map(|s| s.to_string()));
+                },
+                // Smoothing groups, lines, points and free form geometry
+                // aren't supported.
+                _ => ()
+            }
+        }
+
+        Ok(ObjFile {
+            meshes: builders.into_iter().map(|b| b.build(&p, &uv, &n)).collect(),
+            material_libs: libs,
+            materials: HashMap::new()
+        })
+    }
+
+    // Reads an OBJ file from disk along with any material libraries it
+    // references, which are looked up relative to the OBJ file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ObjFile, ObjError> {
+        let path = path.as_ref();
+        let mut obj = try!(ObjFile::parse(&path.to_string_lossy(),
+                                          try!(File::open(path))));
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for lib in obj.material_libs.clone() {
+            let lib_path = dir.join(&lib);
+            let mtls = try!(parse_mtl(&lib_path.to_string_lossy(),
+                                      try!(File::open(&lib_path))));
+            obj.add_materials(mtls);
+        }
+
+        Ok(obj)
+    }
+
+    pub fn add_materials(&mut self, mtls: Vec<ObjMaterial>) {
+        for mtl in mtls {
+            self.materials.insert(mtl.name.clone(), mtl);
+        }
+    }
+
+    pub fn meshes(&self) -> &[ObjMesh] { &self.meshes }
+
+    pub fn material_libs(&self) -> &[String] { &self.material_libs }
+
+    pub fn material(&self, name: &str) -> Option<&ObjMaterial> {
+        self.materials.get(name)
+    }
+
+    // Faces without a material, or whose material wasn't found in any of
+    // the libraries, get the default grey matte.
+    pub fn mesh_material(&self, mesh: &ObjMesh) -> Material {
+        mesh.material.as_ref()
+            .and_then(|name| self.materials.get(name))
+            .map(|mtl| mtl.to_material())
+            .unwrap_or_else(|| ObjMaterial::new("default").to_material())
+    }
+
+    pub fn to_primitives(&self, o2w: &Transform, w2o: &Transform,
+                         ro: bool) -> Vec<Primitive> {
+        self.meshes.iter().map(|mesh| {
+            let shape = Shape::processed_triangle_mesh(o2w.clone(), w2o.clone(), ro,
+                                                       mesh.data.clone(), None);
+            Primitive::geometric_with_material(shape, self.mesh_material(mesh))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use bbox::HasBounds;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use material::Material;
+    use spectrum::Spectrum;
+    use texture::Texture;
+    use transform::transform::Transform;
+
+    fn parse(src: &str) -> Result<ObjFile, ObjError> {
+        ObjFile::parse("test.obj", src.as_bytes())
+    }
+
+    fn error_message(src: &str) -> String {
+        match parse(src) {
+            Err(ObjError::Format(msg)) => msg,
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Expected an error")
+        }
+    }
+
+    #[test]
+    fn it_triangulates_polygons() {
+        let obj = parse("# a unit quad and a pentagon\n\
+                         v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0.5 1.5 0\n\
+                         f 1 2 3 4\n\
+                         f 1 2 3 5 4\n").unwrap();
+        assert_eq!(obj.meshes().len(), 1);
+
+        let mesh = &obj.meshes()[0];
+        assert_eq!(mesh.group, "default");
+        assert_eq!(mesh.material, None);
+        assert_eq!(mesh.data.vertex_index, vec![0, 1, 2, 0, 2, 3,
+                                                0, 1, 2, 0, 2, 4, 0, 4, 3]);
+        assert_eq!(mesh.data.p.len(), 5);
+        assert!(mesh.data.n.is_none());
+        assert!(mesh.data.uvs.is_none());
+    }
+
+    #[test]
+    fn it_resolves_negative_indices() {
+        let obj = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\n\
+                         vt 0 0\nvt 1 0\nvt 1 1\n\
+                         vn 0 0 1\n\
+                         f -3/-3/-1 -2/-2/-1 -1/-1/-1\n\
+                         v 2 2 2 \\\n 1\n\
+                         f 1//1 -1//1 3//-1\n").unwrap();
+        let mesh = &obj.meshes()[0];
+
+        // The second face has no uvs, so they're dropped for the group
+        assert!(mesh.data.uvs.is_none());
+        assert_eq!(mesh.data.n.as_ref().unwrap().len(), 4);
+        assert_eq!(mesh.data.vertex_index, vec![0, 1, 2, 0, 3, 2]);
+        assert_eq!(mesh.data.p[3], Point::new_with(2.0, 2.0, 2.0));
+
+        let uvs = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\n\
+                         vt 0 0\nvt 1 0\nvt 1 1\n\
+                         f 1/3 2/2 3/1\n").unwrap();
+        assert_eq!(uvs.meshes()[0].data.uvs.as_ref().unwrap(),
+                   &vec![1.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn it_splits_groups_and_materials() {
+        let mut obj = parse("mtllib scene.mtl\n\
+                             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                             g floor\nusemtl white\nf 1 2 3\n\
+                             usemtl shiny\nf 1 3 4\n\
+                             g wall\nusemtl white\nf 1 2 4\n\
+                             g floor\nusemtl white\nf 2 3 4\n").unwrap();
+        assert_eq!(obj.material_libs(), &[String::from("scene.mtl")]);
+
+        let groups: Vec<(&str, Option<&str>, usize)> = obj.meshes().iter().map(|m| {
+            (&m.group[..], m.material.as_ref().map(|s| &s[..]), m.data.num_triangles())
+        }).collect();
+        assert_eq!(groups, vec![("floor", Some("white"), 2),
+                                ("floor", Some("shiny"), 1),
+                                ("wall", Some("white"), 1)]);
+
+        obj.add_materials(parse_mtl("scene.mtl", "newmtl white\nKd 0.8\n".as_bytes()).unwrap());
+        let grey = Material::matte(Arc::new(Texture::new(Spectrum::from(0.8))),
+                                   Arc::new(Texture::new(0.0)), None);
+        let default = Material::matte(Arc::new(Texture::new(Spectrum::from(0.5))),
+                                      Arc::new(Texture::new(0.0)), None);
+        assert_eq!(obj.mesh_material(&obj.meshes()[0]), grey);
+        assert_eq!(obj.mesh_material(&obj.meshes()[1]), default);
+
+        let xf = Transform::translate(&Vector::new_with(0.0, 0.0, 5.0));
+        let prims = obj.to_primitives(&xf, &xf.inverse(), false);
+        assert_eq!(prims.len(), 3);
+        let bounds = prims[0].world_bound();
+        assert_eq!(bounds.p_min, Point::new_with(0.0, 0.0, 5.0));
+        assert_eq!(bounds.p_max, Point::new_with(1.0, 1.0, 5.0));
+    }
+
+    #[test]
+    fn it_reads_materials() {
+        let mtls = parse_mtl("test.mtl", "# materials\n\
+                                          newmtl matte\nKd 0.1 0.2 0.3\nillum 1\n\
+                                          newmtl plastic\nKd 0.5\nKs 0.25\nNs 20\n\
+                                          map_Kd -s 2 2 1 wood.png\nbump -bm 0.5 wood_h.png\n\
+                                          newmtl glass\nKs 1\nd 0.25\n".as_bytes()).unwrap();
+        assert_eq!(mtls.len(), 3);
+
+        assert_eq!(mtls[0].kd, Spectrum::from_rgb([0.1, 0.2, 0.3]));
+        assert_eq!(mtls[0].to_material(),
+                   Material::matte(Arc::new(Texture::new(Spectrum::from_rgb([0.1, 0.2, 0.3]))),
+                                   Arc::new(Texture::new(0.0)), None));
+
+        assert_eq!(mtls[1].roughness(), 0.05);
+        assert_eq!(mtls[1].to_material(),
+                   Material::plastic(Arc::new(Texture::new(Spectrum::from(0.5))),
+                                     Arc::new(Texture::new(Spectrum::from(0.25))),
+                                     Arc::new(Texture::new(0.05)), None));
+
+        match mtls[2].to_material() {
+            Material::Uber(_) => (),
+            m => panic!("Expected a translucent uber material, got {:?}", m)
+        }
+    }
+
+    #[test]
+    fn it_reports_malformed_lines() {
+        assert_eq!(error_message("v 0 0 0\nv 1 0 zero\n"),
+                   "test.obj:2: unexpected token 'zero'");
+        assert_eq!(error_message("v 0 0\n"),
+                   "test.obj:1: expected between 3 and 6 values, found 2");
+        assert_eq!(error_message("v 0 0 0\nv 1 0 0\nv 1 1 0\n\nf 1 2 4\n"),
+                   "test.obj:5: index 4 out of range (3 defined)");
+        assert_eq!(error_message("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 0 1 2\n"),
+                   "test.obj:4: index 0 out of range (3 defined)");
+        assert_eq!(error_message("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 -4 2\n"),
+                   "test.obj:4: index -4 out of range (3 defined)");
+        assert_eq!(error_message("v 0 0 0\nv 1 0 0\nf 1 2\n"),
+                   "test.obj:3: face needs at least 3 vertices, found 2");
+        assert_eq!(error_message("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1/1 2 3\n"),
+                   "test.obj:4: index 1 out of range (0 defined)");
+
+        match parse_mtl("bad.mtl", "Kd 1 1 1\n".as_bytes()) {
+            Err(ObjError::Format(msg)) => assert_eq!(msg, "bad.mtl:1: 'Kd' before any newmtl"),
+            _ => panic!("Expected an error")
+        }
+        match parse_mtl("bad.mtl", "newmtl a\nKd 1 1\n".as_bytes()) {
+            Err(ObjError::Format(msg)) => assert_eq!(msg, "bad.mtl:2: expected 1 or 3 values, found 2"),
+            _ => panic!("Expected an error")
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use spectrum::Spectrum;
+use texture::Texture;
+
+// A bag of named parameters as they appear on a directive in a scene
+// description. Values that are never looked up are simply ignored.
+#[derive(Clone, Debug)]
+pub struct ParamSet {
+    floats: HashMap<String, f32>,
+    spectra: HashMap<String, Spectrum>,
+    strings: HashMap<String, String>,
+    float_textures: HashMap<String, Arc<Texture<f32>>>,
+    spectrum_textures: HashMap<String, Arc<Texture<Spectrum>>>
+}
+
+impl ParamSet {
+    pub fn new() -> ParamSet {
+        ParamSet {
+            floats: HashMap::new(),
+            spectra: HashMap::new(),
+            strings: HashMap::new(),
+            float_textures: HashMap::new(),
+            spectrum_textures: HashMap::new()
+        }
+    }
+
+    pub fn add_float(&mut self, name: &str, v: f32) {
+        self.floats.insert(name.to_string(), v);
+    }
+
+    pub fn add_spectrum(&mut self, name: &str, v: Spectrum) {
+        self.spectra.insert(name.to_string(), v);
+    }
+
+    pub fn add_string(&mut self, name: &str, v: &str) {
+        self.strings.insert(name.to_string(), v.to_string());
+    }
+
+    pub fn add_float_texture(&mut self, name: &str, tex: Arc<Texture<f32>>) {
+        self.float_textures.insert(name.to_string(), tex);
+    }
+
+    pub fn add_spectrum_texture(&mut self, name: &str, tex: Arc<Texture<Spectrum>>) {
+        self.spectrum_textures.insert(name.to_string(), tex);
+    }
+
+    pub fn find_float(&self, name: &str, default: f32) -> f32 {
+        self.floats.get(name).cloned().unwrap_or(default)
+    }
+
+    pub fn find_spectrum(&self, name: &str, default: Spectrum) -> Spectrum {
+        self.spectra.get(name).cloned().unwrap_or(default)
+    }
+
+    pub fn find_string(&self, name: &str, default: &str) -> String {
+        self.strings.get(name).cloned().unwrap_or(default.to_string())
+    }
+
+    // Textures can either be bound by name or given as a constant value
+    // directly on the directive, in which case we wrap it up in a
+    // constant texture for the caller.
+    pub fn get_float_texture(&self, name: &str, default: f32) -> Arc<Texture<f32>> {
+        match self.get_float_texture_or_none(name) {
+            Some(tex) => tex,
+            None => Arc::new(Texture::new(self.find_float(name, default)))
+        }
+    }
+
+    pub fn get_float_texture_or_none(&self, name: &str) -> Option<Arc<Texture<f32>>> {
+        if let Some(tex) = self.float_textures.get(name) {
+            Some(tex.clone())
+        } else {
+            self.floats.get(name).map(|v| Arc::new(Texture::new(*v)))
+        }
+    }
+
+    pub fn get_spectrum_texture(&self, name: &str, default: Spectrum)
+                                -> Arc<Texture<Spectrum>> {
+        if let Some(tex) = self.spectrum_textures.get(name) {
+            tex.clone()
+        } else {
+            Arc::new(Texture::new(self.find_spectrum(name, default)))
+        }
+    }
+
+    pub fn get_spectrum_texture_or_none(&self, name: &str)
+                                        -> Option<Arc<Texture<Spectrum>>> {
+        if let Some(tex) = self.spectrum_textures.get(name) {
+            Some(tex.clone())
+        } else {
+            self.spectra.get(name).map(|v| Arc::new(Texture::new(*v)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diff_geom::DifferentialGeometry;
+    use spectrum::Spectrum;
+    use texture::Texture;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn it_falls_back_to_defaults() {
+        let ps = ParamSet::new();
+        assert_eq!(ps.find_float("roughness", 0.1), 0.1);
+        assert_eq!(ps.find_string("filename", ""), "".to_string());
+        assert_eq!(ps.find_spectrum("Kd", Spectrum::from(0.5)), Spectrum::from(0.5));
+        assert!(ps.get_float_texture_or_none("bumpmap").is_none());
+    }
+
+    #[test]
+    fn it_wraps_constants_in_textures() {
+        let mut ps = ParamSet::new();
+        ps.add_float("roughness", 0.3);
+        ps.add_spectrum("Kd", Spectrum::from(0.2));
+
+        let dg = DifferentialGeometry::new();
+        assert_eq!(ps.get_float_texture("roughness", 0.1).evaluate(&dg), 0.3);
+        assert_eq!(ps.get_spectrum_texture("Kd", Spectrum::from(0.5)).evaluate(&dg),
+                   Spectrum::from(0.2));
+    }
+
+    #[test]
+    fn it_prefers_bound_textures() {
+        let mut ps = ParamSet::new();
+        ps.add_float("roughness", 0.3);
+        ps.add_float_texture("roughness", Arc::new(Texture::new(0.7)));
+
+        let dg = DifferentialGeometry::new();
+        assert_eq!(ps.get_float_texture("roughness", 0.1).evaluate(&dg), 0.7);
+        assert!(ps.get_float_texture_or_none("roughness").is_some());
+    }
+}
@@ -1,3 +1,15 @@
+use std::cmp::max;
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use num_cpus;
+use scoped_threadpool::Pool;
+
 use bbox::BBox;
 use bbox::HasBounds;
 use bbox::Union;
@@ -6,16 +18,48 @@ use geometry::vector::Vector;
 use intersection::Intersectable;
 use intersection::Intersection;
 use primitive::Primitive;
+use primitive::aggregates::cache;
+use primitive::aggregates::cache::CacheError;
+use primitive::aggregates::cache::CacheReader;
+use primitive::aggregates::cache::CacheWriter;
+use primitive::aggregates::cache::Cacheable;
 use primitive::FullyRefinable;
 use ray::Ray;
 
 use utils::partition_by;
 
 #[derive(Clone, Debug, PartialEq, Copy)]
-enum SplitMethod {
+pub enum SplitMethod {
     Middle,
     EqualCounts,
-    SAH
+    SAH,
+    // Surface area heuristic evaluated over the given number of bins along
+    // every axis. Large subtrees are built in parallel.
+    BinnedSAH(usize),
+    // Binned SAH that may also split the primitives themselves when the
+    // children of a node would overlap by more than alpha times the
+    // surface area of the whole tree (Stich et al. 2009).
+    SBVH { num_bins: usize, alpha: f32 }
+}
+
+pub const DEFAULT_NUM_BINS: usize = 16;
+pub const DEFAULT_SBVH_ALPHA: f32 = 1e-5;
+
+impl SplitMethod {
+    // The names used for the splitmethod parameter of scene files
+    pub fn from_name(name: &str) -> Option<SplitMethod> {
+        match name {
+            "sah" => Some(SplitMethod::SAH),
+            "middle" => Some(SplitMethod::Middle),
+            "equal" => Some(SplitMethod::EqualCounts),
+            "binnedsah" => Some(SplitMethod::BinnedSAH(DEFAULT_NUM_BINS)),
+            "sbvh" => Some(SplitMethod::SBVH {
+                num_bins: DEFAULT_NUM_BINS,
+                alpha: DEFAULT_SBVH_ALPHA
+            }),
+            _ => None
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -186,6 +230,37 @@ fn split_surface_area_heuristic(total_bounds: BBox, centroid_bounds: BBox, mp: u
 }
 
 const NUM_BUCKETS: usize = 12;
+
+fn make_leaf(bounds: BBox, prims: Vec<BVHPrimitiveInfo>) -> (BVHNode, Vec<Primitive>) {
+    let node = BVHNode::Leaf {
+        bounds: bounds,
+        first_prim_offset: 0,
+        num_primitives: prims.len()
+    };
+
+    (node, prims.into_iter().map(|BVHPrimitiveInfo { primitive, .. }| primitive).collect())
+}
+
+fn join_subtrees(dim: usize, left: (BVHNode, Vec<Primitive>),
+                 right: (BVHNode, Vec<Primitive>)) -> (BVHNode, Vec<Primitive>) {
+    let (left, mut ordered_left) = left;
+    let (mut right, mut ordered_right) = right;
+
+    right.offset(ordered_left.len());
+
+    let num_nodes = right.num_nodes() + left.num_nodes();
+    let node = BVHNode::Inner {
+        bounds: left.bounds().clone().unioned_with_ref(right.bounds()),
+        child1: Box::new(left),
+        child2: Box::new(right),
+        split_axis: dim,
+        num_nodes: num_nodes + 1
+    };
+
+    ordered_left.append(&mut ordered_right);
+    (node, ordered_left)
+}
+
 fn recursive_build(prims: Vec<BVHPrimitiveInfo>,
                    max_prims_in_node: usize,
                    sm: SplitMethod) -> (BVHNode, Vec<Primitive>) {
@@ -195,14 +270,7 @@ fn recursive_build(prims: Vec<BVHPrimitiveInfo>,
     let num_prims = prims.len();
 
     if num_prims == 1 {
-        // Make leaf node
-        let node = BVHNode::Leaf {
-            bounds: bbox,
-            first_prim_offset: 0,
-            num_primitives: num_prims
-        };
-
-        (node, prims.into_iter().map(|BVHPrimitiveInfo { primitive, .. }| primitive).collect())
+        make_leaf(bbox, prims)
     } else {
         let centroid_bounds = prims.iter().fold(BBox::new(), |b, p| {
             b.unioned_with_ref(p.centroid())
@@ -210,14 +278,7 @@ fn recursive_build(prims: Vec<BVHPrimitiveInfo>,
         let dim = centroid_bounds.max_extent();
 
         if centroid_bounds.p_min[dim] == centroid_bounds.p_max[dim] {
-            // Make leaf node
-            let node = BVHNode::Leaf {
-                bounds: bbox,
-                first_prim_offset: 0,
-                num_primitives: num_prims
-            };
-
-            (node, prims.into_iter().map(|BVHPrimitiveInfo { primitive, .. }| primitive).collect())
+            make_leaf(bbox, prims)
         } else {
             // Partition primitives based on split method
             let (p1, p2) = {
@@ -230,31 +291,424 @@ fn recursive_build(prims: Vec<BVHPrimitiveInfo>,
                             Ok(split) => split,
                             Err(node_result) => return node_result
                         }
-                    }
+                    },
+                    SplitMethod::BinnedSAH(num_bins) =>
+                        return build_binned(prims, max_prims_in_node, num_bins),
+                    SplitMethod::SBVH { num_bins, alpha } =>
+                        return build_spatial(prims, max_prims_in_node, max(num_bins, 2),
+                                             alpha * bbox.surface_area(), 0)
                 }
             };
 
             assert!(p1.len() > 0);
             assert!(p2.len() > 0);
 
-            let (left, mut ordered_left) = recursive_build(p1, max_prims_in_node, sm);
-            let (mut right, mut ordered_right) = recursive_build(p2, max_prims_in_node, sm);
+            join_subtrees(dim,
+                          recursive_build(p1, max_prims_in_node, sm),
+                          recursive_build(p2, max_prims_in_node, sm))
+        }
+    }
+}
 
-            right.offset(ordered_left.len());
+// Relative to the cost of intersecting a primitive
+const TRAVERSAL_COST: f32 = 0.125;
 
-            let num_nodes = right.num_nodes() + left.num_nodes();
-            let node = BVHNode::Inner {
-                bounds: left.bounds().clone().unioned_with_ref(right.bounds()),
-                child1: Box::new(left),
-                child2: Box::new(right),
-                split_axis: dim,
-                num_nodes: num_nodes + 1
-            };
+fn node_bounds(prims: &[BVHPrimitiveInfo]) -> (BBox, BBox) {
+    prims.iter().fold((BBox::new(), BBox::new()), |(b, cb), p| {
+        (b.unioned_with_ref(&p.bounds), cb.unioned_with_ref(p.centroid()))
+    })
+}
+
+fn object_bin(p: &BVHPrimitiveInfo, centroid_bounds: &BBox, dim: usize,
+              num_bins: usize) -> usize {
+    let extent = centroid_bounds.p_max[dim] - centroid_bounds.p_min[dim];
+    let b = ((num_bins as f32) *
+             ((p.centroid[dim] - centroid_bounds.p_min[dim]) / extent)) as usize;
+    if b >= num_bins { num_bins - 1 } else { b }
+}
+
+// Bins the primitive centroids along each axis and returns the cost, axis
+// and last bin on the left of the cheapest split, if the centroids can be
+// told apart at all.
+fn find_object_split(bounds: &BBox, centroid_bounds: &BBox, num_bins: usize,
+                     prims: &[BVHPrimitiveInfo]) -> Option<(f32, usize, usize)> {
+    let total_area = bounds.surface_area();
+    let inv_area = if total_area > 0.0 { 1.0 / total_area } else { 0.0 };
+
+    let mut best: Option<(f32, usize, usize)> = None;
+    for dim in 0..3 {
+        if centroid_bounds.p_max[dim] <= centroid_bounds.p_min[dim] {
+            continue;
+        }
+
+        let mut bins = vec![(0, BBox::new()); num_bins];
+        for p in prims.iter() {
+            let b = object_bin(p, centroid_bounds, dim, num_bins);
+            bins[b].0 += 1;
+            bins[b].1.union_with(&p.bounds);
+        }
+
+        // Sweep from the right to get the count and area above each split
+        let mut above = vec![(0, 0f32); num_bins];
+        let mut acc = (0, BBox::new());
+        for i in (1..num_bins).rev() {
+            acc.0 += bins[i].0;
+            acc.1.union_with(&bins[i].1);
+            above[i] = (acc.0, acc.1.surface_area());
+        }
+
+        let mut below = (0, BBox::new());
+        for i in 0..(num_bins - 1) {
+            below.0 += bins[i].0;
+            below.1.union_with(&bins[i].1);
+
+            let (num_above, area_above) = above[i + 1];
+            if below.0 == 0 || num_above == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST +
+                ((below.0 as f32) * below.1.surface_area() +
+                 (num_above as f32) * area_above) * inv_area;
+            if best.map_or(true, |(c, _, _)| cost < c) {
+                best = Some((cost, dim, i));
+            }
+        }
+    }
+
+    best
+}
+
+// Returns the axis and the two halves of the cheapest binned split. If
+// making a leaf is cheaper, or the centroids can't be told apart, the
+// primitives are handed back.
+fn binned_sah_split(bounds: &BBox, centroid_bounds: &BBox, max_prims: usize,
+                    num_bins: usize, prims: Vec<BVHPrimitiveInfo>)
+                    -> Result<(usize, Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>),
+                              Vec<BVHPrimitiveInfo>> {
+    let num_bins = max(num_bins, 2);
+    let num_prims = prims.len();
+    match find_object_split(bounds, centroid_bounds, num_bins, &prims) {
+        Some((cost, dim, split)) if num_prims > max_prims || cost < (num_prims as f32) => {
+            let (left, right) = prims.into_iter().partition(|p| {
+                object_bin(p, centroid_bounds, dim, num_bins) <= split
+            });
+            Ok((dim, left, right))
+        },
+        _ => Err(prims)
+    }
+}
+
+fn build_binned(prims: Vec<BVHPrimitiveInfo>, max_prims: usize,
+                num_bins: usize) -> (BVHNode, Vec<Primitive>) {
+    let (bounds, centroid_bounds) = node_bounds(&prims);
+    if prims.len() <= 1 {
+        return make_leaf(bounds, prims);
+    }
+
+    match binned_sah_split(&bounds, &centroid_bounds, max_prims, num_bins, prims) {
+        Ok((dim, left, right)) =>
+            join_subtrees(dim,
+                          build_binned(left, max_prims, num_bins),
+                          build_binned(right, max_prims, num_bins)),
+        Err(prims) => make_leaf(bounds, prims)
+    }
+}
+
+// The top of a tree whose subtrees are still being built
+enum PendingNode {
+    Subtree(usize),
+    Inner(usize, Box<PendingNode>, Box<PendingNode>)
+}
+
+// Splits the primitives until each piece is small enough to be a task of
+// its own, so that no thread ends up doing most of the work.
+fn split_into_tasks(prims: Vec<BVHPrimitiveInfo>, max_prims: usize, num_bins: usize,
+                    task_size: usize, tasks: &mut Vec<Vec<BVHPrimitiveInfo>>) -> PendingNode {
+    if prims.len() > task_size {
+        let (bounds, centroid_bounds) = node_bounds(&prims);
+        match binned_sah_split(&bounds, &centroid_bounds, max_prims, num_bins, prims) {
+            Ok((dim, left, right)) => {
+                let l = split_into_tasks(left, max_prims, num_bins, task_size, tasks);
+                let r = split_into_tasks(right, max_prims, num_bins, task_size, tasks);
+                return PendingNode::Inner(dim, Box::new(l), Box::new(r));
+            },
+            Err(prims) => tasks.push(prims)
+        }
+    } else {
+        tasks.push(prims);
+    }
+
+    PendingNode::Subtree(tasks.len() - 1)
+}
+
+fn assemble(node: PendingNode, subtrees: &mut Vec<Option<(BVHNode, Vec<Primitive>)>>)
+            -> (BVHNode, Vec<Primitive>) {
+    match node {
+        PendingNode::Subtree(i) => subtrees[i].take().unwrap(),
+        PendingNode::Inner(dim, left, right) => {
+            let l = assemble(*left, subtrees);
+            let r = assemble(*right, subtrees);
+            join_subtrees(dim, l, r)
+        }
+    }
+}
+
+// Below this many primitives it isn't worth handing work to other threads
+const MIN_TASK_PRIMS: usize = 1024;
+
+// Gives the same tree as build_binned, but the subtrees are built
+// concurrently once the top of the tree has been split up.
+fn build_binned_parallel(prims: Vec<BVHPrimitiveInfo>, max_prims: usize, num_bins: usize,
+                         num_threads: usize) -> (BVHNode, Vec<Primitive>) {
+    let task_size = max(prims.len() / (8 * max(num_threads, 1)), MIN_TASK_PRIMS);
+    if num_threads <= 1 || prims.len() <= task_size {
+        return build_binned(prims, max_prims, num_bins);
+    }
+
+    let mut tasks = Vec::new();
+    let top = split_into_tasks(prims, max_prims, num_bins, task_size, &mut tasks);
+
+    let mut subtrees: Vec<Option<(BVHNode, Vec<Primitive>)>> =
+        tasks.iter().map(|_| None).collect();
+    Pool::new(num_threads as u32).scoped(|scope| {
+        for (task, subtree) in tasks.into_iter().zip(subtrees.iter_mut()) {
+            scope.execute(move || {
+                *subtree = Some(build_binned(task, max_prims, num_bins));
+            });
+        }
+    });
+
+    assemble(top, &mut subtrees)
+}
+
+// Spatial splits are only tried this deep into the tree, which bounds how
+// many times the references to any one primitive can be split.
+const MAX_SPATIAL_DEPTH: usize = 48;
+
+fn is_inverted(b: &BBox) -> bool {
+    b.p_min.x > b.p_max.x || b.p_min.y > b.p_max.y || b.p_min.z > b.p_max.z
+}
+
+// Bounds the part of a reference's primitive that lies between lo and hi
+// along dim.
+fn clip_reference(r: &BVHPrimitiveInfo, dim: usize, lo: f32, hi: f32) -> BBox {
+    let mut b = r.bounds.clone();
+    b.p_min[dim] = b.p_min[dim].max(lo);
+    b.p_max[dim] = b.p_max[dim].min(hi);
+    if b.p_min[dim] > b.p_max[dim] {
+        return BBox::new();
+    }
+
+    r.primitive.clipped_bound(&b)
+}
+
+// Bins the references by the space they cover rather than by their
+// centroids, so that a reference counts towards every bin it overlaps, and
+// returns the cost, axis and position of the cheapest splitting plane.
+fn find_spatial_split(bounds: &BBox, num_bins: usize,
+                      refs: &[BVHPrimitiveInfo]) -> Option<(f32, usize, f32)> {
+    let total_area = bounds.surface_area();
+    let inv_area = if total_area > 0.0 { 1.0 / total_area } else { 0.0 };
+
+    let mut best: Option<(f32, usize, f32)> = None;
+    for dim in 0..3 {
+        let lo = bounds.p_min[dim];
+        let width = (bounds.p_max[dim] - lo) / (num_bins as f32);
+        if width <= 0.0 {
+            continue;
+        }
+
+        let plane = |i: usize| {
+            if i == num_bins { bounds.p_max[dim] } else { lo + width * (i as f32) }
+        };
+        let bin_of = |x: f32| {
+            let b = ((x - lo).max(0.0) / width) as usize;
+            if b >= num_bins { num_bins - 1 } else { b }
+        };
+
+        // The clipped bounds of each bin along with the number of
+        // references that start and end in it.
+        let mut bins = vec![(BBox::new(), 0, 0); num_bins];
+        for r in refs.iter() {
+            let first = bin_of(r.bounds.p_min[dim]);
+            let last = bin_of(r.bounds.p_max[dim]);
+            if first == last {
+                bins[first].0.union_with(&r.bounds);
+            } else {
+                for b in first..(last + 1) {
+                    let clipped = clip_reference(r, dim, plane(b), plane(b + 1));
+                    bins[b].0.union_with(&clipped);
+                }
+            }
+
+            bins[first].1 += 1;
+            bins[last].2 += 1;
+        }
+
+        let mut above = vec![(0, 0f32); num_bins];
+        let mut acc = (0, BBox::new());
+        for i in (1..num_bins).rev() {
+            acc.0 += bins[i].2;
+            acc.1.union_with(&bins[i].0);
+            above[i] = (acc.0, acc.1.surface_area());
+        }
+
+        let mut below = (0, BBox::new());
+        for i in 0..(num_bins - 1) {
+            below.0 += bins[i].1;
+            below.1.union_with(&bins[i].0);
+
+            let (num_above, area_above) = above[i + 1];
+            if below.0 == 0 || num_above == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST +
+                ((below.0 as f32) * below.1.surface_area() +
+                 (num_above as f32) * area_above) * inv_area;
+            if best.map_or(true, |(c, _, _)| cost < c) {
+                best = Some((cost, dim, plane(i + 1)));
+            }
+        }
+    }
+
+    best
+}
+
+// Splits the references at the plane through pos. References that cross
+// the plane are either clipped into both children, or kept whole on one
+// side when that is cheaper than splitting them.
+fn spatial_partition(refs: Vec<BVHPrimitiveInfo>, dim: usize, pos: f32)
+                     -> (Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>) {
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+    let mut straddling = Vec::new();
+    let mut left_bounds = BBox::new();
+    let mut right_bounds = BBox::new();
+    for r in refs {
+        if r.bounds.p_max[dim] <= pos {
+            left_bounds.union_with(&r.bounds);
+            left.push(r);
+        } else if r.bounds.p_min[dim] >= pos {
+            right_bounds.union_with(&r.bounds);
+            right.push(r);
+        } else {
+            straddling.push(r);
+        }
+    }
+
+    let clipped: Vec<(BBox, BBox)> = straddling.iter().map(|r| {
+        (clip_reference(r, dim, -::std::f32::INFINITY, pos),
+         clip_reference(r, dim, pos, ::std::f32::INFINITY))
+    }).collect();
+    for &(ref lb, ref rb) in clipped.iter() {
+        left_bounds.union_with(lb);
+        right_bounds.union_with(rb);
+    }
+
+    let mut num_left = (left.len() + straddling.len()) as f32;
+    let mut num_right = (right.len() + straddling.len()) as f32;
+    for (r, (lb, rb)) in straddling.into_iter().zip(clipped.into_iter()) {
+        // The primitive might only touch the part of its bounds on one side
+        if is_inverted(&rb) {
+            num_right -= 1.0;
+            left.push(BVHPrimitiveInfo::new(r.primitive, lb));
+            continue;
+        } else if is_inverted(&lb) {
+            num_left -= 1.0;
+            right.push(BVHPrimitiveInfo::new(r.primitive, rb));
+            continue;
+        }
+
+        let split_cost = left_bounds.surface_area() * num_left +
+            right_bounds.surface_area() * num_right;
+        let left_cost = left_bounds.union(&r.bounds).surface_area() * num_left +
+            right_bounds.surface_area() * (num_right - 1.0);
+        let right_cost = left_bounds.surface_area() * (num_left - 1.0) +
+            right_bounds.union(&r.bounds).surface_area() * num_right;
+
+        if left_cost < split_cost && left_cost <= right_cost {
+            left_bounds.union_with(&r.bounds);
+            num_right -= 1.0;
+            left.push(r);
+        } else if right_cost < split_cost {
+            right_bounds.union_with(&r.bounds);
+            num_left -= 1.0;
+            right.push(r);
+        } else {
+            left.push(BVHPrimitiveInfo::new(r.primitive.clone(), lb));
+            right.push(BVHPrimitiveInfo::new(r.primitive, rb));
+        }
+    }
+
+    (left, right)
+}
+
+// Builds a tree over references to primitives, each of which covers the
+// part of its primitive within the given bounds. The same primitive may
+// end up in more than one leaf.
+fn build_spatial(refs: Vec<BVHPrimitiveInfo>, max_prims: usize, num_bins: usize,
+                 min_overlap: f32, depth: usize) -> (BVHNode, Vec<Primitive>) {
+    let (bounds, centroid_bounds) = node_bounds(&refs);
+    let num_refs = refs.len();
+    if num_refs <= 1 {
+        return make_leaf(bounds, refs);
+    }
+
+    let object = find_object_split(&bounds, &centroid_bounds, num_bins, &refs);
+
+    // Only look for spatial splits when the children of the best object
+    // split overlap noticeably.
+    let overlap = object.map_or(::std::f32::INFINITY, |(_, dim, split)| {
+        let (l, r) = refs.iter().fold((BBox::new(), BBox::new()), |(l, r), p| {
+            if object_bin(p, &centroid_bounds, dim, num_bins) <= split {
+                (l.unioned_with_ref(&p.bounds), r)
+            } else {
+                (l, r.unioned_with_ref(&p.bounds))
+            }
+        });
+        l.clipped_to(&r).surface_area()
+    });
+
+    let spatial = if depth < MAX_SPATIAL_DEPTH && overlap > min_overlap {
+        find_spatial_split(&bounds, num_bins, &refs)
+    } else {
+        None
+    };
+
+    let object_cost = object.map_or(::std::f32::INFINITY, |(c, _, _)| c);
+    let spatial_cost = spatial.map_or(::std::f32::INFINITY, |(c, _, _)| c);
+    let best_cost = object_cost.min(spatial_cost);
+    if best_cost == ::std::f32::INFINITY ||
+        (num_refs <= max_prims && best_cost >= (num_refs as f32)) {
+        return make_leaf(bounds, refs);
+    }
 
-            ordered_left.append(&mut ordered_right);
-            (node, ordered_left)
+    if spatial_cost < object_cost {
+        let (_, dim, pos) = spatial.unwrap();
+        let (left, right) = spatial_partition(refs, dim, pos);
+        if !left.is_empty() && !right.is_empty() {
+            return join_subtrees(
+                dim,
+                build_spatial(left, max_prims, num_bins, min_overlap, depth + 1),
+                build_spatial(right, max_prims, num_bins, min_overlap, depth + 1));
         }
+
+        // Unsplitting moved everything to one side, so settle for an
+        // object split here instead.
+        let mut all = left;
+        all.extend(right);
+        return build_spatial(all, max_prims, num_bins, min_overlap, MAX_SPATIAL_DEPTH);
     }
+
+    let (_, dim, split) = object.unwrap();
+    let (left, right) = refs.into_iter().partition(|p| {
+        object_bin(p, &centroid_bounds, dim, num_bins) <= split
+    });
+    join_subtrees(dim,
+                  build_spatial(left, max_prims, num_bins, min_overlap, depth + 1),
+                  build_spatial(right, max_prims, num_bins, min_overlap, depth + 1))
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -272,13 +726,20 @@ pub enum PackedBVHNode {
 }
 
 impl PackedBVHNode {
-    fn bounds<'a>(&'a self) -> &'a BBox {
+    pub fn bounds<'a>(&'a self) -> &'a BBox {
         match self {
             &PackedBVHNode::Leaf { ref bounds, .. } => bounds,
             &PackedBVHNode::Inner { ref bounds, .. } => bounds
         }
     }
 
+    fn bounds_mut<'a>(&'a mut self) -> &'a mut BBox {
+        match self {
+            &mut PackedBVHNode::Leaf { ref mut bounds, .. } => bounds,
+            &mut PackedBVHNode::Inner { ref mut bounds, .. } => bounds
+        }
+    }
+
     fn flatten_tree(node: &BVHNode, nodes: &mut Vec<PackedBVHNode>, offset: usize) -> usize {
         match node {
             &BVHNode::Leaf { ref bounds, first_prim_offset, num_primitives } => {
@@ -325,40 +786,283 @@ impl PackedBVHNode {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct BVHBuildStats {
+    pub build_time: Duration,
+    // Expected cost of tracing a ray through the tree relative to a single
+    // primitive intersection.
+    pub sah_cost: f32,
+    pub num_nodes: usize,
+    pub num_leaves: usize,
+    // Spatial splits can put a primitive in more than one leaf, in which
+    // case it's counted once for each.
+    pub num_primitives: usize
+}
+
+impl BVHBuildStats {
+    fn new(nodes: &[PackedBVHNode], num_prims: usize, build_time: Duration) -> BVHBuildStats {
+        let root_area = nodes.first().map_or(0.0, |n| n.bounds().surface_area());
+        let cost = nodes.iter().fold(0.0, |cost, node| {
+            match node {
+                &PackedBVHNode::Leaf { ref bounds, num_prims, .. } =>
+                    cost + (num_prims as f32) * bounds.surface_area(),
+                &PackedBVHNode::Inner { ref bounds, .. } =>
+                    cost + TRAVERSAL_COST * bounds.surface_area()
+            }
+        });
+
+        BVHBuildStats {
+            build_time: build_time,
+            sah_cost: if root_area > 0.0 { cost / root_area } else { 0.0 },
+            num_nodes: nodes.len(),
+            num_leaves: nodes.iter().filter(|n| match n {
+                &&PackedBVHNode::Leaf { .. } => true,
+                _ => false
+            }).count(),
+            num_primitives: num_prims
+        }
+    }
+
+    pub fn build_seconds(&self) -> f64 {
+        self.build_time.as_secs() as f64 + (self.build_time.subsec_nanos() as f64) * 1e-9
+    }
+}
+
+impl ::std::fmt::Display for BVHBuildStats {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "BVH over {} primitives: {} nodes, {} leaves, SAH cost {:.3}, built in {:.3}s",
+               self.num_primitives, self.num_nodes, self.num_leaves, self.sah_cost,
+               self.build_seconds())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BVHAccelerator {
     nodes: Vec<PackedBVHNode>,
     primitives: Vec<Primitive>,
+    // Where each of the primitives came from in the refined input, and how
+    // many of those there were, so that moved versions can be swapped in.
+    prim_index: Vec<usize>,
+    num_inputs: usize,
+    stats: BVHBuildStats
 }
 
 impl BVHAccelerator {
-    pub fn new(p: Vec<Primitive>, mp: usize, sm: &'static str) -> BVHAccelerator {
+    pub fn new(p: Vec<Primitive>, mp: usize, sm: SplitMethod) -> BVHAccelerator {
+        BVHAccelerator::new_with_threads(p, mp, sm, num_cpus::get())
+    }
+
+    // Same as new, but reuses the tree stored at path if it was built from
+    // the same primitives and parameters, and stores it there otherwise.
+    // Spatial splits look at more than the bounds of the primitives, so
+    // those trees are always built from scratch.
+    pub fn new_cached(p: Vec<Primitive>, mp: usize, sm: SplitMethod,
+                      path: &Path) -> BVHAccelerator {
         let prims = p.into_iter().fold(Vec::new(), |mut ps, prim| {
             ps.append(&mut prim.fully_refine());
             ps
         });
 
-        let split_method = match sm {
-            "sah" => SplitMethod::SAH,
-            "middle" => SplitMethod::Middle,
-            "equal" => SplitMethod::EqualCounts,
-            _ => {
-                println!("Warning: BVH split method {} unknown. Using \"SAH\"", sm);
-                SplitMethod::SAH
-            }
+        let (method, num_bins) = match sm {
+            SplitMethod::Middle => (0, 0),
+            SplitMethod::EqualCounts => (1, 0),
+            SplitMethod::SAH => (2, 0),
+            SplitMethod::BinnedSAH(num_bins) => (3, num_bins as u64),
+            SplitMethod::SBVH { .. } => return BVHAccelerator::new(prims, mp, sm)
         };
 
+        let hash = cache::hash_inputs(&prims, &[mp as u64, method, num_bins]);
+        cache::load_or_build(path, hash, prims, |ps| BVHAccelerator::new(ps, mp, sm))
+    }
+
+    fn new_with_threads(p: Vec<Primitive>, mp: usize, sm: SplitMethod,
+                        num_threads: usize) -> BVHAccelerator {
+        let start = Instant::now();
+        let prims = p.into_iter().fold(Vec::new(), |mut ps, prim| {
+            ps.append(&mut prim.fully_refine());
+            ps
+        });
+
+        let num_inputs = prims.len();
+        let input_index: HashMap<usize, usize> =
+            prims.iter().enumerate().map(|(i, p)| (p.get_id(), i)).collect();
+
         let build_data: Vec<_> = prims.into_iter().map(|p| {
             let bbox = p.world_bound();
             BVHPrimitiveInfo::new(p, bbox)
         }).collect();
 
-        let (tree, ordered_prims) = recursive_build(build_data, mp, split_method);
+        let (nodes, ordered_prims) = if build_data.is_empty() {
+            (Vec::new(), Vec::new())
+        } else {
+            let (tree, ordered_prims) = match sm {
+                SplitMethod::BinnedSAH(num_bins) =>
+                    build_binned_parallel(build_data, mp, num_bins, num_threads),
+                SplitMethod::SBVH { num_bins, alpha } => {
+                    let root_area = node_bounds(&build_data).0.surface_area();
+                    build_spatial(build_data, mp, max(num_bins, 2), alpha * root_area, 0)
+                },
+                _ => recursive_build(build_data, mp, sm)
+            };
+            (PackedBVHNode::linearize(tree), ordered_prims)
+        };
 
+        let prim_index = ordered_prims.iter().map(|p| input_index[&p.get_id()]).collect();
+        let stats = BVHBuildStats::new(&nodes, ordered_prims.len(), start.elapsed());
         BVHAccelerator {
-            nodes: PackedBVHNode::linearize(tree),
+            nodes: nodes,
             primitives: ordered_prims,
+            prim_index: prim_index,
+            num_inputs: num_inputs,
+            stats: stats
+        }
+    }
+
+    // Swaps in moved versions of the primitives that the tree was built
+    // over, given in the same order, and updates the bounds of every node
+    // without changing the shape of the tree. The tree gets worse the
+    // further things move, which shows up in the SAH cost of its stats.
+    pub fn refit(&mut self, p: Vec<Primitive>) -> Result<(), String> {
+        let prims = p.into_iter().fold(Vec::new(), |mut ps, prim| {
+            ps.append(&mut prim.fully_refine());
+            ps
+        });
+
+        if prims.len() != self.num_inputs {
+            return Err(format!("Can't refit a BVH over {} primitives to {} primitives",
+                               self.num_inputs, prims.len()));
+        }
+
+        for (prim, &i) in self.primitives.iter_mut().zip(self.prim_index.iter()) {
+            *prim = prims[i].clone();
+        }
+
+        // Children always come after their parents
+        for i in (0..self.nodes.len()).rev() {
+            let bounds = match &self.nodes[i] {
+                &PackedBVHNode::Leaf { prim_offset, num_prims, .. } => {
+                    self.primitives[prim_offset..(prim_offset + num_prims)].iter()
+                        .fold(BBox::new(), |b, p| b.unioned_with(p.world_bound()))
+                },
+                &PackedBVHNode::Inner { second_child_offset, .. } =>
+                    self.nodes[i + 1].bounds().union(self.nodes[second_child_offset].bounds())
+            };
+            *self.nodes[i].bounds_mut() = bounds;
+        }
+
+        self.stats = BVHBuildStats::new(&self.nodes, self.primitives.len(),
+                                        self.stats.build_time);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> &BVHBuildStats { &self.stats }
+
+    // The flattened tree along with the primitives in the order that its
+    // leaves refer to them.
+    pub fn into_parts(self) -> (Vec<PackedBVHNode>, Vec<Primitive>, BVHBuildStats) {
+        (self.nodes, self.primitives, self.stats)
+    }
+}
+
+impl Cacheable for BVHAccelerator {
+    fn cache_kind() -> u8 { 0 }
+
+    fn write_cache<W: Write>(&self, w: &mut CacheWriter<W>) -> io::Result<()> {
+        try!(w.write_usize(self.num_inputs));
+        try!(w.write_u64(self.stats.build_time.as_secs()));
+        try!(w.write_u32(self.stats.build_time.subsec_nanos()));
+
+        try!(w.write_usize(self.prim_index.len()));
+        for &i in self.prim_index.iter() {
+            try!(w.write_usize(i));
+        }
+
+        try!(w.write_usize(self.nodes.len()));
+        for node in self.nodes.iter() {
+            match node {
+                &PackedBVHNode::Leaf { ref bounds, prim_offset, num_prims } => {
+                    try!(w.write_u8(0));
+                    try!(w.write_bbox(bounds));
+                    try!(w.write_usize(prim_offset));
+                    try!(w.write_usize(num_prims));
+                },
+                &PackedBVHNode::Inner { ref bounds, second_child_offset, axis } => {
+                    try!(w.write_u8(1));
+                    try!(w.write_bbox(bounds));
+                    try!(w.write_usize(second_child_offset));
+                    try!(w.write_u8(axis as u8));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Everything is checked so that traversing what we read can't go out
+    // of bounds, no matter what was in the file.
+    fn read_cache<R: Read>(r: &mut CacheReader<R>,
+                           prims: &[Primitive]) -> Result<BVHAccelerator, CacheError> {
+        let num_inputs = try!(r.read_usize());
+        if num_inputs != prims.len() {
+            return Err(CacheError::Format(format!(
+                "Built over {} primitives instead of {}", num_inputs, prims.len())));
+        }
+
+        let secs = try!(r.read_u64());
+        let nanos = try!(r.read_u32());
+        if nanos >= 1_000_000_000 {
+            return Err(CacheError::Format(format!("Bad build time {}.{}", secs, nanos)));
+        }
+
+        let num_prims = try!(r.read_usize());
+        let mut prim_index = Vec::new();
+        let mut primitives = Vec::new();
+        for _ in 0..num_prims {
+            let i = try!(r.read_index(num_inputs));
+            prim_index.push(i);
+            primitives.push(prims[i].clone());
         }
+
+        let num_nodes = try!(r.read_usize());
+        let mut nodes = Vec::new();
+        for i in 0..num_nodes {
+            let tag = try!(r.read_u8());
+            let bounds = try!(r.read_bbox());
+            let node = match tag {
+                0 => {
+                    let prim_offset = try!(r.read_usize());
+                    let num = try!(r.read_usize());
+                    if prim_offset > num_prims || num > num_prims - prim_offset {
+                        return Err(CacheError::Format(format!(
+                            "Leaf {} refers to missing primitives", i)));
+                    }
+                    PackedBVHNode::Leaf { bounds: bounds, prim_offset: prim_offset,
+                                          num_prims: num }
+                },
+                1 => {
+                    // Children always come after their parents
+                    let second_child_offset = try!(r.read_index(num_nodes));
+                    let axis = try!(r.read_u8()) as usize;
+                    if second_child_offset <= i + 1 || axis > 2 {
+                        return Err(CacheError::Format(format!("Bad inner node {}", i)));
+                    }
+                    PackedBVHNode::Inner { bounds: bounds,
+                                           second_child_offset: second_child_offset,
+                                           axis: axis }
+                },
+                t => return Err(CacheError::Format(format!("Unknown node type {}", t)))
+            };
+            nodes.push(node);
+        }
+
+        let stats = BVHBuildStats::new(&nodes, primitives.len(), Duration::new(secs, nanos));
+        Ok(BVHAccelerator {
+            nodes: nodes,
+            primitives: primitives,
+            prim_index: prim_index,
+            num_inputs: num_inputs,
+            stats: stats
+        })
     }
 }
 
@@ -425,6 +1129,7 @@ impl Intersectable for BVHAccelerator {
 mod tests  {
     use super::*;
     use bbox::BBox;
+    use bbox::HasBounds;
     use primitive::Primitive;
     use geometry::point::Point;
     use geometry::vector::Vector;
@@ -434,11 +1139,15 @@ mod tests  {
     use transform::transform::Transform;
     use primitive::aggregates::tests::get_spheres;
     use primitive::aggregates::tests::sphere_at;
+    use rng::RNG;
 
     #[test]
     fn it_can_be_created() {
-        for sm in ["sah", "middle", "equal"].iter() {
-            let bvh = BVHAccelerator::new(get_spheres(), 1, sm);
+        for sm in [SplitMethod::SAH, SplitMethod::Middle, SplitMethod::EqualCounts,
+                   SplitMethod::BinnedSAH(DEFAULT_NUM_BINS),
+                   SplitMethod::SBVH { num_bins: DEFAULT_NUM_BINS,
+                                       alpha: DEFAULT_SBVH_ALPHA }].iter() {
+            let bvh = BVHAccelerator::new(get_spheres(), 1, *sm);
             assert_eq!(bvh.primitives.len(), 8);
 
             let mut prims = Vec::with_capacity(8);
@@ -472,7 +1181,7 @@ mod tests  {
         let r = Ray::new_with(Point::new_with(0.25, -1.0, 0.25),
                               Vector::new_with(0.0, 1.0, 0.0), 0.0);
 
-        let g = BVHAccelerator::new(m.clone(), 1, "sah");
+        let g = BVHAccelerator::new(m.clone(), 1, SplitMethod::SAH);
         assert!(g.intersect_p(&r));
 
         g.intersect(&r);
@@ -480,7 +1189,7 @@ mod tests  {
 
         r.set_maxt(10.0);
 
-        let g2 = BVHAccelerator::new(m, 10, "middle");
+        let g2 = BVHAccelerator::new(m, 10, SplitMethod::Middle);
         assert!(g2.intersect_p(&r));
 
         g2.intersect(&r);
@@ -497,7 +1206,7 @@ mod tests  {
             sphere_at(Vector::new_with(6.0, 0.0, 0.0)),
             sphere_at(Vector::new_with(8.0, 0.0, 0.0))];
 
-        let bvh = BVHAccelerator::new(spheres, 1, "middle");
+        let bvh = BVHAccelerator::new(spheres, 1, SplitMethod::Middle);
 
         assert_eq!(*bvh.nodes[0].bounds(),
                    BBox::new_with(Point::new_with(-5.0, -1.0, -1.0),
@@ -520,7 +1229,7 @@ mod tests  {
             sphere_at(Vector::new_with(6.0, 0.0, 0.0)),
             sphere_at(Vector::new_with(8.0, 0.0, 0.0))];
 
-        let bvh = BVHAccelerator::new(spheres, 1, "equal");
+        let bvh = BVHAccelerator::new(spheres, 1, SplitMethod::EqualCounts);
 
         assert_eq!(*bvh.nodes[0].bounds(),
                    BBox::new_with(Point::new_with(-5.0, -1.0, -1.0),
@@ -545,7 +1254,7 @@ mod tests  {
             sphere_at(Vector::new_with(4.0, 2.0, 3.0)),
             sphere_at(Vector::new_with(4.0, 0.0, 0.0))];
 
-        let bvh = BVHAccelerator::new(spheres, 1, "sah");
+        let bvh = BVHAccelerator::new(spheres, 1, SplitMethod::SAH);
 
         assert_eq!(*bvh.nodes[0].bounds(),
                    BBox::new_with(Point::new_with(-5.0, -1.0, -4.0),
@@ -557,4 +1266,255 @@ mod tests  {
                    BBox::new_with(Point::new_with(3.0, -1.0, -4.0),
                                   Point::new_with(5.0, 3.0, 4.0)));
     }
+
+    #[test]
+    fn it_can_arrange_by_binned_sah() {
+        let spheres = vec![
+            sphere_at(Vector::new_with(-2.0, 0.0, 0.0)),
+            sphere_at(Vector::new_with(2.0, 0.0, 0.0))];
+
+        let bvh = BVHAccelerator::new(spheres, 1, SplitMethod::BinnedSAH(4));
+        assert_eq!(bvh.nodes.len(), 3);
+        assert_eq!(*bvh.nodes[1].bounds(),
+                   BBox::new_with(Point::new_with(-3.0, -1.0, -1.0),
+                                  Point::new_with(-1.0, 1.0, 1.0)));
+
+        // One traversal of the root and then each leaf is hit by 24/56 of
+        // the rays that hit the root.
+        let stats = bvh.stats();
+        assert_eq!(stats.num_nodes, 3);
+        assert_eq!(stats.num_leaves, 2);
+        assert_eq!(stats.num_primitives, 2);
+        assert!((stats.sah_cost - (0.125 + 48.0 / 56.0)).abs() < 1e-5);
+
+        // Two spheres that almost coincide are cheaper to intersect
+        // directly than to put in separate leaves
+        let overlapping = vec![
+            sphere_at(Vector::new_with(-0.05, 0.0, 0.0)),
+            sphere_at(Vector::new_with(0.05, 0.0, 0.0))];
+        let leaf = BVHAccelerator::new(overlapping, 2, SplitMethod::BinnedSAH(4));
+        assert_eq!(leaf.nodes.len(), 1);
+        assert!((leaf.stats().sah_cost - 2.0).abs() < 1e-5);
+
+        let empty = BVHAccelerator::new(Vec::new(), 1, SplitMethod::BinnedSAH(4));
+        assert_eq!(empty.stats().num_nodes, 0);
+        assert_eq!(empty.world_bound(), BBox::new());
+    }
+
+    fn bumpy_grid(n: usize, phase: f32) -> Primitive {
+        let mut pts = Vec::new();
+        for j in 0..(n + 1) {
+            for i in 0..(n + 1) {
+                let (x, y) = (i as f32 / n as f32, j as f32 / n as f32);
+                pts.push(Point::new_with(x, y, 0.2 * (7.0 * x + phase).sin() * (5.0 * y).cos()));
+            }
+        }
+
+        let mut vi = Vec::new();
+        for j in 0..n {
+            for i in 0..n {
+                let v = j * (n + 1) + i;
+                vi.extend_from_slice(&[v, v + 1, v + n + 2, v, v + n + 2, v + n + 1]);
+            }
+        }
+
+        Primitive::geometric(Shape::triangle_mesh(
+            Transform::new(), Transform::new(), false, &vi, &pts, None, None, None, None))
+    }
+
+    #[test]
+    fn it_builds_the_same_tree_in_parallel() {
+        let mesh = vec![bumpy_grid(48, 0.0)];
+        let serial = BVHAccelerator::new_with_threads(mesh.clone(), 4,
+                                                      SplitMethod::BinnedSAH(8), 1);
+        let parallel = BVHAccelerator::new_with_threads(mesh, 4,
+                                                        SplitMethod::BinnedSAH(8), 4);
+
+        assert_eq!(serial.stats().num_primitives, 48 * 48 * 2);
+        assert_eq!(serial.nodes, parallel.nodes);
+        assert_eq!(serial.stats().sah_cost, parallel.stats().sah_cost);
+
+        let r = Ray::new_with(Point::new_with(0.3, 0.6, 1.0),
+                              Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        let isect = parallel.intersect(&r).unwrap();
+        let expected = 0.2 * (7.0f32 * 0.3).sin() * (5.0f32 * 0.6).cos();
+        assert!((isect.dg.p.z - expected).abs() < 1e-2);
+    }
+
+    #[test]
+    fn it_can_be_refit_after_vertices_move() {
+        let mut bvh = BVHAccelerator::new(vec![bumpy_grid(16, 0.0)], 4, SplitMethod::SAH);
+        let nodes = bvh.nodes.clone();
+        assert!(bvh.refit(vec![bumpy_grid(8, 0.0)]).is_err());
+
+        bvh.refit(vec![bumpy_grid(16, 1.5)]).unwrap();
+        let rebuilt = BVHAccelerator::new(vec![bumpy_grid(16, 1.5)], 4, SplitMethod::SAH);
+        assert_eq!(bvh.nodes.len(), nodes.len());
+        assert_eq!(bvh.world_bound(), rebuilt.world_bound());
+        assert_eq!(bvh.stats().num_primitives, 16 * 16 * 2);
+
+        // Same tree, new bounds
+        for (old, new) in nodes.iter().zip(bvh.nodes.iter()) {
+            match (old, new) {
+                (&PackedBVHNode::Leaf { prim_offset: o1, num_prims: n1, .. },
+                 &PackedBVHNode::Leaf { prim_offset: o2, num_prims: n2, .. }) =>
+                    assert_eq!((o1, n1), (o2, n2)),
+                (&PackedBVHNode::Inner { second_child_offset: c1, axis: a1, .. },
+                 &PackedBVHNode::Inner { second_child_offset: c2, axis: a2, .. }) =>
+                    assert_eq!((c1, a1), (c2, a2)),
+                _ => panic!("Refitting changed the tree")
+            }
+        }
+
+        for &(x, y) in [(0.3f32, 0.6f32), (0.1, 0.1), (0.9, 0.45), (0.55, 0.8)].iter() {
+            let ray = || Ray::new_with(Point::new_with(x, y, 1.0),
+                                       Vector::new_with(0.0, 0.0, -1.0), 0.0);
+            let isect = bvh.intersect(&ray()).unwrap();
+            let expected = rebuilt.intersect(&ray()).unwrap();
+            assert!((&isect.dg.p - &expected.dg.p).length() < 1e-5);
+            assert!((isect.dg.p.z - 0.2 * (7.0 * x + 1.5).sin() * (5.0 * y).cos()).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn it_can_be_cached_on_disk() {
+        use std::fs;
+        use std::io::Seek;
+        use std::io::SeekFrom;
+        use std::io::Write;
+        use primitive::aggregates::cache::CACHE_VERSION;
+
+        let path = ::std::env::temp_dir().join("pbrt_rust_bvh_cache_test.bin");
+        let _ = fs::remove_file(&path);
+        let grid = || vec![bumpy_grid(16, 0.0)];
+
+        let built = BVHAccelerator::new_cached(grid(), 4, SplitMethod::SAH, &path);
+        assert!(path.exists());
+
+        // Loading gives back the same tree, down to how long it took to build
+        let loaded = BVHAccelerator::new_cached(grid(), 4, SplitMethod::SAH, &path);
+        assert_eq!(loaded.nodes, built.nodes);
+        assert_eq!(loaded.prim_index, built.prim_index);
+        assert_eq!(loaded.stats().build_time, built.stats().build_time);
+        let ray = || Ray::new_with(Point::new_with(0.3, 0.6, 1.0),
+                                   Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        assert!((loaded.intersect(&ray()).unwrap().dg.p.z -
+                 built.intersect(&ray()).unwrap().dg.p.z).abs() < 1e-5);
+
+        // Different parameters replace the cache
+        let other = BVHAccelerator::new_cached(grid(), 4, SplitMethod::Middle, &path);
+        assert!(other.nodes != built.nodes);
+        let loaded = BVHAccelerator::new_cached(grid(), 4, SplitMethod::Middle, &path);
+        assert_eq!(loaded.stats().build_time, other.stats().build_time);
+
+        // So do damaged ones and ones from other versions
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len / 2).unwrap();
+        let rebuilt = BVHAccelerator::new_cached(grid(), 4, SplitMethod::Middle, &path);
+        assert_eq!(rebuilt.nodes, other.nodes);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        {
+            let mut f = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            f.seek(SeekFrom::Start(8)).unwrap();
+            f.write_all(&[(CACHE_VERSION + 1) as u8]).unwrap();
+        }
+        let rebuilt = BVHAccelerator::new_cached(grid(), 4, SplitMethod::Middle, &path);
+        assert_eq!(rebuilt.nodes, other.nodes);
+        assert_eq!(fs::read(&path).unwrap()[8], CACHE_VERSION as u8);
+
+        // Spatial splits are never cached
+        let _ = fs::remove_file(&path);
+        BVHAccelerator::new_cached(grid(), 4, SplitMethod::SBVH { num_bins: 8, alpha: 1e-5 },
+                                   &path);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn split_methods_have_names() {
+        assert_eq!(SplitMethod::from_name("sah"), Some(SplitMethod::SAH));
+        assert_eq!(SplitMethod::from_name("middle"), Some(SplitMethod::Middle));
+        assert_eq!(SplitMethod::from_name("equal"), Some(SplitMethod::EqualCounts));
+        assert_eq!(SplitMethod::from_name("binnedsah"),
+                   Some(SplitMethod::BinnedSAH(DEFAULT_NUM_BINS)));
+        assert_eq!(SplitMethod::from_name("sbvh"),
+                   Some(SplitMethod::SBVH { num_bins: DEFAULT_NUM_BINS,
+                                            alpha: DEFAULT_SBVH_ALPHA }));
+        assert_eq!(SplitMethod::from_name("hlbvh"), None);
+    }
+
+    // Long thin triangles strewn across a box, whose bounds overlap a lot
+    fn slivers(n: usize, rng: &mut RNG) -> Vec<Primitive> {
+        let mut random_point = |s: f32| {
+            Point::new_with(s * rng.random_float(), s * rng.random_float(),
+                            s * rng.random_float())
+        };
+
+        let mut pts = Vec::new();
+        for _ in 0..n {
+            let a = random_point(10.0);
+            let b = random_point(10.0);
+            let c = b.clone() + (random_point(0.2) - Point::new_with(0.1, 0.1, 0.1));
+            pts.extend_from_slice(&[a, b, c]);
+        }
+
+        let vi: Vec<usize> = (0..(3 * n)).collect();
+        Primitive::geometric(Shape::triangle_mesh(
+            Transform::new(), Transform::new(), false, &vi, &pts, None, None, None, None))
+            .fully_refine()
+    }
+
+    #[test]
+    fn it_splits_overlapping_primitives() {
+        let mut rng = RNG::new(7);
+        let prims = slivers(100, &mut rng);
+        let sbvh_method = SplitMethod::SBVH { num_bins: DEFAULT_NUM_BINS,
+                                              alpha: DEFAULT_SBVH_ALPHA };
+
+        let bvh = BVHAccelerator::new(prims.clone(), 4, SplitMethod::BinnedSAH(16));
+        let sbvh = BVHAccelerator::new(prims.clone(), 4, sbvh_method);
+        assert!(sbvh.stats().num_primitives > 100);
+        assert!(sbvh.stats().sah_cost < bvh.stats().sah_cost);
+        assert_eq!(sbvh.world_bound(), bvh.world_bound());
+
+        // With alpha = 1 spatial splits are never tried, so nothing gets split
+        let unsplit = BVHAccelerator::new(
+            prims, 4, SplitMethod::SBVH { num_bins: 16, alpha: 1.0 });
+        assert_eq!(unsplit.stats().num_primitives, 100);
+    }
+
+    #[test]
+    fn it_finds_the_same_hits_as_a_plain_bvh() {
+        let mut rng = RNG::new(13);
+        let prims = slivers(200, &mut rng);
+        let bvh = BVHAccelerator::new(prims.clone(), 4, SplitMethod::SAH);
+        let sbvh = BVHAccelerator::new(prims, 4, SplitMethod::SBVH {
+            num_bins: DEFAULT_NUM_BINS, alpha: DEFAULT_SBVH_ALPHA });
+
+        let mut num_hits = 0;
+        for _ in 0..1000 {
+            let o = Point::new_with(20.0 * rng.random_float() - 5.0,
+                                    20.0 * rng.random_float() - 5.0,
+                                    20.0 * rng.random_float() - 5.0);
+            let target = Point::new_with(10.0 * rng.random_float(),
+                                         10.0 * rng.random_float(),
+                                         10.0 * rng.random_float());
+            let r1 = Ray::new_with(o.clone(), target - o.clone(), 0.0);
+            let r2 = r1.clone();
+
+            assert_eq!(bvh.intersect_p(&r1), sbvh.intersect_p(&r2));
+            match (bvh.intersect(&r1), sbvh.intersect(&r2)) {
+                (None, None) => (),
+                (Some(i1), Some(i2)) => {
+                    num_hits += 1;
+                    assert_eq!(i1.primitive_id, i2.primitive_id);
+                    assert!((i1.dg.p - i2.dg.p).length() < 1e-4);
+                    assert_eq!(r1.maxt(), r2.maxt());
+                },
+                _ => panic!("Trees disagree on whether a ray hits")
+            }
+        }
+
+        assert!(num_hits > 100);
+    }
 }
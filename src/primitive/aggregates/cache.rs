@@ -0,0 +1,305 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use geometry::point::Point;
+use primitive::Primitive;
+
+// Bumped whenever the layout of cache files changes, so that files written
+// by older versions get rebuilt instead of misread.
+pub const CACHE_VERSION: u32 = 1;
+
+const MAGIC: &'static [u8; 8] = b"PBRTACCL";
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    Format(String)
+}
+
+impl ::std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            &CacheError::Io(ref e) => write!(f, "I/O error: {}", e),
+            &CacheError::Format(ref s) => write!(f, "Unusable aggregate cache: {}", s)
+        }
+    }
+}
+
+impl ::std::error::Error for CacheError {
+    fn description(&self) -> &str {
+        match self {
+            &CacheError::Io(ref e) => e.description(),
+            &CacheError::Format(ref s) => s
+        }
+    }
+}
+
+impl ::std::convert::From<io::Error> for CacheError {
+    fn from(e: io::Error) -> CacheError { CacheError::Io(e) }
+}
+
+fn format_error<T>(msg: String) -> Result<T, CacheError> {
+    Err(CacheError::Format(msg))
+}
+
+// FNV-1a, which unlike the hashers in std is guaranteed to stay the same
+// from one build to the next.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv { Fnv(0xcbf29ce484222325) }
+
+    fn write_u64(&mut self, v: u64) {
+        for i in 0..8 {
+            self.0 ^= (v >> (8 * i)) & 0xff;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+// Identifies the inputs of an aggregate. The builders only ever look at
+// the bounds of the primitives, so those together with the parameters of
+// the build decide the tree completely.
+pub fn hash_inputs(prims: &[Primitive], params: &[u64]) -> u64 {
+    let mut h = Fnv::new();
+    h.write_u64(params.len() as u64);
+    for &p in params.iter() {
+        h.write_u64(p);
+    }
+
+    h.write_u64(prims.len() as u64);
+    for p in prims.iter() {
+        let b = p.world_bound();
+        for i in 0..3 {
+            h.write_u64(b.p_min[i].to_bits() as u64);
+            h.write_u64(b.p_max[i].to_bits() as u64);
+        }
+    }
+
+    h.0
+}
+
+pub struct CacheWriter<W: Write> {
+    w: W
+}
+
+impl<W: Write> CacheWriter<W> {
+    pub fn write_u8(&mut self, v: u8) -> io::Result<()> { self.w.write_all(&[v]) }
+
+    pub fn write_u32(&mut self, v: u32) -> io::Result<()> {
+        self.w.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+    }
+
+    pub fn write_u64(&mut self, v: u64) -> io::Result<()> {
+        try!(self.write_u32(v as u32));
+        self.write_u32((v >> 32) as u32)
+    }
+
+    pub fn write_usize(&mut self, v: usize) -> io::Result<()> { self.write_u64(v as u64) }
+    pub fn write_f32(&mut self, v: f32) -> io::Result<()> { self.write_u32(v.to_bits()) }
+
+    pub fn write_bbox(&mut self, b: &BBox) -> io::Result<()> {
+        for i in 0..3 {
+            try!(self.write_f32(b.p_min[i]));
+        }
+        for i in 0..3 {
+            try!(self.write_f32(b.p_max[i]));
+        }
+        Ok(())
+    }
+}
+
+pub struct CacheReader<R: Read> {
+    r: R
+}
+
+impl<R: Read> CacheReader<R> {
+    pub fn read_u8(&mut self) -> Result<u8, CacheError> {
+        let mut buf = [0u8; 1];
+        try!(self.r.read_exact(&mut buf));
+        Ok(buf[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, CacheError> {
+        let mut buf = [0u8; 4];
+        try!(self.r.read_exact(&mut buf));
+        Ok(buf.iter().rev().fold(0, |v, &b| (v << 8) | (b as u32)))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, CacheError> {
+        let lo = try!(self.read_u32()) as u64;
+        let hi = try!(self.read_u32()) as u64;
+        Ok((hi << 32) | lo)
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, CacheError> {
+        let v = try!(self.read_u64());
+        if v > (::std::usize::MAX as u64) {
+            return format_error(format!("{} doesn't fit in a usize", v));
+        }
+        Ok(v as usize)
+    }
+
+    // Reads an index that has to be less than end
+    pub fn read_index(&mut self, end: usize) -> Result<usize, CacheError> {
+        let v = try!(self.read_usize());
+        if v >= end {
+            return format_error(format!("Index {} out of range {}", v, end));
+        }
+        Ok(v)
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, CacheError> {
+        Ok(f32::from_bits(try!(self.read_u32())))
+    }
+
+    pub fn read_bbox(&mut self) -> Result<BBox, CacheError> {
+        let mut v = [0f32; 6];
+        for x in v.iter_mut() {
+            *x = try!(self.read_f32());
+        }
+        Ok(BBox::new_with(Point::new_with(v[0], v[1], v[2]),
+                          Point::new_with(v[3], v[4], v[5])))
+    }
+}
+
+// Aggregates that can be stored in a cache file. Only the structure is
+// written; the primitives themselves are handed back in on load, refined
+// and in the order that they were given to the build.
+pub trait Cacheable : Sized {
+    fn cache_kind() -> u8;
+    fn write_cache<W: Write>(&self, w: &mut CacheWriter<W>) -> io::Result<()>;
+    fn read_cache<R: Read>(r: &mut CacheReader<R>,
+                           prims: &[Primitive]) -> Result<Self, CacheError>;
+}
+
+pub fn load<T: Cacheable>(path: &Path, hash: u64,
+                          prims: &[Primitive]) -> Result<T, CacheError> {
+    let mut r = CacheReader { r: BufReader::new(try!(File::open(path))) };
+
+    let mut magic = [0u8; 8];
+    try!(r.r.read_exact(&mut magic));
+    if &magic != MAGIC {
+        return format_error(String::from("Not an aggregate cache"));
+    }
+
+    let version = try!(r.read_u32());
+    if version != CACHE_VERSION {
+        return format_error(format!("Written by version {}, expected {}",
+                                    version, CACHE_VERSION));
+    }
+
+    let kind = try!(r.read_u8());
+    if kind != T::cache_kind() {
+        return format_error(format!("Holds aggregate kind {}, expected {}",
+                                    kind, T::cache_kind()));
+    }
+
+    if try!(r.read_u64()) != hash {
+        return format_error(String::from("Built from different primitives or parameters"));
+    }
+
+    let result = try!(T::read_cache(&mut r, prims));
+
+    // Anything left over means the file isn't what we think it is
+    if try!(r.r.read(&mut [0u8; 1])) != 0 {
+        return format_error(String::from("Trailing data"));
+    }
+
+    Ok(result)
+}
+
+// Writes to a temporary file first so that nobody ever sees a half
+// written cache, even if we crash halfway through.
+pub fn save<T: Cacheable>(path: &Path, hash: u64, aggregate: &T) -> Result<(), CacheError> {
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    {
+        let mut w = CacheWriter { w: BufWriter::new(try!(File::create(&tmp_path))) };
+        try!(w.w.write_all(MAGIC));
+        try!(w.write_u32(CACHE_VERSION));
+        try!(w.write_u8(T::cache_kind()));
+        try!(w.write_u64(hash));
+        try!(aggregate.write_cache(&mut w));
+        try!(w.w.flush());
+    }
+
+    try!(fs::rename(&tmp_path, path));
+    Ok(())
+}
+
+// Loads the aggregate from the cache at path if it was built from the same
+// inputs, and otherwise builds it and replaces the cache. Problems with
+// the cache file never stop the aggregate from being built.
+//
+// The hash only covers the bounds of the primitives and the parameters of
+// the build, never the geometry inside the bounds. Primitives with the same
+// bounds but a different shape get the tree that was cached for the old
+// ones, which is only right for builders that look at nothing but bounds.
+// Anything that clips or otherwise inspects the geometry itself, like
+// spatial splits, must not go through here.
+pub fn load_or_build<T, F>(path: &Path, hash: u64, prims: Vec<Primitive>, build: F) -> T
+    where T: Cacheable, F: FnOnce(Vec<Primitive>) -> T {
+    if let Ok(aggregate) = load(path, hash, &prims) {
+        return aggregate;
+    }
+
+    let aggregate = build(prims);
+    let _ = save(path, hash, &aggregate);
+    aggregate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use geometry::vector::Vector;
+    use primitive::aggregates::tests::get_spheres;
+    use primitive::aggregates::tests::sphere_at;
+
+    #[test]
+    fn it_round_trips_values() {
+        let mut buf = Vec::new();
+        {
+            let mut w = CacheWriter { w: &mut buf };
+            w.write_u8(7).unwrap();
+            w.write_u32(0xdeadbeef).unwrap();
+            w.write_u64(0x0123456789abcdef).unwrap();
+            w.write_f32(-1.5).unwrap();
+            w.write_bbox(&BBox::new()).unwrap();
+            w.write_usize(3).unwrap();
+        }
+        assert_eq!(&buf[1..5], &[0xef, 0xbe, 0xad, 0xde]);
+
+        let mut r = CacheReader { r: Cursor::new(buf) };
+        assert_eq!(r.read_u8().unwrap(), 7);
+        assert_eq!(r.read_u32().unwrap(), 0xdeadbeef);
+        assert_eq!(r.read_u64().unwrap(), 0x0123456789abcdef);
+        assert_eq!(r.read_f32().unwrap(), -1.5);
+        assert_eq!(r.read_bbox().unwrap(), BBox::new());
+        assert!(r.read_index(3).is_err());
+        assert!(r.read_u8().is_err());
+    }
+
+    #[test]
+    fn hashes_depend_on_bounds_and_parameters() {
+        let spheres = get_spheres();
+        let h = hash_inputs(&spheres, &[1, 2]);
+        assert_eq!(h, hash_inputs(&get_spheres(), &[1, 2]));
+        assert!(h != hash_inputs(&spheres, &[1, 3]));
+        assert!(h != hash_inputs(&spheres[1..], &[1, 2]));
+
+        let mut moved = get_spheres();
+        moved[3] = sphere_at(Vector::new_with(2.0, 2.0, 0.5));
+        assert!(h != hash_inputs(&moved, &[1, 2]));
+    }
+}
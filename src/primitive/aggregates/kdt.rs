@@ -1,3 +1,8 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
 use bbox::BBox;
 use bbox::HasBounds;
 use bbox::Union;
@@ -6,6 +11,11 @@ use intersection::Intersectable;
 use intersection::Intersection;
 use primitive::Primitive;
 use primitive::FullyRefinable;
+use primitive::aggregates::cache;
+use primitive::aggregates::cache::CacheError;
+use primitive::aggregates::cache::CacheReader;
+use primitive::aggregates::cache::CacheWriter;
+use primitive::aggregates::cache::Cacheable;
 use ray::Ray;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -362,6 +372,101 @@ impl KDTreeAccelerator {
             nodes: nodes
         }
     }
+
+    // Same as new, but reuses the tree stored at path if it was built from
+    // the same primitives and parameters, and stores it there otherwise.
+    pub fn new_cached(prims: Vec<Primitive>, icost: i32, tcost: i32, ebonus: f32,
+                      maxp: usize, maxd: usize, path: &Path) -> KDTreeAccelerator {
+        let refined = prims.into_iter().fold(Vec::new(), |mut ps, prim| {
+            ps.append(&mut prim.fully_refine());
+            ps
+        });
+
+        let hash = cache::hash_inputs(&refined, &[icost as i64 as u64, tcost as i64 as u64,
+                                                  ebonus.to_bits() as u64,
+                                                  maxp as u64, maxd as u64]);
+        cache::load_or_build(path, hash, refined, |ps| {
+            KDTreeAccelerator::new(ps, icost, tcost, ebonus, maxp, maxd)
+        })
+    }
+}
+
+impl Cacheable for KDTreeAccelerator {
+    fn cache_kind() -> u8 { 1 }
+
+    fn write_cache<W: Write>(&self, w: &mut CacheWriter<W>) -> io::Result<()> {
+        try!(w.write_bbox(&self.bounds));
+        try!(w.write_usize(self.primitives.len()));
+        try!(w.write_usize(self.nodes.len()));
+        for node in self.nodes.iter() {
+            match node {
+                &KDAccelNode::Leaf(ref prim_ids) => {
+                    try!(w.write_u8(3));
+                    try!(w.write_usize(prim_ids.len()));
+                    for &p in prim_ids.iter() {
+                        try!(w.write_usize(p));
+                    }
+                },
+                _ => {
+                    try!(w.write_u8(node.split_axis() as u8));
+                    try!(w.write_f32(node.split_pos()));
+                    try!(w.write_usize(node.above_child()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Everything is checked so that traversing what we read can't go out
+    // of bounds, no matter what was in the file.
+    fn read_cache<R: Read>(r: &mut CacheReader<R>,
+                           prims: &[Primitive]) -> Result<KDTreeAccelerator, CacheError> {
+        let bounds = try!(r.read_bbox());
+        let num_prims = try!(r.read_usize());
+        if num_prims != prims.len() {
+            return Err(CacheError::Format(format!(
+                "Built over {} primitives instead of {}", num_prims, prims.len())));
+        }
+
+        let num_nodes = try!(r.read_usize());
+        let mut nodes = Vec::new();
+        for i in 0..num_nodes {
+            let node = match try!(r.read_u8()) {
+                3 => {
+                    let n = try!(r.read_usize());
+                    let mut prim_ids = Vec::new();
+                    for _ in 0..n {
+                        prim_ids.push(try!(r.read_index(num_prims)));
+                    }
+                    KDAccelNode::leaf(prim_ids)
+                },
+                a if a < 3 => {
+                    let axis = [SplitAxis::X, SplitAxis::Y, SplitAxis::Z][a as usize];
+                    let split = try!(r.read_f32());
+
+                    // Both children come after their parent
+                    let above_child = try!(r.read_index(num_nodes));
+                    if above_child <= i + 1 {
+                        return Err(CacheError::Format(format!("Bad interior node {}", i)));
+                    }
+                    KDAccelNode::interior(axis, above_child, split)
+                },
+                t => return Err(CacheError::Format(format!("Unknown node type {}", t)))
+            };
+            nodes.push(node);
+        }
+
+        if nodes.is_empty() {
+            return Err(CacheError::Format(String::from("No nodes")));
+        }
+
+        Ok(KDTreeAccelerator {
+            bounds: bounds,
+            primitives: prims.to_vec(),
+            nodes: nodes
+        })
+    }
 }
 
 impl HasBounds for KDTreeAccelerator {
@@ -573,4 +678,29 @@ mod tests {
         kdt2.intersect(&r);
         assert_eq!(r.maxt(), 1.0);
     }
+
+    #[test]
+    fn it_can_be_cached_on_disk() {
+        let path = ::std::env::temp_dir().join("pbrt_rust_kdt_cache_test.bin");
+        let _ = ::std::fs::remove_file(&path);
+
+        let built = KDTreeAccelerator::new_cached(get_spheres(), 80, 1, 1.0, 1, 10, &path);
+        let loaded = KDTreeAccelerator::new_cached(get_spheres(), 80, 1, 1.0, 1, 10, &path);
+        assert_eq!(loaded.nodes, built.nodes);
+        assert_eq!(loaded.world_bound(), built.world_bound());
+
+        let r = Ray::new_with(Point::new_with(-5.0, 0.0, 2.0),
+                              Vector::new_with(1.0, 0.0, 0.0), 0.0);
+        assert!((loaded.intersect(&r).unwrap().dg.p.x + 1.0).abs() < 1e-4);
+
+        // Moving a primitive means building the tree again
+        let mut moved = get_spheres();
+        moved[0] = sphere_at(Vector::new_with(-3.0, 0.0, 0.0));
+        let expected = KDTreeAccelerator::new(moved.clone(), 80, 1, 1.0, 1, 10);
+        let rebuilt = KDTreeAccelerator::new_cached(moved, 80, 1, 1.0, 1, 10, &path);
+        assert_eq!(rebuilt.nodes, expected.nodes);
+        assert!(rebuilt.nodes != built.nodes);
+
+        let _ = ::std::fs::remove_file(&path);
+    }
 }
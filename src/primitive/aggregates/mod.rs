@@ -1,6 +1,10 @@
 mod grid;
 mod bvh;
+mod cache;
 mod kdt;
+mod qbvh;
+
+use std::path::Path;
 
 use bbox::BBox;
 use bbox::HasBounds;
@@ -8,14 +12,18 @@ use intersection::Intersectable;
 use intersection::Intersection;
 use primitive::Primitive;
 use primitive::aggregates::grid::GridAccelerator;
-use primitive::aggregates::bvh::BVHAccelerator;
+pub use primitive::aggregates::bvh::BVHAccelerator;
+pub use primitive::aggregates::bvh::BVHBuildStats;
+pub use primitive::aggregates::bvh::SplitMethod;
 use primitive::aggregates::kdt::KDTreeAccelerator;
+use primitive::aggregates::qbvh::QBVHAccelerator;
 use ray::Ray;
 
 #[derive(Clone, Debug)]
 pub enum Aggregate {
     Grid(GridAccelerator),
     BVH(BVHAccelerator),
+    QBVH(QBVHAccelerator),
     KDT(KDTreeAccelerator)
 }
 
@@ -24,14 +32,42 @@ impl Aggregate {
         Aggregate::Grid(GridAccelerator::new(p, refine_immediately))
     }
 
-    pub fn bvh(p: Vec<Primitive>, max_prims: usize, sm: &'static str) -> Aggregate {
+    pub fn bvh(p: Vec<Primitive>, max_prims: usize, sm: SplitMethod) -> Aggregate {
         Aggregate::BVH(BVHAccelerator::new(p, max_prims, sm))
     }
 
+    // Reuses the tree stored at path if it was built from the same
+    // primitives and parameters, and builds and stores it otherwise.
+    pub fn bvh_cached(p: Vec<Primitive>, max_prims: usize, sm: SplitMethod,
+                      path: &Path) -> Aggregate {
+        Aggregate::BVH(BVHAccelerator::new_cached(p, max_prims, sm, path))
+    }
+
+    // Four wide BVH built the same way as the binary one
+    pub fn qbvh(p: Vec<Primitive>, max_prims: usize, sm: SplitMethod) -> Aggregate {
+        Aggregate::QBVH(QBVHAccelerator::new(p, max_prims, sm))
+    }
+
     pub fn kdt(p: Vec<Primitive>, icost: i32, tcost: i32, ebonus: f32,
                max_prims: usize, max_depth: usize) -> Aggregate {
         Aggregate::KDT(KDTreeAccelerator::new(p, icost, tcost, ebonus, max_prims, max_depth))
     }
+
+    pub fn kdt_cached(p: Vec<Primitive>, icost: i32, tcost: i32, ebonus: f32,
+                      max_prims: usize, max_depth: usize, path: &Path) -> Aggregate {
+        Aggregate::KDT(KDTreeAccelerator::new_cached(p, icost, tcost, ebonus,
+                                                     max_prims, max_depth, path))
+    }
+}
+
+impl Aggregate {
+    pub fn bvh_stats(&self) -> Option<&BVHBuildStats> {
+        match self {
+            &Aggregate::BVH(ref bvh) => Some(bvh.stats()),
+            &Aggregate::QBVH(ref qbvh) => Some(qbvh.stats()),
+            _ => None
+        }
+    }
 }
 
 impl HasBounds for Aggregate {
@@ -39,6 +75,7 @@ impl HasBounds for Aggregate {
         match self {
             &Aggregate::Grid(ref ga) => ga.world_bound(),
             &Aggregate::BVH(ref bvh) => bvh.world_bound(),
+            &Aggregate::QBVH(ref qbvh) => qbvh.world_bound(),
             &Aggregate::KDT(ref kdt) => kdt.world_bound()
         }
     }
@@ -49,6 +86,7 @@ impl Intersectable for Aggregate {
         match self {
             &Aggregate::Grid(ref g) => g.intersect(ray),
             &Aggregate::BVH(ref bvh) => bvh.intersect(ray),
+            &Aggregate::QBVH(ref qbvh) => qbvh.intersect(ray),
             &Aggregate::KDT(ref kdt) => kdt.intersect(ray)
         }
     }
@@ -57,6 +95,7 @@ impl Intersectable for Aggregate {
         match self {
             &Aggregate::Grid(ref g) => g.intersect_p(ray),
             &Aggregate::BVH(ref bvh) => bvh.intersect_p(ray),
+            &Aggregate::QBVH(ref qbvh) => qbvh.intersect_p(ray),
             &Aggregate::KDT(ref kdt) => kdt.intersect_p(ray)
         }
     }
@@ -119,17 +158,28 @@ mod tests {
 
     #[test]
     fn bvhs_can_intersect_with_rays_with_sah() {
-        test_intersection(|ps| Aggregate::bvh(ps, 1, "sah"));
+        test_intersection(|ps| Aggregate::bvh(ps, 1, SplitMethod::SAH));
     }
 
     #[test]
     fn bvhs_can_intersect_with_rays_with_middle() {
-        test_intersection(|ps| Aggregate::bvh(ps, 1, "middle"));
+        test_intersection(|ps| Aggregate::bvh(ps, 1, SplitMethod::Middle));
     }
 
     #[test]
     fn bvhs_can_intersect_with_rays_with_equal() {
-        test_intersection(|ps| Aggregate::bvh(ps, 1, "equal"));
+        test_intersection(|ps| Aggregate::bvh(ps, 1, SplitMethod::EqualCounts));
+    }
+
+    #[test]
+    fn bvhs_can_intersect_with_rays_with_binned_sah() {
+        test_intersection(|ps| Aggregate::bvh(ps, 1, SplitMethod::BinnedSAH(8)));
+    }
+
+    #[test]
+    fn qbvhs_can_intersect_with_rays() {
+        test_intersection(|ps| Aggregate::qbvh(ps, 1, SplitMethod::SAH));
+        test_intersection(|ps| Aggregate::qbvh(ps, 2, SplitMethod::BinnedSAH(8)));
     }
 
     #[test]
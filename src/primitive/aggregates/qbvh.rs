@@ -0,0 +1,404 @@
+use bbox::BBox;
+use bbox::HasBounds;
+use intersection::Intersectable;
+use intersection::Intersection;
+use primitive::Primitive;
+use primitive::aggregates::bvh::BVHAccelerator;
+use primitive::aggregates::bvh::BVHBuildStats;
+use primitive::aggregates::bvh::PackedBVHNode;
+use primitive::aggregates::bvh::SplitMethod;
+use ray::Ray;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QBVHChild {
+    Empty,
+    Node(usize),
+    Leaf { prim_offset: usize, num_prims: usize }
+}
+
+// Four children of a binary BVH node and its two children collapsed into
+// one. The bounds are stored as bounds[min/max][axis][child] so that each
+// slab of all four boxes can be loaded at once.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QBVHNode {
+    bounds: [[[f32; 4]; 3]; 2],
+    children: [QBVHChild; 4],
+    // The split axis between children 0, 1 and 2, 3, then between 0 and
+    // 1 and finally between 2 and 3.
+    axes: [usize; 3]
+}
+
+impl QBVHNode {
+    fn new(axis: usize) -> QBVHNode {
+        // Empty children have inverted bounds that no ray can hit
+        QBVHNode {
+            bounds: [[[::std::f32::INFINITY; 4]; 3], [[::std::f32::NEG_INFINITY; 4]; 3]],
+            children: [QBVHChild::Empty; 4],
+            axes: [axis, 0, 0]
+        }
+    }
+
+    fn set_child(&mut self, i: usize, b: &BBox, child: QBVHChild) {
+        for axis in 0..3 {
+            self.bounds[0][axis][i] = b.p_min[axis];
+            self.bounds[1][axis][i] = b.p_max[axis];
+        }
+        self.children[i] = child;
+    }
+
+    // The order in which to visit the children so that nearer ones come
+    // first, decided only by the signs of the ray direction.
+    fn visit_order(&self, dir_is_neg: &[usize; 3]) -> [usize; 4] {
+        let (a, b) = if dir_is_neg[self.axes[1]] == 1 { (1, 0) } else { (0, 1) };
+        let (c, d) = if dir_is_neg[self.axes[2]] == 1 { (3, 2) } else { (2, 3) };
+        if dir_is_neg[self.axes[0]] == 1 { [c, d, a, b] } else { [a, b, c, d] }
+    }
+}
+
+// Everything about a ray that the box tests need, computed once.
+struct TraversalRay {
+    o: [f32; 3],
+    inv_dir: [f32; 3],
+    dir_is_neg: [usize; 3],
+    tmin: f32
+}
+
+impl TraversalRay {
+    fn new(r: &Ray) -> TraversalRay {
+        let inv_dir = [1f32 / r.d.x, 1f32 / r.d.y, 1f32 / r.d.z];
+        TraversalRay {
+            o: [r.o.x, r.o.y, r.o.z],
+            inv_dir: inv_dir,
+            dir_is_neg: [(inv_dir[0] < 0.0) as usize, (inv_dir[1] < 0.0) as usize,
+                         (inv_dir[2] < 0.0) as usize],
+            tmin: r.mint()
+        }
+    }
+}
+
+// Slab tests against all four children of a node. Returns a mask with bit
+// i set if the ray hits child i within [tmin, tmax], along with the
+// distances at which it enters each of them. The near slab is picked by the
+// sign of the direction so the inverted bounds of empty children never
+// hit, and NaNs from rays lying in a slab plane leave the interval alone.
+#[cfg_attr(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"),
+           allow(dead_code))]
+fn intersect_children_scalar(node: &QBVHNode, r: &TraversalRay, tmax: f32) -> (u32, [f32; 4]) {
+    let mut mask = 0;
+    let mut t_near = [r.tmin; 4];
+    for i in 0..4 {
+        let mut t_far = tmax;
+        for axis in 0..3 {
+            let near = node.bounds[r.dir_is_neg[axis]][axis][i];
+            let far = node.bounds[1 - r.dir_is_neg[axis]][axis][i];
+            t_near[i] = ((near - r.o[axis]) * r.inv_dir[axis]).max(t_near[i]);
+            t_far = ((far - r.o[axis]) * r.inv_dir[axis]).min(t_far);
+        }
+
+        if t_near[i] <= t_far {
+            mask |= 1 << i;
+        }
+    }
+
+    (mask, t_near)
+}
+
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"))]
+fn intersect_children(node: &QBVHNode, r: &TraversalRay, tmax: f32) -> (u32, [f32; 4]) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    // Same as the scalar version, one child per lane. When either operand
+    // is NaN, min and max return the second one.
+    unsafe {
+        let mut t_near = _mm_set1_ps(r.tmin);
+        let mut t_far = _mm_set1_ps(tmax);
+        for axis in 0..3 {
+            let o = _mm_set1_ps(r.o[axis]);
+            let inv_dir = _mm_set1_ps(r.inv_dir[axis]);
+            let near = _mm_loadu_ps(node.bounds[r.dir_is_neg[axis]][axis].as_ptr());
+            let far = _mm_loadu_ps(node.bounds[1 - r.dir_is_neg[axis]][axis].as_ptr());
+            t_near = _mm_max_ps(_mm_mul_ps(_mm_sub_ps(near, o), inv_dir), t_near);
+            t_far = _mm_min_ps(_mm_mul_ps(_mm_sub_ps(far, o), inv_dir), t_far);
+        }
+
+        let mut result = [0f32; 4];
+        _mm_storeu_ps(result.as_mut_ptr(), t_near);
+        (_mm_movemask_ps(_mm_cmple_ps(t_near, t_far)) as u32, result)
+    }
+}
+
+#[cfg(not(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse")))]
+fn intersect_children(node: &QBVHNode, r: &TraversalRay, tmax: f32) -> (u32, [f32; 4]) {
+    intersect_children_scalar(node, r, tmax)
+}
+
+// Turns the binary node at idx into a four wide node holding its
+// grandchildren, or its children where those are leaves.
+fn collapse(packed: &[PackedBVHNode], nodes: &mut Vec<QBVHNode>, idx: usize) -> QBVHChild {
+    match &packed[idx] {
+        &PackedBVHNode::Leaf { prim_offset, num_prims, .. } =>
+            QBVHChild::Leaf { prim_offset: prim_offset, num_prims: num_prims },
+        &PackedBVHNode::Inner { second_child_offset, axis, .. } => {
+            let node_idx = nodes.len();
+            nodes.push(QBVHNode::new(axis));
+
+            let mut slots = [None; 4];
+            for (side, &c) in [idx + 1, second_child_offset].iter().enumerate() {
+                match &packed[c] {
+                    &PackedBVHNode::Inner { second_child_offset: grandchild, axis, .. } => {
+                        nodes[node_idx].axes[side + 1] = axis;
+                        slots[2 * side] = Some(c + 1);
+                        slots[2 * side + 1] = Some(grandchild);
+                    },
+                    _ => slots[2 * side] = Some(c)
+                }
+            }
+
+            for (i, slot) in slots.iter().enumerate() {
+                if let Some(c) = *slot {
+                    let child = collapse(packed, nodes, c);
+                    nodes[node_idx].set_child(i, packed[c].bounds(), child);
+                }
+            }
+
+            QBVHChild::Node(node_idx)
+        }
+    }
+}
+
+// A BVH with four children per node, built by collapsing the binary BVH
+// that the split method produces.
+#[derive(Clone, Debug)]
+pub struct QBVHAccelerator {
+    bounds: BBox,
+    root: QBVHChild,
+    nodes: Vec<QBVHNode>,
+    primitives: Vec<Primitive>,
+    stats: BVHBuildStats
+}
+
+impl QBVHAccelerator {
+    pub fn new(p: Vec<Primitive>, mp: usize, sm: SplitMethod) -> QBVHAccelerator {
+        let (packed, primitives, stats) = BVHAccelerator::new(p, mp, sm).into_parts();
+
+        let mut nodes = Vec::with_capacity(packed.len() / 3 + 1);
+        let (bounds, root) = if packed.is_empty() {
+            (BBox::new(), QBVHChild::Empty)
+        } else {
+            (packed[0].bounds().clone(), collapse(&packed, &mut nodes, 0))
+        };
+
+        QBVHAccelerator {
+            bounds: bounds,
+            root: root,
+            nodes: nodes,
+            primitives: primitives,
+            stats: stats
+        }
+    }
+
+    // Statistics of the binary tree that was collapsed
+    pub fn stats(&self) -> &BVHBuildStats { &self.stats }
+
+    pub fn num_nodes(&self) -> usize { self.nodes.len() }
+
+    // Calls f with every child of the nodes hit by the ray, nearest first,
+    // until it returns true.
+    fn traverse<F>(&self, ray: &Ray, mut f: F) where F: FnMut(usize, usize) -> bool {
+        let r = TraversalRay::new(ray);
+        let mut todo = Vec::with_capacity(64);
+        todo.push((self.root, r.tmin));
+
+        while let Some((child, t_near)) = todo.pop() {
+            // Skip anything farther away than the closest hit so far
+            if t_near > ray.maxt() {
+                continue;
+            }
+
+            match child {
+                QBVHChild::Empty => (),
+                QBVHChild::Leaf { prim_offset, num_prims } => {
+                    if f(prim_offset, num_prims) {
+                        return;
+                    }
+                },
+                QBVHChild::Node(n) => {
+                    let node = &self.nodes[n];
+                    let (mask, t_near) = intersect_children(node, &r, ray.maxt());
+                    for &i in node.visit_order(&r.dir_is_neg).iter().rev() {
+                        if (mask & (1 << i)) != 0 && node.children[i] != QBVHChild::Empty {
+                            todo.push((node.children[i], t_near[i]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl HasBounds for QBVHAccelerator {
+    fn world_bound(&self) -> BBox { self.bounds.clone() }
+}
+
+impl Intersectable for QBVHAccelerator {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let mut isect = None;
+        self.traverse(ray, |offset, num_prims| {
+            for p in self.primitives[offset..(offset + num_prims)].iter() {
+                if let Some(i) = p.intersect(ray) {
+                    isect = Some(i);
+                }
+            }
+            false
+        });
+        isect
+    }
+
+    fn intersect_p(&self, ray: &Ray) -> bool {
+        let mut hit = false;
+        self.traverse(ray, |offset, num_prims| {
+            hit = self.primitives[offset..(offset + num_prims)].iter().any(|p| {
+                p.intersect_p(ray)
+            });
+            hit
+        });
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bbox::BBox;
+    use bbox::HasBounds;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use primitive::aggregates::bvh::BVHAccelerator;
+    use primitive::aggregates::bvh::SplitMethod;
+    use primitive::aggregates::tests::get_spheres;
+    use primitive::aggregates::tests::sphere_at;
+    use ray::Ray;
+    use rng::RNG;
+
+    fn random_point(rng: &mut RNG, lo: f32, hi: f32) -> Point {
+        Point::new_with(lo + (hi - lo) * rng.random_float(),
+                        lo + (hi - lo) * rng.random_float(),
+                        lo + (hi - lo) * rng.random_float())
+    }
+
+    fn random_ray(rng: &mut RNG) -> Ray {
+        let o = random_point(rng, -5.0, 25.0);
+        let target = random_point(rng, 0.0, 20.0);
+        Ray::new_with(o.clone(), target - o, 0.0)
+    }
+
+    #[test]
+    fn it_collapses_binary_trees() {
+        let qbvh = QBVHAccelerator::new(get_spheres(), 1, SplitMethod::SAH);
+        assert_eq!(qbvh.primitives.len(), 8);
+        assert_eq!(qbvh.world_bound(),
+                   BBox::new_with(Point::new_with(-1.0, -1.0, -1.0),
+                                  Point::new_with(3.0, 3.0, 3.0)));
+
+        // Each node takes the place of up to three binary inner nodes
+        let num_inner = qbvh.stats().num_nodes - qbvh.stats().num_leaves;
+        assert!(qbvh.num_nodes() < num_inner);
+
+        let mut prims = Vec::new();
+        for n in qbvh.nodes.iter() {
+            for c in n.children.iter() {
+                if let &QBVHChild::Leaf { prim_offset, num_prims } = c {
+                    assert_eq!(num_prims, 1);
+                    prims.push(prim_offset);
+                }
+            }
+        }
+
+        prims.sort();
+        assert_eq!(prims, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn it_handles_tiny_scenes() {
+        let empty = QBVHAccelerator::new(Vec::new(), 1, SplitMethod::SAH);
+        assert_eq!(empty.root, QBVHChild::Empty);
+        let r = Ray::new_with(Point::new(), Vector::new_with(0.0, 0.0, 1.0), 0.0);
+        assert!(empty.intersect(&r).is_none());
+
+        let single = QBVHAccelerator::new(vec![sphere_at(Vector::new_with(0.0, 0.0, 5.0))],
+                                          1, SplitMethod::SAH);
+        assert_eq!(single.num_nodes(), 0);
+        assert!(single.intersect_p(&r));
+        assert!((single.intersect(&r).unwrap().dg.p.z - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn simd_box_tests_match_scalar_ones() {
+        let mut rng = RNG::new(3);
+        let spheres = (0..64).map(|_| {
+            let p = random_point(&mut rng, 0.0, 20.0);
+            sphere_at(Vector::new_with(p.x, p.y, p.z))
+        }).collect();
+        let qbvh = QBVHAccelerator::new(spheres, 2, SplitMethod::SAH);
+
+        for _ in 0..200 {
+            let r = TraversalRay::new(&random_ray(&mut rng));
+            for n in qbvh.nodes.iter() {
+                let (mask, t_near) = intersect_children(n, &r, 100.0);
+                let (scalar_mask, scalar_t_near) = intersect_children_scalar(n, &r, 100.0);
+                assert_eq!(mask, scalar_mask);
+                for i in 0..4 {
+                    if (mask & (1 << i)) != 0 {
+                        assert_eq!(t_near[i], scalar_t_near[i]);
+                    }
+                }
+            }
+        }
+
+        // Rays lying in the plane of a slab
+        let r = TraversalRay::new(&Ray::new_with(Point::new_with(-1.0, 0.0, 0.0),
+                                                 Vector::new_with(1.0, 0.0, 0.0), 0.0));
+        let mut node = QBVHNode::new(0);
+        node.set_child(1, &BBox::new_with(Point::new_with(0.0, 0.0, 0.0),
+                                          Point::new_with(1.0, 1.0, 1.0)),
+                       QBVHChild::Leaf { prim_offset: 0, num_prims: 1 });
+        assert_eq!(intersect_children(&node, &r, 10.0).0, 2);
+        assert_eq!(intersect_children_scalar(&node, &r, 10.0).0, 2);
+    }
+
+    #[test]
+    fn it_finds_the_same_hits_as_a_binary_bvh() {
+        let mut rng = RNG::new(11);
+        let spheres: Vec<_> = (0..100).map(|_| {
+            let p = random_point(&mut rng, 0.0, 20.0);
+            sphere_at(Vector::new_with(p.x, p.y, p.z))
+        }).collect();
+
+        let bvh = BVHAccelerator::new(spheres.clone(), 2, SplitMethod::SAH);
+        let qbvh = QBVHAccelerator::new(spheres, 2, SplitMethod::SAH);
+        assert_eq!(bvh.world_bound(), qbvh.world_bound());
+
+        let mut num_hits = 0;
+        for _ in 0..1000 {
+            let r1 = random_ray(&mut rng);
+            let r2 = r1.clone();
+
+            assert_eq!(bvh.intersect_p(&r1), qbvh.intersect_p(&r2));
+            match (bvh.intersect(&r1), qbvh.intersect(&r2)) {
+                (None, None) => (),
+                (Some(i1), Some(i2)) => {
+                    num_hits += 1;
+                    assert_eq!(i1.primitive_id, i2.primitive_id);
+                    assert_eq!(r1.maxt(), r2.maxt());
+                },
+                _ => panic!("Trees disagree on whether a ray hits")
+            }
+        }
+
+        assert!(num_hits > 100);
+    }
+}
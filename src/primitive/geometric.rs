@@ -39,6 +39,8 @@ impl GeometricPrimitive {
         }
     }
 
+    pub fn clipped_bound(&self, b: &BBox) -> BBox { self.s.clipped_bound(b) }
+
     pub fn area_light(&self) -> Option<AreaLight> {
         self.area_light.as_ref().clone()
     }
@@ -66,7 +68,7 @@ impl Intersectable for GeometricPrimitive {
                 self.s.base().object2world.clone(),
                 self.s.base().shape_id,
                 0,
-                si.ray_epsilon)
+                si.p_error)
         })
     }
 
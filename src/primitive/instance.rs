@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use primitive::FullyRefinable;
+use primitive::Primitive;
+use transform::animated::AnimatedTransform;
+
+// Named objects for instancing. The primitives given between object_begin
+// and object_end are refined and built into their own aggregate once, and
+// every instance of the object shares that aggregate.
+#[derive(Clone, Debug)]
+pub struct InstanceRegistry {
+    objects: HashMap<String, Arc<Primitive>>,
+    current: Option<(String, Vec<Primitive>)>
+}
+
+impl InstanceRegistry {
+    pub fn new() -> InstanceRegistry {
+        InstanceRegistry {
+            objects: HashMap::new(),
+            current: None
+        }
+    }
+
+    pub fn object_begin(&mut self, name: &str) -> Result<(), String> {
+        if let Some((ref current, _)) = self.current {
+            return Err(format!("ObjectBegin \"{}\" called inside of object \"{}\"",
+                               name, current));
+        }
+
+        self.current = Some((name.to_string(), Vec::new()));
+        Ok(())
+    }
+
+    pub fn add_primitive(&mut self, p: Primitive) -> Result<(), String> {
+        match self.current {
+            Some((_, ref mut prims)) => {
+                prims.push(p);
+                Ok(())
+            },
+            None => Err(String::from("Primitive added outside of an object definition"))
+        }
+    }
+
+    pub fn object_end(&mut self) -> Result<(), String> {
+        match self.current.take() {
+            Some((name, prims)) => self.define(&name, prims),
+            None => Err(String::from("ObjectEnd called outside of an object definition"))
+        }
+    }
+
+    // Builds the aggregate for an object in one go. Redefining an object
+    // replaces it for all future instances.
+    pub fn define(&mut self, name: &str, prims: Vec<Primitive>) -> Result<(), String> {
+        let mut refined = prims.into_iter().fold(Vec::new(), |mut ps, p| {
+            ps.append(&mut p.fully_refine());
+            ps
+        });
+
+        let object = match refined.len() {
+            0 => return Err(format!("Object \"{}\" has no primitives", name)),
+            1 => refined.pop().unwrap(),
+            _ => Primitive::grid(refined, false)
+        };
+
+        self.objects.insert(name.to_string(), Arc::new(object));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Primitive>> {
+        self.objects.get(name)
+    }
+
+    pub fn num_objects(&self) -> usize { self.objects.len() }
+
+    // Places a copy of the named object in the world. Just like for
+    // transformed primitives the transform takes world space to the
+    // object's space, and may be animated.
+    pub fn object_instance(&self, name: &str,
+                           world_to_instance: AnimatedTransform) -> Result<Primitive, String> {
+        if self.current.is_some() {
+            return Err(format!("ObjectInstance \"{}\" can't be called inside of an \
+                                object definition", name));
+        }
+
+        match self.objects.get(name) {
+            Some(object) => Ok(Primitive::transformed(object.clone(), world_to_instance)),
+            None => Err(format!("Unable to find object named \"{}\"", name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use bbox::HasBounds;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use primitive::Primitive;
+    use ray::Ray;
+    use shape::Shape;
+    use time::Time;
+    use transform::animated::AnimatedTransform;
+    use transform::transform::Transform;
+
+    fn sphere_at(v: Vector) -> Primitive {
+        Primitive::geometric(Shape::sphere(
+            Transform::translate(&v), Transform::translate(&(-v)),
+            false, 1.0, -1.0, 1.0, 360.0))
+    }
+
+    fn shifted(v: Vector) -> AnimatedTransform {
+        let w2i = Transform::translate(&(-v));
+        AnimatedTransform::new(w2i.clone(), 0.0, w2i, 1.0)
+    }
+
+    #[test]
+    fn it_defines_objects() {
+        let mut registry = InstanceRegistry::new();
+        assert!(registry.object_instance("pair", AnimatedTransform::identity()).is_err());
+        assert!(registry.add_primitive(sphere_at(Vector::new())).is_err());
+        assert!(registry.object_end().is_err());
+
+        registry.object_begin("pair").unwrap();
+        assert!(registry.object_begin("nested").is_err());
+        assert!(registry.object_instance("pair", AnimatedTransform::identity()).is_err());
+        registry.add_primitive(sphere_at(Vector::new_with(-2.0, 0.0, 0.0))).unwrap();
+        registry.add_primitive(sphere_at(Vector::new_with(2.0, 0.0, 0.0))).unwrap();
+        registry.object_end().unwrap();
+
+        registry.object_begin("empty").unwrap();
+        assert!(registry.object_end().is_err());
+
+        assert_eq!(registry.num_objects(), 1);
+        assert!(registry.get("pair").is_some());
+        assert!(registry.get("empty").is_none());
+    }
+
+    #[test]
+    fn instances_share_their_object() {
+        let mut registry = InstanceRegistry::new();
+        registry.define("pair", vec![sphere_at(Vector::new_with(-2.0, 0.0, 0.0)),
+                                     sphere_at(Vector::new_with(2.0, 0.0, 0.0))]).unwrap();
+
+        let instances: Vec<Primitive> = (0..10).map(|i| {
+            registry.object_instance(
+                "pair", shifted(Vector::new_with(0.0, 0.0, 10.0 * (i as f32)))).unwrap()
+        }).collect();
+        assert_eq!(Arc::strong_count(registry.get("pair").unwrap()), 11);
+
+        // Each instance sees the object in its own place
+        let r = Ray::new_with(Point::new_with(2.0, 0.0, 25.0),
+                              Vector::new_with(0.0, 0.0, 1.0), 0.0);
+        assert!(!instances[2].intersect_p(&r));
+        assert!(instances[3].intersect_p(&r));
+        let isect = instances[3].intersect(&r).unwrap();
+        assert!((isect.dg.p.z - 29.0).abs() < 1e-4);
+
+        let wb = instances[9].world_bound();
+        assert!((wb.p_min - Point::new_with(-3.0, -1.0, 89.0)).length() < 1e-4);
+        assert!((wb.p_max - Point::new_with(3.0, 1.0, 91.0)).length() < 1e-4);
+    }
+
+    #[test]
+    fn moving_instances_are_bounded_over_the_shutter() {
+        let mut registry = InstanceRegistry::new();
+        registry.define("ball", vec![sphere_at(Vector::new())]).unwrap();
+
+        let w2i = AnimatedTransform::new(Transform::new(), 0.0,
+                                         Transform::translate(&Vector::new_with(-5.0, 0.0, 0.0)),
+                                         1.0);
+        let instance = registry.object_instance("ball", w2i).unwrap();
+
+        let wb = instance.world_bound();
+        assert!((wb.p_min - Point::new_with(-1.0, -1.0, -1.0)).length() < 1e-4);
+        assert!((wb.p_max - Point::new_with(6.0, 1.0, 1.0)).length() < 1e-4);
+
+        // Halfway through the shutter the ball is at x = 2.5
+        let mut r = Ray::new_with(Point::new_with(2.5, 0.0, -5.0),
+                                  Vector::new_with(0.0, 0.0, 1.0), 0.0);
+        r.time = Time::from(0.5);
+        assert!(instance.intersect_p(&r));
+        r.time = Time::from(0.0);
+        assert!(!instance.intersect_p(&r));
+    }
+}
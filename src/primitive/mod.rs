@@ -1,6 +1,8 @@
 mod aggregates;
 mod geometric;
+mod instance;
 mod transformed;
+mod two_level;
 
 use area_light::AreaLight;
 use bbox::BBox;
@@ -19,7 +21,12 @@ use transform::transform::Transform;
 use primitive::geometric::GeometricPrimitive;
 use primitive::transformed::TransformedPrimitive;
 use primitive::aggregates::Aggregate;
+pub use primitive::aggregates::BVHBuildStats;
+pub use primitive::aggregates::SplitMethod;
+pub use primitive::instance::InstanceRegistry;
+pub use primitive::two_level::TwoLevelBVH;
 
+use std::path::Path;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
@@ -83,6 +90,13 @@ impl Primitive {
         }
     }
 
+    pub fn geometric_with_material(s: Shape, m: Material) -> Primitive {
+        Primitive {
+            base: PrimitiveBase::new(),
+            prim: Arc::new(Prim::Geometric(GeometricPrimitive::new(s, m)))
+        }
+    }
+
     pub fn transformed(p: Arc<Primitive>, xf: AnimatedTransform) -> Primitive {
         Primitive {
             base: PrimitiveBase::new(),
@@ -97,8 +111,55 @@ impl Primitive {
         }
     }
 
+    pub fn bvh(p: Vec<Primitive>, max_prims: usize, sm: SplitMethod) -> Primitive {
+        Primitive {
+            base: PrimitiveBase::new(),
+            prim: Arc::new(Prim::Aggregate(Aggregate::bvh(p, max_prims, sm)))
+        }
+    }
+
+    // Builds a BVH or loads it from the cache file at path, see
+    // Aggregate::bvh_cached.
+    pub fn bvh_cached(p: Vec<Primitive>, max_prims: usize, sm: SplitMethod,
+                      path: &Path) -> Primitive {
+        Primitive {
+            base: PrimitiveBase::new(),
+            prim: Arc::new(Prim::Aggregate(Aggregate::bvh_cached(p, max_prims, sm, path)))
+        }
+    }
+
+    pub fn kdt_cached(p: Vec<Primitive>, icost: i32, tcost: i32, ebonus: f32,
+                      max_prims: usize, max_depth: usize, path: &Path) -> Primitive {
+        Primitive {
+            base: PrimitiveBase::new(),
+            prim: Arc::new(Prim::Aggregate(Aggregate::kdt_cached(
+                p, icost, tcost, ebonus, max_prims, max_depth, path)))
+        }
+    }
+
+    pub fn qbvh(p: Vec<Primitive>, max_prims: usize, sm: SplitMethod) -> Primitive {
+        Primitive {
+            base: PrimitiveBase::new(),
+            prim: Arc::new(Prim::Aggregate(Aggregate::qbvh(p, max_prims, sm)))
+        }
+    }
+
     pub fn get_id(&self) -> usize { self.base.prim_id }
 
+    pub fn bvh_stats(&self) -> Option<&BVHBuildStats> {
+        match self.prim.as_ref() {
+            &Prim::Aggregate(ref a) => a.bvh_stats(),
+            _ => None
+        }
+    }
+
+    pub fn clipped_bound(&self, b: &BBox) -> BBox {
+        match self.prim.as_ref() {
+            &Prim::Geometric(ref p) => p.clipped_bound(b),
+            _ => self.world_bound().clipped_to(b)
+        }
+    }
+
     pub fn area_light(&self) -> Option<AreaLight> {
         match self.prim.as_ref() {
             &Prim::Geometric(ref p) => p.area_light().clone(),
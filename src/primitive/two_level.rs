@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use primitive::BVHBuildStats;
+use primitive::Prim;
+use primitive::Primitive;
+use primitive::PrimitiveBase;
+use primitive::SplitMethod;
+use primitive::aggregates::Aggregate;
+use primitive::aggregates::BVHAccelerator;
+use transform::animated::AnimatedTransform;
+
+// Acceleration for animated scenes in two levels. Every object gets a
+// bottom level BVH that is built once and shared by all of its instances,
+// and the top level BVH over the instances is only refit when they move
+// from one frame to the next.
+#[derive(Clone, Debug)]
+pub struct TwoLevelBVH {
+    max_prims: usize,
+    split_method: SplitMethod,
+    objects: Vec<Arc<Primitive>>,
+    instances: Vec<(usize, AnimatedTransform)>,
+    top: Option<BVHAccelerator>,
+    moved: bool
+}
+
+impl TwoLevelBVH {
+    pub fn new(max_prims: usize, sm: SplitMethod) -> TwoLevelBVH {
+        TwoLevelBVH {
+            max_prims: max_prims,
+            split_method: sm,
+            objects: Vec::new(),
+            instances: Vec::new(),
+            top: None,
+            moved: false
+        }
+    }
+
+    pub fn add_object(&mut self, prims: Vec<Primitive>) -> usize {
+        self.objects.push(Arc::new(Primitive::bvh(prims, self.max_prims, self.split_method)));
+        self.objects.len() - 1
+    }
+
+    // Just like for transformed primitives the transform takes world space
+    // to the space of the object.
+    pub fn add_instance(&mut self, object: usize,
+                        world_to_instance: AnimatedTransform) -> Result<usize, String> {
+        if object >= self.objects.len() {
+            return Err(format!("No object with index {}", object));
+        }
+
+        // A new instance changes the shape of the top level
+        self.top = None;
+        self.instances.push((object, world_to_instance));
+        Ok(self.instances.len() - 1)
+    }
+
+    pub fn set_transform(&mut self, instance: usize,
+                         world_to_instance: AnimatedTransform) -> Result<(), String> {
+        match self.instances.get_mut(instance) {
+            Some(&mut (_, ref mut xf)) => {
+                *xf = world_to_instance;
+                self.moved = true;
+                Ok(())
+            },
+            None => Err(format!("No instance with index {}", instance))
+        }
+    }
+
+    pub fn object(&self, object: usize) -> Option<&Arc<Primitive>> {
+        self.objects.get(object)
+    }
+
+    pub fn num_objects(&self) -> usize { self.objects.len() }
+    pub fn num_instances(&self) -> usize { self.instances.len() }
+
+    fn instance_primitives(&self) -> Vec<Primitive> {
+        self.instances.iter().map(|&(object, ref xf)| {
+            Primitive::transformed(self.objects[object].clone(), xf.clone())
+        }).collect()
+    }
+
+    // Brings the top level up to date with the instances. It's built from
+    // scratch after instances were added and refit if they only moved.
+    pub fn update(&mut self) {
+        if self.top.is_none() {
+            let instances = self.instance_primitives();
+            self.top = Some(BVHAccelerator::new(instances, self.max_prims, self.split_method));
+        } else if self.moved {
+            let instances = self.instance_primitives();
+            self.top.as_mut().unwrap().refit(instances)
+                .expect("Instances can't be added without rebuilding the top level");
+        }
+
+        self.moved = false;
+    }
+
+    // Refit trees get worse as instances move around, so every now and
+    // then it pays off to start over.
+    pub fn rebuild(&mut self) {
+        self.top = None;
+        self.update();
+    }
+
+    pub fn top_level_stats(&self) -> Option<&BVHBuildStats> {
+        self.top.as_ref().map(|top| top.stats())
+    }
+
+    // The scene as it is now, to be rendered as a single aggregate.
+    pub fn to_primitive(&mut self) -> Primitive {
+        self.update();
+        Primitive {
+            base: PrimitiveBase::new(),
+            prim: Arc::new(Prim::Aggregate(Aggregate::BVH(self.top.clone().unwrap())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use bbox::HasBounds;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use primitive::Primitive;
+    use primitive::SplitMethod;
+    use ray::Ray;
+    use shape::Shape;
+    use transform::animated::AnimatedTransform;
+    use transform::transform::Transform;
+
+    fn sphere_at(v: Vector) -> Primitive {
+        Primitive::geometric(Shape::sphere(
+            Transform::translate(&v), Transform::translate(&(-v)),
+            false, 1.0, -1.0, 1.0, 360.0))
+    }
+
+    fn shifted(v: Vector) -> AnimatedTransform {
+        let w2i = Transform::translate(&(-v));
+        AnimatedTransform::new(w2i.clone(), 0.0, w2i, 1.0)
+    }
+
+    fn ray_along_x(y: f32) -> Ray {
+        Ray::new_with(Point::new_with(-10.0, y, 0.0), Vector::new_with(1.0, 0.0, 0.0), 0.0)
+    }
+
+    #[test]
+    fn it_checks_its_indices() {
+        let mut scene = TwoLevelBVH::new(1, SplitMethod::SAH);
+        assert!(scene.add_instance(0, AnimatedTransform::identity()).is_err());
+        assert!(scene.set_transform(0, AnimatedTransform::identity()).is_err());
+
+        let ball = scene.add_object(vec![sphere_at(Vector::new())]);
+        assert_eq!(scene.add_instance(ball, AnimatedTransform::identity()), Ok(0));
+        assert!(scene.set_transform(0, shifted(Vector::new_with(1.0, 0.0, 0.0))).is_ok());
+        assert_eq!(scene.num_objects(), 1);
+        assert_eq!(scene.num_instances(), 1);
+    }
+
+    #[test]
+    fn moving_instances_reuses_objects() {
+        let mut scene = TwoLevelBVH::new(1, SplitMethod::SAH);
+        let pair = scene.add_object(vec![sphere_at(Vector::new_with(0.0, -2.0, 0.0)),
+                                         sphere_at(Vector::new_with(0.0, 2.0, 0.0))]);
+        for i in 0..4 {
+            scene.add_instance(pair, shifted(Vector::new_with(5.0 * (i as f32), 0.0, 0.0)))
+                .unwrap();
+        }
+
+        let frame = scene.to_primitive();
+        let isect = frame.intersect(&ray_along_x(2.0)).unwrap();
+        assert!((isect.dg.p.x + 1.0).abs() < 1e-4);
+        let num_nodes = scene.top_level_stats().unwrap().num_nodes;
+
+        // Move the first two instances out of the way
+        let object = scene.object(pair).unwrap().clone();
+        scene.set_transform(0, shifted(Vector::new_with(0.0, 0.0, 10.0))).unwrap();
+        scene.set_transform(1, shifted(Vector::new_with(0.0, 0.0, 10.0))).unwrap();
+
+        let frame = scene.to_primitive();
+        let isect = frame.intersect(&ray_along_x(2.0)).unwrap();
+        assert!((isect.dg.p.x - 9.0).abs() < 1e-4);
+        assert!(!frame.intersect_p(&ray_along_x(0.0)));
+        assert!((frame.world_bound().p_max.z - 11.0).abs() < 1e-4);
+
+        // The top level was refit and the object is the very same
+        assert_eq!(scene.top_level_stats().unwrap().num_nodes, num_nodes);
+        assert!(Arc::ptr_eq(&object, scene.object(pair).unwrap()));
+
+        // Adding an instance rebuilds the top level
+        scene.add_instance(pair, shifted(Vector::new_with(-5.0, 0.0, 0.0))).unwrap();
+        assert!(scene.top_level_stats().is_none());
+        let isect = scene.to_primitive().intersect(&ray_along_x(-2.0)).unwrap();
+        assert!((isect.dg.p.x + 6.0).abs() < 1e-4);
+    }
+}
@@ -1,9 +1,13 @@
 use std::cell::RefCell;
 
+use geometry::normal::Normal;
 use geometry::point::Point;
+use geometry::vector::Dot;
 use geometry::vector::Vector;
 use std::f32;
 use time::Time;
+use utils::next_float_down;
+use utils::next_float_up;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Ray {
@@ -128,10 +132,31 @@ impl ::std::convert::From<Ray> for RayDifferential {
     }
 }
 
+// Moves a point p, which is known to be within p_error of a surface with
+// normal n, far enough along the normal that a ray leaving it in the
+// direction w can't intersect the surface it came from.
+pub fn offset_ray_origin(p: &Point, p_error: &Vector, n: &Normal, w: &Vector) -> Point {
+    let nv = Vector::from(n);
+    let d = nv.x.abs() * p_error.x + nv.y.abs() * p_error.y + nv.z.abs() * p_error.z;
+    let offset = if w.dot(&nv) < 0.0 { -d * &nv } else { d * &nv };
+    let mut po = p + &offset;
+
+    // Round the offset point away from p
+    for i in 0..3 {
+        if offset[i] > 0.0 {
+            po[i] = next_float_up(po[i]);
+        } else if offset[i] < 0.0 {
+            po[i] = next_float_down(po[i]);
+        }
+    }
+    po
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::cell::RefCell;
+    use geometry::normal::Normal;
     use geometry::point::Point;
     use geometry::vector::Vector;
     use time::Time;
@@ -232,4 +257,24 @@ mod tests {
         assert_eq!(rd.point_at(0.5), Point::new_with(1.0, 1.0, 3.0));
         assert_eq!(r.point_at(0.5), Point::new_with(1.0, 1.0, 3.0));
     }
+
+    #[test]
+    fn their_origins_can_be_offset_from_surfaces() {
+        let p = Point::new_with(1.0, 2.0, 0.0);
+        let p_error = Vector::new_with(1e-4, 1e-4, 1e-3);
+        let n = Normal::new_with(0.0, 0.0, 1.0);
+
+        // Rays leaving above the surface are pushed up past the error
+        let up = offset_ray_origin(&p, &p_error, &n, &Vector::new_with(1.0, 0.0, 1.0));
+        assert_eq!((up.x, up.y), (1.0, 2.0));
+        assert!(up.z > 1e-3);
+
+        // ... and rays leaving below it are pushed down
+        let down = offset_ray_origin(&p, &p_error, &n, &Vector::new_with(0.0, 1.0, -1.0));
+        assert!(down.z < -1e-3);
+
+        // Points that are known exactly stay where they are
+        let exact = offset_ray_origin(&p, &Vector::new(), &n, &Vector::new_with(0.0, 0.0, 1.0));
+        assert_eq!(exact, p);
+    }
 }
@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use bbox::Union;
+use geometry::normal::Normal;
+use geometry::normal::Normalize;
+use geometry::point::Point;
+use geometry::vector::Cross;
+use geometry::vector::Vector;
+use primitive::Refinable;
+use shape::mesh::Mesh;
+use shape::ShapeBase;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Lerp;
+
+// Sharpness tags on the control mesh. The sharpness is the number of
+// levels of subdivision that the edge or vertex stays sharp for, so
+// fractional values give semi-sharp features and infinity keeps them
+// sharp all the way down to the limit surface.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubdivTag {
+    Crease(usize, usize, f32),
+    Corner(usize, f32)
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn centroid(p: &[Point], vs: &[usize]) -> Point {
+    let sum = vs.iter().fold(Point::new(), |s, &v| s + &p[v]);
+    sum * (1.0 / (vs.len() as f32))
+}
+
+// How a vertex moves during subdivision, along with how much of the
+// sharp rule to blend in with the smooth one
+enum VertexRule {
+    Smooth,
+    Crease(usize, usize, f32),
+    Corner(f32)
+}
+
+// Adjacency information for one level of the control mesh
+struct Topology {
+    // Each edge once, in the order that they're first seen in the faces
+    edges: Vec<(usize, usize)>,
+    edge_index: HashMap<(usize, usize), usize>,
+    edge_faces: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+    vertex_edges: Vec<Vec<usize>>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ControlMesh {
+    p: Vec<Point>,
+    faces: Vec<Vec<usize>>,
+    creases: HashMap<(usize, usize), f32>,
+    corners: HashMap<usize, f32>
+}
+
+impl ControlMesh {
+    fn topology(&self) -> Topology {
+        let mut topo = Topology {
+            edges: Vec::new(),
+            edge_index: HashMap::new(),
+            edge_faces: Vec::new(),
+            vertex_faces: vec![Vec::new(); self.p.len()],
+            vertex_edges: vec![Vec::new(); self.p.len()]
+        };
+
+        for (fi, f) in self.faces.iter().enumerate() {
+            for (i, &v) in f.iter().enumerate() {
+                topo.vertex_faces[v].push(fi);
+
+                let key = edge_key(v, f[(i + 1) % f.len()]);
+                let next_edge = topo.edges.len();
+                let e = *topo.edge_index.entry(key).or_insert(next_edge);
+                if e == next_edge {
+                    topo.edges.push(key);
+                    topo.edge_faces.push(Vec::new());
+                    topo.vertex_edges[key.0].push(e);
+                    topo.vertex_edges[key.1].push(e);
+                }
+                topo.edge_faces[e].push(fi);
+            }
+        }
+
+        topo
+    }
+
+    // Boundary and non-manifold edges are always infinitely sharp
+    fn edge_sharpness(&self, topo: &Topology, e: usize) -> f32 {
+        if topo.edge_faces[e].len() != 2 {
+            ::std::f32::INFINITY
+        } else {
+            *self.creases.get(&topo.edges[e]).unwrap_or(&0.0)
+        }
+    }
+
+    fn vertex_rule(&self, topo: &Topology, v: usize) -> VertexRule {
+        // Vertices that aren't part of any face stay where they are, as
+        // do the outside corners of boundaries
+        if topo.vertex_faces[v].len() <= 1 {
+            return VertexRule::Corner(1.0);
+        }
+
+        let corner = *self.corners.get(&v).unwrap_or(&0.0);
+        if corner > 0.0 {
+            return VertexRule::Corner(corner.min(1.0));
+        }
+
+        let sharp: Vec<(usize, f32)> = topo.vertex_edges[v].iter().filter_map(|&e| {
+            let s = self.edge_sharpness(topo, e);
+            let (a, b) = topo.edges[e];
+            if s > 0.0 { Some((if a == v { b } else { a }, s)) } else { None }
+        }).collect();
+
+        let mean = sharp.iter().fold(0.0, |m, &(_, s)| m + s) / (sharp.len() as f32);
+        match sharp.len() {
+            0 | 1 => VertexRule::Smooth,
+            2 => VertexRule::Crease(sharp[0].0, sharp[1].0, mean.min(1.0)),
+            _ => VertexRule::Corner(mean.min(1.0))
+        }
+    }
+
+    fn subdivide(&self) -> ControlMesh {
+        let topo = self.topology();
+        let nv = self.p.len();
+        let ne = topo.edges.len();
+
+        // Compute new face points
+        let face_points: Vec<Point> = self.faces.iter().map(|f| centroid(&self.p, f)).collect();
+
+        // Compute new edge points, blending towards the midpoint of the
+        // edge as it gets sharper
+        let edge_points: Vec<Point> = topo.edges.iter().enumerate().map(|(e, &(a, b))| {
+            let mid = centroid(&self.p, &[a, b]);
+            let s = self.edge_sharpness(&topo, e);
+            if s >= 1.0 {
+                mid
+            } else {
+                let fs = &topo.edge_faces[e];
+                let smooth = (&self.p[a] + &self.p[b] + &face_points[fs[0]] +
+                              &face_points[fs[1]]) * 0.25;
+                smooth.lerp(&mid, s)
+            }
+        }).collect();
+
+        // Compute new positions for the existing vertices
+        let smooth_point = |v: usize| {
+            let n = topo.vertex_edges[v].len() as f32;
+            let f = topo.vertex_faces[v].iter().fold(Point::new(), |s, &f| s + &face_points[f]) *
+                (1.0 / (topo.vertex_faces[v].len() as f32));
+            let r = topo.vertex_edges[v].iter().fold(Point::new(), |s, &e| {
+                let (a, b) = topo.edges[e];
+                s + centroid(&self.p, &[a, b])
+            }) * (1.0 / n);
+            (f + 2.0 * &r + (n - 3.0) * &self.p[v]) * (1.0 / n)
+        };
+
+        let vertex_points: Vec<Point> = (0..nv).map(|v| {
+            let (sharp, s) = match self.vertex_rule(&topo, v) {
+                VertexRule::Smooth => return smooth_point(v),
+                VertexRule::Crease(a, b, s) =>
+                    (0.75 * &self.p[v] + 0.125 * (&self.p[a] + &self.p[b]), s),
+                VertexRule::Corner(s) => (self.p[v].clone(), s)
+            };
+
+            if s >= 1.0 { sharp } else { smooth_point(v).lerp(&sharp, s) }
+        }).collect();
+
+        // Split each face with n sides into n quads around its face point
+        let mut faces = Vec::new();
+        for (fi, f) in self.faces.iter().enumerate() {
+            let n = f.len();
+            for i in 0..n {
+                let next = topo.edge_index[&edge_key(f[i], f[(i + 1) % n])];
+                let prev = topo.edge_index[&edge_key(f[(i + n - 1) % n], f[i])];
+                faces.push(vec![f[i], nv + next, nv + ne + fi, nv + prev]);
+            }
+        }
+
+        // Each level of subdivision uses up one level of sharpness
+        let mut creases = HashMap::new();
+        for (&(a, b), &s) in self.creases.iter() {
+            if let Some(&e) = topo.edge_index.get(&(a, b)) {
+                if s > 1.0 {
+                    creases.insert(edge_key(a, nv + e), s - 1.0);
+                    creases.insert(edge_key(nv + e, b), s - 1.0);
+                }
+            }
+        }
+
+        let corners = self.corners.iter()
+            .filter(|&(_, &s)| s > 1.0)
+            .map(|(&v, &s)| (v, s - 1.0))
+            .collect();
+
+        ControlMesh {
+            p: vertex_points.into_iter().chain(edge_points.into_iter())
+                .chain(face_points.into_iter()).collect(),
+            faces: faces,
+            creases: creases,
+            corners: corners
+        }
+    }
+
+    // The edge neighbors and diagonal neighbors around an interior vertex
+    // of a quad mesh, in counter-clockwise order. Returns None if the
+    // vertex is on a boundary or touches a face that isn't a quad.
+    fn one_ring(&self, topo: &Topology, v: usize) -> Option<Vec<(usize, usize)>> {
+        let mut next = HashMap::new();
+        for &f in topo.vertex_faces[v].iter() {
+            let q = &self.faces[f];
+            if q.len() != 4 {
+                return None;
+            }
+
+            let k = q.iter().position(|&w| w == v).unwrap();
+            next.insert(q[(k + 1) % 4], (q[(k + 2) % 4], q[(k + 3) % 4]));
+        }
+
+        let start = match next.keys().next() {
+            Some(&a) => a,
+            None => return None
+        };
+
+        let mut ring = Vec::new();
+        let mut a = start;
+        loop {
+            let (d, b) = match next.get(&a) {
+                Some(&db) => db,
+                None => return None
+            };
+
+            ring.push((a, d));
+            a = b;
+            if a == start || ring.len() > next.len() {
+                break;
+            }
+        }
+
+        if a == start && ring.len() == next.len() { Some(ring) } else { None }
+    }
+
+    // Moves the vertices of a quad mesh to their positions on the limit
+    // surface and computes the limit normals there.
+    fn limit(&self) -> (Vec<Point>, Vec<Normal>) {
+        let topo = self.topology();
+        let rings: Vec<_> = (0..self.p.len()).map(|v| self.one_ring(&topo, v)).collect();
+
+        let smooth_limit = |v: usize| {
+            match rings[v] {
+                Some(ref ring) => {
+                    let n = ring.len() as f32;
+                    let sum = ring.iter().fold(Point::new(), |s, &(e, f)| {
+                        s + 4.0 * &self.p[e] + &self.p[f]
+                    });
+                    (sum + n * n * &self.p[v]) * (1.0 / (n * (n + 5.0)))
+                }
+                None => self.p[v].clone()
+            }
+        };
+
+        let p: Vec<Point> = (0..self.p.len()).map(|v| {
+            let (sharp, s) = match self.vertex_rule(&topo, v) {
+                VertexRule::Smooth => return smooth_limit(v),
+                VertexRule::Crease(a, b, s) =>
+                    ((4.0 * &self.p[v] + &self.p[a] + &self.p[b]) * (1.0 / 6.0), s),
+                VertexRule::Corner(s) => (self.p[v].clone(), s)
+            };
+
+            if s >= 1.0 { sharp } else { smooth_limit(v).lerp(&sharp, s) }
+        }).collect();
+
+        // Smooth vertices get the exact limit normal from the cross product
+        // of the limit tangents, and the rest average the normals of the
+        // faces around them.
+        let n = (0..self.p.len()).map(|v| {
+            let ring = match (self.vertex_rule(&topo, v), rings[v].as_ref()) {
+                (VertexRule::Smooth, Some(ring)) => ring,
+                _ => {
+                    let n = topo.vertex_faces[v].iter().fold(Vector::new(), |n, &f| {
+                        let q = &self.faces[f];
+                        n + (&p[q[2]] - &p[q[0]]).into_cross(&p[q[3]] - &p[q[1]])
+                    });
+                    return Normal::from(n.normalize());
+                }
+            };
+
+            let valence = ring.len();
+            let theta = 2.0 * PI / (valence as f32);
+            let a_n = 1.0 + theta.cos() +
+                (0.5 * theta).cos() * (2.0 * (9.0 + theta.cos())).sqrt();
+
+            let mut s = Vector::new();
+            let mut t = Vector::new();
+            for (i, &(e, f)) in ring.iter().enumerate() {
+                let (sin0, cos0) = (theta * (i as f32)).sin_cos();
+                let (sin1, cos1) = (theta * ((i + 1) as f32)).sin_cos();
+                let pe = Vector::from(self.p[e].clone());
+                let pf = Vector::from(self.p[f].clone());
+                s = s + (a_n * cos0) * &pe + (cos0 + cos1) * &pf;
+                t = t + (a_n * sin0) * &pe + (sin0 + sin1) * &pf;
+            }
+
+            Normal::from(s.into_cross(t).normalize())
+        }).collect();
+
+        (p, n)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatmullClark {
+    base: ShapeBase,
+    n_levels: usize,
+    cage: ControlMesh
+}
+
+impl CatmullClark {
+    // Faces are given as polygons, with nverts[i] vertices for the i'th
+    // face taken in order from vertex_indices.
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               nverts: &[usize], vertex_indices: &[usize], points: &[Point],
+               nl: usize, tags: &[SubdivTag]) -> CatmullClark {
+        assert_eq!(nverts.iter().fold(0, |n, &nv| n + nv), vertex_indices.len());
+
+        let mut faces = Vec::with_capacity(nverts.len());
+        let mut start = 0;
+        for &nv in nverts.iter() {
+            assert!(nv >= 3);
+            faces.push(vertex_indices[start..(start + nv)].to_vec());
+            start += nv;
+        }
+
+        let mut creases = HashMap::new();
+        let mut corners = HashMap::new();
+        for tag in tags.iter() {
+            match tag {
+                &SubdivTag::Crease(a, b, s) => { creases.insert(edge_key(a, b), s); },
+                &SubdivTag::Corner(v, s) => { corners.insert(v, s); }
+            }
+        }
+
+        CatmullClark {
+            base: ShapeBase::new(o2w, w2o, ro),
+            n_levels: nl,
+            cage: ControlMesh {
+                p: points.to_vec(),
+                faces: faces,
+                creases: creases,
+                corners: corners
+            }
+        }
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    pub fn object_bound(&self) -> BBox {
+        // The limit surface is always within the hull of the control points
+        self.cage.p.iter().fold(BBox::new(), |b, p| b.unioned_with_ref(p))
+    }
+
+    // Subdivides the control mesh and returns the triangulated result as
+    // vertex indices, points and normals. Without any levels of subdivision
+    // the control mesh itself is returned, since it may not be made of quads.
+    fn limit_mesh(&self) -> (Vec<usize>, Vec<Point>, Option<Vec<Normal>>) {
+        let mut mesh = self.cage.clone();
+        for _ in 0..self.n_levels {
+            mesh = mesh.subdivide();
+        }
+
+        let mut indices = Vec::new();
+        for f in mesh.faces.iter() {
+            for i in 1..(f.len() - 1) {
+                indices.extend_from_slice(&[f[0], f[i], f[i + 1]]);
+            }
+        }
+
+        if self.n_levels == 0 {
+            (indices, mesh.p, None)
+        } else {
+            let (p, n) = mesh.limit();
+            (indices, p, Some(n))
+        }
+    }
+}
+
+impl Refinable<Mesh> for CatmullClark {
+    fn is_refined(&self) -> bool { false }
+    fn refine(self) -> Vec<Mesh> {
+        let (indices, p, n) = self.limit_mesh();
+        vec![Mesh::new(self.base.object2world.clone(),
+                       self.base.world2object.clone(),
+                       self.base.reverse_orientation,
+                       &indices, &p, n.as_ref().map(|n| &n[..]),
+                       None, None, None)]
+    }
+}
+
+impl HasBounds for CatmullClark {
+    fn world_bound(&self) -> BBox {
+        let o2w = &self.base().object2world;
+        self.cage.p.iter().fold(BBox::new(), |b, p| b.unioned_with(o2w.t(p)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use geometry::normal::Normalize;
+    use geometry::point::Point;
+    use geometry::vector::Dot;
+    use geometry::vector::Vector;
+    use primitive::Refinable;
+    use transform::transform::Transform;
+
+    // Unit cube, with vertex i at the corner given by the bits of i
+    fn cube_pts() -> Vec<Point> {
+        (0..8).map(|i| {
+            Point::new_with((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32)
+        }).collect()
+    }
+
+    static CUBE_QUADS: [usize; 24] =
+        [0, 2, 3, 1,  4, 5, 7, 6,  0, 1, 5, 4,
+         2, 6, 7, 3,  0, 4, 6, 2,  1, 3, 7, 5];
+
+    fn cube(nl: usize, tags: &[SubdivTag]) -> CatmullClark {
+        CatmullClark::new(Transform::new(), Transform::new(), false,
+                          &[4; 6], &CUBE_QUADS, &cube_pts(), nl, tags)
+    }
+
+    fn cube_edges() -> Vec<(usize, usize)> {
+        let mut edges: Vec<(usize, usize)> = CUBE_QUADS.chunks(4).flat_map(|q| {
+            (0..4).map(|i| edge_key(q[i], q[(i + 1) % 4])).collect::<Vec<_>>()
+        }).collect();
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+
+    // A flat 3x3 grid of quads in the xy plane
+    fn grid(nl: usize) -> CatmullClark {
+        let pts: Vec<Point> = (0..16).map(|i| {
+            Point::new_with((i % 4) as f32, (i / 4) as f32, 0.0)
+        }).collect();
+        let mut quads = Vec::new();
+        for y in 0..3 {
+            for x in 0..3 {
+                let v = y * 4 + x;
+                quads.extend_from_slice(&[v, v + 1, v + 5, v + 4]);
+            }
+        }
+        CatmullClark::new(Transform::new(), Transform::new(), false,
+                          &[4; 9], &quads, &pts, nl, &[])
+    }
+
+    fn max_z(p: &[Point]) -> f32 {
+        p.iter().fold(::std::f32::MIN, |z, p| z.max(p.z))
+    }
+
+    #[test]
+    fn it_can_be_created() {
+        let c = cube(2, &[SubdivTag::Crease(1, 0, 2.5), SubdivTag::Corner(7, 1.0)]);
+        assert_eq!(c.n_levels, 2);
+        assert_eq!(c.cage.p.len(), 8);
+        assert_eq!(c.cage.faces.len(), 6);
+        assert_eq!(c.cage.faces[1], vec![4, 5, 7, 6]);
+        assert_eq!(c.cage.creases.get(&(0, 1)), Some(&2.5));
+        assert_eq!(c.cage.corners.get(&7), Some(&1.0));
+    }
+
+    #[test]
+    fn it_has_bounds() {
+        let bounds = BBox::new_with(Point::new(), Point::new_with(1.0, 1.0, 1.0));
+        assert_eq!(cube(1, &[]).object_bound(), bounds);
+
+        let xf = Transform::translate(&Vector::new_with(1.0, 2.0, 3.0));
+        let moved = CatmullClark::new(xf.clone(), xf.inverse(), false, &[4; 6],
+                                      &CUBE_QUADS, &cube_pts(), 1, &[]);
+        assert_eq!(moved.world_bound(),
+                   BBox::new_with(Point::new_with(1.0, 2.0, 3.0),
+                                  Point::new_with(2.0, 3.0, 4.0)));
+    }
+
+    #[test]
+    fn it_subdivides_polygons_into_quads() {
+        let once = cube(0, &[]).cage.subdivide();
+        assert_eq!(once.p.len(), 8 + 12 + 6);
+        assert_eq!(once.faces.len(), 24);
+        assert!(once.faces.iter().all(|f| f.len() == 4));
+
+        // Triangles and pentagons turn into quads too
+        let pts: Vec<Point> = (0..6).map(|i| {
+            let a = (i as f32) * 1.1;
+            Point::new_with(a.cos(), a.sin(), 0.0)
+        }).collect();
+        let polys = CatmullClark::new(Transform::new(), Transform::new(), false,
+                                      &[3, 5], &[0, 1, 2, 0, 2, 3, 4, 5], &pts, 0, &[]);
+        let split = polys.cage.subdivide();
+        assert_eq!(split.faces.len(), 8);
+        assert!(split.faces.iter().all(|f| f.len() == 4));
+        assert_eq!(split.p.len(), 6 + 7 + 2);
+    }
+
+    #[test]
+    fn it_converges_to_a_smooth_limit() {
+        let (_, p, n) = cube(3, &[]).limit_mesh();
+        let n = n.unwrap();
+        let center = Point::new_with(0.5, 0.5, 0.5);
+
+        // The cube gets rounded off so that it pulls away from the
+        // corners and the faces
+        for (p, n) in p.iter().zip(n.iter()) {
+            for i in 0..3 {
+                assert!(p[i] > 0.05 && p[i] < 0.95);
+            }
+
+            // Normals point out of the surface
+            let radial = (p - &center).normalize();
+            assert!(Vector::from(n).dot(&radial) > 0.8);
+        }
+
+        // The middle of the top face is flat
+        let top_z = max_z(&p);
+        let top = p.iter().position(|p| p.z == top_z).unwrap();
+        assert!(n[top].z > 0.999);
+    }
+
+    #[test]
+    fn it_keeps_sharp_creases() {
+        let tags: Vec<SubdivTag> = cube_edges().iter().map(|&(a, b)| {
+            SubdivTag::Crease(a, b, ::std::f32::INFINITY)
+        }).collect();
+        let (_, p, _) = cube(2, &tags).limit_mesh();
+
+        // Every point stays on the surface of the cube, and the corners
+        // stay put
+        for p in p.iter() {
+            assert!((0..3).all(|i| p[i] > -1e-5 && p[i] < 1.0 + 1e-5));
+            assert!((0..3).any(|i| p[i].abs() < 1e-5 || (p[i] - 1.0).abs() < 1e-5));
+        }
+
+        for c in cube_pts().iter() {
+            assert!(p.contains(c));
+        }
+    }
+
+    #[test]
+    fn it_softens_semi_sharp_creases() {
+        let tags = |s: f32| -> Vec<SubdivTag> {
+            cube_edges().iter().map(|&(a, b)| SubdivTag::Crease(a, b, s)).collect()
+        };
+
+        // The sharper the creases, the closer the surface gets to the
+        // corner of the cube
+        let reach = |s: f32| {
+            cube(3, &tags(s)).limit_mesh().1.iter()
+                .fold(0.0f32, |r, p| r.max(p.x + p.y + p.z))
+        };
+        assert!(reach(0.0) < reach(0.5));
+        assert!(reach(0.5) < reach(1.0));
+        assert!(reach(1.0) < reach(2.0));
+        assert!(reach(2.0) < 3.0);
+    }
+
+    #[test]
+    fn it_keeps_tagged_corners() {
+        let (_, p, _) = cube(2, &[SubdivTag::Corner(7, ::std::f32::INFINITY)]).limit_mesh();
+        assert!(p.contains(&Point::new_with(1.0, 1.0, 1.0)));
+        assert!(!p.contains(&Point::new()));
+    }
+
+    #[test]
+    fn it_handles_boundaries() {
+        let (_, p, n) = grid(2).limit_mesh();
+
+        // The grid stays flat and keeps its corners
+        assert!(p.iter().all(|p| p.z == 0.0));
+        assert!(p.iter().all(|p| p.x >= 0.0 && p.x <= 3.0 && p.y >= 0.0 && p.y <= 3.0));
+        assert!(p.contains(&Point::new_with(0.0, 0.0, 0.0)));
+        assert!(p.contains(&Point::new_with(3.0, 3.0, 0.0)));
+
+        // ... and faces up everywhere
+        assert!(n.unwrap().iter().all(|n| n.z > 0.999));
+    }
+
+    #[test]
+    fn it_can_be_refined() {
+        let meshes = cube(2, &[]).refine();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].clone().refine().len(), 6 * 16 * 2);
+
+        // Without subdivision we just get the control mesh back
+        assert_eq!(cube(0, &[]).refine()[0].clone().refine().len(), 12);
+    }
+}
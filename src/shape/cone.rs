@@ -0,0 +1,288 @@
+use std::f32::consts::PI;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use geometry::point::Point;
+use geometry::vector::Vector;
+use intersection::Intersectable;
+use ray::Ray;
+use shape::ShapeBase;
+use shape::ShapeIntersection;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Clamp;
+use utils::Degrees;
+
+use shape::helpers::compute_dg;
+use shape::helpers::reproject_onto_radius;
+
+// A cone with its base on the xy plane and its tip at z = height
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cone {
+    base: ShapeBase,
+    radius: f32,
+    height: f32,
+    phi_max: f32
+}
+
+impl Cone {
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               height: f32, rad: f32, pm: f32) -> Cone {
+        Cone {
+            base: ShapeBase::new(o2w, w2o, ro),
+            radius: rad,
+            height: height,
+            phi_max: pm.clamp(0.0, 360.0).as_radians()
+        }
+    }
+
+    fn get_intersection_point(&self, r: &Ray) -> Option<(f32, f32)> {
+        // Compute quadratic cone coefficients
+        let k = (self.radius / self.height) * (self.radius / self.height);
+        let a = r.d.x * r.d.x + r.d.y * r.d.y - k * r.d.z * r.d.z;
+        let b = 2.0 * (r.d.x * r.o.x + r.d.y * r.o.y - k * r.d.z * (r.o.z - self.height));
+        let c = r.o.x * r.o.x + r.o.y * r.o.y
+            - k * (r.o.z - self.height) * (r.o.z - self.height);
+
+        // Solve quadratic equation for t values
+        let (t0, t1) = {
+            match ::utils::quadratic(a, b, c) {
+                None => return None,
+                Some((x, y)) => (x, y)
+            }
+        };
+
+        // Compute intersection distance along ray
+        if t0 > r.maxt() || t1 < r.mint() {
+            return None
+        }
+
+        let mut t_hit = t0;
+        if t0 < r.mint() {
+            t_hit = t1;
+            if t_hit > r.maxt() {
+                return None;
+            }
+        }
+
+        // Compute cone hit point and phi
+        let get_hit = |t: f32| {
+            let hit = r.point_at(t);
+            let mut angle = hit.y.atan2(hit.x);
+            if angle < 0.0 {
+                angle = angle + 2.0 * PI;
+            }
+            (hit, angle)
+        };
+
+        let invalid_hit = |hit: &(Point, f32)| {
+            hit.0.z < 0.0 || hit.0.z > self.height || hit.1 > self.phi_max
+        };
+
+        // Test cone intersection against clipping parameters
+        let mut p_hit = get_hit(t_hit);
+        if invalid_hit(&p_hit) {
+            if t_hit == t1 { return None; }
+            if t1 > r.maxt() { return None; }
+            t_hit = t1;
+            p_hit = get_hit(t_hit);
+            if invalid_hit(&p_hit) { return None; }
+        }
+
+        return Some((t_hit, p_hit.1))
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    pub fn object_bound(&self) -> BBox {
+        BBox::new_with(
+            Point::new_with(-self.radius, -self.radius, 0.0),
+            Point::new_with(self.radius, self.radius, self.height))
+    }
+
+    pub fn area(&self) -> f32 {
+        // Unroll the cone into a sector of a disk with the slant height
+        // as its radius
+        let slant = (self.height * self.height + self.radius * self.radius).sqrt();
+        0.5 * self.radius * slant * self.phi_max
+    }
+}
+
+impl HasBounds for Cone {
+    fn world_bound(&self) -> BBox {
+        self.base().object2world.xf(self.object_bound())
+    }
+}
+
+impl Intersectable<ShapeIntersection> for Cone {
+    fn intersect_p(&self, r: &Ray) -> bool {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+        self.get_intersection_point(&ray).is_some()
+    }
+
+    fn intersect(&self, r: &Ray) -> Option<ShapeIntersection> {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+
+        let (t_hit, phi) = {
+            let hit = self.get_intersection_point(&ray);
+            if hit.is_some() { hit.unwrap() } else { return None; }
+        };
+
+        // Refine cone intersection point
+        let (p_hit, p_error) = {
+            let p = ray.point_at(t_hit);
+            let r = self.radius * (1.0 - p.z / self.height);
+            reproject_onto_radius(p, r, 6)
+        };
+
+        // Find parametric representation of cone hit
+        let u = phi / self.phi_max;
+        let v = p_hit.z / self.height;
+
+        // Compute cone dpdu and dpdv
+        let dpdu = self.phi_max * Vector::new_with(-p_hit.y, p_hit.x, 0.0);
+        let dpdv = Vector::new_with(-p_hit.x / (1.0 - v), -p_hit.y / (1.0 - v),
+                                    self.height);
+
+        // Compute cone dndu and dndv
+        let d2pduu = -self.phi_max * self.phi_max *
+            Vector::new_with(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = self.phi_max / (1.0 - v) *
+            Vector::new_with(p_hit.y, -p_hit.x, 0.0);
+        let d2pdvv = Vector::new();
+
+        // Initialize DifferentialGeometry from parametric information
+        let p_error = self.base().object2world.xf_point_with_error(&p_hit, &p_error).1;
+        let dg = compute_dg(self.base(), u, v, p_hit,
+                            dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::f32::consts::PI;
+
+    use bbox::BBox;
+    use geometry::normal::Normalize;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use ray::Ray;
+    use shape::ShapeBase;
+    use transform::transform::Transform;
+    use utils::Degrees;
+
+    #[test]
+    fn it_can_be_created() {
+        let xf = Transform::translate(&Vector::new_with(1.0, 2.0, 3.0));
+        assert_eq!(Cone::new(xf.clone(), xf.inverse(), false, 2.0, 0.5, 400.0),
+                   Cone {
+                       base: ShapeBase::new(xf.clone(), xf.inverse(), false),
+                       radius: 0.5,
+                       height: 2.0,
+                       phi_max: 360f32.as_radians()
+                   });
+    }
+
+    #[test]
+    fn it_has_bounds() {
+        assert_eq!(Cone::new(Transform::new(), Transform::new(), false,
+                             2.0, 0.5, 360.0).object_bound(),
+                   BBox::new_with(
+                       Point::new_with(-0.5, -0.5, 0.0),
+                       Point::new_with(0.5, 0.5, 2.0)));
+
+        // It ignores phi_max when computing the bounds
+        assert_eq!(Cone::new(Transform::new(), Transform::new(), false,
+                             2.0, 0.5, 90.0).object_bound(),
+                   BBox::new_with(
+                       Point::new_with(-0.5, -0.5, 0.0),
+                       Point::new_with(0.5, 0.5, 2.0)));
+    }
+
+    #[test]
+    fn it_can_be_intersected() {
+        let simple = Cone::new(Transform::new(), Transform::new(), false,
+                               1.0, 1.0, 360.0);
+
+        // Straight through the side
+        assert!(simple.intersect_p(
+            &Ray::new_with(Point::new_with(2.0, 0.0, 0.5),
+                           Vector::new_with(-1.0, 0.0, 0.0), 0.0)));
+
+        // Above the tip, and below the base, the ray only hits the part
+        // of the infinite cone that is clipped away
+        assert!(!simple.intersect_p(
+            &Ray::new_with(Point::new_with(2.0, 0.0, 1.5),
+                           Vector::new_with(-1.0, 0.0, 0.0), 0.0)));
+        assert!(!simple.intersect_p(
+            &Ray::new_with(Point::new_with(2.0, 0.0, -0.5),
+                           Vector::new_with(-1.0, 0.0, 0.0), 0.0)));
+
+        // Up through the open base hits the inside
+        assert!(simple.intersect_p(
+            &Ray::new_with(Point::new_with(0.2, 0.0, -1.0),
+                           Vector::new_with(0.0, 0.0, 1.0), 0.0)));
+
+        // Partial cones only cover part of the sweep
+        let partial = Cone::new(Transform::new(), Transform::new(), false,
+                                1.0, 1.0, 90.0);
+        assert!(partial.intersect_p(
+            &Ray::new_with(Point::new_with(1.0, 1.0, 0.2),
+                           Vector::new_with(-1.0, -1.0, 0.0), 0.0)));
+        assert!(!partial.intersect_p(
+            &Ray::new_with(Point::new_with(-0.5, -2.0, 0.2),
+                           Vector::new_with(0.0, 1.0, 0.0), 0.0)));
+
+        // The ray stops short of the cone
+        assert!(!simple.intersect_p(&{
+            let r = Ray::new_with(Point::new_with(2.0, 0.0, 0.5),
+                                  Vector::new_with(-1.0, 0.0, 0.0), 0.0);
+            r.set_maxt(1.0);
+            r
+        }));
+    }
+
+    #[test]
+    fn it_has_intersection_information() {
+        let c = Cone::new(Transform::new(), Transform::new(), false,
+                          1.0, 1.0, 360.0);
+
+        let r = Ray::new_with(Point::new_with(0.0, 2.0, 0.5),
+                              Vector::new_with(0.0, -1.0, 0.0), 0.0);
+        let shape_int = c.intersect(&r).unwrap();
+
+        assert!((shape_int.t_hit - 1.5).abs() < 1e-6);
+        assert!((shape_int.dg.p - Point::new_with(0.0, 0.5, 0.5)).length_squared() < 1e-6);
+        assert_eq!(shape_int.dg.shape.as_ref().unwrap(), c.base());
+
+        // The normal points out and up the side of the cone
+        let expected_normal = Vector::new_with(0.0, 1.0, 1.0).normalize();
+        assert!((Vector::from(shape_int.dg.nn.clone()) - expected_normal).length_squared() < 1e-6);
+
+        assert!((shape_int.dg.u - 0.25).abs() < 1e-6);
+        assert!((shape_int.dg.v - 0.5).abs() < 1e-6);
+        assert!((shape_int.dg.dpdu - Vector::new_with(-PI, 0.0, 0.0)).length_squared() < 1e-6);
+        assert!((shape_int.dg.dpdv - Vector::new_with(0.0, -1.0, 1.0)).length_squared() < 1e-6);
+
+        // The cone curves around phi but not along its sides
+        assert!(Vector::from(shape_int.dg.dndu.clone()).length_squared() > 0.0);
+        assert!(Vector::from(shape_int.dg.dndv.clone()).length_squared() < 1e-6);
+    }
+
+    #[test]
+    fn it_has_a_surface_area() {
+        // Slant height of 5
+        assert!((Cone::new(Transform::new(), Transform::new(), false,
+                           4.0, 3.0, 360.0).area() - 15.0 * PI).abs() < 1e-4);
+        assert!((Cone::new(Transform::new(), Transform::new(), false,
+                           4.0, 3.0, 180.0).area() - 7.5 * PI).abs() < 1e-4);
+        assert_eq!(Cone::new(Transform::new(), Transform::new(), false,
+                             4.0, 0.0, 360.0).area(), 0.0);
+    }
+}
@@ -0,0 +1,496 @@
+use std::sync::Arc;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use bbox::Union;
+use diff_geom::DifferentialGeometry;
+use geometry::normal::Normal;
+use geometry::normal::Normalize;
+use geometry::point::Point;
+use geometry::vector::Cross;
+use geometry::vector::Dot;
+use geometry::vector::Vector;
+use geometry::vector::coordinate_system;
+use intersection::Intersectable;
+use ray::Ray;
+use shape::ShapeBase;
+use shape::ShapeIntersection;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Clamp;
+use utils::Lerp;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveType {
+    // A flat strip that always faces the ray
+    Flat,
+    // A flat strip that shades as if it were a cylinder
+    Cylinder,
+    // A strip whose orientation is given by normals at its endpoints
+    Ribbon
+}
+
+// Data shared between all the segments that a curve is split into
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurveCommon {
+    ty: CurveType,
+    cp_obj: [Point; 4],
+    width: [f32; 2],
+    n: [Vector; 2],
+    normal_angle: f32,
+    inv_sin_normal_angle: f32
+}
+
+impl CurveCommon {
+    pub fn new(cp: [Point; 4], w0: f32, w1: f32, ty: CurveType,
+               norm: Option<[Normal; 2]>) -> CurveCommon {
+        let (n, normal_angle, inv_sin_normal_angle) = match norm {
+            Some(ref ns) => {
+                let n0 = Vector::from(&ns[0]).normalize();
+                let n1 = Vector::from(&ns[1]).normalize();
+                let angle = n0.dot(&n1).clamp(0.0, 1.0).acos();
+                ([n0, n1], angle, 1.0 / angle.sin())
+            }
+            None => ([Vector::new(), Vector::new()], 0.0, 0.0)
+        };
+
+        CurveCommon {
+            ty: ty,
+            cp_obj: cp,
+            width: [w0, w1],
+            n: n,
+            normal_angle: normal_angle,
+            inv_sin_normal_angle: inv_sin_normal_angle
+        }
+    }
+}
+
+// Evaluates a cubic Bézier curve and its derivative at u
+fn eval_bezier(cp: &[Point; 4], u: f32) -> (Point, Vector) {
+    let cp1 = [cp[0].lerp(&cp[1], u), cp[1].lerp(&cp[2], u), cp[2].lerp(&cp[3], u)];
+    let cp2 = [cp1[0].lerp(&cp1[1], u), cp1[1].lerp(&cp1[2], u)];
+    let deriv = if (&cp2[1] - &cp2[0]).length_squared() > 0.0 {
+        3.0 * (&cp2[1] - &cp2[0])
+    } else {
+        // For a degenerate curve the derivative at the endpoints
+        // is the direction to the other end
+        &cp[3] - &cp[0]
+    };
+    (cp2[0].lerp(&cp2[1], u), deriv)
+}
+
+// Splits a cubic Bézier curve in half, returning the control points
+// of both halves with the middle one shared.
+fn subdivide_bezier(cp: &[Point; 4]) -> [Point; 7] {
+    let half = |a: &Point, b: &Point| (a + b) * 0.5;
+    let quarter = |a: &Point, b: &Point, c: &Point| (a + &(b * 2.0) + c.clone()) * 0.25;
+    let eighth = |a: &Point, b: &Point, c: &Point, d: &Point| {
+        (a + &(b * 3.0) + (c * 3.0) + d.clone()) * 0.125
+    };
+
+    [cp[0].clone(),
+     half(&cp[0], &cp[1]),
+     quarter(&cp[0], &cp[1], &cp[2]),
+     eighth(&cp[0], &cp[1], &cp[2], &cp[3]),
+     quarter(&cp[1], &cp[2], &cp[3]),
+     half(&cp[2], &cp[3]),
+     cp[3].clone()]
+}
+
+// Computes the control point of the Bézier curve at the polar form
+// (blossom) of the three parameters.
+fn blossom_bezier(p: &[Point; 4], u0: f32, u1: f32, u2: f32) -> Point {
+    let a = [p[0].lerp(&p[1], u0), p[1].lerp(&p[2], u0), p[2].lerp(&p[3], u0)];
+    let b = [a[0].lerp(&a[1], u1), a[1].lerp(&a[2], u1)];
+    b[0].lerp(&b[1], u2)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    base: ShapeBase,
+    common: Arc<CurveCommon>,
+    u_min: f32,
+    u_max: f32
+}
+
+impl Curve {
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               common: Arc<CurveCommon>, u_min: f32, u_max: f32) -> Curve {
+        Curve {
+            base: ShapeBase::new(o2w, w2o, ro),
+            common: common,
+            u_min: u_min,
+            u_max: u_max
+        }
+    }
+
+    // Splits the curve into 2^split_depth segments that can be bounded
+    // more tightly than the whole curve.
+    pub fn create_segments(o2w: Transform, w2o: Transform, ro: bool,
+                           cp: [Point; 4], w0: f32, w1: f32, ty: CurveType,
+                           norm: Option<[Normal; 2]>, split_depth: usize) -> Vec<Curve> {
+        let common = Arc::new(CurveCommon::new(cp, w0, w1, ty, norm));
+        let num_segments = 1 << split_depth;
+        (0..num_segments).map(|i| {
+            let u_min = (i as f32) / (num_segments as f32);
+            let u_max = ((i + 1) as f32) / (num_segments as f32);
+            Curve::new(o2w.clone(), w2o.clone(), ro, common.clone(), u_min, u_max)
+        }).collect()
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    fn segment_control_points(&self) -> [Point; 4] {
+        let cp = &self.common.cp_obj;
+        [blossom_bezier(cp, self.u_min, self.u_min, self.u_min),
+         blossom_bezier(cp, self.u_min, self.u_min, self.u_max),
+         blossom_bezier(cp, self.u_min, self.u_max, self.u_max),
+         blossom_bezier(cp, self.u_max, self.u_max, self.u_max)]
+    }
+
+    fn width_at(&self, u: f32) -> f32 {
+        self.common.width[0].lerp(&self.common.width[1], u)
+    }
+
+    pub fn object_bound(&self) -> BBox {
+        // The curve is inside the convex hull of its control points
+        let cp = self.segment_control_points();
+        let b = BBox::from(&cp[0]).unioned_with_ref(&cp[1])
+            .unioned_with_ref(&cp[2]).unioned_with_ref(&cp[3]);
+        let width = self.width_at(self.u_min).max(self.width_at(self.u_max));
+        let mut b = b;
+        b.expand(width * 0.5);
+        b
+    }
+
+    pub fn area(&self) -> f32 {
+        // Approximate with the length of the control polygon
+        let cp = self.segment_control_points();
+        let length = (0..3).fold(0.0, |l, i| l + cp[i].distance(&cp[i + 1]));
+        let avg_width = (self.width_at(self.u_min) + self.width_at(self.u_max)) * 0.5;
+        length * avg_width
+    }
+
+    // Intersects the object space ray with the control points cp, given
+    // in a space where the ray starts at the origin and heads down the z
+    // axis. Returns the ray parameter, dpdu, dpdv, u and v of the hit.
+    fn recursive_intersect(&self, ray: &Ray, cp: &[Point; 4], object_to_ray: &Transform,
+                           ray_to_object: &Transform, u0: f32, u1: f32, depth: usize)
+                           -> Option<(f32, Vector, Vector, f32, f32)> {
+        let ray_length = ray.d.length();
+
+        if depth > 0 {
+            // Split the curve and recurse into each half that the ray
+            // might still hit
+            let cp_split = subdivide_bezier(cp);
+            let u = [u0, (u0 + u1) * 0.5, u1];
+            let mut closest = None;
+            for seg in 0..2 {
+                let z_max = ray_length * ray.maxt();
+                let cps = [cp_split[3 * seg].clone(), cp_split[3 * seg + 1].clone(),
+                           cp_split[3 * seg + 2].clone(), cp_split[3 * seg + 3].clone()];
+                let max_width = self.width_at(u[seg]).max(self.width_at(u[seg + 1]));
+                if !overlaps_ray(&cps, max_width, z_max) {
+                    continue;
+                }
+
+                if let Some(hit) = self.recursive_intersect(ray, &cps, object_to_ray, ray_to_object,
+                                                            u[seg], u[seg + 1], depth - 1) {
+                    // Clip the ray so that only closer hits are reported
+                    ray.set_maxt(hit.0);
+                    closest = Some(hit);
+                }
+            }
+            return closest;
+        }
+
+        // Intersect ray with the curve segment: make sure the point lies
+        // between the planes perpendicular to the curve at its endpoints
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return None;
+        }
+
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return None;
+        }
+
+        // Compute the closest point on the line segment to the ray
+        let (sx, sy) = (cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = sx * sx + sy * sy;
+        if denom == 0.0 {
+            return None;
+        }
+
+        let w = (-cp[0].x * sx - cp[0].y * sy) / denom;
+
+        // Compute u coordinate of the curve intersection and its width
+        let u = u0.lerp(&u1, w.clamp(0.0, 1.0)).clamp(u0, u1);
+        let mut hit_width = self.width_at(u);
+        let mut n_hit = Vector::new();
+        if self.common.ty == CurveType::Ribbon {
+            // Scale the width by the angle between the ribbon and the ray
+            let c = &self.common;
+            n_hit = if c.normal_angle == 0.0 {
+                c.n[0].clone()
+            } else {
+                let sin0 = ((1.0 - u) * c.normal_angle).sin() * c.inv_sin_normal_angle;
+                let sin1 = (u * c.normal_angle).sin() * c.inv_sin_normal_angle;
+                sin0 * &c.n[0] + sin1 * &c.n[1]
+            };
+            hit_width *= n_hit.abs_dot(&ray.d) / ray_length;
+        }
+
+        // Test the intersection point against the curve width
+        let (pc, dpcdw) = eval_bezier(cp, w.clamp(0.0, 1.0));
+        let pt_curve_dist2 = pc.x * pc.x + pc.y * pc.y;
+        if pt_curve_dist2 > hit_width * hit_width * 0.25 {
+            return None;
+        }
+
+        let z_max = ray_length * ray.maxt();
+        if pc.z < ray.mint() * ray_length || pc.z > z_max {
+            return None;
+        }
+
+        // Compute v coordinate, which side of the curve the point is on
+        let pt_curve_dist = pt_curve_dist2.sqrt();
+        let edge_func = dpcdw.x * -pc.y + pc.x * dpcdw.y;
+        let v = if edge_func > 0.0 {
+            0.5 + pt_curve_dist / hit_width
+        } else {
+            0.5 - pt_curve_dist / hit_width
+        };
+
+        let t_hit = pc.z / ray_length;
+
+        // Compute the partial derivatives in object space
+        let (_, dpdu) = eval_bezier(&self.common.cp_obj, u);
+        let dpdv = if self.common.ty == CurveType::Ribbon {
+            n_hit.cross_with(&dpdu).normalize() * hit_width
+        } else {
+            // Compute the curve's dpdv as seen from the ray
+            let dpdu_plane = object_to_ray.t(&dpdu);
+            let mut dpdv_plane =
+                Vector::new_with(-dpdu_plane.y, dpdu_plane.x, 0.0).normalize() * hit_width;
+            if self.common.ty == CurveType::Cylinder {
+                // Rotate dpdv around dpdu to give the impression of a cylinder
+                let theta = (-90.0f32).lerp(&90.0, v);
+                let rot = Transform::rotate(-theta, &dpdu_plane);
+                dpdv_plane = rot.xf(dpdv_plane);
+            }
+            ray_to_object.xf(dpdv_plane)
+        };
+
+        Some((t_hit, dpdu, dpdv, u, v))
+    }
+
+    fn intersect_object(&self, ray: &Ray) -> Option<(f32, Vector, DifferentialGeometry)> {
+        let cp_obj = self.segment_control_points();
+
+        // Project the curve into a coordinate system where the ray
+        // starts at the origin and goes down the z axis
+        let mut dx = ray.d.cross_with(&(&cp_obj[3] - &cp_obj[0]));
+        if dx.length_squared() == 0.0 {
+            let (dx2, _) = coordinate_system(&ray.d.clone().normalize());
+            dx = dx2;
+        }
+
+        let object_to_ray = Transform::look_at(&ray.o, &(&ray.o + &ray.d), &dx);
+        let cp = [object_to_ray.t(&cp_obj[0]), object_to_ray.t(&cp_obj[1]),
+                  object_to_ray.t(&cp_obj[2]), object_to_ray.t(&cp_obj[3])];
+
+        // Bail out early if the bounds of the curve miss the ray
+        let max_width = self.width_at(self.u_min).max(self.width_at(self.u_max));
+        let z_max = ray.d.length() * ray.maxt();
+        if !overlaps_ray(&cp, max_width, z_max) {
+            return None;
+        }
+
+        // Refine the curve until the segments are nearly linear
+        let l0 = (0..2).fold(0.0f32, |l, i| {
+            l.max((cp[i].x - 2.0 * cp[i + 1].x + cp[i + 2].x).abs())
+             .max((cp[i].y - 2.0 * cp[i + 1].y + cp[i + 2].y).abs())
+             .max((cp[i].z - 2.0 * cp[i + 1].z + cp[i + 2].z).abs())
+        });
+
+        let eps = self.common.width[0].max(self.common.width[1]) * 0.05;
+        let max_depth = if l0 > 0.0 {
+            let r0 = (1.41421356237 * 6.0 * l0 / (8.0 * eps)).log2() * 0.5;
+            r0.round().clamp(0.0, 10.0) as usize
+        } else {
+            0
+        };
+
+        // Work on a copy of the ray since the recursion clips it
+        let local = ray.clone();
+        let ray_to_object = object_to_ray.inverse();
+        let (t_hit, dpdu, dpdv, u, v) =
+            match self.recursive_intersect(&local, &cp, &object_to_ray, &ray_to_object,
+                                           self.u_min, self.u_max, max_depth) {
+                Some(hit) => hit,
+                None => return None
+            };
+
+        // The hit point is only known to be somewhere within the width of
+        // the curve, which is the length of dpdv
+        let p_hit = ray.point_at(t_hit);
+        let w = 2.0 * dpdv.length();
+        let o2w = &self.base.object2world;
+        let p_error = o2w.xf_point_with_error(&p_hit, &Vector::new_with(w, w, w)).1;
+        let dg = DifferentialGeometry::new_with(
+            o2w.xf(p_hit), o2w.xf(dpdu), o2w.xf(dpdv),
+            Normal::new(), Normal::new(), u, v, Some(self.base.clone()));
+        Some((t_hit, p_error, dg))
+    }
+}
+
+// Tests the bounds of the control points against a ray starting at the
+// origin and heading down the z axis.
+fn overlaps_ray(cp: &[Point; 4], max_width: f32, z_max: f32) -> bool {
+    let min_max = |f: &Fn(&Point) -> f32| {
+        cp.iter().fold((::std::f32::MAX, ::std::f32::MIN), |(lo, hi), p| {
+            (lo.min(f(p)), hi.max(f(p)))
+        })
+    };
+
+    let half = 0.5 * max_width;
+    let (x0, x1) = min_max(&|p: &Point| p.x);
+    let (y0, y1) = min_max(&|p: &Point| p.y);
+    let (z0, z1) = min_max(&|p: &Point| p.z);
+    !(x1 + half < 0.0 || x0 - half > 0.0 ||
+      y1 + half < 0.0 || y0 - half > 0.0 ||
+      z1 + half < 0.0 || z0 - half > z_max)
+}
+
+impl HasBounds for Curve {
+    fn world_bound(&self) -> BBox {
+        self.base().object2world.xf(self.object_bound())
+    }
+}
+
+impl Intersectable<ShapeIntersection> for Curve {
+    fn intersect_p(&self, r: &Ray) -> bool {
+        let ray = self.base().world2object.t(r);
+        self.intersect_object(&ray).is_some()
+    }
+
+    fn intersect(&self, r: &Ray) -> Option<ShapeIntersection> {
+        let ray = self.base().world2object.t(r);
+        self.intersect_object(&ray).map(|(t_hit, p_error, dg)| {
+            ShapeIntersection::new(t_hit, p_error, dg)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::normal::Normal;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use ray::Ray;
+    use transform::transform::Transform;
+
+    fn straight_curve(ty: CurveType) -> Vec<Curve> {
+        // A straight curve along the x axis
+        let cp = [Point::new_with(-1.0, 0.0, 0.0), Point::new_with(-1.0 / 3.0, 0.0, 0.0),
+                  Point::new_with(1.0 / 3.0, 0.0, 0.0), Point::new_with(1.0, 0.0, 0.0)];
+        let n = Some([Normal::new_with(0.0, 0.0, 1.0), Normal::new_with(0.0, 0.0, 1.0)]);
+        Curve::create_segments(Transform::new(), Transform::new(), false,
+                               cp, 0.2, 0.1, ty, n, 1)
+    }
+
+    #[test]
+    fn it_splits_into_segments() {
+        let segments = straight_curve(CurveType::Flat);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].u_max, segments[1].u_min);
+
+        // Bounds include the width of the curve
+        let b = segments[0].object_bound();
+        assert!(b.p_min.x <= -1.0 && b.p_max.x >= 0.0);
+        assert!(b.p_min.y <= -0.1 + 1e-6 && b.p_max.y >= 0.1 - 1e-6);
+
+        let area = segments.iter().fold(0.0, |a, s| a + s.area());
+        assert!((area - 0.3).abs() < 1e-4);
+    }
+
+    #[test]
+    fn it_subdivides_bezier_curves() {
+        let cp = [Point::new_with(0.0, 0.0, 0.0), Point::new_with(1.0, 2.0, 0.0),
+                  Point::new_with(2.0, 2.0, 1.0), Point::new_with(3.0, 0.0, 1.0)];
+        let split = subdivide_bezier(&cp);
+        let (mid, _) = eval_bezier(&cp, 0.5);
+        assert!((&split[3] - &mid).length_squared() < 1e-6);
+
+        // Blossoming gives the same control points for [0, 0.5]
+        for (i, &(a, b, c)) in [(0.0, 0.0, 0.0), (0.0, 0.0, 0.5),
+                                (0.0, 0.5, 0.5), (0.5, 0.5, 0.5)].iter().enumerate() {
+            assert!((&blossom_bezier(&cp, a, b, c) - &split[i]).length_squared() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn it_can_be_intersected() {
+        for ty in [CurveType::Flat, CurveType::Cylinder, CurveType::Ribbon].iter() {
+            let segments = straight_curve(*ty);
+
+            // Straight down onto the curve
+            let r = Ray::new_with(Point::new_with(-0.5, 0.02, 2.0),
+                                  Vector::new_with(0.0, 0.0, -1.0), 0.0);
+            let hits: Vec<ShapeIntersection> = segments.iter()
+                .filter_map(|s| s.intersect(&r)).collect();
+            assert_eq!(hits.len(), 1);
+            assert!((hits[0].t_hit - 2.0).abs() < 1e-3);
+            assert!((hits[0].dg.u - 0.25).abs() < 1e-2);
+            assert!((hits[0].dg.v - 0.5).abs() > 0.05);
+
+            // Outside of the width of the curve
+            let r = Ray::new_with(Point::new_with(-0.5, 0.2, 2.0),
+                                  Vector::new_with(0.0, 0.0, -1.0), 0.0);
+            assert!(!segments.iter().any(|s| s.intersect_p(&r)));
+
+            // Past the end of the curve
+            let r = Ray::new_with(Point::new_with(1.2, 0.0, 2.0),
+                                  Vector::new_with(0.0, 0.0, -1.0), 0.0);
+            assert!(!segments.iter().any(|s| s.intersect_p(&r)));
+        }
+    }
+
+    #[test]
+    fn ribbons_are_thin_edge_on() {
+        let segments = straight_curve(CurveType::Ribbon);
+
+        // Looking at the ribbon edge-on it has no width
+        let r = Ray::new_with(Point::new_with(-0.5, 2.0, 0.01),
+                              Vector::new_with(0.0, -1.0, 0.0), 0.0);
+        assert!(!segments.iter().any(|s| s.intersect_p(&r)));
+
+        // But a flat curve always faces the ray
+        let flat = straight_curve(CurveType::Flat);
+        let r = Ray::new_with(Point::new_with(-0.5, 2.0, 0.01),
+                              Vector::new_with(0.0, -1.0, 0.0), 0.0);
+        assert!(flat.iter().any(|s| s.intersect_p(&r)));
+    }
+
+    #[test]
+    fn it_has_differential_geometry() {
+        let segments = straight_curve(CurveType::Cylinder);
+        let r = Ray::new_with(Point::new_with(0.5, 0.0, 2.0),
+                              Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        let hit = segments.iter().filter_map(|s| s.intersect(&r)).next().unwrap();
+
+        // dpdu follows the curve and the normal faces back up the ray
+        assert!(hit.dg.dpdu.x > 0.0);
+        assert!(hit.dg.dpdu.y.abs() < 1e-4 && hit.dg.dpdu.z.abs() < 1e-4);
+        assert!((hit.dg.v - 0.5).abs() < 1e-3);
+        assert!(Vector::from(hit.dg.nn).z.abs() > 0.99);
+
+        // The error in the hit point covers the width of the curve
+        let w = 2.0 * hit.dg.dpdv.length();
+        assert!(hit.p_error.x >= w && hit.p_error.y >= w && hit.p_error.z >= w);
+    }
+}
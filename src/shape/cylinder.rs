@@ -14,6 +14,7 @@ use utils::Clamp;
 use utils::Degrees;
 
 use shape::helpers::compute_dg;
+use shape::helpers::reproject_onto_radius;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Cylinder {
@@ -130,7 +131,8 @@ impl Intersectable<ShapeIntersection> for Cylinder {
             if hit.is_some() { hit.unwrap() } else { return None; }
         };
 
-        let p_hit = ray.point_at(t_hit);
+        // Refine cylinder intersection point
+        let (p_hit, p_error) = reproject_onto_radius(ray.point_at(t_hit), self.radius, 3);
 
         // Find parametric representation of cylinder hit
         let u = phi / self.phi_max;
@@ -147,10 +149,11 @@ impl Intersectable<ShapeIntersection> for Cylinder {
         let d2pdvv = Vector::new();
 
         // Initialize DifferentialGeometry from parametric information
+        let p_error = self.base().object2world.xf_point_with_error(&p_hit, &p_error).1;
         let dg = compute_dg(self.base(), u, v, p_hit,
                             dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
 
-        Some(ShapeIntersection::new(t_hit, t_hit * 5e-4, dg))
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
     }
 }
 
@@ -313,7 +316,8 @@ mod tests {
         let shape_int = c.intersect(&r).unwrap();
 
         assert!((shape_int.t_hit - (2f32.sqrt() - 1.0)).abs() < 1e-6);
-        assert!((shape_int.ray_epsilon - ((2f32.sqrt() - 1.0) * 5e-4)).abs() < 1e-6);
+        assert!(shape_int.p_error.length() > 0.0);
+        assert!(shape_int.p_error.length() < 1e-5);
 
         let sqrt2_2 = 2f32.sqrt() * 0.5;
         assert!((shape_int.dg.p -
@@ -106,7 +106,11 @@ impl Intersectable<ShapeIntersection> for Disk {
             if hit.is_some() { hit.unwrap() } else { return None; }
         };
 
-        let p_hit = ray.point_at(t_hit);
+        // Snap the hit point onto the plane of the disk, so that the only
+        // error left comes from transforming it into world space
+        let mut p_hit = ray.point_at(t_hit);
+        p_hit.z = self.height;
+
         let u = phi / self.phi_max;
         let dist = (p_hit.x * p_hit.x + p_hit.y * p_hit.y).sqrt();
         let v = 1.0 - (dist - self.inner_radius) /
@@ -119,6 +123,7 @@ impl Intersectable<ShapeIntersection> for Disk {
             Vector::new_with(p_hit.x, p_hit.y, 0.0);
 
         let o2w = &(self.base().object2world);
+        let p_error = o2w.xf_point_with_error(&p_hit, &Vector::new()).1;
         let mut dg = DifferentialGeometry::new_with(
             o2w.xf(p_hit), o2w.xf(dpdu), o2w.xf(dpdv), o2w.xf(Normal::new()),
             o2w.xf(Normal::new()), u, v, Some(self.base().clone()));
@@ -129,7 +134,7 @@ impl Intersectable<ShapeIntersection> for Disk {
             dg.nn = o2w.xf(Normal::new_with(0.0, 0.0, -1.0));
         }
 
-        Some(ShapeIntersection::new(t_hit, t_hit * 5e-4, dg))
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
     }
 }
 
@@ -258,7 +263,7 @@ mod tests {
                 Vector::new_with(0.0, 0.0, -1.0), 0.0)).unwrap();
 
         assert_eq!(info.t_hit, 1.0);
-        assert_eq!(info.ray_epsilon, 5e-4);
+        assert_eq!(info.p_error, Vector::new());
         assert_eq!(info.dg.p, Point::new());
         assert_eq!(info.dg.nn, Normal::new_with(0.0, 0.0, 1.0));
 
@@ -272,7 +277,7 @@ mod tests {
                 Vector::new_with(0.0, 0.0, -1.0), 0.0)).unwrap();
 
         assert_eq!(half_pipe_int.t_hit, 1.0);
-        assert_eq!(half_pipe_int.ray_epsilon, 5e-4);
+        assert!(half_pipe_int.p_error.length() < 1e-6);
         assert_eq!(half_pipe_int.dg.p, Point::new_with(0.0, 0.5, 0.0));
         assert_eq!(half_pipe_int.dg.nn, Normal::new_with(0.0, 0.0, 1.0));
         assert_eq!(half_pipe_int.dg.u, 0.5);
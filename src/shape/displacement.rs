@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use camera::Camera;
+use diff_geom::DifferentialGeometry;
+use geometry::normal::Normal;
+use geometry::point::Point;
+use texture::Texture;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+
+// A screen space target for tessellating displaced surfaces: edges are
+// split until they cover at most edge_length pixels on the film, or until
+// they have been split max_depth times.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dicing {
+    world_to_camera: Transform,
+    camera_to_raster: Transform,
+    edge_length: f32,
+    max_depth: usize
+}
+
+impl Dicing {
+    pub fn new(camera: &Camera, edge_length: f32, max_depth: usize) -> Option<Dicing> {
+        camera.world_to_camera().and_then(|w2c| {
+            camera.camera_to_raster().map(|c2r| Dicing {
+                world_to_camera: w2c,
+                camera_to_raster: c2r,
+                edge_length: edge_length,
+                max_depth: max_depth
+            })
+        })
+    }
+
+    pub fn max_depth(&self) -> usize { self.max_depth }
+
+    // Length in pixels of the projection of the world space edge p0-p1.
+    // Edges entirely behind the camera don't show up on the film.
+    pub fn raster_length(&self, p0: &Point, p1: &Point) -> f32 {
+        let c0 = self.world_to_camera.t(p0);
+        let c1 = self.world_to_camera.t(p1);
+        if c0.z <= 0.0 && c1.z <= 0.0 {
+            return 0.0;
+        }
+
+        // Edges that cross the plane of the camera are as long as they
+        // can be, and get split until max_depth runs out.
+        if c0.z <= 0.0 || c1.z <= 0.0 {
+            return ::std::f32::INFINITY;
+        }
+
+        let r0 = self.camera_to_raster.xf(c0);
+        let r1 = self.camera_to_raster.xf(c1);
+        ((r1.x - r0.x) * (r1.x - r0.x) + (r1.y - r0.y) * (r1.y - r0.y)).sqrt()
+    }
+
+    pub fn needs_split(&self, p0: &Point, p1: &Point) -> bool {
+        self.raster_length(p0, p1) > self.edge_length
+    }
+
+    // The number of uniform subdivision levels, each of which halves the
+    // length of every edge, needed to bring the given edges on target.
+    pub fn levels_for<'a, I>(&self, edges: I) -> usize
+        where I: Iterator<Item = (&'a Point, &'a Point)> {
+        let longest = edges.fold(0.0, |l, (p0, p1)| {
+            let len = self.raster_length(p0, p1);
+            if len > l { len } else { l }
+        });
+
+        if longest <= self.edge_length {
+            0
+        } else {
+            let levels = (longest / self.edge_length).log2().ceil();
+            if levels >= self.max_depth as f32 { self.max_depth } else { levels as usize }
+        }
+    }
+}
+
+// Offsets a surface along its normals by a scalar texture, measured in
+// object space units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Displacement {
+    map: Arc<Texture<f32>>,
+    dicing: Option<Dicing>
+}
+
+impl Displacement {
+    pub fn new(map: Arc<Texture<f32>>, dicing: Option<Dicing>) -> Displacement {
+        Displacement {
+            map: map,
+            dicing: dicing
+        }
+    }
+
+    pub fn dicing(&self) -> Option<&Dicing> { self.dicing.as_ref() }
+
+    // Evaluates the displacement map at a world space vertex
+    pub fn evaluate(&self, p: Point, n: Normal, u: f32, v: f32) -> f32 {
+        let mut dg = DifferentialGeometry::new();
+        dg.p = p;
+        dg.nn = n;
+        dg.u = u;
+        dg.v = v;
+        self.map.evaluate(&dg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use camera::Camera;
+    use camera::film::Film;
+    use filter::Filter;
+    use geometry::normal::Normal;
+    use geometry::point::Point;
+    use texture::Texture;
+    use transform::animated::AnimatedTransform;
+
+    // 100x100 pixels with a 90 degree field of view, so that a unit long
+    // edge one unit in front of the camera is 50 pixels long
+    fn test_camera() -> Camera {
+        let film = Film::image(100, 100, Filter::mean(1.0, 1.0),
+                               [0.0, 1.0, 0.0, 1.0], String::from(""), false);
+        Camera::perspective(AnimatedTransform::identity(), [-1.0, 1.0, -1.0, 1.0],
+                            0.0, 1.0, 0.0, 1e6, 90.0, film)
+    }
+
+    #[test]
+    fn it_measures_edges_on_the_film() {
+        let dicing = Dicing::new(&test_camera(), 4.0, 8).unwrap();
+
+        let near = dicing.raster_length(&Point::new_with(0.0, 0.0, 1.0),
+                                        &Point::new_with(1.0, 0.0, 1.0));
+        assert!((near - 50.0).abs() < 1e-3);
+
+        let far = dicing.raster_length(&Point::new_with(0.0, 0.0, 10.0),
+                                       &Point::new_with(0.0, 1.0, 10.0));
+        assert!((far - 5.0).abs() < 1e-3);
+
+        // Edges seen end on have no length
+        assert!(dicing.raster_length(&Point::new_with(0.0, 0.0, 1.0),
+                                     &Point::new_with(0.0, 0.0, 2.0)) < 1e-6);
+
+        // Behind the camera nothing is visible
+        assert_eq!(dicing.raster_length(&Point::new_with(0.0, 0.0, -1.0),
+                                        &Point::new_with(1.0, 0.0, -1.0)), 0.0);
+        assert!(dicing.needs_split(&Point::new_with(0.0, 0.0, -1.0),
+                                   &Point::new_with(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn it_picks_subdivision_levels_from_edge_lengths() {
+        let dicing = Dicing::new(&test_camera(), 4.0, 5).unwrap();
+        let near = [Point::new_with(0.0, 0.0, 1.0), Point::new_with(1.0, 0.0, 1.0)];
+        let far = [Point::new_with(0.0, 0.0, 10.0), Point::new_with(1.0, 0.0, 10.0)];
+
+        // 50 pixels needs four halvings to get under 4 pixels, 5 pixels
+        // only needs one.
+        assert_eq!(dicing.levels_for(vec![(&near[0], &near[1])].into_iter()), 4);
+        assert_eq!(dicing.levels_for(vec![(&far[0], &far[1])].into_iter()), 1);
+        assert_eq!(dicing.levels_for(vec![(&far[0], &far[1]),
+                                          (&near[0], &near[1])].into_iter()), 4);
+        assert_eq!(dicing.levels_for(Vec::<(&Point, &Point)>::new().into_iter()), 0);
+
+        // The deepest level is capped
+        let close = [Point::new_with(0.0, 0.0, 0.1), Point::new_with(1.0, 0.0, 0.1)];
+        assert_eq!(dicing.levels_for(vec![(&close[0], &close[1])].into_iter()), 5);
+    }
+
+    #[test]
+    fn it_needs_a_projective_camera() {
+        let film = Film::image(100, 100, Filter::mean(1.0, 1.0),
+                               [0.0, 1.0, 0.0, 1.0], String::from(""), false);
+        let env = Camera::environment(AnimatedTransform::identity(), 0.0, 1.0, film);
+        assert!(Dicing::new(&env, 1.0, 4).is_none());
+    }
+
+    #[test]
+    fn it_evaluates_the_displacement_map() {
+        let d = Displacement::new(Arc::new(Texture::new(0.25)), None);
+        assert!(d.dicing().is_none());
+        assert_eq!(d.evaluate(Point::new(), Normal::new_with(0.0, 0.0, 1.0), 0.5, 0.5), 0.25);
+    }
+}
@@ -0,0 +1,345 @@
+use bbox::BBox;
+use bbox::HasBounds;
+use geometry::normal::Normalize;
+use geometry::point::Point;
+use geometry::vector::Cross;
+use geometry::vector::Vector;
+use intersection::Intersectable;
+use primitive::Refinable;
+use ray::Ray;
+use shape::ShapeBase;
+use shape::ShapeIntersection;
+use shape::mesh::Mesh;
+use shape::mesh::intersect_triangle;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+
+use geometry::vector::coordinate_system;
+use shape::helpers::compute_dg;
+use utils::gamma;
+
+// A grid of nx by ny heights spread evenly over [0, 1] x [0, 1] in the xy
+// plane, where each cell of the grid is split into two triangles. It can
+// be refined into a Mesh, but rays can also walk the grid directly, which
+// keeps large terrains from having to be stored as individual triangles.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Heightfield {
+    base: ShapeBase,
+    nx: usize,
+    ny: usize,
+    z: Vec<f32>,
+    z_min: f32,
+    z_max: f32
+}
+
+impl Heightfield {
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               nx: usize, ny: usize, z: &[f32]) -> Heightfield {
+        assert!(nx >= 2 && ny >= 2);
+        assert_eq!(z.len(), nx * ny);
+        Heightfield {
+            base: ShapeBase::new(o2w, w2o, ro),
+            nx: nx,
+            ny: ny,
+            z: z.to_vec(),
+            z_min: z.iter().fold(::std::f32::INFINITY, |m, &h| m.min(h)),
+            z_max: z.iter().fold(::std::f32::NEG_INFINITY, |m, &h| m.max(h))
+        }
+    }
+
+    // Object space position of the sample at (x, y)
+    fn point(&self, x: usize, y: usize) -> Point {
+        Point::new_with((x as f32) / ((self.nx - 1) as f32),
+                        (y as f32) / ((self.ny - 1) as f32),
+                        self.z[y * self.nx + x])
+    }
+
+    // The two triangles covering the cell whose lower corner is (x, y)
+    fn cell_triangles(&self, x: usize, y: usize) -> [[Point; 3]; 2] {
+        [[self.point(x, y), self.point(x + 1, y), self.point(x + 1, y + 1)],
+         [self.point(x, y), self.point(x + 1, y + 1), self.point(x, y + 1)]]
+    }
+
+    // Walks the cells under the ray with a 2D DDA and returns the first
+    // triangle hit along with its distance and barycentrics. Every
+    // triangle lies in the column above its cell, so the first cell with
+    // a hit holds the closest one.
+    fn get_intersection_point(&self, r: &Ray) -> Option<([Point; 3], f32, [f32; 3])> {
+        let (t0, t1) = match self.object_bound().intersect(r) {
+            None => return None,
+            Some(t) => t
+        };
+
+        // Set up the DDA for the x and y axes
+        let p_start = r.point_at(t0);
+        let start = [p_start.x, p_start.y];
+        let dir = [r.d.x, r.d.y];
+        let res = [(self.nx - 1) as i64, (self.ny - 1) as i64];
+        let mut cell = [0i64; 2];
+        let mut next_t = [0f32; 2];
+        let mut delta_t = [0f32; 2];
+        let mut step = [0i64; 2];
+        let mut out = [0i64; 2];
+        for axis in 0..2 {
+            let n = res[axis] as f32;
+            cell[axis] = ((start[axis] * n) as i64).max(0).min(res[axis] - 1);
+            if dir[axis] > 0.0 {
+                next_t[axis] = t0 + (((cell[axis] + 1) as f32) / n - start[axis]) / dir[axis];
+                delta_t[axis] = 1.0 / (n * dir[axis]);
+                step[axis] = 1;
+                out[axis] = res[axis];
+            } else if dir[axis] < 0.0 {
+                next_t[axis] = t0 + ((cell[axis] as f32) / n - start[axis]) / dir[axis];
+                delta_t[axis] = -1.0 / (n * dir[axis]);
+                step[axis] = -1;
+                out[axis] = -1;
+            } else {
+                next_t[axis] = ::std::f32::INFINITY;
+            }
+        }
+
+        loop {
+            // Check for an intersection in the current cell
+            let tris = self.cell_triangles(cell[0] as usize, cell[1] as usize);
+            let mut hit: Option<([Point; 3], f32, [f32; 3])> = None;
+            for tri in tris.iter() {
+                if let Some((t, b)) = intersect_triangle(r, &tri[0], &tri[1], &tri[2]) {
+                    if hit.as_ref().map_or(true, |h| t < h.1) {
+                        hit = Some((tri.clone(), t, b));
+                    }
+                }
+            }
+
+            if hit.is_some() {
+                return hit;
+            }
+
+            // Advance to the next cell
+            let axis = if next_t[0] < next_t[1] { 0 } else { 1 };
+            if next_t[axis] > t1 {
+                return None;
+            }
+
+            cell[axis] += step[axis];
+            if cell[axis] == out[axis] {
+                return None;
+            }
+            next_t[axis] += delta_t[axis];
+        }
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    pub fn object_bound(&self) -> BBox {
+        BBox::new_with(Point::new_with(0.0, 0.0, self.z_min),
+                       Point::new_with(1.0, 1.0, self.z_max))
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        let mut p = Vec::with_capacity(self.nx * self.ny);
+        let mut uvs = Vec::with_capacity(2 * self.nx * self.ny);
+        for y in 0..self.ny {
+            for x in 0..self.nx {
+                let pt = self.point(x, y);
+                uvs.push(pt.x);
+                uvs.push(pt.y);
+                p.push(pt);
+            }
+        }
+
+        let vert = |x: usize, y: usize| y * self.nx + x;
+        let mut indices = Vec::with_capacity(6 * (self.nx - 1) * (self.ny - 1));
+        for y in 0..(self.ny - 1) {
+            for x in 0..(self.nx - 1) {
+                indices.extend_from_slice(&[vert(x, y), vert(x + 1, y), vert(x + 1, y + 1),
+                                            vert(x, y), vert(x + 1, y + 1), vert(x, y + 1)]);
+            }
+        }
+
+        Mesh::new(self.base.object2world.clone(), self.base.world2object.clone(),
+                  self.base.reverse_orientation, &indices, &p, None, None, Some(&uvs[..]), None)
+    }
+
+    pub fn area(&self) -> f32 {
+        self.to_mesh().refine().iter().fold(0.0, |a, t| a + t.area())
+    }
+}
+
+impl Refinable<Mesh> for Heightfield {
+    fn is_refined(&self) -> bool { false }
+    fn refine(self) -> Vec<Mesh> { vec![self.to_mesh()] }
+}
+
+impl HasBounds for Heightfield {
+    fn world_bound(&self) -> BBox {
+        self.base().object2world.xf(self.object_bound())
+    }
+}
+
+impl Intersectable<ShapeIntersection> for Heightfield {
+    fn intersect_p(&self, r: &Ray) -> bool {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+        self.get_intersection_point(&ray).is_some()
+    }
+
+    fn intersect(&self, r: &Ray) -> Option<ShapeIntersection> {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+        let (tri, t_hit, b) = match self.get_intersection_point(&ray) {
+            None => return None,
+            Some(hit) => hit
+        };
+
+        // The xy coordinates of the grid double as its (u, v)
+        // parameterization, so the partial derivatives follow directly
+        // from the slope of the triangle.
+        let dp1 = &tri[1] - &tri[0];
+        let dp2 = &tri[2] - &tri[0];
+        let det = dp1.x * dp2.y - dp1.y * dp2.x;
+        let (dpdu, dpdv) = if det == 0.0 {
+            coordinate_system(&dp1.into_cross(dp2).normalize())
+        } else {
+            let dzdu = (dp1.z * dp2.y - dp2.z * dp1.y) / det;
+            let dzdv = (dp2.z * dp1.x - dp1.z * dp2.x) / det;
+            (Vector::new_with(1.0, 0.0, dzdu), Vector::new_with(0.0, 1.0, dzdv))
+        };
+
+        let p_hit = Point::new_with(
+            b[0] * tri[0].x + b[1] * tri[1].x + b[2] * tri[2].x,
+            b[0] * tri[0].y + b[1] * tri[1].y + b[2] * tri[2].y,
+            b[0] * tri[0].z + b[1] * tri[1].z + b[2] * tri[2].z);
+
+        // Compute error bounds for the triangle intersection
+        let abs_sum = Vector::new_with(
+            (b[0] * tri[0].x).abs() + (b[1] * tri[1].x).abs() + (b[2] * tri[2].x).abs(),
+            (b[0] * tri[0].y).abs() + (b[1] * tri[1].y).abs() + (b[2] * tri[2].y).abs(),
+            (b[0] * tri[0].z).abs() + (b[1] * tri[1].z).abs() + (b[2] * tri[2].z).abs());
+        let p_error = gamma(7) * &abs_sum;
+
+        let (u, v) = (p_hit.x, p_hit.y);
+        let p_error = self.base().object2world.xf_point_with_error(&p_hit, &p_error).1;
+        let dg = compute_dg(self.base(), u, v, p_hit, dpdu, dpdv,
+                            Vector::new(), Vector::new(), Vector::new());
+
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bbox::BBox;
+    use bbox::HasBounds;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use primitive::Refinable;
+    use ray::Ray;
+    use transform::transform::Transform;
+
+    // A bumpy 5x4 grid
+    fn bumpy() -> Heightfield {
+        let z: Vec<f32> = (0..20).map(|i| {
+            let (x, y) = ((i % 5) as f32, (i / 5) as f32);
+            0.3 * (1.7 * x).sin() + 0.2 * (2.3 * y).cos()
+        }).collect();
+        Heightfield::new(Transform::new(), Transform::new(), false, 5, 4, &z)
+    }
+
+    #[test]
+    fn it_can_be_created() {
+        let hf = Heightfield::new(Transform::new(), Transform::new(), false,
+                                  2, 2, &[0.0, 1.0, 3.0, 2.0]);
+        assert_eq!(hf.z_min, 0.0);
+        assert_eq!(hf.z_max, 3.0);
+        assert_eq!(hf.point(1, 1), Point::new_with(1.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn it_has_bounds() {
+        let xf = Transform::translate(&Vector::new_with(1.0, 2.0, 3.0));
+        let hf = Heightfield::new(xf.clone(), xf.inverse(), false,
+                                  2, 2, &[0.0, 1.0, 3.0, 2.0]);
+        assert_eq!(hf.object_bound(),
+                   BBox::new_with(Point::new_with(0.0, 0.0, 0.0),
+                                  Point::new_with(1.0, 1.0, 3.0)));
+        assert_eq!(hf.world_bound(),
+                   BBox::new_with(Point::new_with(1.0, 2.0, 3.0),
+                                  Point::new_with(2.0, 3.0, 6.0)));
+    }
+
+    #[test]
+    fn it_can_be_refined() {
+        let hf = Heightfield::new(Transform::new(), Transform::new(), false,
+                                  3, 2, &[0.0; 6]);
+        let meshes = hf.clone().refine();
+        assert_eq!(meshes.len(), 1);
+        let tris = meshes[0].clone().refine();
+        assert_eq!(tris.len(), 4);
+        assert!((hf.area() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_can_be_intersected() {
+        let hf = Heightfield::new(Transform::new(), Transform::new(), false,
+                                  4, 4, &[0.5; 16]);
+
+        let r = Ray::new_with(Point::new_with(0.3, 0.6, 2.0),
+                              Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        let shape_int = hf.intersect(&r).unwrap();
+        assert!((shape_int.t_hit - 1.5).abs() < 1e-6);
+        assert!((shape_int.dg.p - Point::new_with(0.3, 0.6, 0.5)).length_squared() < 1e-10);
+        assert!((shape_int.dg.u - 0.3).abs() < 1e-6);
+        assert!((shape_int.dg.v - 0.6).abs() < 1e-6);
+        assert_eq!(shape_int.dg.dpdu, Vector::new_with(1.0, 0.0, 0.0));
+        assert_eq!(shape_int.dg.dpdv, Vector::new_with(0.0, 1.0, 0.0));
+        assert!((shape_int.dg.nn.z - 1.0).abs() < 1e-6);
+
+        // Outside of the grid
+        assert!(!hf.intersect_p(&Ray::new_with(Point::new_with(1.3, 0.6, 2.0),
+                                               Vector::new_with(0.0, 0.0, -1.0), 0.0)));
+
+        // Parallel to the grid above and below it
+        assert!(!hf.intersect_p(&Ray::new_with(Point::new_with(-1.0, 0.5, 0.6),
+                                               Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+        assert!(!hf.intersect_p(&Ray::new_with(Point::new_with(-1.0, 0.5, 0.4),
+                                               Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+
+        // Slopes follow the grid
+        let ramp = Heightfield::new(Transform::new(), Transform::new(), false,
+                                    2, 2, &[0.0, 1.0, 0.0, 1.0]);
+        let shape_int = ramp.intersect(
+            &Ray::new_with(Point::new_with(0.25, 0.5, 2.0),
+                           Vector::new_with(0.0, 0.0, -1.0), 0.0)).unwrap();
+        assert!((shape_int.t_hit - 1.75).abs() < 1e-6);
+        assert!((shape_int.dg.dpdu - Vector::new_with(1.0, 0.0, 1.0)).length_squared() < 1e-10);
+        assert!((shape_int.dg.dpdv - Vector::new_with(0.0, 1.0, 0.0)).length_squared() < 1e-10);
+    }
+
+    #[test]
+    fn it_matches_its_mesh() {
+        // Walking the grid finds the same hits as testing every triangle
+        let hf = bumpy();
+        let tris = hf.to_mesh().refine();
+        for i in 0..10 {
+            for j in 0..10 {
+                let o = Point::new_with(-0.5 + 0.2 * (i as f32), -0.3 + 0.15 * (j as f32), 1.0);
+                let d = Vector::new_with(0.4 - 0.07 * (j as f32), 0.1 * (i as f32) - 0.3, -1.0);
+                let r = Ray::new_with(o, d, 0.0);
+
+                let expected = tris.iter().filter_map(|t| t.intersect(&r))
+                    .fold(None, |best: Option<f32>, si| {
+                        Some(best.map_or(si.t_hit, |b| b.min(si.t_hit)))
+                    });
+                match (hf.intersect(&r), expected) {
+                    (Some(si), Some(t)) => assert!((si.t_hit - t).abs() < 1e-5),
+                    (None, None) => (),
+                    (got, expected) => panic!("Ray {:?} got {:?} expected {:?}",
+                                              r, got.map(|si| si.t_hit), expected)
+                }
+            }
+        }
+    }
+}
@@ -7,6 +7,7 @@ use geometry::vector::Vector;
 use geometry::normal::Normal;
 use geometry::normal::Normalize;
 use transform::transform::ApplyTransform;
+use utils::gamma;
 
 // Note: This is the part where the math kind of escapes me as I haven't
 // actually taken a course on differential geometry. For that, the book recommends
@@ -41,3 +42,20 @@ pub fn compute_dg(shape: &ShapeBase, u: f32, v: f32, p_hit: Point,
         o2w.xf(p_hit), o2w.xf(dpdu), o2w.xf(dpdv), o2w.xf(dndu),
         o2w.xf(dndv), u, v, Some(shape.clone()))
 }
+
+// Moves a hit point onto a surface of revolution around the z axis, given
+// the radius r of the surface at the height of the point. The result then
+// lies on the surface no matter how far off its height was, so its error
+// is bounded by the n operations that it took to compute r and rescale
+// the point.
+pub fn reproject_onto_radius(p: Point, r: f32, n: i32) -> (Point, Vector) {
+    let hit_rad = (p.x * p.x + p.y * p.y).sqrt();
+    let p = if hit_rad > 0.0 {
+        Point::new_with(p.x * r / hit_rad, p.y * r / hit_rad, p.z)
+    } else {
+        p
+    };
+
+    let p_error = gamma(n) * &Vector::new_with(p.x, p.y, 0.0).abs();
+    (p, p_error)
+}
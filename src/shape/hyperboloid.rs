@@ -0,0 +1,345 @@
+use std::f32::consts::PI;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use geometry::point::Point;
+use geometry::vector::Vector;
+use intersection::Intersectable;
+use ray::Ray;
+use shape::ShapeBase;
+use shape::ShapeIntersection;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Clamp;
+use utils::Degrees;
+use utils::Lerp;
+
+use shape::helpers::compute_dg;
+use shape::helpers::reproject_onto_radius;
+
+// The surface swept out by rotating the line segment from p1 to p2
+// around the z axis, which satisfies a*x^2 + a*y^2 - c*z^2 = 1.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Hyperboloid {
+    base: ShapeBase,
+    p1: Point,
+    p2: Point,
+    z_min: f32,
+    z_max: f32,
+    r_max: f32,
+    phi_max: f32,
+    a: f32,
+    c: f32
+}
+
+impl Hyperboloid {
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               point1: Point, point2: Point, pm: f32) -> Hyperboloid {
+        let (p1, p2) = if point2.z == 0.0 {
+            (point2, point1)
+        } else {
+            (point1, point2)
+        };
+
+        let radius1 = (p1.x * p1.x + p1.y * p1.y).sqrt();
+        let radius2 = (p2.x * p2.x + p2.y * p2.y).sqrt();
+
+        // Compute implicit function coefficients, walking along the line
+        // until we find a point that gives a well defined solution
+        let mut pp = p1.clone();
+        let (mut a, mut c);
+        loop {
+            pp = &pp + 2.0 * (&p2 - &p1);
+            let xy1 = pp.x * pp.x + pp.y * pp.y;
+            let xy2 = p2.x * p2.x + p2.y * p2.y;
+            a = (1.0 / xy1 - (pp.z * pp.z) / (xy1 * p2.z * p2.z)) /
+                (1.0 - (xy2 * pp.z * pp.z) / (xy1 * p2.z * p2.z));
+            c = (a * xy2 - 1.0) / (p2.z * p2.z);
+            if a.is_finite() && c.is_finite() {
+                break;
+            }
+        }
+
+        Hyperboloid {
+            base: ShapeBase::new(o2w, w2o, ro),
+            z_min: p1.z.min(p2.z),
+            z_max: p1.z.max(p2.z),
+            r_max: radius1.max(radius2),
+            p1: p1,
+            p2: p2,
+            phi_max: pm.clamp(0.0, 360.0).as_radians(),
+            a: a,
+            c: c
+        }
+    }
+
+    // Angle around the z axis from the line between p1 and p2 to the hit
+    fn get_phi(&self, hit: &Point) -> f32 {
+        let v = (hit.z - self.p1.z) / (self.p2.z - self.p1.z);
+        let pr = self.p1.lerp(&self.p2, v);
+        let mut angle = (pr.x * hit.y - hit.x * pr.y).atan2(hit.x * pr.x + hit.y * pr.y);
+        if angle < 0.0 {
+            angle = angle + 2.0 * PI;
+        }
+        angle
+    }
+
+    fn get_intersection_point(&self, r: &Ray) -> Option<(f32, f32)> {
+        // Compute quadratic hyperboloid coefficients
+        let a = self.a * (r.d.x * r.d.x + r.d.y * r.d.y) - self.c * r.d.z * r.d.z;
+        let b = 2.0 * (self.a * (r.d.x * r.o.x + r.d.y * r.o.y) - self.c * r.d.z * r.o.z);
+        let c = self.a * (r.o.x * r.o.x + r.o.y * r.o.y) - self.c * r.o.z * r.o.z - 1.0;
+
+        // Solve quadratic equation for t values
+        let (t0, t1) = {
+            match ::utils::quadratic(a, b, c) {
+                None => return None,
+                Some((x, y)) => (x, y)
+            }
+        };
+
+        // Compute intersection distance along ray
+        if t0 > r.maxt() || t1 < r.mint() {
+            return None
+        }
+
+        let mut t_hit = t0;
+        if t0 < r.mint() {
+            t_hit = t1;
+            if t_hit > r.maxt() {
+                return None;
+            }
+        }
+
+        // Compute hyperboloid hit point and phi
+        let get_hit = |t: f32| {
+            let hit = r.point_at(t);
+            let angle = self.get_phi(&hit);
+            (hit, angle)
+        };
+
+        let invalid_hit = |hit: &(Point, f32)| {
+            hit.0.z < self.z_min || hit.0.z > self.z_max || hit.1 > self.phi_max
+        };
+
+        // Test hyperboloid intersection against clipping parameters
+        let mut p_hit = get_hit(t_hit);
+        if invalid_hit(&p_hit) {
+            if t_hit == t1 { return None; }
+            if t1 > r.maxt() { return None; }
+            t_hit = t1;
+            p_hit = get_hit(t_hit);
+            if invalid_hit(&p_hit) { return None; }
+        }
+
+        return Some((t_hit, p_hit.1))
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    pub fn object_bound(&self) -> BBox {
+        BBox::new_with(
+            Point::new_with(-self.r_max, -self.r_max, self.z_min),
+            Point::new_with(self.r_max, self.r_max, self.z_max))
+    }
+
+    pub fn area(&self) -> f32 {
+        // The radius at height z satisfies r^2 = (1 + c*z^2) / a, so the
+        // area element r * sqrt(1 + (dr/dz)^2) reduces to
+        // sqrt(alpha + beta * z^2), which has a closed form integral.
+        let alpha = 1.0 / self.a;
+        let beta = (self.c / self.a) * (1.0 + self.c / self.a);
+        let integral = |z: f32| {
+            let s = (alpha + beta * z * z).max(0.0).sqrt();
+            if beta.abs() < 1e-6 {
+                alpha.sqrt() * z
+            } else if beta > 0.0 {
+                0.5 * z * s + alpha / (2.0 * beta.sqrt()) * (z * (beta / alpha).sqrt()).asinh()
+            } else {
+                let arg = (z * (-beta / alpha).sqrt()).clamp(-1.0, 1.0);
+                0.5 * z * s + alpha / (2.0 * (-beta).sqrt()) * arg.asin()
+            }
+        };
+
+        self.phi_max * (integral(self.z_max) - integral(self.z_min))
+    }
+}
+
+impl HasBounds for Hyperboloid {
+    fn world_bound(&self) -> BBox {
+        self.base().object2world.xf(self.object_bound())
+    }
+}
+
+impl Intersectable<ShapeIntersection> for Hyperboloid {
+    fn intersect_p(&self, r: &Ray) -> bool {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+        self.get_intersection_point(&ray).is_some()
+    }
+
+    fn intersect(&self, r: &Ray) -> Option<ShapeIntersection> {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+
+        let (t_hit, phi) = {
+            let hit = self.get_intersection_point(&ray);
+            if hit.is_some() { hit.unwrap() } else { return None; }
+        };
+
+        // Refine hyperboloid intersection point
+        let (p_hit, p_error) = {
+            let p = ray.point_at(t_hit);
+            let r = ((1.0 + self.c * p.z * p.z) / self.a).max(0.0).sqrt();
+            reproject_onto_radius(p, r, 8)
+        };
+
+        // Find parametric representation of hyperboloid hit
+        let u = phi / self.phi_max;
+        let v = (p_hit.z - self.p1.z) / (self.p2.z - self.p1.z);
+
+        // Compute hyperboloid dpdu and dpdv
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let dpdu = self.phi_max * Vector::new_with(-p_hit.y, p_hit.x, 0.0);
+        let dpdv = Vector::new_with(
+            (self.p2.x - self.p1.x) * cos_phi - (self.p2.y - self.p1.y) * sin_phi,
+            (self.p2.x - self.p1.x) * sin_phi + (self.p2.y - self.p1.y) * cos_phi,
+            self.p2.z - self.p1.z);
+
+        // Compute hyperboloid dndu and dndv
+        let d2pduu = -self.phi_max * self.phi_max *
+            Vector::new_with(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = self.phi_max * Vector::new_with(-dpdv.y, dpdv.x, 0.0);
+        let d2pdvv = Vector::new();
+
+        // Initialize DifferentialGeometry from parametric information
+        let p_error = self.base().object2world.xf_point_with_error(&p_hit, &p_error).1;
+        let dg = compute_dg(self.base(), u, v, p_hit,
+                            dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::f32::consts::PI;
+
+    use bbox::BBox;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use ray::Ray;
+    use transform::transform::Transform;
+
+    // Rotating the segment from (1, -1, -1) to (1, 1, 1) gives the
+    // hyperboloid x^2 + y^2 - z^2 = 1 with its waist at the origin
+    fn waisted() -> Hyperboloid {
+        Hyperboloid::new(Transform::new(), Transform::new(), false,
+                         Point::new_with(1.0, -1.0, -1.0),
+                         Point::new_with(1.0, 1.0, 1.0), 360.0)
+    }
+
+    #[test]
+    fn it_computes_implicit_coefficients() {
+        let h = waisted();
+        assert!((h.a - 1.0).abs() < 1e-4);
+        assert!((h.c - 1.0).abs() < 1e-4);
+
+        // Both endpoints lie on the surface
+        for p in [&h.p1, &h.p2].iter() {
+            let f = h.a * (p.x * p.x + p.y * p.y) - h.c * p.z * p.z;
+            assert!((f - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn it_has_bounds() {
+        let r = 2f32.sqrt();
+        assert_eq!(waisted().object_bound(),
+                   BBox::new_with(Point::new_with(-r, -r, -1.0),
+                                  Point::new_with(r, r, 1.0)));
+    }
+
+    #[test]
+    fn it_can_be_intersected() {
+        let h = waisted();
+
+        // Through the waist
+        assert!(h.intersect_p(
+            &Ray::new_with(Point::new_with(-2.0, 0.0, 0.0),
+                           Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+
+        // Down the middle without touching the walls
+        assert!(!h.intersect_p(
+            &Ray::new_with(Point::new_with(0.5, 0.0, -2.0),
+                           Vector::new_with(0.0, 0.0, 1.0), 0.0)));
+
+        // Past the waist but inside of the flared ends
+        assert!(!h.intersect_p(
+            &Ray::new_with(Point::new_with(-2.0, 1.2, 0.0),
+                           Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+        assert!(h.intersect_p(
+            &Ray::new_with(Point::new_with(-2.0, 1.2, 0.9),
+                           Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+
+        // Above the top
+        assert!(!h.intersect_p(
+            &Ray::new_with(Point::new_with(-2.0, 0.0, 1.5),
+                           Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+
+        // Partial sweeps
+        let partial = Hyperboloid::new(Transform::new(), Transform::new(), false,
+                                       Point::new_with(1.0, 0.0, -1.0),
+                                       Point::new_with(1.0, 0.0, 1.0), 90.0);
+        assert!(partial.intersect_p(
+            &Ray::new_with(Point::new_with(0.0, 0.0, 0.0),
+                           Vector::new_with(1.0, 1.0, 0.0), 0.0)));
+        assert!(!partial.intersect_p(
+            &Ray::new_with(Point::new_with(0.0, 0.0, 0.0),
+                           Vector::new_with(-1.0, 1.0, 0.0), 0.0)));
+    }
+
+    #[test]
+    fn it_has_intersection_information() {
+        let h = waisted();
+        let r = Ray::new_with(Point::new_with(3.0, 0.0, 0.0),
+                              Vector::new_with(-1.0, 0.0, 0.0), 0.0);
+        let shape_int = h.intersect(&r).unwrap();
+
+        assert!((shape_int.t_hit - 2.0).abs() < 1e-4);
+        assert!((shape_int.dg.p.clone() - Point::new_with(1.0, 0.0, 0.0)).length_squared() < 1e-6);
+        assert_eq!(shape_int.dg.shape.as_ref().unwrap(), h.base());
+        assert!((shape_int.dg.v - 0.5).abs() < 1e-6);
+
+        // At the waist the normal points straight out
+        assert!((shape_int.dg.nn.x.abs() - 1.0).abs() < 1e-4);
+        assert!(shape_int.dg.nn.y.abs() < 1e-4 && shape_int.dg.nn.z.abs() < 1e-4);
+
+        // The surface is saddle shaped so it curves in both directions
+        assert!(Vector::from(shape_int.dg.dndu.clone()).length_squared() > 0.0);
+        assert!((shape_int.dg.dpdu - Vector::new_with(0.0, 2.0 * PI, 0.0)).length_squared() < 1e-4);
+    }
+
+    #[test]
+    fn it_has_a_surface_area() {
+        // Straight up and down segments give cylinders
+        let cylinder = Hyperboloid::new(Transform::new(), Transform::new(), false,
+                                        Point::new_with(1.0, 0.0, 0.0),
+                                        Point::new_with(1.0, 0.0, 2.0), 360.0);
+        assert!((cylinder.area() - 4.0 * PI).abs() < 1e-3);
+
+        // Compare against a numerical integration of the surface of revolution
+        let h = waisted();
+        let n = 10000;
+        let dz = 2.0 / (n as f32);
+        let expected = (0..n).fold(0.0, |area, i| {
+            let z = -1.0 + (i as f32 + 0.5) * dz;
+            let r = ((1.0 + h.c * z * z) / h.a).sqrt();
+            let drdz = h.c * z / (h.a * r);
+            area + 2.0 * PI * r * (1.0 + drdz * drdz).sqrt() * dz
+        });
+        assert!((h.area() - expected).abs() / expected < 1e-3);
+    }
+}
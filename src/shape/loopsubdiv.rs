@@ -12,7 +12,9 @@ use geometry::point::Point;
 use geometry::vector::Cross;
 use geometry::vector::Vector;
 use primitive::Refinable;
+use shape::displacement::Displacement;
 use shape::mesh::Mesh;
+use shape::mesh_processing::MeshData;
 use shape::ShapeBase;
 use transform::transform::ApplyTransform;
 use transform::transform::Transform;
@@ -271,13 +273,20 @@ pub struct LoopSubdiv {
     n_levels: usize,
     vertices: Vec<Arc<SDVertex>>,
     faces: Vec<Arc<SDFace>>,
-    max_vert_id: usize
+    max_vert_id: usize,
+    displacement: Option<Displacement>
 }
 
 impl LoopSubdiv {
     pub fn new(o2w: Transform, w2o: Transform, ro: bool,
                vertex_indices: &[usize], points: &[Point], nl: usize)
                -> LoopSubdiv {
+        LoopSubdiv::new_with(o2w, w2o, ro, vertex_indices, points, nl, None)
+    }
+
+    pub fn new_with(o2w: Transform, w2o: Transform, ro: bool,
+                    vertex_indices: &[usize], points: &[Point], nl: usize,
+                    disp: Option<Displacement>) -> LoopSubdiv {
         // Allocate vertices
         let mut vert_id = 0;
         let mut verts = {
@@ -387,7 +396,8 @@ impl LoopSubdiv {
             n_levels: nl,
             vertices: verts,
             faces: faces,
-            max_vert_id: vert_id
+            max_vert_id: vert_id,
+            displacement: disp
         }
     }
 
@@ -396,6 +406,25 @@ impl LoopSubdiv {
     pub fn object_bound(&self) -> BBox {
         self.vertices.iter().fold(BBox::new(), |b, v| b.unioned_with_ref(&v.p))
     }
+
+    // Displaced surfaces are subdivided at least until the edges of the
+    // control mesh would meet the dicing target on screen.
+    fn num_levels(&self) -> usize {
+        let dicing = match self.displacement.as_ref().and_then(|d| d.dicing()) {
+            None => return self.n_levels,
+            Some(d) => d
+        };
+
+        let o2w = &self.base.object2world;
+        let world: Vec<Point> = self.vertices.iter().map(|v| o2w.t(&v.p)).collect();
+        let edges: Vec<(usize, usize)> = self.faces.iter().flat_map(|f| {
+            (0..3).map(|k| (f.v[k].upgrade().unwrap().id,
+                            f.v[next(k)].upgrade().unwrap().id)).collect::<Vec<_>>()
+        }).collect();
+
+        let levels = dicing.levels_for(edges.iter().map(|&(a, b)| (&world[a], &world[b])));
+        ::std::cmp::max(self.n_levels, levels)
+    }
 }
 
 impl Refinable<Mesh> for LoopSubdiv {
@@ -405,7 +434,7 @@ impl Refinable<Mesh> for LoopSubdiv {
         let mut v = self.vertices.clone();
         
         let mut vtx_id = self.max_vert_id;
-        for _ in 0..self.n_levels {
+        for _ in 0..self.num_levels() {
             // Update f and v for next level of subdivision
             let mut new_vertices = Vec::new();
 
@@ -631,13 +660,15 @@ impl Refinable<Mesh> for LoopSubdiv {
             }
         }
 
-        vec![Mesh::new(self.base.object2world.clone(),
-                       self.base.world2object.clone(),
-                       self.base.reverse_orientation,
-                       &*indices.into_boxed_slice(),
-                       &*p_limit.into_boxed_slice(),
-                       Some(&*ns.into_boxed_slice()),
-                       None, None, None)]
+        // Offset the limit surface along its normals
+        let mut data = MeshData::new(&indices, &p_limit, Some(&ns[..]), None, None);
+        if let Some(ref disp) = self.displacement {
+            data = data.displace(disp, &self.base.object2world);
+        }
+
+        vec![data.into_mesh(self.base.object2world.clone(),
+                            self.base.world2object.clone(),
+                            self.base.reverse_orientation, None)]
     }
 }
 
@@ -9,7 +9,6 @@ use geometry::normal::Normal;
 use geometry::normal::Normalize;
 use geometry::point::Point;
 use geometry::vector::Cross;
-use geometry::vector::Dot;
 use geometry::vector::Vector;
 use intersection::Intersectable;
 use primitive::Refinable;
@@ -21,8 +20,103 @@ use transform::transform::ApplyTransform;
 use transform::transform::Transform;
 
 use geometry::vector::coordinate_system;
+use utils::gamma;
 use utils::solve_linear_system_2x2;
 
+// Intersects the ray with the triangle using the watertight algorithm
+// of Woop et al.: the vertices are transformed into a space where the
+// ray starts at the origin and points down +z, which reduces the test
+// to 2D edge functions that are consistent across shared edges.
+// Returns the distance to the hit and its barycentric coordinates.
+pub fn intersect_triangle(r: &Ray, p1: &Point, p2: &Point, p3: &Point)
+                          -> Option<(f32, [f32; 3])> {
+    // Translate vertices based on ray origin
+    let p0t = p1 - &r.o;
+    let p1t = p2 - &r.o;
+    let p2t = p3 - &r.o;
+
+    // Permute components of triangle vertices and ray direction so
+    // that z is the largest dimension of the direction
+    let ad = r.d.abs();
+    let kz = if ad.x > ad.y && ad.x > ad.z { 0 } else if ad.y > ad.z { 1 } else { 2 };
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+    let permute = |v: &Vector| Vector::new_with(v[kx], v[ky], v[kz]);
+    let d = permute(&r.d);
+    let mut p0t = permute(&p0t);
+    let mut p1t = permute(&p1t);
+    let mut p2t = permute(&p2t);
+
+    // Apply shear transformation to translated vertex positions
+    let sx = -d.x / d.z;
+    let sy = -d.y / d.z;
+    let sz = 1.0 / d.z;
+    for p in [&mut p0t, &mut p1t, &mut p2t].iter_mut() {
+        p.x += sx * p.z;
+        p.y += sy * p.z;
+    }
+
+    // Compute edge function coefficients
+    let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+    let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+    let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+    // Fall back to double precision test at triangle edges
+    if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+        let edge = |a: &Vector, b: &Vector| {
+            ((a.x as f64) * (b.y as f64) - (a.y as f64) * (b.x as f64)) as f32
+        };
+        e0 = edge(&p1t, &p2t);
+        e1 = edge(&p2t, &p0t);
+        e2 = edge(&p0t, &p1t);
+    }
+
+    // Perform triangle edge and determinant tests
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+
+    // Compute scaled hit distance to triangle and test against ray t range
+    p0t.z *= sz;
+    p1t.z *= sz;
+    p2t.z *= sz;
+    let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+    if det < 0.0 && (t_scaled >= 0.0 || t_scaled < r.maxt() * det) {
+        return None;
+    } else if det > 0.0 && (t_scaled <= 0.0 || t_scaled > r.maxt() * det) {
+        return None;
+    }
+
+    // Compute barycentric coordinates and t value for triangle intersection
+    let inv_det = 1.0 / det;
+    let b = [e0 * inv_det, e1 * inv_det, e2 * inv_det];
+    let t = t_scaled * inv_det;
+
+    // Ensure that computed triangle t is conservatively greater than zero
+    let max_zt = p0t.z.abs().max(p1t.z.abs()).max(p2t.z.abs());
+    let delta_z = gamma(3) * max_zt;
+
+    let max_xt = p0t.x.abs().max(p1t.x.abs()).max(p2t.x.abs());
+    let max_yt = p0t.y.abs().max(p1t.y.abs()).max(p2t.y.abs());
+    let delta_x = gamma(5) * (max_xt + max_zt);
+    let delta_y = gamma(5) * (max_yt + max_zt);
+
+    let delta_e = 2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+    let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+    let delta_t = 3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) *
+        inv_det.abs();
+    if t <= delta_t || t < r.mint() {
+        return None;
+    }
+
+    Some((t, b))
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Triangle {
     mesh: Arc<Mesh>,
@@ -38,37 +132,9 @@ impl Triangle {
         (p1.clone(), p2.clone(), p3.clone())
     }
 
-    fn get_intersection_point(&self, r: &Ray) -> Option<(f32, f32, f32)> {
-        // Compute s1
+    fn get_intersection_point(&self, r: &Ray) -> Option<(f32, [f32; 3])> {
         let (p1, p2, p3) = self.get_vertices();
-
-        let e1 = &p2 - &p1;
-        let e2 = &p3 - &p1;
-        let s1 = r.d.cross_with(&e2);
-        let divisor = s1.dot(&e1);
-        if divisor == 0f32 {
-            return None;
-        }
-
-        // Compute first barycentric coordinate
-        let inv_divisor = 1.0 / divisor;
-        let s = &(r.o) - p1;
-        let b1 = s1.dot(&s) * inv_divisor;
-        if b1 < 0.0 || b1 > 1.0 {
-            return None;
-        }
-
-        // Compute second barycentric coordinate
-        let s2 = s.cross_with(&e1);
-        let b2 = r.d.dot(&s2) * inv_divisor;
-        if b2 < 0.0 || (b1 + b2) > 1.0 {
-            return None;
-        }
-
-        // Compute t to intersection point
-        let t = e2.dot(&s2) * inv_divisor;
-
-        if t < r.mint() || t > r.maxt() { None } else { Some((t, b1, b2)) }
+        intersect_triangle(r, &p1, &p2, &p3)
     }
 
     fn get_uvs(&self) -> [[f32; 2]; 3] {
@@ -193,6 +259,48 @@ impl Triangle {
     }
 }
 
+// Keeps the part of a convex polygon on one side of an axis aligned plane
+fn clip_polygon(poly: &[[f32; 3]], axis: usize, pos: f32, keep_below: bool) -> Vec<[f32; 3]> {
+    let inside = |v: &[f32; 3]| if keep_below { v[axis] <= pos } else { v[axis] >= pos };
+
+    let mut clipped = Vec::with_capacity(poly.len() + 1);
+    for i in 0..poly.len() {
+        let a = &poly[i];
+        let b = &poly[(i + 1) % poly.len()];
+        if inside(a) {
+            clipped.push(*a);
+        }
+
+        if inside(a) != inside(b) {
+            let t = (pos - a[axis]) / (b[axis] - a[axis]);
+            let mut v = [a[0] + t * (b[0] - a[0]),
+                         a[1] + t * (b[1] - a[1]),
+                         a[2] + t * (b[2] - a[2])];
+            v[axis] = pos;
+            clipped.push(v);
+        }
+    }
+    clipped
+}
+
+impl Triangle {
+    // Bounds the part of the triangle that lies within b, which is usually
+    // a lot smaller than the world bound clipped to b for long and thin
+    // triangles that cross the box diagonally.
+    pub fn clipped_bound(&self, b: &BBox) -> BBox {
+        let (p1, p2, p3) = self.get_vertices();
+        let mut poly = vec![[p1.x, p1.y, p1.z], [p2.x, p2.y, p2.z], [p3.x, p3.y, p3.z]];
+        for axis in 0..3 {
+            poly = clip_polygon(&poly, axis, b.p_min[axis], false);
+            poly = clip_polygon(&poly, axis, b.p_max[axis], true);
+        }
+
+        poly.iter().fold(BBox::new(), |bb, v| {
+            bb.unioned_with(Point::new_with(v[0], v[1], v[2]))
+        }).clipped_to(b)
+    }
+}
+
 impl HasBounds for Triangle {
     fn world_bound(&self) -> BBox {
         let (p1, p2, p3) = self.get_vertices();
@@ -210,7 +318,7 @@ impl Intersectable<ShapeIntersection> for Triangle {
     }
 
     fn intersect(&self, r: &Ray) -> Option<ShapeIntersection> {
-        let (t, b1, b2) = {
+        let (t, b) = {
             match self.get_intersection_point(r) {
                 None => return None,
                 Some(t) => t
@@ -243,14 +351,24 @@ impl Intersectable<ShapeIntersection> for Triangle {
             }
         };
 
-        // Interpolate (u, v) triangle parametric coordinates
-        let b0 = 1.0 - b1 - b2;
-        let tu = b0 * uvs[0][0] + b1 * uvs[1][0] + b2 * uvs[2][0];
-        let tv = b0 * uvs[0][1] + b1 * uvs[1][1] + b2 * uvs[2][1];
+        // Interpolate (u, v) parametric coordinates and hit point
+        let tu = b[0] * uvs[0][0] + b[1] * uvs[1][0] + b[2] * uvs[2][0];
+        let tv = b[0] * uvs[0][1] + b[1] * uvs[1][1] + b[2] * uvs[2][1];
+        let p_hit = Point::new_with(
+            b[0] * p1.x + b[1] * p2.x + b[2] * p3.x,
+            b[0] * p1.y + b[1] * p2.y + b[2] * p3.y,
+            b[0] * p1.z + b[1] * p2.z + b[2] * p3.z);
+
+        // Compute error bounds for triangle intersection
+        let abs_sum = Vector::new_with(
+            (b[0] * p1.x).abs() + (b[1] * p2.x).abs() + (b[2] * p3.x).abs(),
+            (b[0] * p1.y).abs() + (b[1] * p2.y).abs() + (b[2] * p3.y).abs(),
+            (b[0] * p1.z).abs() + (b[1] * p2.z).abs() + (b[2] * p3.z).abs());
+        let p_error = gamma(7) * &abs_sum;
 
         // Test intersection against alpha texture, if present
         let dg = DifferentialGeometry::new_with(
-            r.point_at(t), dpdu, dpdv, Normal::new(), Normal::new(), tu, tv,
+            p_hit, dpdu, dpdv, Normal::new(), Normal::new(), tu, tv,
             Some(self.base().clone()));
 
         if let Some(tex_ref) = self.mesh.atex.as_ref().map(|t| t.clone()) {
@@ -259,7 +377,7 @@ impl Intersectable<ShapeIntersection> for Triangle {
             }
         }
 
-        Some(ShapeIntersection::new(t, t * 5e-4, dg))
+        Some(ShapeIntersection::new(t, p_error, dg))
     }
 }
 
@@ -344,6 +462,7 @@ mod tests {
     use primitive::Refinable;
     use ray::Ray;
     use transform::transform::Transform;
+    use utils::Lerp;
 
     // Tetrahedron
     static TET_PTS : [Point; 4] =
@@ -485,10 +604,68 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn its_triangles_have_intersection_information() {
-        // !FIXME! Implement this when I know how to actually measure it.
-        unimplemented!();
+        let mesh = Mesh::new(Transform::new(), Transform::new(), false,
+                             &TET_TRIS, &TET_PTS, None, None, None, None);
+        let tris = mesh.refine();
+
+        let r = Ray::new_with(Point::new_with(0.25, 0.5, 1.0),
+                              Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        // refine() hands the triangles back in reverse, so look for the
+        // face lying in the z = 0 plane instead of relying on the order
+        let bottom = tris.iter().find(|t| t.v.iter().all(|&i| TET_PTS[i].z == 0.0)).unwrap();
+        let shape_int = bottom.intersect(&r).unwrap();
+        assert_eq!(shape_int.t_hit, 1.0);
+        assert_eq!(shape_int.dg.p, Point::new_with(0.25, 0.5, 0.0));
+
+        // The hit point is known to within a few ulps
+        assert!(shape_int.p_error.x > 0.0 && shape_int.p_error.y > 0.0);
+        assert_eq!(shape_int.p_error.z, 0.0);
+        assert!(shape_int.p_error.length() < 1e-6);
+    }
+
+    #[test]
+    fn its_triangles_are_watertight() {
+        // Two triangles sharing an edge that isn't aligned with any axis
+        let pts = [Point::new_with(-1.3, 0.1, 0.7), Point::new_with(2.1, -0.3, 1.9),
+                   Point::new_with(0.3, 2.7, -0.4), Point::new_with(1.7, 1.9, 3.1)];
+        let mesh = Mesh::new(Transform::new(), Transform::new(), false,
+                             &[0, 1, 2, 2, 1, 3], &pts, None, None, None, None);
+        let tris = mesh.refine();
+
+        // Rays aimed at points right on the shared edge have to hit at
+        // least one of the two triangles
+        let o = Point::new_with(0.1, 0.2, 10.0);
+        for i in 1..1000 {
+            let target = pts[1].lerp(&pts[2], (i as f32) / 1000.0);
+            let r = Ray::new_with(o.clone(), target - &o, 0.0);
+            assert!(tris[0].intersect_p(&r) || tris[1].intersect_p(&r));
+        }
+    }
+
+    #[test]
+    fn its_triangles_bound_the_error_of_hit_points() {
+        let pts = [Point::new_with(1000.0, 0.0, 0.0), Point::new_with(1000.0, 1.0, 0.3),
+                   Point::new_with(1001.0, 0.0, 0.7)];
+        let mesh = Mesh::new(Transform::new(), Transform::new(), false,
+                             &[0, 1, 2], &pts, None, None, None, None);
+        let tri = mesh.refine().pop().unwrap();
+
+        let r = Ray::new_with(Point::new_with(1000.25, 0.25, 5.0),
+                              Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        let shape_int = tri.intersect(&r).unwrap();
+
+        // The hit point is far from the origin so its error has to be
+        // more than rounding by the triangle's small size would suggest
+        let (p, e) = (&shape_int.dg.p, &shape_int.p_error);
+        assert!(e.x > 1e-5);
+
+        // The box around the hit point has to touch the triangle's plane,
+        // z = 0.7 (x - 1000) + 0.3 y, which we check in double precision
+        let (a, b) = (0.7f32 as f64, 0.3f32 as f64);
+        let dist = a * ((p.x as f64) - 1000.0) + b * (p.y as f64) - (p.z as f64);
+        let slack = a * (e.x as f64) + b * (e.y as f64) + (e.z as f64);
+        assert!(dist.abs() <= slack);
     }
 
     #[test]
@@ -511,6 +688,31 @@ mod tests {
         assert_eq!(xf_tris[3].area(), 2.0);
     }
 
+    #[test]
+    fn its_triangles_can_be_clipped_to_boxes() {
+        // A long sliver running diagonally through its bounds
+        let pts = [Point::new(), Point::new_with(10.0, 10.0, 0.0),
+                   Point::new_with(10.0, 10.0, 1.0)];
+        let mesh = Mesh::new(Transform::new(), Transform::new(), false,
+                             &[0, 1, 2], &pts, None, None, None, None);
+        let tri = mesh.refine().pop().unwrap();
+
+        let slab = BBox::new_with(Point::new_with(0.0, -100.0, -100.0),
+                                  Point::new_with(1.0, 100.0, 100.0));
+        let clipped = tri.clipped_bound(&slab);
+        assert!((clipped.p_min - Point::new()).length() < 1e-5);
+        assert!((clipped.p_max - Point::new_with(1.0, 1.0, 0.1)).length() < 1e-5);
+        assert_eq!(tri.world_bound().clipped_to(&slab),
+                   BBox::new_with(Point::new(), Point::new_with(1.0, 10.0, 1.0)));
+
+        // The whole triangle fits in its own bounds
+        assert_eq!(tri.clipped_bound(&tri.world_bound()), tri.world_bound());
+
+        let away = BBox::new_with(Point::new_with(20.0, 0.0, 0.0),
+                                  Point::new_with(30.0, 10.0, 10.0));
+        assert_eq!(tri.clipped_bound(&away), BBox::new());
+    }
+
     #[test]
     #[ignore]
     fn its_triangles_have_shading_geometry() {
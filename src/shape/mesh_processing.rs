@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use geometry::normal::Normal;
+use geometry::normal::Normalize;
+use geometry::point::Point;
+use geometry::vector::Cross;
+use geometry::vector::coordinate_system;
+use geometry::vector::Dot;
+use geometry::vector::Vector;
+use shape::displacement::Displacement;
+use shape::mesh::Mesh;
+use texture::Texture;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Clamp;
+use utils::Degrees;
+
+// Attributes closer than this are considered equal when welding
+const ATTRIBUTE_EPSILON: f32 = 1e-5;
+
+// The object space vertex data of a triangle mesh as it comes out of a
+// file. Imported meshes often have split vertices, slivers and no
+// normals, so each of the cleanup steps below can be run on the data
+// before it is turned into a Mesh:
+//
+//   MeshData::new(&vi, &p, None, None, Some(&uvs))
+//       .weld(1e-4)
+//       .remove_degenerates()
+//       .smooth_normals(60.0)
+//       .generate_tangents()
+//       .into_mesh(o2w, w2o, false, None)
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshData {
+    pub vertex_index: Vec<usize>,
+    pub p: Vec<Point>,
+    pub n: Option<Vec<Normal>>,
+    pub s: Option<Vec<Vector>>,
+    pub uvs: Option<Vec<f32>>
+}
+
+fn safe_normalize(v: Vector) -> Vector {
+    if v.length_squared() > 0.0 { v.normalize() } else { v }
+}
+
+// Angle at vertex a of the triangle abc
+fn corner_angle(a: &Point, b: &Point, c: &Point) -> f32 {
+    let e1 = safe_normalize(b - a);
+    let e2 = safe_normalize(c - a);
+    e1.dot(&e2).clamp(-1.0, 1.0).acos()
+}
+
+impl MeshData {
+    pub fn new(vi: &[usize], p: &[Point], n: Option<&[Normal]>,
+               s: Option<&[Vector]>, uv: Option<&[f32]>) -> MeshData {
+        assert!(vi.len() % 3 == 0);
+        MeshData {
+            vertex_index: vi.to_vec(),
+            p: p.to_vec(),
+            n: n.map(|v| v.to_vec()),
+            s: s.map(|v| v.to_vec()),
+            uvs: uv.map(|v| v.to_vec())
+        }
+    }
+
+    pub fn num_triangles(&self) -> usize { self.vertex_index.len() / 3 }
+
+    pub fn into_mesh(self, o2w: Transform, w2o: Transform, ro: bool,
+                     atex: Option<Arc<Texture<f32>>>) -> Mesh {
+        Mesh::new(o2w, w2o, ro, &self.vertex_index, &self.p,
+                  self.n.as_ref().map(|n| &n[..]),
+                  self.s.as_ref().map(|s| &s[..]),
+                  self.uvs.as_ref().map(|uv| &uv[..]), atex)
+    }
+
+    fn triangle(&self, t: usize) -> [usize; 3] {
+        [self.vertex_index[3 * t],
+         self.vertex_index[3 * t + 1],
+         self.vertex_index[3 * t + 2]]
+    }
+
+    // Unnormalized face normal, whose length is twice the area
+    fn face_normal(&self, t: usize) -> Vector {
+        let tri = self.triangle(t);
+        (&self.p[tri[1]] - &self.p[tri[0]]).into_cross(&self.p[tri[2]] - &self.p[tri[0]])
+    }
+
+    fn uv(&self, v: usize) -> Option<[f32; 2]> {
+        self.uvs.as_ref().map(|uvs| [uvs[2 * v], uvs[2 * v + 1]])
+    }
+
+    fn same_attributes(&self, a: usize, b: usize) -> bool {
+        let close = |x: &Vector, y: &Vector| (x - y).length_squared() <=
+            ATTRIBUTE_EPSILON * ATTRIBUTE_EPSILON;
+
+        let same_n = self.n.as_ref().map_or(true, |n| {
+            close(&Vector::from(&n[a]), &Vector::from(&n[b]))
+        });
+        let same_s = self.s.as_ref().map_or(true, |s| close(&s[a], &s[b]));
+        let same_uv = match (self.uv(a), self.uv(b)) {
+            (Some(ua), Some(ub)) => (ua[0] - ub[0]).abs() <= ATTRIBUTE_EPSILON
+                && (ua[1] - ub[1]).abs() <= ATTRIBUTE_EPSILON,
+            _ => true
+        };
+        same_n && same_s && same_uv
+    }
+
+    // Builds a new set of vertices from the old ones, where each new
+    // vertex copies the attributes of the old vertex it came from.
+    fn with_vertices(self, sources: &[usize], vertex_index: Vec<usize>) -> MeshData {
+        MeshData {
+            vertex_index: vertex_index,
+            p: sources.iter().map(|&v| self.p[v].clone()).collect(),
+            n: self.n.as_ref().map(|n| sources.iter().map(|&v| n[v].clone()).collect()),
+            s: self.s.as_ref().map(|s| sources.iter().map(|&v| s[v].clone()).collect()),
+            uvs: self.uvs.as_ref().map(|uvs| {
+                sources.iter().flat_map(|&v| vec![uvs[2 * v], uvs[2 * v + 1]]).collect()
+            })
+        }
+    }
+
+    // Drops any vertices that aren't used by a triangle
+    fn compact(self) -> MeshData {
+        let mut remap = vec![None; self.p.len()];
+        let mut sources = Vec::new();
+        let vertex_index = self.vertex_index.iter().map(|&v| {
+            *remap[v].get_or_insert_with(|| {
+                sources.push(v);
+                sources.len() - 1
+            })
+        }).collect();
+        self.with_vertices(&sources, vertex_index)
+    }
+
+    // Merges vertices whose positions are within tolerance of each other
+    // and that have the same normals, tangents and uvs, so that seams in
+    // the uvs or hard edges stay split.
+    pub fn weld(self, tolerance: f32) -> MeshData {
+        let cell_size = tolerance.max(1e-6);
+        let cell = |p: &Point| {
+            ((p.x / cell_size).floor() as i64,
+             (p.y / cell_size).floor() as i64,
+             (p.z / cell_size).floor() as i64)
+        };
+
+        // Hash each vertex into a grid and look for a match in the
+        // neighboring cells, since matches can straddle a cell boundary
+        let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        let mut sources = Vec::new();
+        let mut remap = Vec::with_capacity(self.p.len());
+        for v in 0..self.p.len() {
+            let (cx, cy, cz) = cell(&self.p[v]);
+            let mut found = None;
+            'search: for dx in -1..2 {
+                for dy in -1..2 {
+                    for dz in -1..2 {
+                        if let Some(candidates) = grid.get(&(cx + dx, cy + dy, cz + dz)) {
+                            for &w in candidates.iter() {
+                                let src = sources[w];
+                                if self.p[v].distance_squared(&self.p[src]) <= tolerance * tolerance
+                                    && self.same_attributes(v, src) {
+                                    found = Some(w);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            let w = found.unwrap_or_else(|| {
+                sources.push(v);
+                grid.entry((cx, cy, cz)).or_insert_with(Vec::new).push(sources.len() - 1);
+                sources.len() - 1
+            });
+            remap.push(w);
+        }
+
+        let vertex_index = self.vertex_index.iter().map(|&v| remap[v]).collect();
+        self.with_vertices(&sources, vertex_index)
+    }
+
+    // Removes triangles that have no area or that repeat another
+    // triangle, along with any vertices that are no longer used.
+    pub fn remove_degenerates(self) -> MeshData {
+        let mut seen = HashSet::new();
+        let mut vertex_index = Vec::with_capacity(self.vertex_index.len());
+        for t in 0..self.num_triangles() {
+            let tri = self.triangle(t);
+            if tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] {
+                continue;
+            }
+
+            if self.face_normal(t).length_squared() == 0.0 {
+                continue;
+            }
+
+            // Triangles are duplicates no matter the winding
+            let mut key = tri;
+            key.sort();
+            if !seen.insert(key) {
+                continue;
+            }
+
+            vertex_index.extend_from_slice(&tri);
+        }
+
+        MeshData { vertex_index: vertex_index, .. self }.compact()
+    }
+
+    // Generates vertex normals by averaging the normals of the triangles
+    // around each vertex. Triangles that meet at more than max_angle
+    // degrees form a hard edge, and the vertices along it are split so
+    // that each side gets its own normal.
+    pub fn smooth_normals(self, max_angle: f32) -> MeshData {
+        let num_tris = self.num_triangles();
+        let face_normals: Vec<Vector> = (0..num_tris)
+            .map(|t| safe_normalize(self.face_normal(t))).collect();
+
+        // Triangles around each vertex
+        let mut adjacent = vec![Vec::new(); self.p.len()];
+        for t in 0..num_tris {
+            for &v in self.triangle(t).iter() {
+                adjacent[v].push(t);
+            }
+        }
+
+        // Each corner gets the angle weighted average of the normals of
+        // the triangles around the vertex that are smooth with it. Corners
+        // that end up with the same set of triangles share a vertex.
+        let cos_max = max_angle.as_radians().cos();
+        let mut groups: HashMap<(usize, Vec<usize>), usize> = HashMap::new();
+        let mut sources = Vec::new();
+        let mut normals = Vec::new();
+        let mut vertex_index = Vec::with_capacity(self.vertex_index.len());
+        for t in 0..num_tris {
+            let tri = self.triangle(t);
+            for &v in tri.iter() {
+                let smooth: Vec<usize> = adjacent[v].iter().cloned().filter(|&u| {
+                    u == t || face_normals[t].dot(&face_normals[u]) >= cos_max
+                }).collect();
+
+                let next = sources.len();
+                let idx = *groups.entry((v, smooth.clone())).or_insert(next);
+                if idx == next {
+                    let n = smooth.iter().fold(Vector::new(), |n, &u| {
+                        let adj = self.triangle(u);
+                        let i = adj.iter().position(|&w| w == v).unwrap();
+                        let angle = corner_angle(&self.p[v], &self.p[adj[(i + 1) % 3]],
+                                                 &self.p[adj[(i + 2) % 3]]);
+                        n + angle * &face_normals[u]
+                    });
+                    sources.push(v);
+                    normals.push(Normal::from(safe_normalize(n)));
+                }
+                vertex_index.push(idx);
+            }
+        }
+
+        let mut result = self.with_vertices(&sources, vertex_index);
+        result.n = Some(normals);
+        result
+    }
+
+    // Generates per vertex tangents along the direction of increasing u,
+    // following the MikkTSpace conventions: tangents are accumulated per
+    // corner weighted by the angle of the corner, projected into the
+    // tangent plane of the vertex normal, and vertices are split where
+    // the uv mapping is mirrored so that the handedness of the frame is
+    // consistent across each vertex. Does nothing if there are no uvs.
+    pub fn generate_tangents(self) -> MeshData {
+        if self.uvs.is_none() {
+            return self;
+        }
+
+        let num_tris = self.num_triangles();
+
+        // Use the given normals if there are any, otherwise the angle
+        // weighted average of the faces
+        let normals: Vec<Vector> = match self.n {
+            Some(ref n) => n.iter().map(|n| safe_normalize(Vector::from(n))).collect(),
+            None => {
+                let mut n = vec![Vector::new(); self.p.len()];
+                for t in 0..num_tris {
+                    let fn_t = safe_normalize(self.face_normal(t));
+                    let tri = self.triangle(t);
+                    for i in 0..3 {
+                        let angle = corner_angle(&self.p[tri[i]], &self.p[tri[(i + 1) % 3]],
+                                                 &self.p[tri[(i + 2) % 3]]);
+                        n[tri[i]] = &n[tri[i]] + angle * &fn_t;
+                    }
+                }
+                n.into_iter().map(safe_normalize).collect()
+            }
+        };
+
+        let mut groups: HashMap<(usize, bool), usize> = HashMap::new();
+        let mut sources = Vec::new();
+        let mut tangents: Vec<Vector> = Vec::new();
+        let mut vertex_index = Vec::with_capacity(self.vertex_index.len());
+        for t in 0..num_tris {
+            let tri = self.triangle(t);
+            let uv = [self.uv(tri[0]).unwrap(), self.uv(tri[1]).unwrap(),
+                      self.uv(tri[2]).unwrap()];
+
+            // Solve for dp/du and dp/dv of the triangle
+            let e1 = &self.p[tri[1]] - &self.p[tri[0]];
+            let e2 = &self.p[tri[2]] - &self.p[tri[0]];
+            let (du1, dv1) = (uv[1][0] - uv[0][0], uv[1][1] - uv[0][1]);
+            let (du2, dv2) = (uv[2][0] - uv[0][0], uv[2][1] - uv[0][1]);
+            let det = du1 * dv2 - du2 * dv1;
+            let (dpdu, orientation) = if det != 0.0 {
+                ((dv2 * &e1 - dv1 * &e2) / det, det > 0.0)
+            } else {
+                (Vector::new(), true)
+            };
+
+            for i in 0..3 {
+                let v = tri[i];
+                let next = sources.len();
+                let idx = *groups.entry((v, orientation)).or_insert(next);
+                if idx == next {
+                    sources.push(v);
+                    tangents.push(Vector::new());
+                }
+
+                // Project into the tangent plane of the vertex
+                let n = &normals[v];
+                let projected = &dpdu - n.dot(&dpdu) * n;
+                let angle = corner_angle(&self.p[v], &self.p[tri[(i + 1) % 3]],
+                                         &self.p[tri[(i + 2) % 3]]);
+                tangents[idx] = &tangents[idx] + angle * &safe_normalize(projected);
+                vertex_index.push(idx);
+            }
+        }
+
+        // Vertices without a usable uv mapping get an arbitrary tangent
+        let tangents = tangents.into_iter().zip(sources.iter()).map(|(t, &v)| {
+            let n = &normals[v];
+            let t = &t - n.dot(&t) * n;
+            if t.length_squared() > 0.0 {
+                t.normalize()
+            } else {
+                coordinate_system(n).0
+            }
+        }).collect();
+
+        let mut result = self.with_vertices(&sources, vertex_index);
+        result.s = Some(tangents);
+        result
+    }
+
+    // Adds a vertex halfway along the edge between a and b
+    fn push_midpoint(&mut self, a: usize, b: usize) -> usize {
+        let p = 0.5 * (&self.p[a] + &self.p[b]);
+        self.p.push(p);
+        if let Some(ref mut n) = self.n {
+            let mid = Normal::from(safe_normalize(Vector::from(&n[a] + &n[b])));
+            n.push(mid);
+        }
+        if let Some(ref mut s) = self.s {
+            let mid = safe_normalize(&s[a] + &s[b]);
+            s.push(mid);
+        }
+        if let Some(ref mut uvs) = self.uvs {
+            let (u, v) = (0.5 * (uvs[2 * a] + uvs[2 * b]), 0.5 * (uvs[2 * a + 1] + uvs[2 * b + 1]));
+            uvs.push(u);
+            uvs.push(v);
+        }
+        self.p.len() - 1
+    }
+
+    // Splits every edge for which split holds at its midpoint, and
+    // replaces each triangle by the two, three or four triangles that
+    // fill it between its split edges. Both triangles on an edge see the
+    // same decision, so no T-junctions are introduced. Returns whether
+    // any edge was split.
+    fn split_edges<F>(mut self, split: F) -> (bool, MeshData)
+        where F: Fn(&Point, &Point) -> bool {
+        let edge_key = |a: usize, b: usize| if a < b { (a, b) } else { (b, a) };
+
+        let mut midpoints: HashMap<(usize, usize), Option<usize>> = HashMap::new();
+        for t in 0..self.num_triangles() {
+            let tri = self.triangle(t);
+            for k in 0..3 {
+                let key = edge_key(tri[k], tri[(k + 1) % 3]);
+                if !midpoints.contains_key(&key) {
+                    let mid = if split(&self.p[key.0], &self.p[key.1]) {
+                        Some(self.push_midpoint(key.0, key.1))
+                    } else {
+                        None
+                    };
+                    midpoints.insert(key, mid);
+                }
+            }
+        }
+
+        if midpoints.values().all(|m| m.is_none()) {
+            return (false, self);
+        }
+
+        let mut vertex_index = Vec::with_capacity(4 * self.vertex_index.len());
+        for t in 0..self.num_triangles() {
+            let v = self.triangle(t);
+            let m: Vec<Option<usize>> = (0..3).map(|k| {
+                midpoints[&edge_key(v[k], v[(k + 1) % 3])]
+            }).collect();
+
+            match m.iter().filter(|m| m.is_some()).count() {
+                0 => vertex_index.extend_from_slice(&v),
+                1 => {
+                    let k = m.iter().position(|m| m.is_some()).unwrap();
+                    let mk = m[k].unwrap();
+                    vertex_index.extend_from_slice(&[v[k], mk, v[(k + 2) % 3],
+                                                     mk, v[(k + 1) % 3], v[(k + 2) % 3]]);
+                },
+                2 => {
+                    // Cut off the corner between the split edges and fill
+                    // the remaining quad
+                    let k = m.iter().position(|m| m.is_none()).unwrap();
+                    let (m1, m2) = (m[(k + 1) % 3].unwrap(), m[(k + 2) % 3].unwrap());
+                    vertex_index.extend_from_slice(&[m1, v[(k + 2) % 3], m2,
+                                                     v[k], v[(k + 1) % 3], m1,
+                                                     v[k], m1, m2]);
+                },
+                _ => {
+                    let (m0, m1, m2) = (m[0].unwrap(), m[1].unwrap(), m[2].unwrap());
+                    vertex_index.extend_from_slice(&[v[0], m0, m2,
+                                                     m0, v[1], m1,
+                                                     m2, m1, v[2],
+                                                     m0, m1, m2]);
+                }
+            }
+        }
+
+        self.vertex_index = vertex_index;
+        (true, self)
+    }
+
+    // Moves each vertex along its normal by the displacement map,
+    // evaluated at the vertex uvs. If the displacement has a screen space
+    // dicing target, edges are split first until they are short enough
+    // on the film. The normals are recomputed from the displaced surface,
+    // and any tangents are dropped since they no longer apply. Vertices
+    // that are split along uv seams can be displaced apart.
+    pub fn displace(self, disp: &Displacement, o2w: &Transform) -> MeshData {
+        let mut data = if self.n.is_some() { self } else { self.smooth_normals(180.0) };
+        if let Some(dicing) = disp.dicing() {
+            for _ in 0..dicing.max_depth() {
+                let (split, refined) = data.split_edges(|p0, p1| {
+                    dicing.needs_split(&o2w.t(p0), &o2w.t(p1))
+                });
+                data = refined;
+                if !split {
+                    break;
+                }
+            }
+        }
+
+        let p: Vec<Point> = {
+            let n = data.n.as_ref().unwrap();
+            data.p.iter().enumerate().map(|(i, p)| {
+                let uv = data.uv(i).unwrap_or([0.0, 0.0]);
+                let ni = safe_normalize(Vector::from(&n[i]));
+                let d = disp.evaluate(o2w.t(p), o2w.t(&n[i]), uv[0], uv[1]);
+                p + d * &ni
+            }).collect()
+        };
+
+        MeshData { p: p, n: None, s: None, .. data }.smooth_normals(180.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::normal::Normal;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use primitive::Refinable;
+    use transform::transform::Transform;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use camera::Camera;
+    use camera::film::Film;
+    use filter::Filter;
+    use shape::displacement::Dicing;
+    use shape::displacement::Displacement;
+    use texture::Texture;
+    use transform::animated::AnimatedTransform;
+
+    // A unit cube with each face given its own four vertices, the way
+    // that it usually comes out of a modeling package.
+    fn split_cube() -> MeshData {
+        let corners = |axis: usize, side: f32| -> Vec<Point> {
+            let quad = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+            quad.iter().map(|&(a, b)| {
+                let mut c = [0.0; 3];
+                c[axis] = side;
+                c[(axis + 1) % 3] = if side > 0.0 { a } else { b };
+                c[(axis + 2) % 3] = if side > 0.0 { b } else { a };
+                Point::new_with(c[0], c[1], c[2])
+            }).collect()
+        };
+
+        let mut p = Vec::new();
+        let mut vi = Vec::new();
+        for axis in 0..3 {
+            for &side in [0.0, 1.0].iter() {
+                let base = p.len();
+                p.extend(corners(axis, side));
+                vi.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+            }
+        }
+        MeshData::new(&vi, &p, None, None, None)
+    }
+
+    fn quad_with_uvs() -> MeshData {
+        let p = [Point::new_with(0.0, 0.0, 0.0), Point::new_with(2.0, 0.0, 0.0),
+                 Point::new_with(2.0, 1.0, 0.0), Point::new_with(0.0, 1.0, 0.0)];
+        let uvs = [0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+        MeshData::new(&[0, 1, 2, 0, 2, 3], &p, None, None, Some(&uvs))
+    }
+
+    #[test]
+    fn it_welds_vertices() {
+        let cube = split_cube();
+        assert_eq!(cube.p.len(), 24);
+
+        let welded = cube.clone().weld(1e-4);
+        assert_eq!(welded.p.len(), 8);
+        assert_eq!(welded.num_triangles(), 12);
+
+        // Nothing is welded if the tolerance is too small
+        let mut jittered = cube.clone();
+        for (i, p) in jittered.p.iter_mut().enumerate() {
+            p.x += (i as f32) * 1e-3;
+        }
+        assert_eq!(jittered.clone().weld(1e-5).p.len(), 24);
+        assert!(jittered.weld(0.1).p.len() < 24);
+    }
+
+    #[test]
+    fn it_keeps_uv_seams_when_welding() {
+        let mut quad = quad_with_uvs();
+        quad.p.push(Point::new_with(2.0, 0.0, 0.0));
+        quad.uvs.as_mut().unwrap().extend_from_slice(&[0.0, 0.0]);
+        quad.vertex_index[1] = 4;
+
+        // Vertex 4 sits on top of vertex 1 but has a different uv
+        assert_eq!(quad.weld(1e-4).p.len(), 5);
+    }
+
+    #[test]
+    fn it_removes_degenerate_triangles() {
+        let p = [Point::new_with(0.0, 0.0, 0.0), Point::new_with(1.0, 0.0, 0.0),
+                 Point::new_with(0.0, 1.0, 0.0), Point::new_with(2.0, 0.0, 0.0),
+                 Point::new_with(5.0, 5.0, 5.0)];
+        let vi = [0, 1, 2,   // Fine
+                  0, 0, 2,   // Repeated vertex
+                  0, 1, 3,   // Collinear
+                  2, 0, 1,   // Duplicate of the first
+                  0, 2, 1];  // Same triangle facing the other way
+        let cleaned = MeshData::new(&vi, &p, None, None, None).remove_degenerates();
+        assert_eq!(cleaned.num_triangles(), 1);
+
+        // Unused vertices are dropped too
+        assert_eq!(cleaned.p.len(), 3);
+        assert_eq!(cleaned.vertex_index, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn it_generates_smooth_normals() {
+        let cube = split_cube().weld(1e-4);
+
+        // All of the edges of a cube are 90 degrees so at a small
+        // threshold each face keeps its own vertices
+        let faceted = cube.clone().smooth_normals(30.0);
+        assert_eq!(faceted.p.len(), 24);
+        for (v, n) in faceted.n.as_ref().unwrap().iter().enumerate() {
+            let n = Vector::from(n);
+            assert!((n.length() - 1.0).abs() < 1e-5);
+
+            // Each normal is along an axis and points out of the cube
+            let c = &faceted.p[v] - &Point::new_with(0.5, 0.5, 0.5);
+            assert!((n.x.abs() + n.y.abs() + n.z.abs() - 1.0).abs() < 1e-5);
+            assert!(n.dot(&c) > 0.0);
+        }
+
+        // But at a large threshold all of the corners are smooth
+        let smooth = cube.smooth_normals(100.0);
+        assert_eq!(smooth.p.len(), 8);
+        for (v, n) in smooth.n.as_ref().unwrap().iter().enumerate() {
+            let c = (&smooth.p[v] - &Point::new_with(0.5, 0.5, 0.5)).normalize();
+            assert!((Vector::from(n) - c).length_squared() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn it_generates_tangents_from_uvs() {
+        let quad = quad_with_uvs().smooth_normals(30.0).generate_tangents();
+        for s in quad.s.as_ref().unwrap().iter() {
+            assert!((s - &Vector::new_with(1.0, 0.0, 0.0)).length_squared() < 1e-5);
+        }
+
+        // Flipping the uvs flips the tangents
+        let mut flipped = quad_with_uvs();
+        for uv in flipped.uvs.as_mut().unwrap().chunks_mut(2) {
+            uv[0] = 1.0 - uv[0];
+        }
+        let flipped = flipped.generate_tangents();
+        for s in flipped.s.as_ref().unwrap().iter() {
+            assert!((s - &Vector::new_with(-1.0, 0.0, 0.0)).length_squared() < 1e-5);
+        }
+
+        // Nothing happens without uvs
+        assert!(split_cube().generate_tangents().s.is_none());
+    }
+
+    #[test]
+    fn it_splits_mirrored_tangent_frames() {
+        // Two triangles sharing an edge with mirrored uvs
+        let p = [Point::new_with(0.0, 0.0, 0.0), Point::new_with(1.0, 0.0, 0.0),
+                 Point::new_with(1.0, 1.0, 0.0), Point::new_with(2.0, 0.0, 0.0)];
+        let uvs = [0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0];
+        let n = vec![Normal::new_with(0.0, 0.0, 1.0); 4];
+        let mesh = MeshData::new(&[0, 1, 2, 1, 3, 2], &p, Some(&n), None, Some(&uvs))
+            .generate_tangents();
+
+        // The shared edge gets split
+        assert_eq!(mesh.p.len(), 6);
+    }
+
+    #[test]
+    fn it_can_be_turned_into_a_mesh() {
+        let mesh = split_cube().weld(1e-4).smooth_normals(30.0)
+            .into_mesh(Transform::new(), Transform::new(), false, None);
+        assert_eq!(mesh.refine().len(), 12);
+    }
+
+    // 100x100 pixels with a 90 degree field of view, looking down z
+    fn test_camera() -> Camera {
+        let film = Film::image(100, 100, Filter::mean(1.0, 1.0),
+                               [0.0, 1.0, 0.0, 1.0], String::from(""), false);
+        Camera::perspective(AnimatedTransform::identity(), [-1.0, 1.0, -1.0, 1.0],
+                            0.0, 1.0, 0.0, 1e6, 90.0, film)
+    }
+
+    // A unit square in the xy plane centered on the origin
+    fn unit_square() -> MeshData {
+        let p = [Point::new_with(-0.5, -0.5, 0.0), Point::new_with(0.5, -0.5, 0.0),
+                 Point::new_with(0.5, 0.5, 0.0), Point::new_with(-0.5, 0.5, 0.0)];
+        MeshData::new(&[0, 1, 2, 0, 2, 3], &p, None, None, None)
+    }
+
+    #[test]
+    fn it_displaces_along_normals() {
+        let disp = Displacement::new(Arc::new(Texture::new(0.5)), None);
+        let quad = quad_with_uvs().displace(&disp, &Transform::new());
+        assert_eq!(quad.p.len(), 4);
+        assert_eq!(quad.num_triangles(), 2);
+        assert!(quad.p.iter().all(|p| p.z == 0.5));
+        assert!(quad.n.unwrap().iter().all(|n| (n.z - 1.0).abs() < 1e-6));
+
+        // The corners of a closed cube move out along the diagonals
+        let center = Point::new_with(0.5, 0.5, 0.5);
+        let disp = Displacement::new(Arc::new(Texture::new(0.25)), None);
+        let cube = split_cube().weld(1e-4).displace(&disp, &Transform::new());
+        assert_eq!(cube.p.len(), 8);
+        for p in cube.p.iter() {
+            assert!((p.distance(&center) - (0.75f32.sqrt() + 0.25)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn it_tessellates_displacements_in_screen_space() {
+        let dicing = Dicing::new(&test_camera(), 4.0, 16).unwrap();
+        let disp = Displacement::new(Arc::new(Texture::new(0.0)), Some(dicing.clone()));
+        let near_xf = Transform::translate(&Vector::new_with(0.0, 0.0, 2.0));
+        let near = unit_square().displace(&disp, &near_xf);
+
+        // Every edge ends up under the target
+        let mut edges = HashMap::new();
+        for t in 0..near.num_triangles() {
+            let tri = near.triangle(t);
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                assert!(dicing.raster_length(&near_xf.t(&near.p[a]),
+                                             &near_xf.t(&near.p[b])) <= 4.0);
+                *edges.entry(if a < b { (a, b) } else { (b, a) }).or_insert(0) += 1;
+            }
+        }
+        assert!(near.num_triangles() > 100);
+
+        // There are no cracks: only the edges on the outside of the
+        // square are used by a single triangle
+        let outline = edges.iter().filter(|&(_, &count)| count == 1)
+            .fold(0.0, |l, (&(a, b), _)| l + near.p[a].distance(&near.p[b]));
+        assert!((outline - 4.0).abs() < 1e-5);
+        assert!(edges.values().all(|&count| count <= 2));
+
+        // Nothing moves and the triangles keep facing the same way
+        assert!(near.p.iter().all(|p| p.z == 0.0));
+        assert!(near.n.unwrap().iter().all(|n| (n.z - 1.0).abs() < 1e-6));
+
+        // Far away the square is small enough to leave alone
+        let far_xf = Transform::translate(&Vector::new_with(0.0, 0.0, 20.0));
+        assert_eq!(unit_square().displace(&disp, &far_xf).num_triangles(), 2);
+
+        // The number of splits is capped
+        let shallow = Displacement::new(Arc::new(Texture::new(0.0)),
+                                        Dicing::new(&test_camera(), 4.0, 2));
+        assert_eq!(unit_square().displace(&shallow, &near_xf).num_triangles(), 32);
+    }
+}
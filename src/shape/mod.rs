@@ -1,9 +1,19 @@
 mod helpers;
 
+mod catmullclark;
+mod cone;
+mod curve;
 mod cylinder;
 mod disk;
+mod displacement;
+mod heightfield;
+mod hyperboloid;
 mod loopsubdiv;
 mod mesh;
+mod mesh_processing;
+mod nurbs;
+mod paraboloid;
+mod ply;
 mod sphere;
 
 use std::sync::Arc;
@@ -23,11 +33,26 @@ use texture::Texture;
 use transform::transform::Transform;
 
 use shape::sphere::Sphere;
+use shape::cone::Cone;
 use shape::cylinder::Cylinder;
+use shape::hyperboloid::Hyperboloid;
+use shape::paraboloid::Paraboloid;
+use shape::curve::Curve;
+pub use shape::curve::CurveType;
 use shape::disk::Disk;
+use shape::heightfield::Heightfield;
+use shape::nurbs::Nurbs;
 use shape::mesh::Triangle;
 use shape::mesh::Mesh;
+pub use shape::mesh_processing::MeshData;
+pub use shape::displacement::Dicing;
+pub use shape::displacement::Displacement;
+pub use shape::ply::PlyError;
+pub use shape::ply::load_ply;
+pub use shape::ply::read_ply;
 use shape::loopsubdiv::LoopSubdiv;
+use shape::catmullclark::CatmullClark;
+pub use shape::catmullclark::SubdivTag;
 
 #[derive(Debug, Clone)]
 pub struct ShapeBase {
@@ -66,16 +91,17 @@ impl ::std::cmp::PartialEq for ShapeBase {
 #[derive(Debug, PartialEq, Clone)]
 pub struct ShapeIntersection {
     pub t_hit: f32,
-    pub ray_epsilon: f32,
+    // Conservative bound on the world space error in dg.p
+    pub p_error: Vector,
     pub dg: DifferentialGeometry
 }
 
 impl ShapeIntersection {
-    pub fn new(t: f32, eps: f32, dgeom: DifferentialGeometry)
+    pub fn new(t: f32, err: Vector, dgeom: DifferentialGeometry)
            -> ShapeIntersection {
         ShapeIntersection {
             t_hit: t,
-            ray_epsilon: eps,
+            p_error: err,
             dg: dgeom
         }
     }
@@ -86,9 +112,16 @@ pub enum Shape {
     Sphere(Sphere),
     Disk(Disk),
     Cylinder(Cylinder),
+    Cone(Cone),
+    Paraboloid(Paraboloid),
+    Hyperboloid(Hyperboloid),
+    Curve(Curve),
+    Heightfield(Heightfield),
     Triangle(Triangle),
     TriangleMesh(Mesh),
-    LoopSubdiv(LoopSubdiv)
+    LoopSubdiv(LoopSubdiv),
+    CatmullClark(CatmullClark),
+    Nurbs(Nurbs)
 }
 
 impl HasBounds for Shape {
@@ -97,9 +130,16 @@ impl HasBounds for Shape {
             &Shape::Sphere(ref s) => s.world_bound(),
             &Shape::Disk(ref d) => d.world_bound(),
             &Shape::Cylinder(ref c) => c.world_bound(),
+            &Shape::Cone(ref c) => c.world_bound(),
+            &Shape::Paraboloid(ref p) => p.world_bound(),
+            &Shape::Hyperboloid(ref h) => h.world_bound(),
+            &Shape::Curve(ref c) => c.world_bound(),
+            &Shape::Heightfield(ref h) => h.world_bound(),
             &Shape::Triangle(ref t) => t.world_bound(),
             &Shape::TriangleMesh(ref m) => m.world_bound(),
-            &Shape::LoopSubdiv(ref m) => m.world_bound()
+            &Shape::LoopSubdiv(ref m) => m.world_bound(),
+            &Shape::CatmullClark(ref m) => m.world_bound(),
+            &Shape::Nurbs(ref n) => n.world_bound()
         }
     }
 }
@@ -111,9 +151,16 @@ impl Refinable for Shape {
             &Shape::Sphere(_) => true,
             &Shape::Disk(_) => true,
             &Shape::Cylinder(_) => true,
+            &Shape::Cone(_) => true,
+            &Shape::Paraboloid(_) => true,
+            &Shape::Hyperboloid(_) => true,
+            &Shape::Curve(_) => true,
+            &Shape::Heightfield(_) => true,
             &Shape::Triangle(_) => true,
             &Shape::TriangleMesh(_) => false,
-            &Shape::LoopSubdiv(_) => false
+            &Shape::LoopSubdiv(_) => false,
+            &Shape::CatmullClark(_) => false,
+            &Shape::Nurbs(_) => false
         }
     }
 
@@ -122,9 +169,16 @@ impl Refinable for Shape {
             Shape::Sphere(s) => vec![Shape::Sphere(s)],
             Shape::Disk(d) => vec![Shape::Disk(d)],
             Shape::Cylinder(c) => vec![Shape::Cylinder(c)],
+            Shape::Cone(c) => vec![Shape::Cone(c)],
+            Shape::Paraboloid(p) => vec![Shape::Paraboloid(p)],
+            Shape::Hyperboloid(h) => vec![Shape::Hyperboloid(h)],
+            Shape::Curve(c) => vec![Shape::Curve(c)],
+            Shape::Heightfield(h) => vec![Shape::Heightfield(h)],
             Shape::Triangle(t) => vec![Shape::Triangle(t)],
             Shape::TriangleMesh(m) => m.refine().iter().cloned().map(Shape::Triangle).collect(),
-            Shape::LoopSubdiv(m) => m.refine().iter().cloned().map(Shape::TriangleMesh).collect()
+            Shape::LoopSubdiv(m) => m.refine().iter().cloned().map(Shape::TriangleMesh).collect(),
+            Shape::CatmullClark(m) => m.refine().iter().cloned().map(Shape::TriangleMesh).collect(),
+            Shape::Nurbs(n) => n.refine().iter().cloned().map(Shape::TriangleMesh).collect()
         }
     }
 }
@@ -137,9 +191,16 @@ impl Intersectable<ShapeIntersection> for Shape {
             &Shape::Sphere(ref s) => s.intersect(ray),
             &Shape::Disk(ref d) => d.intersect(ray),
             &Shape::Cylinder(ref c) => c.intersect(ray),
+            &Shape::Cone(ref c) => c.intersect(ray),
+            &Shape::Paraboloid(ref p) => p.intersect(ray),
+            &Shape::Hyperboloid(ref h) => h.intersect(ray),
+            &Shape::Curve(ref c) => c.intersect(ray),
+            &Shape::Heightfield(ref h) => h.intersect(ray),
             &Shape::Triangle(ref t) => t.intersect(ray),
             &Shape::TriangleMesh(_) => None,
-            &Shape::LoopSubdiv(_) => None
+            &Shape::LoopSubdiv(_) => None,
+            &Shape::CatmullClark(_) => None,
+            &Shape::Nurbs(_) => None
         }
     }
 
@@ -148,9 +209,16 @@ impl Intersectable<ShapeIntersection> for Shape {
             &Shape::Sphere(ref s) => s.intersect_p(ray),
             &Shape::Disk(ref d) => d.intersect_p(ray),
             &Shape::Cylinder(ref c) => c.intersect_p(ray),
+            &Shape::Cone(ref c) => c.intersect_p(ray),
+            &Shape::Paraboloid(ref p) => p.intersect_p(ray),
+            &Shape::Hyperboloid(ref h) => h.intersect_p(ray),
+            &Shape::Curve(ref c) => c.intersect_p(ray),
+            &Shape::Heightfield(ref h) => h.intersect_p(ray),
             &Shape::Triangle(ref t) => t.intersect_p(ray),
             &Shape::TriangleMesh(_) => false,
-            &Shape::LoopSubdiv(_) => false
+            &Shape::LoopSubdiv(_) => false,
+            &Shape::CatmullClark(_) => false,
+            &Shape::Nurbs(_) => false
         }
     }
 }
@@ -161,9 +229,16 @@ impl Shape {
             &Shape::Sphere(ref s) => s.base(),
             &Shape::Disk(ref d) => d.base(),
             &Shape::Cylinder(ref c) => c.base(),
+            &Shape::Cone(ref c) => c.base(),
+            &Shape::Paraboloid(ref p) => p.base(),
+            &Shape::Hyperboloid(ref h) => h.base(),
+            &Shape::Curve(ref c) => c.base(),
+            &Shape::Heightfield(ref h) => h.base(),
             &Shape::Triangle(ref t) => t.base(),
             &Shape::TriangleMesh(ref m) => m.base(),
-            &Shape::LoopSubdiv(ref m) => m.base()
+            &Shape::LoopSubdiv(ref m) => m.base(),
+            &Shape::CatmullClark(ref m) => m.base(),
+            &Shape::Nurbs(ref n) => n.base()
         }
     }
 
@@ -177,6 +252,41 @@ impl Shape {
         Shape::Cylinder( Cylinder::new(o2w, w2o, ro, rad, z0, z1, pm) )
     }
 
+    pub fn cone(o2w: Transform, w2o: Transform, ro: bool,
+                height: f32, rad: f32, pm: f32) -> Shape {
+        Shape::Cone( Cone::new(o2w, w2o, ro, height, rad, pm) )
+    }
+
+    pub fn paraboloid(o2w: Transform, w2o: Transform, ro: bool,
+                      rad: f32, z0: f32, z1: f32, pm: f32) -> Shape {
+        Shape::Paraboloid( Paraboloid::new(o2w, w2o, ro, rad, z0, z1, pm) )
+    }
+
+    pub fn hyperboloid(o2w: Transform, w2o: Transform, ro: bool,
+                       p1: Point, p2: Point, pm: f32) -> Shape {
+        Shape::Hyperboloid( Hyperboloid::new(o2w, w2o, ro, p1, p2, pm) )
+    }
+
+    pub fn curves(o2w: Transform, w2o: Transform, ro: bool, cp: [Point; 4],
+                  w0: f32, w1: f32, ty: CurveType, n: Option<[Normal; 2]>,
+                  split_depth: usize) -> Vec<Shape> {
+        Curve::create_segments(o2w, w2o, ro, cp, w0, w1, ty, n, split_depth)
+            .into_iter().map(Shape::Curve).collect()
+    }
+
+    pub fn heightfield(o2w: Transform, w2o: Transform, ro: bool,
+                       nx: usize, ny: usize, z: &[f32]) -> Shape {
+        Shape::Heightfield( Heightfield::new(o2w, w2o, ro, nx, ny, z) )
+    }
+
+    pub fn nurbs(o2w: Transform, w2o: Transform, ro: bool,
+                 nu: usize, u_order: usize, u_knot: &[f32], u0: f32, u1: f32,
+                 nv: usize, v_order: usize, v_knot: &[f32], v0: f32, v1: f32,
+                 p: &[Point], w: Option<&[f32]>) -> Shape {
+        Shape::Nurbs( Nurbs::new(o2w, w2o, ro, nu, u_order, u_knot, u0, u1,
+                                 nv, v_order, v_knot, v0, v1, p, w) )
+    }
+
     pub fn disk(o2w: Transform, w2o: Transform, ro: bool,
                 ht: f32, r: f32, ri: f32, t_max: f32) -> Shape {
         Shape::Disk( Disk::new(o2w, w2o, ro, ht, r, ri, t_max) )
@@ -188,19 +298,55 @@ impl Shape {
         Shape::TriangleMesh( Mesh::new(o2w, w2o, ro, vi, _p, _n, _s, uv, _atex) )
     }
 
+    pub fn processed_triangle_mesh(o2w: Transform, w2o: Transform, ro: bool,
+                                   data: MeshData,
+                                   atex: Option<Arc<Texture<f32>>>) -> Shape {
+        Shape::TriangleMesh( data.into_mesh(o2w, w2o, ro, atex) )
+    }
+
+    // Loads the triangles of a plymesh from disk
+    pub fn ply_mesh(o2w: Transform, w2o: Transform, ro: bool,
+                    filename: &str) -> Result<Shape, PlyError> {
+        let data = try!(load_ply(filename));
+        Ok(Shape::triangle_mesh(o2w, w2o, ro, &data.vertex_index, &data.p,
+                                data.n.as_ref().map(|n| &n[..]), None,
+                                data.uvs.as_ref().map(|uv| &uv[..]), None))
+    }
+
     pub fn loop_subdiv(o2w: Transform, w2o: Transform, ro: bool,
                        vertex_indices: &[usize], points: &[Point], nl: usize) -> Shape {
         Shape::LoopSubdiv( LoopSubdiv::new(o2w, w2o, ro, vertex_indices, points, nl) )
     }
 
+    pub fn displaced_loop_subdiv(o2w: Transform, w2o: Transform, ro: bool,
+                                 vertex_indices: &[usize], points: &[Point], nl: usize,
+                                 disp: Displacement) -> Shape {
+        Shape::LoopSubdiv(
+            LoopSubdiv::new_with(o2w, w2o, ro, vertex_indices, points, nl, Some(disp)) )
+    }
+
+    pub fn catmull_clark(o2w: Transform, w2o: Transform, ro: bool, nverts: &[usize],
+                         vertex_indices: &[usize], points: &[Point], nl: usize,
+                         tags: &[SubdivTag]) -> Shape {
+        Shape::CatmullClark(
+            CatmullClark::new(o2w, w2o, ro, nverts, vertex_indices, points, nl, tags) )
+    }
+
     pub fn object_bound(&self) -> BBox {
         match self {
             &Shape::Sphere(ref s) => s.object_bound(),
             &Shape::Disk(ref d) => d.object_bound(),
             &Shape::Cylinder(ref c) => c.object_bound(),
+            &Shape::Cone(ref c) => c.object_bound(),
+            &Shape::Paraboloid(ref p) => p.object_bound(),
+            &Shape::Hyperboloid(ref h) => h.object_bound(),
+            &Shape::Curve(ref c) => c.object_bound(),
+            &Shape::Heightfield(ref h) => h.object_bound(),
             &Shape::Triangle(ref t) => t.object_bound(),
             &Shape::TriangleMesh(ref m) => m.object_bound(),
-            &Shape::LoopSubdiv(ref m) => m.object_bound()
+            &Shape::LoopSubdiv(ref m) => m.object_bound(),
+            &Shape::CatmullClark(ref m) => m.object_bound(),
+            &Shape::Nurbs(ref n) => n.object_bound()
         }
     }
 
@@ -213,11 +359,25 @@ impl Shape {
         }
     }
 
+    // A bound on the part of the shape inside of b. Only triangles do
+    // better than clipping their world bound.
+    pub fn clipped_bound(&self, b: &BBox) -> BBox {
+        match self {
+            &Shape::Triangle(ref t) => t.clipped_bound(b),
+            _ => self.world_bound().clipped_to(b)
+        }
+    }
+
     pub fn area(&self) -> f32 {
         match self {
             &Shape::Sphere(ref s) => s.area(),
             &Shape::Disk(ref d) => d.area(),
             &Shape::Cylinder(ref c) => c.area(),
+            &Shape::Cone(ref c) => c.area(),
+            &Shape::Paraboloid(ref p) => p.area(),
+            &Shape::Hyperboloid(ref h) => h.area(),
+            &Shape::Curve(ref c) => c.area(),
+            &Shape::Heightfield(ref h) => h.area(),
             &Shape::Triangle(ref t) => t.area(),
             _ => self.clone().refine().iter().fold(0f32, |a, t| a + t.area())
         }
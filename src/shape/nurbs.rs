@@ -0,0 +1,324 @@
+use bbox::BBox;
+use bbox::HasBounds;
+use bbox::Union;
+use geometry::normal::Normal;
+use geometry::normal::Normalize;
+use geometry::point::Point;
+use geometry::vector::Cross;
+use geometry::vector::Vector;
+use primitive::Refinable;
+use shape::ShapeBase;
+use shape::mesh::Mesh;
+use shape::mesh_processing::MeshData;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Lerp;
+
+// Number of samples along each parametric direction when tessellating
+const DICE: usize = 30;
+
+// Control points are kept in homogeneous coordinates (x w, y w, z w, w)
+type Homogeneous = [f32; 4];
+
+// Finds the knot span that contains t
+fn knot_offset(knot: &[f32], order: usize, np: usize, t: f32) -> usize {
+    let mut offset = order - 1;
+    while offset + 1 < np && t > knot[offset + 1] {
+        offset += 1;
+    }
+    offset
+}
+
+// Evaluates the curve with np control points, as returned by cp, at t
+// using de Boor's algorithm. Also returns the derivative of the
+// projected curve.
+fn nurbs_evaluate<F>(order: usize, knot: &[f32], cp: F, np: usize, t: f32)
+                     -> (Homogeneous, Vector) where F: Fn(usize) -> Homogeneous {
+    let ko = knot_offset(knot, order, np, t);
+    let first_cp = ko + 1 - order;
+    let lerp = |a: &Homogeneous, b: &Homogeneous, alpha: f32| {
+        [a[0] * alpha + b[0] * (1.0 - alpha), a[1] * alpha + b[1] * (1.0 - alpha),
+         a[2] * alpha + b[2] * (1.0 - alpha), a[3] * alpha + b[3] * (1.0 - alpha)]
+    };
+
+    let mut work: Vec<Homogeneous> = (0..order).map(|i| cp(first_cp + i)).collect();
+    for i in 0..(order - 2) {
+        for j in 0..(order - 1 - i) {
+            let alpha = (knot[ko + 1 + j] - t) /
+                (knot[ko + 1 + j] - knot[ko + j + 2 + i - order]);
+            work[j] = lerp(&work[j], &work[j + 1], alpha);
+        }
+    }
+
+    let span = knot[ko + 1] - knot[ko];
+    let val = lerp(&work[0], &work[1], (knot[ko + 1] - t) / span);
+
+    // Differentiate the last step of the recurrence and apply the
+    // quotient rule for the division by w
+    let factor = ((order - 1) as f32) / span;
+    let delta: Vec<f32> = (0..4).map(|i| (work[1][i] - work[0][i]) * factor).collect();
+    let w2 = val[3] * val[3];
+    let deriv = Vector::new_with(delta[0] / val[3] - val[0] * delta[3] / w2,
+                                 delta[1] / val[3] - val[1] * delta[3] / w2,
+                                 delta[2] / val[3] - val[2] * delta[3] / w2);
+    (val, deriv)
+}
+
+// A rational B-spline patch with nu x nv control points, where u runs
+// fastest through the control points. The patch is tessellated into a
+// Mesh, with normals and tangents taken from the exact partial
+// derivatives of the surface.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Nurbs {
+    base: ShapeBase,
+    nu: usize,
+    u_order: usize,
+    u_knot: Vec<f32>,
+    u_min: f32,
+    u_max: f32,
+    nv: usize,
+    v_order: usize,
+    v_knot: Vec<f32>,
+    v_min: f32,
+    v_max: f32,
+    p: Vec<Homogeneous>
+}
+
+impl Nurbs {
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               nu: usize, u_order: usize, u_knot: &[f32], u0: f32, u1: f32,
+               nv: usize, v_order: usize, v_knot: &[f32], v0: f32, v1: f32,
+               p: &[Point], w: Option<&[f32]>) -> Nurbs {
+        assert!(u_order >= 2 && v_order >= 2);
+        assert!(nu >= u_order && nv >= v_order);
+        assert_eq!(u_knot.len(), nu + u_order);
+        assert_eq!(v_knot.len(), nv + v_order);
+        assert_eq!(p.len(), nu * nv);
+
+        let p = p.iter().enumerate().map(|(i, pt)| {
+            let wt = w.map_or(1.0, |w| w[i]);
+            [pt.x * wt, pt.y * wt, pt.z * wt, wt]
+        }).collect();
+
+        Nurbs {
+            base: ShapeBase::new(o2w, w2o, ro),
+            nu: nu,
+            u_order: u_order,
+            u_knot: u_knot.to_vec(),
+            u_min: u0,
+            u_max: u1,
+            nv: nv,
+            v_order: v_order,
+            v_knot: v_knot.to_vec(),
+            v_min: v0,
+            v_max: v1,
+            p: p
+        }
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    fn control_points(&self) -> Vec<Point> {
+        self.p.iter().map(|p| Point::new_with(p[0] / p[3], p[1] / p[3], p[2] / p[3])).collect()
+    }
+
+    // The patch lies within the convex hull of its control points
+    pub fn object_bound(&self) -> BBox {
+        self.control_points().iter().fold(BBox::new(), |b, p| b.unioned_with_ref(p))
+    }
+
+    // Returns the point on the surface at (u, v) along with dp/du and dp/dv
+    pub fn evaluate(&self, u: f32, v: f32) -> (Point, Vector, Vector) {
+        // Evaluate the curves along v through each column of control
+        // points that affects u, and then the resulting curve along u
+        let u_first = knot_offset(&self.u_knot, self.u_order, self.nu, u) + 1 - self.u_order;
+        let iso: Vec<Homogeneous> = (0..self.u_order).map(|i| {
+            nurbs_evaluate(self.v_order, &self.v_knot,
+                           |j| self.p[j * self.nu + u_first + i], self.nv, v).0
+        }).collect();
+        let (p, dpdu) = nurbs_evaluate(self.u_order, &self.u_knot,
+                                       |i| iso[i - u_first], self.nu, u);
+
+        // Do the same the other way around for dp/dv
+        let v_first = knot_offset(&self.v_knot, self.v_order, self.nv, v) + 1 - self.v_order;
+        let iso: Vec<Homogeneous> = (0..self.v_order).map(|j| {
+            nurbs_evaluate(self.u_order, &self.u_knot,
+                           |i| self.p[(v_first + j) * self.nu + i], self.nu, u).0
+        }).collect();
+        let (_, dpdv) = nurbs_evaluate(self.v_order, &self.v_knot,
+                                       |j| iso[j - v_first], self.nv, v);
+
+        (Point::new_with(p[0] / p[3], p[1] / p[3], p[2] / p[3]), dpdu, dpdv)
+    }
+
+    // Samples the patch on a regular grid over its parametric range
+    fn tessellate(&self) -> MeshData {
+        let mut p = Vec::with_capacity(DICE * DICE);
+        let mut n = Vec::with_capacity(DICE * DICE);
+        let mut s = Vec::with_capacity(DICE * DICE);
+        let mut uvs = Vec::with_capacity(2 * DICE * DICE);
+        for j in 0..DICE {
+            let v = self.v_min.lerp(&self.v_max, (j as f32) / ((DICE - 1) as f32));
+            for i in 0..DICE {
+                let u = self.u_min.lerp(&self.u_max, (i as f32) / ((DICE - 1) as f32));
+                let (pt, dpdu, dpdv) = self.evaluate(u, v);
+                let nn = dpdu.clone().into_cross(dpdv);
+                n.push(Normal::from(if nn.length_squared() > 0.0 { nn.normalize() } else { nn }));
+                s.push(if dpdu.length_squared() > 0.0 { dpdu.normalize() } else { dpdu });
+                p.push(pt);
+                uvs.push(u);
+                uvs.push(v);
+            }
+        }
+
+        let vert = |i: usize, j: usize| j * DICE + i;
+        let mut indices = Vec::with_capacity(6 * (DICE - 1) * (DICE - 1));
+        for j in 0..(DICE - 1) {
+            for i in 0..(DICE - 1) {
+                indices.extend_from_slice(&[vert(i, j), vert(i + 1, j), vert(i + 1, j + 1),
+                                            vert(i, j), vert(i + 1, j + 1), vert(i, j + 1)]);
+            }
+        }
+
+        MeshData::new(&indices, &p, Some(&n[..]), Some(&s[..]), Some(&uvs[..]))
+    }
+}
+
+impl Refinable<Mesh> for Nurbs {
+    fn is_refined(&self) -> bool { false }
+    fn refine(self) -> Vec<Mesh> {
+        let data = self.tessellate();
+        vec![data.into_mesh(self.base.object2world.clone(),
+                            self.base.world2object.clone(),
+                            self.base.reverse_orientation, None)]
+    }
+}
+
+impl HasBounds for Nurbs {
+    fn world_bound(&self) -> BBox {
+        let o2w = &self.base().object2world;
+        self.control_points().into_iter().fold(BBox::new(), |b, p| b.unioned_with(o2w.xf(p)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bbox::BBox;
+    use geometry::point::Point;
+    use geometry::vector::Dot;
+    use geometry::vector::Vector;
+    use primitive::Refinable;
+    use transform::transform::Transform;
+
+    // A quarter of a unit cylinder around z, from z = 0 to z = 1, with
+    // an exact circular arc along u
+    fn quarter_cylinder() -> Nurbs {
+        let s = 0.5f32.sqrt();
+        let p = [Point::new_with(1.0, 0.0, 0.0), Point::new_with(1.0, 1.0, 0.0),
+                 Point::new_with(0.0, 1.0, 0.0), Point::new_with(1.0, 0.0, 1.0),
+                 Point::new_with(1.0, 1.0, 1.0), Point::new_with(0.0, 1.0, 1.0)];
+        Nurbs::new(Transform::new(), Transform::new(), false,
+                   3, 3, &[0.0, 0.0, 0.0, 1.0, 1.0, 1.0], 0.0, 1.0,
+                   2, 2, &[0.0, 0.0, 1.0, 1.0], 0.0, 1.0,
+                   &p, Some(&[1.0, s, 1.0, 1.0, s, 1.0]))
+    }
+
+    fn bilinear() -> Nurbs {
+        let p = [Point::new_with(0.0, 0.0, 0.0), Point::new_with(1.0, 0.0, 0.0),
+                 Point::new_with(0.0, 1.0, 0.0), Point::new_with(1.0, 1.0, 0.0)];
+        Nurbs::new(Transform::new(), Transform::new(), false,
+                   2, 2, &[0.0, 0.0, 1.0, 1.0], 0.0, 1.0,
+                   2, 2, &[0.0, 0.0, 1.0, 1.0], 0.0, 1.0, &p, None)
+    }
+
+    #[test]
+    fn it_can_be_created() {
+        let patch = bilinear();
+        assert_eq!(patch.nu, 2);
+        assert_eq!(patch.v_order, 2);
+        assert_eq!(patch.p[3], [1.0, 1.0, 0.0, 1.0]);
+
+        // Weights are premultiplied
+        assert_eq!(quarter_cylinder().p[1][3], 0.5f32.sqrt());
+        assert_eq!(quarter_cylinder().p[1][0], 0.5f32.sqrt());
+    }
+
+    #[test]
+    fn it_has_bounds() {
+        assert_eq!(quarter_cylinder().object_bound(),
+                   BBox::new_with(Point::new_with(0.0, 0.0, 0.0),
+                                  Point::new_with(1.0, 1.0, 1.0)));
+
+        let xf = Transform::translate(&Vector::new_with(1.0, 2.0, 3.0));
+        let moved = Nurbs { base: ShapeBase::new(xf.clone(), xf.inverse(), false),
+                            .. bilinear() };
+        assert_eq!(moved.world_bound(),
+                   BBox::new_with(Point::new_with(1.0, 2.0, 3.0),
+                                  Point::new_with(2.0, 3.0, 3.0)));
+    }
+
+    #[test]
+    fn it_evaluates_bilinear_patches() {
+        let (p, dpdu, dpdv) = bilinear().evaluate(0.3, 0.7);
+        assert!((p - Point::new_with(0.3, 0.7, 0.0)).length_squared() < 1e-12);
+        assert!((dpdu - Vector::new_with(1.0, 0.0, 0.0)).length_squared() < 1e-12);
+        assert!((dpdv - Vector::new_with(0.0, 1.0, 0.0)).length_squared() < 1e-12);
+    }
+
+    #[test]
+    fn it_evaluates_rational_patches() {
+        let patch = quarter_cylinder();
+        for i in 0..11 {
+            for j in 0..3 {
+                let (u, v) = ((i as f32) / 10.0, (j as f32) / 2.0);
+                let (p, dpdu, dpdv) = patch.evaluate(u, v);
+
+                // Every point is on the cylinder and the derivatives are
+                // tangent to it
+                assert!(((p.x * p.x + p.y * p.y).sqrt() - 1.0).abs() < 1e-5);
+                assert!((p.z - v).abs() < 1e-6);
+                assert!(Vector::new_with(p.x, p.y, 0.0).dot(&dpdu).abs() < 1e-5);
+                assert!((dpdv - Vector::new_with(0.0, 0.0, 1.0)).length_squared() < 1e-10);
+
+                // ... and match finite differences
+                let h = 1e-3;
+                let (p2, _, _) = patch.evaluate(u.min(1.0 - h) + h, v);
+                let (p1, _, _) = patch.evaluate(u.min(1.0 - h), v);
+                assert!(((p2 - p1) / h - dpdu).length() < 1e-2);
+            }
+        }
+
+        // The end tangents of a circular arc are scaled by the weights
+        let (_, dpdu, _) = patch.evaluate(0.0, 0.0);
+        assert!((dpdu - Vector::new_with(0.0, 2f32.sqrt(), 0.0)).length_squared() < 1e-10);
+    }
+
+    #[test]
+    fn it_tessellates_with_exact_normals() {
+        let mesh = quarter_cylinder().tessellate();
+        assert_eq!(mesh.p.len(), DICE * DICE);
+        assert_eq!(mesh.num_triangles(), 2 * (DICE - 1) * (DICE - 1));
+
+        let n = mesh.n.as_ref().unwrap();
+        let s = mesh.s.as_ref().unwrap();
+        let uvs = mesh.uvs.as_ref().unwrap();
+        for (i, p) in mesh.p.iter().enumerate() {
+            let radial = Vector::new_with(p.x, p.y, 0.0);
+            assert!((radial.length() - 1.0).abs() < 1e-5);
+            assert!((Vector::from(&n[i]) - &radial).length_squared() < 1e-8);
+            assert!(s[i].dot(&radial).abs() < 1e-5);
+            assert!((p.z - uvs[2 * i + 1]).abs() < 1e-6);
+        }
+        assert_eq!(uvs[2 * DICE * DICE - 2], 1.0);
+        assert_eq!(uvs[2 * DICE * DICE - 1], 1.0);
+    }
+
+    #[test]
+    fn it_can_be_refined() {
+        let meshes = quarter_cylinder().refine();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].clone().refine().len(), 2 * (DICE - 1) * (DICE - 1));
+    }
+}
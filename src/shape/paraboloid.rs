@@ -0,0 +1,307 @@
+use std::f32::consts::PI;
+
+use bbox::BBox;
+use bbox::HasBounds;
+use geometry::point::Point;
+use geometry::vector::Vector;
+use intersection::Intersectable;
+use ray::Ray;
+use shape::ShapeBase;
+use shape::ShapeIntersection;
+use transform::transform::ApplyTransform;
+use transform::transform::Transform;
+use utils::Clamp;
+use utils::Degrees;
+
+use shape::helpers::compute_dg;
+use shape::helpers::reproject_onto_radius;
+
+// A paraboloid around the z axis, z = z_max * (x^2 + y^2) / radius^2,
+// clipped to the range [z_min, z_max].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Paraboloid {
+    base: ShapeBase,
+    radius: f32,
+    z_min: f32,
+    z_max: f32,
+    phi_max: f32
+}
+
+impl Paraboloid {
+    pub fn new(o2w: Transform, w2o: Transform, ro: bool,
+               rad: f32, z0: f32, z1: f32, pm: f32) -> Paraboloid {
+        Paraboloid {
+            base: ShapeBase::new(o2w, w2o, ro),
+            radius: rad,
+            z_min: z0.min(z1),
+            z_max: z0.max(z1),
+            phi_max: pm.clamp(0.0, 360.0).as_radians()
+        }
+    }
+
+    fn get_intersection_point(&self, r: &Ray) -> Option<(f32, f32)> {
+        // Compute quadratic paraboloid coefficients
+        let k = self.z_max / (self.radius * self.radius);
+        let a = k * (r.d.x * r.d.x + r.d.y * r.d.y);
+        let b = 2.0 * k * (r.d.x * r.o.x + r.d.y * r.o.y) - r.d.z;
+        let c = k * (r.o.x * r.o.x + r.o.y * r.o.y) - r.o.z;
+
+        // Solve quadratic equation for t values. Rays parallel to the
+        // axis only cross the paraboloid once.
+        let (t0, t1) = {
+            if a == 0.0 {
+                if b == 0.0 { return None; }
+                (-c / b, -c / b)
+            } else {
+                match ::utils::quadratic(a, b, c) {
+                    None => return None,
+                    Some((x, y)) => (x, y)
+                }
+            }
+        };
+
+        // Compute intersection distance along ray
+        if t0 > r.maxt() || t1 < r.mint() {
+            return None
+        }
+
+        let mut t_hit = t0;
+        if t0 < r.mint() {
+            t_hit = t1;
+            if t_hit > r.maxt() {
+                return None;
+            }
+        }
+
+        // Compute paraboloid hit point and phi
+        let get_hit = |t: f32| {
+            let hit = r.point_at(t);
+            let mut angle = hit.y.atan2(hit.x);
+            if angle < 0.0 {
+                angle = angle + 2.0 * PI;
+            }
+            (hit, angle)
+        };
+
+        let invalid_hit = |hit: &(Point, f32)| {
+            hit.0.z < self.z_min || hit.0.z > self.z_max || hit.1 > self.phi_max
+        };
+
+        // Test paraboloid intersection against clipping parameters
+        let mut p_hit = get_hit(t_hit);
+        if invalid_hit(&p_hit) {
+            if t_hit == t1 { return None; }
+            if t1 > r.maxt() { return None; }
+            t_hit = t1;
+            p_hit = get_hit(t_hit);
+            if invalid_hit(&p_hit) { return None; }
+        }
+
+        return Some((t_hit, p_hit.1))
+    }
+
+    pub fn base<'a>(&'a self) -> &'a ShapeBase { &self.base }
+
+    pub fn object_bound(&self) -> BBox {
+        BBox::new_with(
+            Point::new_with(-self.radius, -self.radius, self.z_min),
+            Point::new_with(self.radius, self.radius, self.z_max))
+    }
+
+    pub fn area(&self) -> f32 {
+        // Integrate the surface of revolution of r(z) = radius * sqrt(z / z_max)
+        let radius2 = self.radius * self.radius;
+        let k = 4.0 * self.z_max / radius2;
+        (radius2 * radius2 * self.phi_max / (12.0 * self.z_max * self.z_max)) *
+            ((k * self.z_max + 1.0).powf(1.5) - (k * self.z_min + 1.0).powf(1.5))
+    }
+}
+
+impl HasBounds for Paraboloid {
+    fn world_bound(&self) -> BBox {
+        self.base().object2world.xf(self.object_bound())
+    }
+}
+
+impl Intersectable<ShapeIntersection> for Paraboloid {
+    fn intersect_p(&self, r: &Ray) -> bool {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+        self.get_intersection_point(&ray).is_some()
+    }
+
+    fn intersect(&self, r: &Ray) -> Option<ShapeIntersection> {
+        // Transform ray to object space
+        let ray = self.base().world2object.t(r);
+
+        let (t_hit, phi) = {
+            let hit = self.get_intersection_point(&ray);
+            if hit.is_some() { hit.unwrap() } else { return None; }
+        };
+
+        // Refine paraboloid intersection point
+        let (p_hit, p_error) = {
+            let p = ray.point_at(t_hit);
+            let r = self.radius * (p.z / self.z_max).max(0.0).sqrt();
+            reproject_onto_radius(p, r, 6)
+        };
+
+        // Find parametric representation of paraboloid hit
+        let u = phi / self.phi_max;
+        let v = (p_hit.z - self.z_min) / (self.z_max - self.z_min);
+
+        // Compute paraboloid dpdu and dpdv
+        let dz = self.z_max - self.z_min;
+        let dpdu = self.phi_max * Vector::new_with(-p_hit.y, p_hit.x, 0.0);
+        let dpdv = dz * Vector::new_with(p_hit.x / (2.0 * p_hit.z),
+                                         p_hit.y / (2.0 * p_hit.z), 1.0);
+
+        // Compute paraboloid dndu and dndv
+        let d2pduu = -self.phi_max * self.phi_max *
+            Vector::new_with(p_hit.x, p_hit.y, 0.0);
+        let d2pduv = dz * self.phi_max *
+            Vector::new_with(-p_hit.y / (2.0 * p_hit.z), p_hit.x / (2.0 * p_hit.z), 0.0);
+        let d2pdvv = -dz * dz *
+            Vector::new_with(p_hit.x / (4.0 * p_hit.z * p_hit.z),
+                             p_hit.y / (4.0 * p_hit.z * p_hit.z), 0.0);
+
+        // Initialize DifferentialGeometry from parametric information
+        let p_error = self.base().object2world.xf_point_with_error(&p_hit, &p_error).1;
+        let dg = compute_dg(self.base(), u, v, p_hit,
+                            dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
+
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::f32::consts::PI;
+
+    use bbox::BBox;
+    use geometry::normal::Normalize;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use intersection::Intersectable;
+    use ray::Ray;
+    use shape::ShapeBase;
+    use transform::transform::Transform;
+    use utils::Degrees;
+
+    #[test]
+    fn it_can_be_created() {
+        let xf = Transform::translate(&Vector::new_with(1.0, 2.0, 3.0));
+        assert_eq!(Paraboloid::new(xf.clone(), xf.inverse(), false,
+                                   1.5, 2.0, 0.0, 90.0),
+                   Paraboloid {
+                       base: ShapeBase::new(xf.clone(), xf.inverse(), false),
+                       radius: 1.5,
+                       z_min: 0.0,
+                       z_max: 2.0,
+                       phi_max: 90f32.as_radians()
+                   });
+    }
+
+    #[test]
+    fn it_has_bounds() {
+        assert_eq!(Paraboloid::new(Transform::new(), Transform::new(), false,
+                                   1.0, 0.5, 2.0, 360.0).object_bound(),
+                   BBox::new_with(
+                       Point::new_with(-1.0, -1.0, 0.5),
+                       Point::new_with(1.0, 1.0, 2.0)));
+    }
+
+    #[test]
+    fn it_can_be_intersected() {
+        // z = x^2 + y^2 up to z = 1
+        let simple = Paraboloid::new(Transform::new(), Transform::new(), false,
+                                     1.0, 0.0, 1.0, 360.0);
+
+        // Straight down into the bowl
+        assert!(simple.intersect_p(
+            &Ray::new_with(Point::new_with(0.5, 0.0, 2.0),
+                           Vector::new_with(0.0, 0.0, -1.0), 0.0)));
+
+        // Straight down outside of the rim
+        assert!(!simple.intersect_p(
+            &Ray::new_with(Point::new_with(1.5, 0.0, 2.0),
+                           Vector::new_with(0.0, 0.0, -1.0), 0.0)));
+
+        // Sideways above the rim
+        assert!(!simple.intersect_p(
+            &Ray::new_with(Point::new_with(-2.0, 0.0, 1.5),
+                           Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+
+        // Sideways through the walls
+        assert!(simple.intersect_p(
+            &Ray::new_with(Point::new_with(-2.0, 0.0, 0.5),
+                           Vector::new_with(1.0, 0.0, 0.0), 0.0)));
+
+        // Clipping off the bottom lets rays through the hole
+        let clipped = Paraboloid::new(Transform::new(), Transform::new(), false,
+                                      1.0, 0.5, 1.0, 360.0);
+        assert!(!clipped.intersect_p(
+            &Ray::new_with(Point::new_with(0.1, 0.1, 2.0),
+                           Vector::new_with(0.0, 0.0, -1.0), 0.0)));
+
+        // Partial sweeps
+        let partial = Paraboloid::new(Transform::new(), Transform::new(), false,
+                                      1.0, 0.0, 1.0, 90.0);
+        assert!(partial.intersect_p(
+            &Ray::new_with(Point::new_with(0.5, 0.5, 2.0),
+                           Vector::new_with(0.0, 0.0, -1.0), 0.0)));
+        assert!(!partial.intersect_p(
+            &Ray::new_with(Point::new_with(-0.5, 0.5, 2.0),
+                           Vector::new_with(0.0, 0.0, -1.0), 0.0)));
+    }
+
+    #[test]
+    fn it_has_intersection_information() {
+        let p = Paraboloid::new(Transform::new(), Transform::new(), false,
+                                1.0, 0.0, 1.0, 360.0);
+
+        let r = Ray::new_with(Point::new_with(0.5, 0.0, 2.0),
+                              Vector::new_with(0.0, 0.0, -1.0), 0.0);
+        let shape_int = p.intersect(&r).unwrap();
+
+        assert!((shape_int.t_hit - 1.75).abs() < 1e-6);
+        assert!((shape_int.dg.p - Point::new_with(0.5, 0.0, 0.25)).length_squared() < 1e-6);
+        assert_eq!(shape_int.dg.shape.as_ref().unwrap(), p.base());
+
+        assert_eq!(shape_int.dg.u, 0.0);
+        assert!((shape_int.dg.v - 0.25).abs() < 1e-6);
+        assert!((shape_int.dg.dpdu - Vector::new_with(0.0, PI, 0.0)).length_squared() < 1e-6);
+        assert!((shape_int.dg.dpdv - Vector::new_with(1.0, 0.0, 1.0)).length_squared() < 1e-6);
+
+        // The normal is perpendicular to the slope dz/dr = 2r = 1 and
+        // points away from the axis
+        let expected_normal = Vector::new_with(1.0, 0.0, -1.0).normalize();
+        assert!((Vector::from(shape_int.dg.nn.clone()) - expected_normal).length_squared() < 1e-6);
+
+        // The bowl curves in both directions
+        assert!(Vector::from(shape_int.dg.dndu.clone()).length_squared() > 0.0);
+        assert!(Vector::from(shape_int.dg.dndv.clone()).length_squared() > 0.0);
+    }
+
+    #[test]
+    fn it_has_a_surface_area() {
+        // Compare against a numerical integration of the surface of
+        // revolution 2 * pi * r(z) * sqrt(1 + r'(z)^2) dz
+        let p = Paraboloid::new(Transform::new(), Transform::new(), false,
+                                2.0, 0.5, 3.0, 360.0);
+        let n = 10000;
+        let dz = 2.5 / (n as f32);
+        let expected = (0..n).fold(0.0, |a, i| {
+            let z = 0.5 + (i as f32 + 0.5) * dz;
+            let r = 2.0 * (z / 3.0).sqrt();
+            let drdz = r / (2.0 * z);
+            a + 2.0 * PI * r * (1.0 + drdz * drdz).sqrt() * dz
+        });
+        assert!((p.area() - expected).abs() / expected < 1e-3);
+
+        let half = Paraboloid::new(Transform::new(), Transform::new(), false,
+                                   2.0, 0.5, 3.0, 180.0);
+        assert!((2.0 * half.area() - p.area()).abs() < 1e-3);
+    }
+}
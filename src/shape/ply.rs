@@ -0,0 +1,653 @@
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+
+use geometry::normal::Normal;
+use geometry::point::Point;
+use shape::mesh_processing::MeshData;
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(io::Error),
+    Format(String)
+}
+
+impl ::std::fmt::Display for PlyError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            &PlyError::Io(ref e) => write!(f, "I/O error: {}", e),
+            &PlyError::Format(ref s) => write!(f, "Malformed PLY data: {}", s)
+        }
+    }
+}
+
+impl ::std::error::Error for PlyError {
+    fn description(&self) -> &str {
+        match self {
+            &PlyError::Io(ref e) => e.description(),
+            &PlyError::Format(ref s) => s
+        }
+    }
+}
+
+impl ::std::convert::From<io::Error> for PlyError {
+    fn from(e: io::Error) -> PlyError { PlyError::Io(e) }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    Int8, UInt8, Int16, UInt16, Int32, UInt32, Float32, Float64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            &ScalarType::Int8 | &ScalarType::UInt8 => 1,
+            &ScalarType::Int16 | &ScalarType::UInt16 => 2,
+            &ScalarType::Int32 | &ScalarType::UInt32 | &ScalarType::Float32 => 4,
+            &ScalarType::Float64 => 8
+        }
+    }
+
+    // Interprets the low size() bytes of bits as a value of this type
+    fn from_bits(&self, bits: u64) -> f64 {
+        match self {
+            &ScalarType::Int8 => bits as u8 as i8 as f64,
+            &ScalarType::UInt8 => bits as u8 as f64,
+            &ScalarType::Int16 => bits as u16 as i16 as f64,
+            &ScalarType::UInt16 => bits as u16 as f64,
+            &ScalarType::Int32 => bits as u32 as i32 as f64,
+            &ScalarType::UInt32 => bits as u32 as f64,
+            &ScalarType::Float32 =>
+                unsafe { ::std::mem::transmute::<u32, f32>(bits as u32) as f64 },
+            &ScalarType::Float64 =>
+                unsafe { ::std::mem::transmute::<u64, f64>(bits) }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Property {
+    Scalar(String, ScalarType),
+    List(String, ScalarType, ScalarType)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+// Pulls values out of the body of a PLY file one at a time, so that the
+// only thing we ever hold on to is the mesh being built.
+struct PlyReader<R: BufRead> {
+    r: R,
+    filename: String,
+    encoding: Encoding,
+    line: usize,
+    token_line: usize,
+    token: Vec<u8>
+}
+
+impl<R: BufRead> PlyReader<R> {
+    fn error(&self, msg: String) -> PlyError {
+        match self.encoding {
+            Encoding::Ascii =>
+                PlyError::Format(format!("{}:{}: {}", self.filename, self.token_line, msg)),
+            _ => PlyError::Format(format!("{}: {}", self.filename, msg))
+        }
+    }
+
+    fn next_token(&mut self) -> Result<(), PlyError> {
+        self.token.clear();
+        loop {
+            let (used, done) = {
+                let buf = try!(self.r.fill_buf());
+                if buf.len() == 0 {
+                    break;
+                }
+
+                let mut used = 0;
+                let mut done = false;
+                for &b in buf {
+                    used += 1;
+                    if b == b'\n' {
+                        self.line += 1;
+                    }
+
+                    if b == b' ' || b == b'\t' || b == b'\r' || b == b'\n' {
+                        if self.token.len() > 0 {
+                            done = true;
+                            break;
+                        }
+                    } else {
+                        if self.token.len() == 0 {
+                            self.token_line = self.line;
+                        }
+                        self.token.push(b);
+                    }
+                }
+                (used, done)
+            };
+
+            self.r.consume(used);
+            if done {
+                return Ok(());
+            }
+        }
+
+        if self.token.len() == 0 {
+            self.token_line = self.line;
+            Err(self.error(String::from("unexpected end of file")))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, PlyError> {
+        if self.encoding == Encoding::Ascii {
+            try!(self.next_token());
+            let v = ::std::str::from_utf8(&self.token).ok()
+                .and_then(|s| s.parse::<f64>().ok());
+            return match v {
+                Some(v) => Ok(v),
+                None => {
+                    let tok = String::from_utf8_lossy(&self.token).into_owned();
+                    Err(self.error(format!("unexpected token '{}'", tok)))
+                }
+            };
+        }
+
+        let size = ty.size();
+        let mut bytes = [0u8; 8];
+        if let Err(e) = self.r.read_exact(&mut bytes[..size]) {
+            return if e.kind() == io::ErrorKind::UnexpectedEof {
+                Err(self.error(String::from("unexpected end of file")))
+            } else {
+                Err(PlyError::Io(e))
+            };
+        }
+
+        let bits = (0..size).fold(0u64, |acc, i| {
+            let b = if self.encoding == Encoding::BinaryLittleEndian {
+                bytes[i]
+            } else {
+                bytes[size - 1 - i]
+            };
+            acc | ((b as u64) << (8 * i))
+        });
+        Ok(ty.from_bits(bits))
+    }
+
+    fn read_count(&mut self, ty: ScalarType) -> Result<usize, PlyError> {
+        let n = try!(self.read(ty));
+        if n < 0.0 || n.fract() != 0.0 {
+            return Err(self.error(format!("invalid list length {}", n)));
+        }
+        Ok(n as usize)
+    }
+
+    fn skip(&mut self, prop: &Property) -> Result<(), PlyError> {
+        match prop {
+            &Property::Scalar(_, ty) => { try!(self.read(ty)); },
+            &Property::List(_, count_ty, item_ty) => {
+                let n = try!(self.read_count(count_ty));
+                for _ in 0..n {
+                    try!(self.read(item_ty));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_header<R: BufRead>(filename: &str, r: &mut R)
+                           -> Result<(Encoding, Vec<Element>, usize), PlyError> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line = String::new();
+    let mut line_num = 0;
+    loop {
+        line.clear();
+        line_num += 1;
+        if try!(r.read_line(&mut line)) == 0 {
+            return Err(PlyError::Format(format!(
+                "{}:{}: unexpected end of file in header", filename, line_num)));
+        }
+
+        let err = |msg: &str| PlyError::Format(format!("{}:{}: {}", filename, line_num, msg));
+        let toks: Vec<&str> = line.split_whitespace().collect();
+        if line_num == 1 {
+            if toks != ["ply"] {
+                return Err(err("not a PLY file"));
+            }
+            continue;
+        }
+
+        if toks.len() == 0 {
+            continue;
+        }
+
+        match toks[0] {
+            "format" => {
+                encoding = match toks.get(1) {
+                    Some(&"ascii") => Some(Encoding::Ascii),
+                    Some(&"binary_little_endian") => Some(Encoding::BinaryLittleEndian),
+                    Some(&"binary_big_endian") => Some(Encoding::BinaryBigEndian),
+                    _ => return Err(err(&format!("unknown format '{}'", toks[1..].join(" "))))
+                };
+            },
+            "element" => {
+                let count = toks.get(2).and_then(|c| c.parse::<usize>().ok());
+                match count {
+                    Some(c) if toks.len() == 3 => elements.push(Element {
+                        name: toks[1].to_string(),
+                        count: c,
+                        properties: Vec::new()
+                    }),
+                    _ => return Err(err("expected an element name and count"))
+                }
+            },
+            "property" => {
+                let prop = if toks.get(1) == Some(&"list") && toks.len() == 5 {
+                    match (ScalarType::parse(toks[2]), ScalarType::parse(toks[3])) {
+                        (Some(c), Some(i)) => Property::List(toks[4].to_string(), c, i),
+                        _ => return Err(err(&format!("unknown list type '{} {}'",
+                                                     toks[2], toks[3])))
+                    }
+                } else if toks.len() == 3 {
+                    match ScalarType::parse(toks[1]) {
+                        Some(ty) => Property::Scalar(toks[2].to_string(), ty),
+                        None => return Err(err(&format!("unknown type '{}'", toks[1])))
+                    }
+                } else {
+                    return Err(err("malformed property"));
+                };
+
+                match elements.last_mut() {
+                    Some(elt) => elt.properties.push(prop),
+                    None => return Err(err("property before any element"))
+                }
+            },
+            "end_header" => break,
+            "comment" | "obj_info" => (),
+            _ => return Err(err(&format!("unexpected keyword '{}'", toks[0])))
+        }
+    }
+
+    match encoding {
+        Some(e) => Ok((e, elements, line_num)),
+        None => Err(PlyError::Format(format!("{}: missing format", filename)))
+    }
+}
+
+// Element counts come straight from the header, so a broken or hostile
+// file can claim any number of elements. Nothing beyond this many values
+// is allocated up front, the rest only once it has actually been read.
+const MAX_RESERVATION: usize = 1 << 20;
+
+fn reservation(filename: &str, elt: &Element, per_element: usize) -> Result<usize, PlyError> {
+    match elt.count.checked_mul(per_element) {
+        Some(n) => Ok(n.min(MAX_RESERVATION)),
+        None => Err(PlyError::Format(format!("{}: {} element count {} is too large",
+                                             filename, elt.name, elt.count)))
+    }
+}
+
+// Reads the vertices and faces of a PLY file. Vertex positions are
+// required, while normals and texture coordinates are optional. Faces are
+// triangulated as fans, which handles both triangles and quads. Any other
+// elements and properties are skipped.
+pub fn read_ply<R: BufRead>(filename: &str, mut r: R) -> Result<MeshData, PlyError> {
+    let (encoding, elements, header_lines) = try!(read_header(filename, &mut r));
+
+    let (num_verts, uv_capacity) = match elements.iter().find(|e| e.name == "vertex") {
+        Some(e) => (e.count, try!(reservation(filename, e, 2))),
+        None => (0, 0)
+    };
+    let mut p = Vec::with_capacity(uv_capacity / 2);
+    let mut n = Vec::with_capacity(uv_capacity / 2);
+    let mut uvs = Vec::with_capacity(uv_capacity);
+    let mut has_n = false;
+    let mut has_uv = false;
+    let mut vertex_index = Vec::new();
+
+    // The body starts on the line right after the header
+    let mut reader = PlyReader {
+        r: r,
+        filename: filename.to_string(),
+        encoding: encoding,
+        line: header_lines + 1,
+        token_line: header_lines + 1,
+        token: Vec::new()
+    };
+
+    for elt in elements.iter() {
+        match &elt.name[..] {
+            "vertex" => {
+                // Which of x, y, z, nx, ny, nz, u, v each property is
+                let slots: Vec<Option<usize>> = elt.properties.iter().map(|prop| {
+                    match prop {
+                        &Property::List(_, _, _) => None,
+                        &Property::Scalar(ref name, _) => match &name[..] {
+                            "x" => Some(0), "y" => Some(1), "z" => Some(2),
+                            "nx" => Some(3), "ny" => Some(4), "nz" => Some(5),
+                            "u" | "s" | "texture_u" | "texture_s" => Some(6),
+                            "v" | "t" | "texture_v" | "texture_t" => Some(7),
+                            _ => None
+                        }
+                    }
+                }).collect();
+
+                let has = |i: usize| slots.iter().any(|s| *s == Some(i));
+                if !(has(0) && has(1) && has(2)) {
+                    return Err(PlyError::Format(format!(
+                        "{}: vertex element is missing x, y or z", filename)));
+                }
+                has_n = has(3) && has(4) && has(5);
+                has_uv = has(6) && has(7);
+
+                let mut v = [0.0f64; 8];
+                for _ in 0..elt.count {
+                    for (prop, slot) in elt.properties.iter().zip(slots.iter()) {
+                        match (prop, *slot) {
+                            (&Property::Scalar(_, ty), Some(i)) => v[i] = try!(reader.read(ty)),
+                            _ => try!(reader.skip(prop))
+                        }
+                    }
+
+                    p.push(Point::new_with(v[0] as f32, v[1] as f32, v[2] as f32));
+                    if has_n {
+                        n.push(Normal::new_with(v[3] as f32, v[4] as f32, v[5] as f32));
+                    }
+                    if has_uv {
+                        uvs.push(v[6] as f32);
+                        uvs.push(v[7] as f32);
+                    }
+                }
+            },
+            "face" => {
+                let indices = elt.properties.iter().position(|prop| {
+                    match prop {
+                        &Property::List(ref name, _, _) =>
+                            name == "vertex_indices" || name == "vertex_index",
+                        _ => false
+                    }
+                });
+
+                let indices = match indices {
+                    Some(i) => i,
+                    None => return Err(PlyError::Format(format!(
+                        "{}: face element is missing vertex_indices", filename)))
+                };
+
+                // Most faces are triangles or quads
+                vertex_index.reserve(try!(reservation(filename, elt, 3)));
+                let mut face = Vec::with_capacity(4);
+                for f in 0..elt.count {
+                    for (i, prop) in elt.properties.iter().enumerate() {
+                        match prop {
+                            &Property::List(_, count_ty, item_ty) if i == indices => {
+                                let count = try!(reader.read_count(count_ty));
+                                face.clear();
+                                for _ in 0..count {
+                                    let idx = try!(reader.read(item_ty));
+                                    if idx < 0.0 || idx >= num_verts as f64 {
+                                        return Err(reader.error(format!(
+                                            "face {} references vertex {} of {}",
+                                            f, idx, num_verts)));
+                                    }
+                                    face.push(idx as usize);
+                                }
+
+                                if count < 3 {
+                                    return Err(reader.error(format!(
+                                        "face {} has only {} vertices", f, count)));
+                                }
+                            },
+                            _ => try!(reader.skip(prop))
+                        }
+                    }
+
+                    for i in 1..(face.len() - 1) {
+                        vertex_index.push(face[0]);
+                        vertex_index.push(face[i]);
+                        vertex_index.push(face[i + 1]);
+                    }
+                }
+            },
+            _ => {
+                for _ in 0..elt.count {
+                    for prop in elt.properties.iter() {
+                        try!(reader.skip(prop));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(MeshData {
+        vertex_index: vertex_index,
+        p: p,
+        n: if has_n { Some(n) } else { None },
+        s: None,
+        uvs: if has_uv { Some(uvs) } else { None }
+    })
+}
+
+pub fn load_ply(filename: &str) -> Result<MeshData, PlyError> {
+    let f = try!(File::open(filename));
+    read_ply(filename, BufReader::new(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    use bbox::HasBounds;
+    use geometry::normal::Normal;
+    use geometry::point::Point;
+    use geometry::vector::Vector;
+    use shape::Shape;
+    use transform::transform::Transform;
+
+    fn header(format: &str) -> String {
+        format!("ply\nformat {} 1.0\ncomment made by hand\n\
+                 element vertex 4\n\
+                 property float x\nproperty float y\nproperty float z\n\
+                 property double nx\nproperty double ny\nproperty double nz\n\
+                 property uchar red\nproperty float u\nproperty float v\n\
+                 element face 2\n\
+                 property list uchar int vertex_indices\nproperty int flags\n\
+                 element edge 1\nproperty int vertex1\nproperty int vertex2\n\
+                 end_header\n", format)
+    }
+
+    const POSITIONS: [[f32; 3]; 4] =
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+
+    fn ascii_ply() -> String {
+        header("ascii") +
+            "0 0 0 0 0 1 255 0 0\n\
+             1 0 0 0 0 1 255 1 0\n\
+             1 1 0 0 0 1 255 1 1\n\
+             0 1 0 0 0 1 255 0 1\n\
+             4 0 1 2 3 7\n\
+             3 1 2 3 7\n\
+             0 2\n"
+    }
+
+    fn binary_ply(big_endian: bool) -> Vec<u8> {
+        let mut data = header(if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        }).into_bytes();
+
+        let push = |data: &mut Vec<u8>, bits: u64, size: usize| {
+            let bytes: Vec<u8> = (0..size).map(|i| (bits >> (8 * i)) as u8).collect();
+            if big_endian {
+                data.extend(bytes.iter().rev());
+            } else {
+                data.extend(bytes.iter());
+            }
+        };
+
+        let f32_bits = |v: f32| unsafe { ::std::mem::transmute::<f32, u32>(v) as u64 };
+        let f64_bits = |v: f64| unsafe { ::std::mem::transmute::<f64, u64>(v) };
+        for p in POSITIONS.iter() {
+            for &x in p.iter() { push(&mut data, f32_bits(x), 4); }
+            for &x in [0.0, 0.0, 1.0].iter() { push(&mut data, f64_bits(x), 8); }
+            push(&mut data, 255, 1);
+            push(&mut data, f32_bits(p[0]), 4);
+            push(&mut data, f32_bits(p[1]), 4);
+        }
+
+        for face in [vec![0, 1, 2, 3], vec![1, 2, 3]].iter() {
+            push(&mut data, face.len() as u64, 1);
+            for &i in face.iter() { push(&mut data, i, 4); }
+            push(&mut data, 7, 4);
+        }
+
+        push(&mut data, 0, 4);
+        push(&mut data, 2, 4);
+        data
+    }
+
+    fn error_message(data: &[u8]) -> String {
+        match read_ply("test.ply", data) {
+            Err(PlyError::Format(msg)) => msg,
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Expected an error")
+        }
+    }
+
+    #[test]
+    fn it_reads_ascii_files() {
+        let mesh = read_ply("test.ply", ascii_ply().as_bytes()).unwrap();
+        assert_eq!(mesh.vertex_index, vec![0, 1, 2, 0, 2, 3, 1, 2, 3]);
+        assert_eq!(mesh.p, POSITIONS.iter().map(|p| Point::new_with(p[0], p[1], p[2]))
+                   .collect::<Vec<_>>());
+        assert_eq!(mesh.n, Some(vec![Normal::new_with(0.0, 0.0, 1.0); 4]));
+        assert_eq!(mesh.uvs, Some(vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0]));
+        assert!(mesh.s.is_none());
+    }
+
+    #[test]
+    fn it_reads_binary_files_of_either_endianness() {
+        let ascii = read_ply("test.ply", ascii_ply().as_bytes()).unwrap();
+        assert_eq!(read_ply("test.ply", &binary_ply(false)[..]).unwrap(), ascii);
+        assert_eq!(read_ply("test.ply", &binary_ply(true)[..]).unwrap(), ascii);
+    }
+
+    #[test]
+    fn it_only_requires_positions() {
+        let mesh = read_ply("test.ply", "ply\nformat ascii 1.0\nelement vertex 3\n\
+                                         property float x\nproperty float y\n\
+                                         property float z\nproperty float nx\n\
+                                         element face 1\n\
+                                         property list uchar uint vertex_index\n\
+                                         end_header\n\
+                                         0 0 0 1\n1 0 0 1\n0 1 0 1\n3 2 1 0\n".as_bytes()).unwrap();
+        assert_eq!(mesh.vertex_index, vec![2, 1, 0]);
+        assert!(mesh.n.is_none());
+        assert!(mesh.uvs.is_none());
+    }
+
+    #[test]
+    fn it_reports_malformed_files() {
+        assert_eq!(error_message(b"plx\n"), "test.ply:1: not a PLY file");
+        assert_eq!(error_message(b"ply\nformat ascii 1.0\nelement vertex 0\n\
+                                   property float x\nproperty float y\nend_header\n"),
+                   "test.ply: vertex element is missing x, y or z");
+        assert_eq!(error_message(b"ply\nformat ascii 1.0\nproperty float x\n"),
+                   "test.ply:3: property before any element");
+        assert_eq!(error_message(b"ply\nformat binary_middle_endian 1.0\n"),
+                   "test.ply:2: unknown format 'binary_middle_endian 1.0'");
+
+        // The body of the ascii file starts on line 21
+        let bad_token = ascii_ply().replace("1 1 0 0 0 1", "1 one 0 0 0 1");
+        assert_eq!(error_message(bad_token.as_bytes()),
+                   "test.ply:23: unexpected token 'one'");
+
+        let bad_index = ascii_ply().replace("3 1 2 3 7", "3 1 2 4 7");
+        assert_eq!(error_message(bad_index.as_bytes()),
+                   "test.ply:26: face 1 references vertex 4 of 4");
+
+        let truncated = ascii_ply().replace("0 2\n", "0");
+        assert_eq!(error_message(truncated.as_bytes()),
+                   "test.ply:27: unexpected end of file");
+
+        let binary = binary_ply(false);
+        assert_eq!(error_message(&binary[..(binary.len() - 3)]),
+                   "test.ply: unexpected end of file");
+    }
+
+    #[test]
+    fn it_doesnt_trust_element_counts() {
+        let ply = |num_verts: &str, num_faces: &str| {
+            format!("ply\nformat ascii 1.0\nelement vertex {}\n\
+                     property float x\nproperty float y\nproperty float z\n\
+                     element face {}\nproperty list uchar int vertex_indices\n\
+                     end_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n", num_verts, num_faces)
+        };
+
+        assert_eq!(error_message(ply("18446744073709551615", "1").as_bytes()),
+                   "test.ply: vertex element count 18446744073709551615 is too large");
+        assert_eq!(error_message(ply("3", "18446744073709551615").as_bytes()),
+                   "test.ply: face element count 18446744073709551615 is too large");
+
+        // Counts that don't overflow just run out of data
+        assert_eq!(error_message(ply("1099511627776", "1").as_bytes()),
+                   "test.ply:14: unexpected end of file");
+        assert_eq!(error_message(ply("3", "1099511627776").as_bytes()),
+                   "test.ply:14: unexpected end of file");
+    }
+
+    #[test]
+    fn it_creates_triangle_meshes() {
+        let path = ::std::env::temp_dir().join("pbrt_rust_ply_test.ply");
+        {
+            let mut f = File::create(&path).unwrap();
+            f.write_all(&binary_ply(true)).unwrap();
+        }
+
+        let xf = Transform::translate(&Vector::new_with(0.0, 0.0, 2.0));
+        let shape = Shape::ply_mesh(xf.clone(), xf.inverse(), false,
+                                    &path.to_string_lossy()).unwrap();
+        let bounds = shape.world_bound();
+        assert_eq!(bounds.p_min, Point::new_with(0.0, 0.0, 2.0));
+        assert_eq!(bounds.p_max, Point::new_with(1.0, 1.0, 2.0));
+
+        match Shape::ply_mesh(xf.clone(), xf.inverse(), false, "no/such/file.ply") {
+            Err(PlyError::Io(_)) => (),
+            _ => panic!("Expected an I/O error")
+        }
+    }
+}
@@ -11,6 +11,7 @@ use transform::transform::Transform;
 use transform::transform::ApplyTransform;
 use utils::Degrees;
 use utils::Clamp;
+use utils::gamma;
 
 use shape::helpers::compute_dg;
 
@@ -142,7 +143,14 @@ impl Intersectable<ShapeIntersection> for Sphere {
             if hit.is_some() { hit.unwrap() } else { return None; }
         };
 
-        let p_hit = ray.point_at(t_hit);
+        // Refine the sphere hit point by reprojecting it onto the surface,
+        // which bounds its error by the few operations that takes
+        let p_hit = {
+            let p = ray.point_at(t_hit);
+            let scale = self.radius / p.distance(&Point::new());
+            &p * scale
+        };
+        let p_error = gamma(5) * &Vector::from(p_hit.clone()).abs();
 
         // Find parametric representation of sphere hit
         let u = phi / self.phi_max;
@@ -170,9 +178,10 @@ impl Intersectable<ShapeIntersection> for Sphere {
             (self.theta_max - self.theta_min) *
             Vector::from(p_hit.clone());
 
+        let p_error = self.base().object2world.xf_point_with_error(&p_hit, &p_error).1;
         let dg = compute_dg(self.base(), u, v, p_hit,
                             dpdu, dpdv, d2pduu, d2pduv, d2pdvv);
-        Some(ShapeIntersection::new(t_hit, t_hit * 5e-4, dg))
+        Some(ShapeIntersection::new(t_hit, p_error, dg))
     }
 }
 
@@ -275,7 +284,8 @@ mod tests {
         let shape_int = s.intersect(&r).unwrap();
 
         assert_eq!(shape_int.t_hit, 0.5);
-        assert_eq!(shape_int.ray_epsilon, 0.5 * 5e-4);
+        assert!(shape_int.p_error.y > 0.0);
+        assert!(shape_int.p_error.length() < 1e-5);
         assert_eq!(shape_int.dg.p, Point::new_with(0.0, -0.5, 0.0));
         assert_eq!(shape_int.dg.shape.as_ref().unwrap(), s.base());
 
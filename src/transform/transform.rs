@@ -10,6 +10,7 @@ use ray::Ray;
 use ray::RayDifferential;
 use transform::matrix4x4::Matrix4x4;
 use utils::Degrees;
+use utils::gamma;
 
 #[derive(Debug, PartialEq, PartialOrd, Clone)]
 pub struct Transform {
@@ -183,6 +184,21 @@ impl Transform {
     }
 
     pub fn get_matrix<'a>(&'a self) -> &'a Matrix4x4 { &(self.m) }
+
+    // Transforms a point that is already known to within p_error and
+    // returns a conservative bound on the error of the result. This
+    // assumes that the transform is affine.
+    pub fn xf_point_with_error(&self, p: &Point, p_error: &Vector) -> (Point, Vector) {
+        let m = &self.m;
+        let err = |i: usize| {
+            (gamma(3) + 1.0) * (m[i][0].abs() * p_error.x + m[i][1].abs() * p_error.y +
+                                m[i][2].abs() * p_error.z) +
+                gamma(3) * ((m[i][0] * p.x).abs() + (m[i][1] * p.y).abs() +
+                            (m[i][2] * p.z).abs() + m[i][3].abs())
+        };
+
+        (self.t(p), Vector::new_with(err(0), err(1), err(2)))
+    }
 }
 
 pub trait ApplyTransform<T : Clone> {
@@ -612,6 +628,30 @@ mod tests {
         assert_eq!(trd.ray.depth, trd_expected.ray.depth);
     }
 
+    #[test]
+    fn it_can_transform_points_with_error() {
+        // Identity transforms don't introduce any error of their own
+        // beyond what's needed to be conservative
+        let p = Point::new_with(1.0, -2.0, 3.0);
+        let (q, err) = Transform::new().xf_point_with_error(&p, &Vector::new());
+        assert_eq!(q, p);
+        assert!(err.x <= 2.0 * ::utils::gamma(3));
+
+        // The error grows with the scale of the transform and existing
+        // error gets carried along
+        let xf = Transform::scale(10.0, 10.0, 10.0) *
+            Transform::translate(&Vector::new_with(100.0, 0.0, 0.0));
+        let p_error = Vector::new_with(1e-3, 0.0, 0.0);
+        let (q, err) = xf.xf_point_with_error(&p, &p_error);
+        assert_eq!(q, Point::new_with(1010.0, -20.0, 30.0));
+        assert!(err.x > 1e-2);
+        assert!(err.x > err.y && err.y > 0.0);
+
+        // The true result is always within the bound
+        let exact = 10.0 * (1.0 + 100.0 + 1e-3) as f64;
+        assert!(((q.x as f64) - exact).abs() <= (err.x as f64));
+    }
+
     #[test]
     fn it_can_transform_bboxes() {
         let bbox = BBox::new_with(Point::new_with(-1.0, -1.0, -1.0),
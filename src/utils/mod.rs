@@ -100,6 +100,38 @@ pub fn quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
     if t0 < t1 { Some((t0, t1)) } else { Some((t1, t0)) }
 }
 
+// Upper bound on the relative error of a single rounded floating point
+// operation
+pub const MACHINE_EPSILON: f32 = ::std::f32::EPSILON * 0.5;
+
+// Conservative bound on the relative error accumulated over n floating
+// point operations, (1 +- e)^n <= 1 +- gamma(n)
+pub fn gamma(n: i32) -> f32 {
+    let ne = (n as f32) * MACHINE_EPSILON;
+    ne / (1.0 - ne)
+}
+
+pub fn next_float_up(v: f32) -> f32 {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+
+    // Skip negative zero so that we step to the smallest positive float
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    f32::from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+pub fn next_float_down(v: f32) -> f32 {
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    f32::from_bits(if v > 0.0 { bits - 1 } else { bits + 1 })
+}
+
 pub fn solve_linear_system_2x2(a: [[f32; 2]; 2], b: [f32; 2])
                                -> Option<(f32, f32)> {
     let det = a[0][0] * a[1][1] - a[0][1] * a[1][0];
@@ -491,6 +523,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_bounds_floating_point_error() {
+        assert_eq!(gamma(0), 0.0);
+        assert!(gamma(1) > MACHINE_EPSILON);
+        assert!(gamma(3) < gamma(5));
+
+        // Accumulating a sum loses at most gamma(n) relative precision
+        let xs: Vec<f32> = (0..100).map(|i| 1.0 / ((i + 1) as f32)).collect();
+        let sum = xs.iter().fold(0f32, |a, &x| a + x);
+        let exact = xs.iter().fold(0f64, |a, &x| a + (x as f64));
+        assert!(((sum as f64) - exact).abs() <= (gamma(100) as f64) * exact);
+    }
+
+    #[test]
+    fn it_can_step_to_adjacent_floats() {
+        assert!(next_float_up(1.0) > 1.0);
+        assert!(next_float_down(1.0) < 1.0);
+        assert_eq!(next_float_down(next_float_up(1.0)), 1.0);
+        assert_eq!(next_float_up(-1.0), -next_float_down(1.0));
+
+        // Zero steps to the smallest denormal in either direction
+        assert!(next_float_up(0.0) > 0.0);
+        assert!(next_float_up(-0.0) > 0.0);
+        assert!(next_float_down(0.0) < 0.0);
+        assert_eq!(next_float_up(f32::INFINITY), f32::INFINITY);
+        assert_eq!(next_float_down(f32::NEG_INFINITY), f32::NEG_INFINITY);
+    }
+
     #[test]
     fn it_can_partition_points() {
         let mut pts = vec![
@@ -0,0 +1,93 @@
+extern crate pbrt_rust;
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use pbrt_rust::geometry::point::Point;
+use pbrt_rust::geometry::vector::Vector;
+use pbrt_rust::primitive::InstanceRegistry;
+use pbrt_rust::primitive::Primitive;
+use pbrt_rust::shape::Shape;
+use pbrt_rust::transform::animated::AnimatedTransform;
+use pbrt_rust::transform::transform::Transform;
+
+// Keeps track of how many bytes are live on the heap. This is the only
+// test in this binary so nothing else is allocating while it runs.
+struct CountingAllocator;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn live_bytes() -> usize { LIVE_BYTES.load(Ordering::SeqCst) }
+
+// A flat n x n grid of quads in the xy plane
+fn tile(n: usize) -> Primitive {
+    let mut p = Vec::new();
+    for j in 0..(n + 1) {
+        for i in 0..(n + 1) {
+            p.push(Point::new_with(i as f32 / n as f32, j as f32 / n as f32, 0.0));
+        }
+    }
+
+    let mut vi = Vec::new();
+    for j in 0..n {
+        for i in 0..n {
+            let v = j * (n + 1) + i;
+            vi.extend_from_slice(&[v, v + 1, v + n + 2, v, v + n + 2, v + n + 1]);
+        }
+    }
+
+    Primitive::geometric(Shape::triangle_mesh(Transform::new(), Transform::new(), false,
+                                              &vi, &p, None, None, None, None))
+}
+
+fn instance_at(registry: &InstanceRegistry, i: usize) -> Primitive {
+    let w2i = Transform::translate(&Vector::new_with(-(i as f32), 0.0, 0.0));
+    registry.object_instance("tile", AnimatedTransform::new(w2i.clone(), 0.0, w2i, 1.0))
+        .unwrap()
+}
+
+#[test]
+fn instancing_memory_stays_flat() {
+    let start = live_bytes();
+    let mut registry = InstanceRegistry::new();
+    registry.define("tile", vec![tile(32)]).unwrap();
+    let object_bytes = live_bytes() - start;
+
+    let num_instances = 10000;
+    let mut instances = Vec::with_capacity(num_instances);
+
+    let start = live_bytes();
+    for i in 0..100 {
+        instances.push(instance_at(&registry, i));
+    }
+    let hundred = live_bytes() - start;
+
+    for i in 100..num_instances {
+        instances.push(instance_at(&registry, i));
+    }
+    let all = live_bytes() - start;
+
+    // Every instance costs the same fixed amount no matter how many there
+    // are, and that amount is tiny next to the geometry it refers to.
+    assert_eq!(all, hundred * (num_instances / 100));
+    assert!(hundred < object_bytes);
+    assert_eq!(instances.len(), num_instances);
+}